@@ -0,0 +1,630 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use crate::grammar::lexer::Span;
+use crate::grammar::parser::{Expression, Function, Macro, NodeId, Pattern, Program, Statement};
+
+// How many macro uses can be expanded inside one another, which stops a macro
+// that uses itself from expanding forever
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug)]
+pub enum MacroError {
+    // The span is that of the whole call
+    ArityMismatch { name: String, expected: usize, found: usize, span: Span },
+    // Arguments are put in place of parameters one for one, so there's
+    // nowhere for the elements of a spread to go
+    SpreadArgument { span: Span },
+    // The macro assigns to the parameter, so the argument has to be something
+    // that can be assigned to
+    InvalidAssignmentTarget { span: Span },
+    // The span is that of the call that went past `MAX_DEPTH`
+    TooDeeplyNested { name: String, span: Span },
+}
+
+impl Display for MacroError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MacroError::ArityMismatch { name, expected, found, span } => write!(
+                f,
+                "Macro '{}' takes {} arguments but was given {} at line {}, character {}",
+                name, expected, found, span.line, span.character
+            ),
+            MacroError::SpreadArgument { span } => write!(
+                f,
+                "A macro can't be given a spread argument at line {}, character {}",
+                span.line, span.character
+            ),
+            MacroError::InvalidAssignmentTarget { span } => write!(
+                f,
+                "The macro assigns to the argument at line {}, character {}, which isn't a variable",
+                span.line, span.character
+            ),
+            MacroError::TooDeeplyNested { name, span } => write!(
+                f,
+                "Macro '{}' expands more than {} levels deep at line {}, character {}",
+                name, MAX_DEPTH, span.line, span.character
+            ),
+        }
+    }
+}
+
+impl Error for MacroError {}
+
+// Replaces every call to a macro with its body, from the macro's declaration
+// on. Macros are hygienic: the names a body introduces are renamed to ones
+// that can't be written in source, so they can't clash with the names of the
+// arguments or of the code around the call. Names the body only uses are left
+// alone, and refer to whatever they name where the macro is used.
+pub fn expand_macros(mut program: Program) -> Result<Program, MacroError> {
+    let mut largest = LargestId(0);
+    for statement in &mut program.statements {
+        walk_statement(&mut largest, statement)?;
+    }
+
+    let mut expander = Expander { macros: HashMap::new(), next_id: largest.0, expansions: 0, depth: 0 };
+    for statement in &mut program.statements {
+        walk_statement(&mut expander, statement)?;
+    }
+    Ok(program)
+}
+
+// A change made in place to every node of a tree. `expression` and
+// `statement` see each node before anything inside it, and can skip its
+// insides by giving back false. `name` sees every variable name, with `binds`
+// set where the name is being introduced rather than used.
+trait Rewrite {
+    fn expression(&mut self, _: &mut Expression) -> Result<bool, MacroError> {
+        Ok(true)
+    }
+
+    fn statement(&mut self, _: &Statement) -> bool {
+        true
+    }
+
+    fn name(&mut self, _: &mut String, _binds: bool) {}
+    fn id(&mut self, _: &mut NodeId) {}
+}
+
+struct Expander {
+    macros: HashMap<String, Macro>,
+    // The largest id given to a node so far
+    next_id: u32,
+    // How many calls have been expanded, which keeps renamed names apart
+    expansions: u32,
+    depth: usize,
+}
+
+impl Rewrite for Expander {
+    fn expression(&mut self, expression: &mut Expression) -> Result<bool, MacroError> {
+        let Expression::Call(call) = expression else {
+            return Ok(true);
+        };
+        let Expression::Variable(callee) = &*call.callee else {
+            return Ok(true);
+        };
+        let Some(r#macro) = self.macros.get(&callee.name).cloned() else {
+            return Ok(true);
+        };
+
+        if let Some(spread) = call.arguments.iter().find(|argument| matches!(argument, Expression::Spread(_))) {
+            return Err(MacroError::SpreadArgument { span: spread.span() });
+        }
+        if call.arguments.len() != r#macro.parameters.len() {
+            let (expected, found) = (r#macro.parameters.len(), call.arguments.len());
+            return Err(MacroError::ArityMismatch { name: r#macro.name, expected, found, span: call.span });
+        }
+        if self.depth == MAX_DEPTH {
+            return Err(MacroError::TooDeeplyNested { name: r#macro.name, span: call.span });
+        }
+        for argument in &mut call.arguments {
+            walk_expression(self, argument)?;
+        }
+
+        let mut body = self.instantiate(&r#macro, &call.arguments)?;
+        self.depth += 1;
+        walk_expression(self, &mut body)?;
+        self.depth -= 1;
+        *expression = body;
+        Ok(false)
+    }
+
+    // The body of a macro is expanded each time it's used instead, so that
+    // one can use itself until `MAX_DEPTH` stops it
+    fn statement(&mut self, statement: &Statement) -> bool {
+        let Statement::Macro(r#macro) = statement else {
+            return true;
+        };
+        self.macros.insert(r#macro.name.clone(), r#macro.clone());
+        false
+    }
+}
+
+impl Expander {
+    // A copy of the body with its own names and node ids, and with the
+    // arguments in place of the parameters
+    fn instantiate(&mut self, r#macro: &Macro, arguments: &[Expression]) -> Result<Expression, MacroError> {
+        let mut body = r#macro.body.clone();
+        let mut binders = Binders(HashSet::new());
+        walk_expression(&mut binders, &mut body)?;
+
+        self.expansions += 1;
+        let renames = binders.0.into_iter()
+            .filter(|name| !r#macro.parameters.contains(name))
+            .map(|name| {
+                let renamed = format!("{}#{}", name, self.expansions);
+                (name, renamed)
+            })
+            .collect();
+        walk_expression(&mut Hygiene { renames, next_id: &mut self.next_id }, &mut body)?;
+
+        let arguments = r#macro.parameters.iter().map(String::as_str).zip(arguments).collect();
+        walk_expression(&mut Substitute { arguments, next_id: &mut self.next_id }, &mut body)?;
+        Ok(body)
+    }
+}
+
+struct LargestId(u32);
+
+impl Rewrite for LargestId {
+    fn id(&mut self, id: &mut NodeId) {
+        self.0 = self.0.max(id.0);
+    }
+}
+
+// Gives every node a new id after the largest one given so far
+struct Renumber<'a>(&'a mut u32);
+
+impl Rewrite for Renumber<'_> {
+    fn id(&mut self, id: &mut NodeId) {
+        *self.0 += 1;
+        *id = NodeId(*self.0);
+    }
+}
+
+// The names a tree introduces anywhere in it
+struct Binders(HashSet<String>);
+
+impl Rewrite for Binders {
+    fn name(&mut self, name: &mut String, binds: bool) {
+        if binds {
+            self.0.insert(name.clone());
+        }
+    }
+}
+
+struct Hygiene<'a> {
+    renames: HashMap<String, String>,
+    next_id: &'a mut u32,
+}
+
+impl Rewrite for Hygiene<'_> {
+    fn name(&mut self, name: &mut String, _: bool) {
+        if let Some(renamed) = self.renames.get(name) {
+            name.clone_from(renamed);
+        }
+    }
+
+    fn id(&mut self, id: &mut NodeId) {
+        Renumber(self.next_id).id(id);
+    }
+}
+
+// Each use of a parameter gets a copy of the argument with ids of its own.
+// An argument isn't looked inside, since it's code from around the call.
+struct Substitute<'a> {
+    arguments: HashMap<&'a str, &'a Expression>,
+    next_id: &'a mut u32,
+}
+
+impl Rewrite for Substitute<'_> {
+    fn expression(&mut self, expression: &mut Expression) -> Result<bool, MacroError> {
+        match expression {
+            Expression::Variable(variable) => {
+                let Some(&argument) = self.arguments.get(variable.name.as_str()) else {
+                    return Ok(true);
+                };
+                let mut argument = argument.clone();
+                walk_expression(&mut Renumber(self.next_id), &mut argument)?;
+                *expression = argument;
+                Ok(false)
+            }
+            Expression::Assign(assign) => {
+                match self.arguments.get(assign.name.as_str()) {
+                    Some(Expression::Variable(variable)) => assign.name.clone_from(&variable.name),
+                    Some(argument) => return Err(MacroError::InvalidAssignmentTarget { span: argument.span() }),
+                    None => {}
+                }
+                Ok(true)
+            }
+            // The evaluator needs the target of an index assignment to come
+            // down to a variable
+            Expression::IndexAssign(assign) => {
+                let mut target = &*assign.target;
+                while let Expression::Index(index) = target {
+                    target = &index.target;
+                }
+                let Expression::Variable(variable) = target else {
+                    return Ok(true);
+                };
+                match self.arguments.get(variable.name.as_str()) {
+                    Some(argument) if !assignable(argument) => {
+                        Err(MacroError::InvalidAssignmentTarget { span: argument.span() })
+                    }
+                    _ => Ok(true),
+                }
+            }
+            _ => Ok(true),
+        }
+    }
+}
+
+// A variable, or an index into one, as an index assignment needs
+fn assignable(expression: &Expression) -> bool {
+    match expression {
+        Expression::Variable(_) => true,
+        Expression::Index(index) => assignable(&index.target),
+        _ => false,
+    }
+}
+
+fn walk_expression<R: Rewrite>(rewrite: &mut R, expression: &mut Expression) -> Result<(), MacroError> {
+    if !rewrite.expression(expression)? {
+        return Ok(());
+    }
+    rewrite.id(id_mut(expression));
+    walk_children(rewrite, expression)
+}
+
+fn walk_children<R: Rewrite>(rewrite: &mut R, expression: &mut Expression) -> Result<(), MacroError> {
+    match expression {
+        Expression::Integer(_)
+        | Expression::Float(_)
+        | Expression::Char(_)
+        | Expression::Str(_)
+        | Expression::Bool(_)
+        | Expression::Nil(_) => Ok(()),
+        // Down the left side of a chain of operators in a loop, in the same
+        // order as recursing, so that a long chain doesn't overflow
+        Expression::Binary(binary) => {
+            let mut rights = vec![&mut binary.right];
+            let mut left = &mut binary.left;
+            loop {
+                if !matches!(**left, Expression::Binary(_)) {
+                    walk_expression(rewrite, left)?;
+                    break;
+                }
+                if !rewrite.expression(left)? {
+                    break;
+                }
+                rewrite.id(id_mut(left));
+                match &mut **left {
+                    Expression::Binary(binary) => {
+                        rights.push(&mut binary.right);
+                        left = &mut binary.left;
+                    }
+                    expression => {
+                        walk_children(rewrite, expression)?;
+                        break;
+                    }
+                }
+            }
+            for right in rights.into_iter().rev() {
+                walk_expression(rewrite, right)?;
+            }
+            Ok(())
+        }
+        Expression::Unary(unary) => walk_expression(rewrite, &mut unary.right),
+        Expression::Interpolation(interpolation) => walk_all(rewrite, &mut interpolation.parts),
+        Expression::Variable(variable) => {
+            rewrite.name(&mut variable.name, false);
+            Ok(())
+        }
+        Expression::Assign(assign) => {
+            rewrite.name(&mut assign.name, false);
+            walk_expression(rewrite, &mut assign.value)
+        }
+        Expression::Block(block) => {
+            for statement in &mut block.statements {
+                walk_statement(rewrite, statement)?;
+            }
+            block.value.as_deref_mut().map_or(Ok(()), |value| walk_expression(rewrite, value))
+        }
+        Expression::If(r#if) => {
+            walk_expression(rewrite, &mut r#if.condition)?;
+            walk_expression(rewrite, &mut r#if.then_branch)?;
+            r#if.else_branch.as_deref_mut().map_or(Ok(()), |branch| walk_expression(rewrite, branch))
+        }
+        Expression::Conditional(conditional) => {
+            walk_expression(rewrite, &mut conditional.condition)?;
+            walk_expression(rewrite, &mut conditional.then_branch)?;
+            walk_expression(rewrite, &mut conditional.else_branch)
+        }
+        Expression::Coalesce(coalesce) => {
+            walk_expression(rewrite, &mut coalesce.value)?;
+            walk_expression(rewrite, &mut coalesce.fallback)
+        }
+        Expression::Match(r#match) => {
+            walk_expression(rewrite, &mut r#match.scrutinee)?;
+            for arm in &mut r#match.arms {
+                walk_pattern(rewrite, &mut arm.pattern)?;
+                if let Some(guard) = &mut arm.guard {
+                    walk_expression(rewrite, guard)?;
+                }
+                walk_expression(rewrite, &mut arm.body)?;
+            }
+            Ok(())
+        }
+        Expression::Try(r#try) => {
+            walk_expression(rewrite, &mut r#try.body)?;
+            rewrite.name(&mut r#try.name, true);
+            walk_expression(rewrite, &mut r#try.handler)
+        }
+        Expression::Range(range) => {
+            walk_expression(rewrite, &mut range.start)?;
+            walk_expression(rewrite, &mut range.end)
+        }
+        Expression::Call(call) => {
+            walk_expression(rewrite, &mut call.callee)?;
+            walk_all(rewrite, &mut call.arguments)
+        }
+        Expression::Lambda(lambda) => {
+            lambda.parameters.iter_mut().for_each(|parameter| rewrite.name(parameter, true));
+            walk_expression(rewrite, &mut lambda.body)
+        }
+        Expression::Array(array) => walk_all(rewrite, &mut array.elements),
+        Expression::Map(map) => {
+            for (key, value) in &mut map.entries {
+                walk_expression(rewrite, key)?;
+                walk_expression(rewrite, value)?;
+            }
+            Ok(())
+        }
+        Expression::Tuple(tuple) => walk_all(rewrite, &mut tuple.elements),
+        Expression::TupleIndex(index) => walk_expression(rewrite, &mut index.tuple),
+        Expression::Index(index) => {
+            walk_expression(rewrite, &mut index.target)?;
+            walk_expression(rewrite, &mut index.index)
+        }
+        Expression::IndexAssign(assign) => {
+            walk_expression(rewrite, &mut assign.target)?;
+            walk_expression(rewrite, &mut assign.index)?;
+            walk_expression(rewrite, &mut assign.value)
+        }
+        Expression::StructLiteral(literal) => {
+            literal.fields.iter_mut().try_for_each(|(_, value)| walk_expression(rewrite, value))
+        }
+        Expression::Field(field) => walk_expression(rewrite, &mut field.target),
+        Expression::Spread(spread) => walk_expression(rewrite, &mut spread.value),
+    }
+}
+
+fn walk_all<R: Rewrite>(rewrite: &mut R, expressions: &mut [Expression]) -> Result<(), MacroError> {
+    expressions.iter_mut().try_for_each(|expression| walk_expression(rewrite, expression))
+}
+
+fn walk_statement<R: Rewrite>(rewrite: &mut R, statement: &mut Statement) -> Result<(), MacroError> {
+    if !rewrite.statement(statement) {
+        return Ok(());
+    }
+
+    match statement {
+        Statement::Expression(statement) => {
+            rewrite.id(&mut statement.id);
+            walk_expression(rewrite, &mut statement.expression)
+        }
+        Statement::Let(declaration) => {
+            rewrite.id(&mut declaration.id);
+            rewrite.name(&mut declaration.name, true);
+            walk_expression(rewrite, &mut declaration.initializer)
+        }
+        Statement::Destructure(declaration) => {
+            rewrite.id(&mut declaration.id);
+            walk_pattern(rewrite, &mut declaration.pattern)?;
+            walk_expression(rewrite, &mut declaration.initializer)
+        }
+        Statement::While(r#while) => {
+            rewrite.id(&mut r#while.id);
+            walk_expression(rewrite, &mut r#while.condition)?;
+            walk_expression(rewrite, &mut r#while.body)
+        }
+        Statement::For(r#for) => {
+            rewrite.id(&mut r#for.id);
+            rewrite.name(&mut r#for.variable, true);
+            walk_expression(rewrite, &mut r#for.iterable)?;
+            walk_expression(rewrite, &mut r#for.body)
+        }
+        Statement::Break(r#break) => {
+            rewrite.id(&mut r#break.id);
+            Ok(())
+        }
+        Statement::Continue(r#continue) => {
+            rewrite.id(&mut r#continue.id);
+            Ok(())
+        }
+        Statement::Function(function) => {
+            rewrite.name(&mut function.name, true);
+            walk_function(rewrite, function)
+        }
+        Statement::Return(r#return) => {
+            rewrite.id(&mut r#return.id);
+            r#return.value.as_mut().map_or(Ok(()), |value| walk_expression(rewrite, value))
+        }
+        Statement::Struct(r#struct) => {
+            rewrite.id(&mut r#struct.id);
+            Ok(())
+        }
+        Statement::Enum(r#enum) => {
+            rewrite.id(&mut r#enum.id);
+            Ok(())
+        }
+        // Method names aren't variables, so only what's inside the methods
+        // can be renamed
+        Statement::Impl(r#impl) => {
+            rewrite.id(&mut r#impl.id);
+            r#impl.methods.iter_mut().try_for_each(|method| walk_function(rewrite, method))
+        }
+        Statement::Trait(r#trait) => {
+            rewrite.id(&mut r#trait.id);
+            Ok(())
+        }
+        Statement::Import(import) => {
+            rewrite.id(&mut import.id);
+            rewrite.name(&mut import.name, true);
+            Ok(())
+        }
+        Statement::Macro(r#macro) => {
+            rewrite.id(&mut r#macro.id);
+            walk_expression(rewrite, &mut r#macro.body)
+        }
+    }
+}
+
+fn walk_function<R: Rewrite>(rewrite: &mut R, function: &mut Function) -> Result<(), MacroError> {
+    rewrite.id(&mut function.id);
+    function.parameters.iter_mut().for_each(|parameter| rewrite.name(parameter, true));
+    walk_expression(rewrite, &mut function.body)
+}
+
+fn walk_pattern<R: Rewrite>(rewrite: &mut R, pattern: &mut Pattern) -> Result<(), MacroError> {
+    match pattern {
+        Pattern::Wildcard(_) => Ok(()),
+        Pattern::Binding(name, _) => {
+            rewrite.name(name, true);
+            Ok(())
+        }
+        Pattern::Literal(literal) => walk_expression(rewrite, literal),
+        Pattern::Variant(variant) => variant.fields.iter_mut().try_for_each(|field| walk_pattern(rewrite, field)),
+        Pattern::Tuple(tuple) => tuple.elements.iter_mut().try_for_each(|element| walk_pattern(rewrite, element)),
+        Pattern::Array(array) => {
+            array.elements.iter_mut().try_for_each(|element| walk_pattern(rewrite, element))?;
+            array.rest.as_deref_mut().map_or(Ok(()), |rest| walk_pattern(rewrite, rest))
+        }
+    }
+}
+
+fn id_mut(expression: &mut Expression) -> &mut NodeId {
+    match expression {
+        Expression::Binary(binary) => &mut binary.id,
+        Expression::Unary(unary) => &mut unary.id,
+        Expression::Integer(integer) => &mut integer.id,
+        Expression::Float(float) => &mut float.id,
+        Expression::Char(char) => &mut char.id,
+        Expression::Str(str) => &mut str.id,
+        Expression::Bool(bool) => &mut bool.id,
+        Expression::Nil(nil) => &mut nil.id,
+        Expression::Interpolation(interpolation) => &mut interpolation.id,
+        Expression::Variable(variable) => &mut variable.id,
+        Expression::Assign(assign) => &mut assign.id,
+        Expression::Block(block) => &mut block.id,
+        Expression::If(r#if) => &mut r#if.id,
+        Expression::Conditional(conditional) => &mut conditional.id,
+        Expression::Coalesce(coalesce) => &mut coalesce.id,
+        Expression::Match(r#match) => &mut r#match.id,
+        Expression::Try(r#try) => &mut r#try.id,
+        Expression::Range(range) => &mut range.id,
+        Expression::Call(call) => &mut call.id,
+        Expression::Lambda(lambda) => &mut lambda.id,
+        Expression::Array(array) => &mut array.id,
+        Expression::Map(map) => &mut map.id,
+        Expression::Tuple(tuple) => &mut tuple.id,
+        Expression::TupleIndex(index) => &mut index.id,
+        Expression::Index(index) => &mut index.id,
+        Expression::IndexAssign(assign) => &mut assign.id,
+        Expression::StructLiteral(literal) => &mut literal.id,
+        Expression::Field(field) => &mut field.id,
+        Expression::Spread(spread) => &mut spread.id,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::evaluate::execute;
+    use crate::grammar::lexer::get_tokens;
+    use crate::grammar::parser::get_program;
+    use crate::grammar::unparse::unparse_program;
+
+    fn expand_source(input: &str) -> Result<Program, MacroError> {
+        expand_macros(get_program(get_tokens(input).unwrap()).unwrap())
+    }
+
+    #[test]
+    fn test_expands_macros() -> Result<(), String> {
+        let program = expand_source("macro twice(x) { x + x }\ntwice(1 * 2)\n[twice(twice(y))]").unwrap();
+        let source = unparse_program(&program);
+        assert_eq!(source.lines().skip(1).collect::<Vec<_>>(), ["{ 1 * 2 + 1 * 2 }", "[{ { y + y } + { y + y } }]"]);
+
+        // A call before the macro is declared is left alone
+        assert_eq!(unparse_program(&expand_source("m(1)\nmacro m(x) { x }\nm(2)").unwrap()), "m(1)\nmacro m(x) { x }\n{ 2 }\n");
+
+        let program = "macro swap(a, b) { let tmp = a; a = b; b = tmp }\nlet x = 1; let y = 2\nswap(x, y)\n(x, y)";
+        let result = execute(&expand_source(program).unwrap()).unwrap();
+        assert_eq!(result.unwrap().to_string(), "(2, 1)");
+
+        let program = expand_source(&format!("macro m(x) {{ x }}\n{} + m(2)", vec!["m(1)"; 50_000].join(" + "))).unwrap();
+        assert!(unparse_program(&program).ends_with("{ 1 } + { 1 } + { 2 }\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hygiene() -> Result<(), String> {
+        // The `tmp` the macro declares is a different variable from the one
+        // passed to it
+        let program = "macro swap(a, b) { let tmp = a; a = b; b = tmp }\nlet tmp = 1; let other = 2\nswap(tmp, other)\n(tmp, other)";
+        let result = execute(&expand_source(program).unwrap()).unwrap();
+        assert_eq!(result.unwrap().to_string(), "(2, 1)");
+
+        let source = unparse_program(&expand_source("macro m(x) { let y = x; y }\nlet y = 5\nm(y) + m(y)").unwrap());
+        assert_eq!(source.lines().last().unwrap(), "{ let y#1 = y; y#1 } + { let y#2 = y; y#2 }");
+        // Names the body only uses come from wherever the macro is used
+        let program = "macro scaled(x) { x * factor }\nfn f(factor) { scaled(2) }\nf(5)";
+        assert_eq!(execute(&expand_source(program).unwrap()).unwrap().unwrap().to_string(), "10");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_node_ids_stay_unique() -> Result<(), String> {
+        struct Ids(Vec<NodeId>);
+        impl Rewrite for Ids {
+            fn id(&mut self, id: &mut NodeId) {
+                self.0.push(*id);
+            }
+        }
+
+        let mut program = expand_source("macro m(x) { [x, x] }\nm(1 + 2)\nm(m(3))").unwrap();
+        let mut ids = Ids(Vec::new());
+        for statement in &mut program.statements {
+            walk_statement(&mut ids, statement).unwrap();
+        }
+        let count = ids.0.len();
+        ids.0.sort();
+        ids.0.dedup();
+        assert_eq!(ids.0.len(), count);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_macro_errors() -> Result<(), String> {
+        match expand_source("macro m(a, b) { a }\n\nm(1)") {
+            Err(MacroError::ArityMismatch { name, expected: 2, found: 1, span }) => {
+                assert_eq!(name, "m");
+                assert_eq!((span.line, span.character), (3, 1));
+            }
+            result => panic!("Expected an arity mismatch, got {:?}", result),
+        }
+        assert!(matches!(expand_source("macro m(a) { a }\nm(...xs)"), Err(MacroError::SpreadArgument { .. })));
+        let result = expand_source("macro set(a) { a = 1 }\nset(x + 1)");
+        assert!(matches!(result, Err(MacroError::InvalidAssignmentTarget { span }) if span.character == 5));
+        assert!(matches!(expand_source("macro set(a) { a[0] = 1 }\nset(f())"), Err(MacroError::InvalidAssignmentTarget { .. })));
+        assert!(expand_source("macro set(a) { a[0] = 1 }\nset(xs[1])").is_ok());
+        assert!(matches!(expand_source("macro m(a) { m(a) }\nm(1)"), Err(MacroError::TooDeeplyNested { .. })));
+        // A macro that uses itself is fine as long as it's never expanded
+        assert!(expand_source("macro m(a) { m(a) }").is_ok());
+
+        Ok(())
+    }
+}
@@ -1,3 +1,16 @@
-pub mod lexer;
-pub mod parser;
-pub mod evaluate;
+pub mod lexer;
+pub mod parser;
+pub mod visit;
+pub mod unparse;
+pub mod dot;
+pub mod doc;
+pub mod evaluate;
+pub mod runtime;
+pub mod bigint;
+pub mod rational;
+pub mod heap;
+pub mod math;
+pub mod format;
+pub mod random;
+pub mod compile;
+pub mod vm;
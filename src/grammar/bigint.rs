@@ -0,0 +1,464 @@
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use std::ops::{Add, BitAnd, BitOr, BitXor, Mul, Neg, Not, Sub};
+
+// An integer of any size, for the ints that don't fit in 64 bits. The
+// magnitude is kept in base 2^32, least significant digit first and with no
+// zeros at the end, so every number is stored only one way and zero has no
+// digits at all.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    negative: bool,
+    magnitude: Vec<u32>,
+}
+
+impl BigInt {
+    fn new(negative: bool, mut magnitude: Vec<u32>) -> BigInt {
+        trim(&mut magnitude);
+        BigInt { negative: negative && !magnitude.is_empty(), magnitude }
+    }
+
+    // Decimal digits with an optional sign in front, like an int literal
+    pub fn parse(text: &str) -> Option<BigInt> {
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, text.strip_prefix('+').unwrap_or(text)),
+        };
+        if digits.is_empty() {
+            return None;
+        }
+        let mut magnitude = Vec::new();
+        for digit in digits.chars() {
+            magnitude = add(&mul(&magnitude, &[10]), &[digit.to_digit(10)?]);
+        }
+        Some(BigInt::new(negative, magnitude))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.magnitude.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.magnitude.len() > 2 {
+            return None;
+        }
+        let magnitude = self.magnitude.iter().rev().fold(0, |value, digit| value << 32 | u64::from(*digit));
+        if self.negative {
+            0_i64.checked_sub_unsigned(magnitude)
+        } else {
+            i64::try_from(magnitude).ok()
+        }
+    }
+
+    // The nearest float, or an infinity if it's too large for one
+    pub fn to_f64(&self) -> f64 {
+        self.to_string().parse().unwrap()
+    }
+
+    // How many bits the magnitude takes up
+    pub fn bits(&self) -> u64 {
+        match self.magnitude.last() {
+            Some(last) => (self.magnitude.len() as u64 - 1) * 32 + u64::from(32 - last.leading_zeros()),
+            None => 0,
+        }
+    }
+
+    // Division that rounds toward zero, so the remainder has the sign of the
+    // dividend, the same as for i64. Nothing when the divisor is zero.
+    pub fn div_rem(&self, divisor: &BigInt) -> Option<(BigInt, BigInt)> {
+        if divisor.is_zero() {
+            return None;
+        }
+        let (quotient, remainder) = div_rem(&self.magnitude, &divisor.magnitude);
+        Some((BigInt::new(self.negative != divisor.negative, quotient), BigInt::new(self.negative, remainder)))
+    }
+
+    // Exponentiation by squaring
+    pub fn pow(&self, mut exponent: u64) -> BigInt {
+        let (mut result, mut base) = (BigInt::from(1), self.clone());
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = &result * &base;
+            }
+            exponent >>= 1;
+            if exponent > 0 {
+                base = &base * &base;
+            }
+        }
+        result
+    }
+
+    // The digits of the number in two's complement, `length` of them, which
+    // has to be enough for the sign to fit too
+    fn twos_complement(&self, length: usize) -> Vec<u32> {
+        let mut digits = self.magnitude.clone();
+        digits.resize(length, 0);
+        if self.negative {
+            negate(&mut digits);
+        }
+        digits
+    }
+
+    fn from_twos_complement(mut digits: Vec<u32>) -> BigInt {
+        let negative = digits.last().is_some_and(|last| last >> 31 == 1);
+        if negative {
+            negate(&mut digits);
+        }
+        BigInt::new(negative, digits)
+    }
+
+    fn bitwise(&self, other: &BigInt, operation: fn(u32, u32) -> u32) -> BigInt {
+        let length = self.magnitude.len().max(other.magnitude.len()) + 1;
+        let digits = self.twos_complement(length).into_iter()
+            .zip(other.twos_complement(length))
+            .map(|(left, right)| operation(left, right))
+            .collect();
+        BigInt::from_twos_complement(digits)
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(value: i64) -> BigInt {
+        let magnitude = value.unsigned_abs();
+        BigInt::new(value < 0, vec![magnitude as u32, (magnitude >> 32) as u32])
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => compare(&self.magnitude, &other.magnitude),
+            (true, true) => compare(&other.magnitude, &self.magnitude),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::new(self.negative, add(&self.magnitude, &other.magnitude));
+        }
+        match compare(&self.magnitude, &other.magnitude) {
+            Ordering::Less => BigInt::new(other.negative, sub(&other.magnitude, &self.magnitude)),
+            _ => BigInt::new(self.negative, sub(&self.magnitude, &other.magnitude)),
+        }
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, other: &BigInt) -> BigInt {
+        self + &-other
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        BigInt::new(self.negative != other.negative, mul(&self.magnitude, &other.magnitude))
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::new(!self.negative, self.magnitude.clone())
+    }
+}
+
+// The same as for i64, `~x` is `-x - 1`
+impl Not for &BigInt {
+    type Output = BigInt;
+
+    fn not(self) -> BigInt {
+        &-self - &BigInt::from(1)
+    }
+}
+
+impl BitAnd for &BigInt {
+    type Output = BigInt;
+
+    fn bitand(self, other: &BigInt) -> BigInt {
+        self.bitwise(other, |left, right| left & right)
+    }
+}
+
+impl BitOr for &BigInt {
+    type Output = BigInt;
+
+    fn bitor(self, other: &BigInt) -> BigInt {
+        self.bitwise(other, |left, right| left | right)
+    }
+}
+
+impl BitXor for &BigInt {
+    type Output = BigInt;
+
+    fn bitxor(self, other: &BigInt) -> BigInt {
+        self.bitwise(other, |left, right| left ^ right)
+    }
+}
+
+// Nine decimal digits at a time, which is the most a power of ten below 2^32
+// has
+impl Display for BigInt {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_zero() {
+            return f.write_str("0");
+        }
+        let mut chunks = Vec::new();
+        let mut magnitude = self.magnitude.clone();
+        while !magnitude.is_empty() {
+            let (quotient, remainder) = div_small(&magnitude, 1_000_000_000);
+            chunks.push(remainder);
+            magnitude = quotient;
+        }
+        if self.negative {
+            f.write_str("-")?;
+        }
+        write!(f, "{}", chunks.pop().unwrap())?;
+        for chunk in chunks.iter().rev() {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+fn trim(digits: &mut Vec<u32>) {
+    while digits.last() == Some(&0) {
+        digits.pop();
+    }
+}
+
+fn compare(left: &[u32], right: &[u32]) -> Ordering {
+    left.len().cmp(&right.len()).then_with(|| left.iter().rev().cmp(right.iter().rev()))
+}
+
+fn add(left: &[u32], right: &[u32]) -> Vec<u32> {
+    let mut digits = Vec::with_capacity(left.len().max(right.len()) + 1);
+    let mut carry = 0;
+    for position in 0..left.len().max(right.len()) {
+        let left = u64::from(left.get(position).copied().unwrap_or(0));
+        let right = u64::from(right.get(position).copied().unwrap_or(0));
+        let sum = left + right + carry;
+        digits.push(sum as u32);
+        carry = sum >> 32;
+    }
+    digits.push(carry as u32);
+    trim(&mut digits);
+    digits
+}
+
+// `left` can't be any smaller than `right`
+fn sub(left: &[u32], right: &[u32]) -> Vec<u32> {
+    let mut digits = Vec::with_capacity(left.len());
+    let mut borrow = false;
+    for (position, left) in left.iter().enumerate() {
+        let right = right.get(position).copied().unwrap_or(0);
+        let (difference, under) = left.overflowing_sub(right);
+        let (difference, under_again) = difference.overflowing_sub(u32::from(borrow));
+        digits.push(difference);
+        borrow = under || under_again;
+    }
+    trim(&mut digits);
+    digits
+}
+
+fn mul(left: &[u32], right: &[u32]) -> Vec<u32> {
+    let mut digits = vec![0; left.len() + right.len()];
+    for (i, left) in left.iter().enumerate() {
+        let mut carry = 0;
+        for (j, right) in right.iter().enumerate() {
+            let product = u64::from(digits[i + j]) + u64::from(*left) * u64::from(*right) + carry;
+            digits[i + j] = product as u32;
+            carry = product >> 32;
+        }
+        digits[i + right.len()] = carry as u32;
+    }
+    trim(&mut digits);
+    digits
+}
+
+fn div_small(dividend: &[u32], divisor: u32) -> (Vec<u32>, u32) {
+    let mut quotient = vec![0; dividend.len()];
+    let mut remainder = 0_u64;
+    for (position, digit) in dividend.iter().enumerate().rev() {
+        let current = remainder << 32 | u64::from(*digit);
+        quotient[position] = (current / u64::from(divisor)) as u32;
+        remainder = current % u64::from(divisor);
+    }
+    trim(&mut quotient);
+    (quotient, remainder as u32)
+}
+
+// Long division one bit at a time, unless the divisor is a single digit.
+// `divisor` can't be zero.
+fn div_rem(dividend: &[u32], divisor: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if let [divisor] = divisor {
+        let (quotient, remainder) = div_small(dividend, *divisor);
+        let mut remainder = vec![remainder];
+        trim(&mut remainder);
+        return (quotient, remainder);
+    }
+    let mut quotient = vec![0; dividend.len()];
+    let mut remainder = Vec::new();
+    for bit in (0..dividend.len() * 32).rev() {
+        let mut carry = dividend[bit / 32] >> (bit % 32) & 1;
+        for digit in remainder.iter_mut() {
+            let next = *digit >> 31;
+            *digit = *digit << 1 | carry;
+            carry = next;
+        }
+        if carry != 0 {
+            remainder.push(carry);
+        }
+        if compare(&remainder, divisor) != Ordering::Less {
+            remainder = sub(&remainder, divisor);
+            quotient[bit / 32] |= 1 << (bit % 32);
+        }
+    }
+    trim(&mut quotient);
+    (quotient, remainder)
+}
+
+// Two's complement negation: every bit flipped, then one added
+fn negate(digits: &mut [u32]) {
+    let mut carry = true;
+    for digit in digits.iter_mut() {
+        let (sum, over) = (!*digit).overflowing_add(u32::from(carry));
+        *digit = sum;
+        carry = over;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(text: &str) -> BigInt {
+        BigInt::parse(text).unwrap()
+    }
+
+    #[test]
+    fn test_parse_and_display() -> Result<(), String> {
+        for text in ["0", "7", "-7", "4294967296", "18446744073709551616", "-123456789012345678901234567890"] {
+            assert_eq!(big(text).to_string(), text);
+        }
+        assert_eq!(big("+0042").to_string(), "42");
+        assert_eq!(big("-0"), BigInt::from(0));
+        assert_eq!(big("-0").to_string(), "0");
+        assert_eq!(BigInt::parse(""), None);
+        assert_eq!(BigInt::parse("-"), None);
+        assert_eq!(BigInt::parse("12a"), None);
+        assert_eq!(BigInt::from(i64::MIN).to_string(), i64::MIN.to_string());
+        assert_eq!(BigInt::from(i64::MAX).to_string(), i64::MAX.to_string());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_conversion() -> Result<(), String> {
+        assert_eq!(BigInt::from(i64::MIN).to_i64(), Some(i64::MIN));
+        assert_eq!(BigInt::from(i64::MAX).to_i64(), Some(i64::MAX));
+        assert_eq!(BigInt::from(-1).to_i64(), Some(-1));
+        assert_eq!(big("9223372036854775808").to_i64(), None);
+        assert_eq!(big("-9223372036854775809").to_i64(), None);
+        assert_eq!(BigInt::from(0).bits(), 0);
+        assert_eq!(BigInt::from(-255).bits(), 8);
+        assert_eq!(big("18446744073709551616").bits(), 65);
+        assert_eq!(big("-18446744073709551616").to_f64(), -18446744073709551616.0);
+        assert_eq!(BigInt::from(2).pow(1024).to_f64(), f64::INFINITY);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_arithmetic() -> Result<(), String> {
+        let max = BigInt::from(i64::MAX);
+        assert_eq!((&max + &BigInt::from(1)).to_string(), "9223372036854775808");
+        assert_eq!((&BigInt::from(i64::MIN) - &BigInt::from(1)).to_string(), "-9223372036854775809");
+        assert_eq!((&BigInt::from(5) - &BigInt::from(8)), BigInt::from(-3));
+        assert_eq!((&BigInt::from(-5) + &BigInt::from(5)), BigInt::from(0));
+        assert_eq!((&max * &max).to_string(), "85070591730234615847396907784232501249");
+        assert_eq!((&max * &BigInt::from(-2)).to_string(), "-18446744073709551614");
+        assert_eq!(BigInt::from(2).pow(100).to_string(), "1267650600228229401496703205376");
+        assert_eq!(BigInt::from(-3).pow(41).to_string(), "-36472996377170786403");
+        assert_eq!(BigInt::from(7).pow(0), BigInt::from(1));
+
+        let factorial = (1..=30).fold(BigInt::from(1), |product, n| &product * &BigInt::from(n));
+        assert_eq!(factorial.to_string(), "265252859812191058636308480000000");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_division() -> Result<(), String> {
+        let divide = |left: &str, right: &str| {
+            let (quotient, remainder) = big(left).div_rem(&big(right)).unwrap();
+            (quotient.to_string(), remainder.to_string())
+        };
+        assert_eq!(divide("100", "7"), ("14".to_string(), "2".to_string()));
+        assert_eq!(divide("-100", "7"), ("-14".to_string(), "-2".to_string()));
+        assert_eq!(divide("100", "-7"), ("-14".to_string(), "2".to_string()));
+        assert_eq!(divide("265252859812191058636308480000000", "265252859812191058636308480000000"), ("1".to_string(), "0".to_string()));
+        assert_eq!(
+            divide("85070591730234615847396907784232501250", "9223372036854775807"),
+            ("9223372036854775807".to_string(), "1".to_string())
+        );
+        assert_eq!(divide("5", "18446744073709551616"), ("0".to_string(), "5".to_string()));
+        assert_eq!(big("1").div_rem(&BigInt::from(0)), None);
+
+        // Every quotient and remainder agrees with i64's
+        for (left, right) in [(i64::MAX, 3), (i64::MIN + 1, -10), (-17, 5), (1 << 40, (1 << 33) + 1)] {
+            let (quotient, remainder) = BigInt::from(left).div_rem(&BigInt::from(right)).unwrap();
+            assert_eq!((quotient.to_i64(), remainder.to_i64()), (Some(left / right), Some(left % right)));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ordering() -> Result<(), String> {
+        let mut values = ["5", "-18446744073709551616", "0", "18446744073709551616", "-5", "4294967296"].map(big);
+        values.sort();
+        let sorted = values.iter().map(BigInt::to_string).collect::<Vec<_>>();
+        assert_eq!(sorted, ["-18446744073709551616", "-5", "0", "5", "4294967296", "18446744073709551616"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bitwise() -> Result<(), String> {
+        // Agrees with i64 wherever i64 can say
+        for (left, right) in [(12, 10), (-12, 10), (12, -10), (-12, -10), (i64::MIN, -1), (0, -1)] {
+            let (big_left, big_right) = (BigInt::from(left), BigInt::from(right));
+            assert_eq!((&big_left & &big_right).to_i64(), Some(left & right));
+            assert_eq!((&big_left | &big_right).to_i64(), Some(left | right));
+            assert_eq!((&big_left ^ &big_right).to_i64(), Some(left ^ right));
+            assert_eq!((!&big_left).to_i64(), Some(!left));
+        }
+        let large = big("36893488147419103232");
+        assert_eq!((&large | &BigInt::from(1)).to_string(), "36893488147419103233");
+        assert_eq!((&large & &BigInt::from(-1)), large);
+        assert_eq!((!&large).to_string(), "-36893488147419103233");
+
+        Ok(())
+    }
+}
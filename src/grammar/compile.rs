@@ -0,0 +1,906 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use crate::grammar::evaluate::Callable;
+use crate::grammar::lexer::Span;
+use crate::grammar::parser::{
+    BinaryOperator, Block, Call, Expression, For, Function as Declaration, IndexAssign, Program, Statement, UnaryOperator,
+    While,
+};
+use crate::grammar::runtime::Value;
+
+// The instructions the VM runs. Each is a byte, followed by its operands,
+// which are two bytes each, lowest first, unless it says otherwise. Values
+// go on the stack, where the locals of the function running are too, one
+// slot each from the first parameter up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Opcode {
+    // Pushes the constant at the operand
+    Constant,
+    Nil,
+    True,
+    False,
+    Pop,
+    // Drops the operand's number of values from under the one on top, which
+    // is how a block gets rid of its locals and keeps its value
+    PopBelow,
+    GetLocal,
+    SetLocal,
+    // The operand is the constant that is the global's name
+    GetGlobal,
+    // The same, but for the function of a call, which gets its own error for
+    // a name that isn't defined
+    GetFunction,
+    SetGlobal,
+    // Pops the value for the global
+    DefineGlobal,
+    // The operand is the operation in the chunk's table of them
+    Binary,
+    Negate,
+    Invert,
+    // Jumps forward by the operand, from the end of the instruction
+    Jump,
+    // Pops a condition and jumps forward by the operand if it's false
+    JumpIfFalse,
+    // Jumps forward by the operand if the value on top isn't nil, and pops it
+    // otherwise
+    JumpIfNotNil,
+    // Jumps back by the operand, from the end of the instruction
+    Loop,
+    // The operand is one byte, the number of arguments, which are on top of
+    // the function
+    Call,
+    // The same, but the call replaces the one running
+    TailCall,
+    Return,
+    // The operand is the number of elements on the stack
+    Array,
+    Tuple,
+    // The operand is one byte, whether the end is part of the range
+    Range,
+    // The operand is the number of parts on the stack
+    Interpolate,
+    // The operand is the position
+    TupleIndex,
+    Index,
+    // Pops the value, the index and the target, and pushes the value back.
+    // The operand is the operation that combines the element with the value,
+    // or `NO_OPERATION` for a plain assignment.
+    SetIndex,
+    // Pushes the first int of the range on top, or nil if it's empty, for
+    // `Iterate` to count from. An array, tuple or string is replaced by a
+    // tuple of what's in it, with the position of the first on top.
+    Count,
+    // The first operand is the slot of the range or tuple, with the next int
+    // in the slot after it. Pushes that int, or the element at it, and moves
+    // on to the one after, or jumps forward by the second operand once there
+    // are none left.
+    Iterate,
+}
+
+pub const NO_OPERATION: usize = u16::MAX as usize;
+
+// In the order of their bytes
+const OPCODES: [Opcode; 31] = [
+    Opcode::Constant,
+    Opcode::Nil,
+    Opcode::True,
+    Opcode::False,
+    Opcode::Pop,
+    Opcode::PopBelow,
+    Opcode::GetLocal,
+    Opcode::SetLocal,
+    Opcode::GetGlobal,
+    Opcode::GetFunction,
+    Opcode::SetGlobal,
+    Opcode::DefineGlobal,
+    Opcode::Binary,
+    Opcode::Negate,
+    Opcode::Invert,
+    Opcode::Jump,
+    Opcode::JumpIfFalse,
+    Opcode::JumpIfNotNil,
+    Opcode::Loop,
+    Opcode::Call,
+    Opcode::TailCall,
+    Opcode::Return,
+    Opcode::Array,
+    Opcode::Tuple,
+    Opcode::Range,
+    Opcode::Interpolate,
+    Opcode::TupleIndex,
+    Opcode::Index,
+    Opcode::SetIndex,
+    Opcode::Count,
+    Opcode::Iterate,
+];
+
+impl Opcode {
+    pub fn decode(byte: u8) -> Option<Opcode> {
+        OPCODES.get(usize::from(byte)).copied()
+    }
+
+    // How many bytes its operands take up
+    fn operands(self) -> usize {
+        match self {
+            Opcode::Call | Opcode::TailCall | Opcode::Range => 1,
+            Opcode::Iterate => 4,
+            Opcode::Constant | Opcode::PopBelow | Opcode::GetLocal | Opcode::SetLocal | Opcode::GetGlobal => 2,
+            Opcode::GetFunction | Opcode::SetGlobal | Opcode::DefineGlobal | Opcode::Binary | Opcode::Jump => 2,
+            Opcode::JumpIfFalse | Opcode::JumpIfNotNil | Opcode::Loop | Opcode::Array | Opcode::Tuple => 2,
+            Opcode::Interpolate | Opcode::TupleIndex | Opcode::SetIndex => 2,
+            _ => 0,
+        }
+    }
+}
+
+// A binary operator in the code, with the spans `apply` puts its errors down
+// to: the whole operation, just the operator and the right operand
+#[derive(Debug, Clone)]
+pub struct Operation {
+    pub operator: BinaryOperator,
+    pub span: Span,
+    pub operator_span: Span,
+    pub right: Span,
+}
+
+// The code of one function, with the values it pushes as constants
+#[derive(Debug, Clone, Default)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<Value>,
+    pub operations: Vec<Operation>,
+    // The span of the node each instruction came from, by the offset it
+    // starts at. A call has the span of its function one byte on, and a
+    // tuple index that of its tuple.
+    spans: Vec<(usize, Span)>,
+}
+
+impl Chunk {
+    // The span of the last instruction at or before `offset`
+    pub fn span(&self, offset: usize) -> Span {
+        let position = self.spans.partition_point(|&(start, _)| start <= offset);
+        position.checked_sub(1).map_or_else(Span::default, |position| self.spans[position].1)
+    }
+
+    pub fn read(&self, offset: usize) -> usize {
+        usize::from(u16::from_le_bytes([self.code[offset], self.code[offset + 1]]))
+    }
+}
+
+// One instruction per line: its offset, the line of the source it came from,
+// or `|` when that's the same as the one before, then the opcode and its
+// operands. Constants, names and operators are shown after the operands
+// they're looked up by, and jumps with the offset they go to. The code of
+// every function among the constants comes after, under its name.
+pub fn disassemble(chunk: &Chunk) -> String {
+    let mut output = String::new();
+    let mut offset = 0;
+    let mut line = None;
+    while offset < chunk.code.len() {
+        let span = chunk.span(offset);
+        let source = match line.replace(span.line) {
+            Some(previous) if previous == span.line => "|".to_string(),
+            _ => span.line.to_string(),
+        };
+        let Some(opcode) = Opcode::decode(chunk.code[offset]) else {
+            output.push_str(&format!("{:04} {:>4} <byte {}>\n", offset, source, chunk.code[offset]));
+            offset += 1;
+            continue;
+        };
+        let end = offset + 1 + opcode.operands();
+        let operands = operands(chunk, opcode, offset + 1, end);
+        output.push_str(format!("{:04} {:>4} {:<13}{}", offset, source, format!("{:?}", opcode), operands).trim_end());
+        output.push('\n');
+        offset = end;
+    }
+    for constant in &chunk.constants {
+        let Value::Function(function) = constant else {
+            continue;
+        };
+        if let Some(function) = function.as_compiled() {
+            output.push_str(&format!("\n{}:\n{}", constant, disassemble(&function.chunk)));
+        }
+    }
+    output
+}
+
+fn operands(chunk: &Chunk, opcode: Opcode, start: usize, end: usize) -> String {
+    let shown = |value: &Value| match value {
+        Value::Str(value) => format!("{:?}", value),
+        value => value.to_string(),
+    };
+    match opcode {
+        Opcode::Constant | Opcode::GetGlobal | Opcode::GetFunction | Opcode::SetGlobal | Opcode::DefineGlobal => {
+            let index = chunk.read(start);
+            format!("{} {}", index, shown(&chunk.constants[index]))
+        }
+        Opcode::Binary => {
+            let index = chunk.read(start);
+            format!("{} {}", index, chunk.operations[index].operator)
+        }
+        Opcode::SetIndex => match chunk.read(start) {
+            NO_OPERATION => String::new(),
+            index => format!("{} {}=", index, chunk.operations[index].operator),
+        },
+        Opcode::Jump | Opcode::JumpIfFalse | Opcode::JumpIfNotNil => {
+            format!("{} -> {:04}", chunk.read(start), end + chunk.read(start))
+        }
+        Opcode::Loop => format!("{} -> {:04}", chunk.read(start), end - chunk.read(start)),
+        Opcode::Call | Opcode::TailCall => chunk.code[start].to_string(),
+        Opcode::Range => (if chunk.code[start] == 0 { ".." } else { "..=" }).to_string(),
+        Opcode::Iterate => format!("{} {} -> {:04}", chunk.read(start), chunk.read(start + 2), end + chunk.read(start + 2)),
+        _ if end > start => chunk.read(start).to_string(),
+        _ => String::new(),
+    }
+}
+
+// A function compiled to bytecode. The arguments of a call are its first
+// locals.
+#[derive(Debug)]
+pub struct Function {
+    pub name: Option<String>,
+    pub arity: usize,
+    pub chunk: Chunk,
+}
+
+#[derive(Debug)]
+pub enum CompileError {
+    // Something the compiler can't lower yet, which only the evaluator runs
+    Unsupported { construct: &'static str, span: Span },
+    // A function uses a variable of one it's inside, which it would need a
+    // closure to keep
+    Captured { name: String, span: Span },
+    // More constants, locals, elements, arguments or code to jump over than
+    // the operands can count
+    TooLarge { span: Span },
+}
+
+impl CompileError {
+    pub fn span(&self) -> Span {
+        match self {
+            CompileError::Unsupported { span, .. } | CompileError::Captured { span, .. } | CompileError::TooLarge { span } => {
+                *span
+            }
+        }
+    }
+}
+
+impl Display for CompileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let span = self.span();
+        match self {
+            CompileError::Unsupported { construct, .. } => write!(f, "The VM can't run {}", construct),
+            CompileError::Captured { name, .. } => {
+                write!(f, "The VM can't run a function using '{}', which is a variable of the function around it", name)
+            }
+            CompileError::TooLarge { .. } => f.write_str("There's too much here for the VM to run"),
+        }?;
+        write!(f, " at line {}, character {}", span.line, span.character)
+    }
+}
+
+impl Error for CompileError {}
+
+// Lowers the program to a function that takes no arguments and gives back
+// the value of the last statement, for the VM to run. Declarations at the
+// top level, outside any block, are globals, the same as for the evaluator,
+// and everything else is a local of the function it's in.
+//
+// Only part of the language compiles: calls of functions, arithmetic,
+// variables, blocks, `if`, `for` and `while` loops, arrays, tuples and
+// indexing. Functions can use globals and their own variables, but not those
+// of the function around them. Anything else is `CompileError::Unsupported`,
+// with the construct it is: fields and methods, which include `math.sqrt`
+// and the like, maps, structs, enums, impls, traits, `match`, `try`,
+// destructuring, spreads, imports and macros.
+pub fn compile(program: &Program) -> Result<Function, CompileError> {
+    let mut compiler = Compiler { scopes: vec![Scope::default()] };
+    let Some((last, statements)) = program.statements.split_last() else {
+        compiler.emit(Opcode::Nil, Span::default());
+        compiler.emit(Opcode::Return, Span::default());
+        return Ok(compiler.finish(None, 0));
+    };
+    for statement in statements {
+        compiler.statement(statement)?;
+    }
+    match last {
+        Statement::Expression(statement) => compiler.expression(&statement.expression, false)?,
+        statement => {
+            compiler.statement(statement)?;
+            compiler.emit(Opcode::Nil, statement.span());
+        }
+    }
+    compiler.emit(Opcode::Return, last.span());
+    Ok(compiler.finish(None, 0))
+}
+
+struct Local {
+    name: String,
+    slot: usize,
+}
+
+// Where `break` and `continue` go, and how many values are on the stack
+// when they get there
+struct Loop {
+    start: usize,
+    height: usize,
+    breaks: Vec<usize>,
+}
+
+// What's known about the function being compiled
+#[derive(Default)]
+struct Scope {
+    chunk: Chunk,
+    locals: Vec<Local>,
+    // How many blocks deep it is, counting its body, which is 0 at the top
+    // level of the program
+    depth: usize,
+    // How many values its code will have put on the stack by this point
+    height: usize,
+    loops: Vec<Loop>,
+}
+
+struct Compiler {
+    // The function being compiled last, after every one it's inside
+    scopes: Vec<Scope>,
+}
+
+enum Name {
+    Local(usize),
+    Global,
+}
+
+fn too_large(span: Span) -> CompileError {
+    CompileError::TooLarge { span }
+}
+
+fn unsupported(construct: &'static str, span: Span) -> Result<(), CompileError> {
+    Err(CompileError::Unsupported { construct, span })
+}
+
+impl Compiler {
+    fn scope(&mut self) -> &mut Scope {
+        self.scopes.last_mut().expect("there's always a function being compiled")
+    }
+
+    fn finish(&mut self, name: Option<String>, arity: usize) -> Function {
+        let scope = self.scopes.pop().expect("there's always a function being compiled");
+        Function { name, arity, chunk: scope.chunk }
+    }
+
+    // How many values the instruction puts on the stack less how many it
+    // takes off, for those whose operands don't change it
+    fn effect(opcode: Opcode) -> isize {
+        match opcode {
+            Opcode::Constant | Opcode::Nil | Opcode::True | Opcode::False => 1,
+            Opcode::GetLocal | Opcode::GetGlobal | Opcode::GetFunction | Opcode::Count | Opcode::Iterate => 1,
+            Opcode::Pop | Opcode::DefineGlobal | Opcode::Binary | Opcode::JumpIfFalse | Opcode::JumpIfNotNil => -1,
+            Opcode::Range | Opcode::Index => -1,
+            Opcode::SetIndex => -2,
+            _ => 0,
+        }
+    }
+
+    fn emit(&mut self, opcode: Opcode, span: Span) {
+        let scope = self.scope();
+        scope.chunk.spans.push((scope.chunk.code.len(), span));
+        scope.chunk.code.push(opcode as u8);
+        scope.height = scope.height.wrapping_add_signed(Compiler::effect(opcode));
+    }
+
+    // Gives the byte that comes next a span of its own
+    fn mark(&mut self, span: Span) {
+        let chunk = &mut self.scope().chunk;
+        chunk.spans.push((chunk.code.len(), span));
+    }
+
+    fn operand(&mut self, operand: usize, span: Span) -> Result<(), CompileError> {
+        let operand = u16::try_from(operand).map_err(|_| too_large(span))?;
+        self.scope().chunk.code.extend(operand.to_le_bytes());
+        Ok(())
+    }
+
+    fn byte(&mut self, operand: usize, span: Span) -> Result<(), CompileError> {
+        let operand = u8::try_from(operand).map_err(|_| too_large(span))?;
+        self.scope().chunk.code.push(operand);
+        Ok(())
+    }
+
+    fn emit_operand(&mut self, opcode: Opcode, operand: usize, span: Span) -> Result<(), CompileError> {
+        self.emit(opcode, span);
+        self.operand(operand, span)
+    }
+
+    // For the instructions that take `count` values off the stack and put one
+    // back
+    fn collapse(&mut self, count: usize) {
+        let scope = self.scope();
+        scope.height = scope.height - count + 1;
+    }
+
+    fn constant(&mut self, value: Value, span: Span) -> Result<(), CompileError> {
+        let chunk = &mut self.scope().chunk;
+        chunk.constants.push(value);
+        let index = chunk.constants.len() - 1;
+        self.emit_operand(Opcode::Constant, index, span)
+    }
+
+    fn name(&mut self, name: &str) -> usize {
+        let chunk = &mut self.scope().chunk;
+        chunk.constants.push(Value::Str(name.to_string()));
+        chunk.constants.len() - 1
+    }
+
+    fn operation(&mut self, operator: &BinaryOperator, span: Span, operator_span: Span, right: Span) -> usize {
+        let operations = &mut self.scope().chunk.operations;
+        operations.push(Operation { operator: operator.clone(), span, operator_span, right });
+        operations.len() - 1
+    }
+
+    fn offset(&mut self) -> usize {
+        self.scope().chunk.code.len()
+    }
+
+    // Where a jump goes isn't known when it's emitted, so this gives back
+    // where its operand is, for `patch` to fill in once it is
+    fn jump(&mut self, opcode: Opcode, span: Span) -> Result<usize, CompileError> {
+        self.emit_operand(opcode, 0, span)?;
+        Ok(self.offset() - 2)
+    }
+
+    // Makes the jump with its operand at `operand` go to what comes next
+    fn patch(&mut self, operand: usize, span: Span) -> Result<(), CompileError> {
+        let chunk = &mut self.scope().chunk;
+        let distance = u16::try_from(chunk.code.len() - operand - 2).map_err(|_| too_large(span))?;
+        chunk.code[operand..operand + 2].copy_from_slice(&distance.to_le_bytes());
+        Ok(())
+    }
+
+    fn back(&mut self, start: usize, span: Span) -> Result<(), CompileError> {
+        let distance = self.offset() + 3 - start;
+        self.emit_operand(Opcode::Loop, distance, span)
+    }
+
+    // The value on top of the stack becomes the local called `name`
+    fn declare(&mut self, name: &str) {
+        let scope = self.scope();
+        let slot = scope.height - 1;
+        scope.locals.push(Local { name: name.to_string(), slot });
+    }
+
+    // Declares the value on top of the stack the way `let` would
+    fn define(&mut self, name: &str, span: Span) -> Result<(), CompileError> {
+        if self.scopes.len() == 1 && self.scopes[0].depth == 0 {
+            let index = self.name(name);
+            self.emit_operand(Opcode::DefineGlobal, index, span)
+        } else {
+            self.declare(name);
+            Ok(())
+        }
+    }
+
+    // A local of the function being compiled, the innermost first, or else a
+    // global
+    fn resolve(&self, name: &str, span: Span) -> Result<Name, CompileError> {
+        let (scope, outer) = self.scopes.split_last().expect("there's always a function being compiled");
+        if let Some(local) = scope.locals.iter().rev().find(|local| local.name == name) {
+            return Ok(Name::Local(local.slot));
+        }
+        if outer.iter().any(|scope| scope.locals.iter().any(|local| local.name == name)) {
+            return Err(CompileError::Captured { name: name.to_string(), span });
+        }
+        Ok(Name::Global)
+    }
+
+    fn load(&mut self, name: &str, span: Span, global: Opcode) -> Result<(), CompileError> {
+        match self.resolve(name, span)? {
+            Name::Local(slot) => self.emit_operand(Opcode::GetLocal, slot, span),
+            Name::Global => {
+                let index = self.name(name);
+                self.emit_operand(global, index, span)
+            }
+        }
+    }
+
+    // Gives back how many locals there were before the block, for `end_block`
+    fn begin_block(&mut self) -> usize {
+        let scope = self.scope();
+        scope.depth += 1;
+        scope.locals.len()
+    }
+
+    // Drops the locals the block declared from under its value
+    fn end_block(&mut self, start: usize, span: Span) -> Result<(), CompileError> {
+        let scope = self.scope();
+        scope.depth -= 1;
+        let count = scope.locals.len() - start;
+        scope.locals.truncate(start);
+        if count > 0 {
+            self.emit_operand(Opcode::PopBelow, count, span)?;
+            self.scope().height -= count;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<(), CompileError> {
+        match statement {
+            Statement::Expression(statement) => {
+                self.expression(&statement.expression, false)?;
+                self.emit(Opcode::Pop, statement.span);
+            }
+            Statement::Let(declaration) => {
+                self.expression(&declaration.initializer, false)?;
+                self.define(&declaration.name, declaration.span)?;
+            }
+            Statement::While(r#while) => self.r#while(r#while)?,
+            Statement::For(r#for) => self.r#for(r#for)?,
+            Statement::Break(r#break) => {
+                self.leave_loop(r#break.span);
+                let operand = self.jump(Opcode::Jump, r#break.span)?;
+                self.scope().loops.last_mut().expect("the parser only accepts break inside loops").breaks.push(operand);
+            }
+            Statement::Continue(r#continue) => {
+                let start = self.leave_loop(r#continue.span);
+                self.back(start, r#continue.span)?;
+            }
+            Statement::Function(function) => self.declaration(function)?,
+            // Whatever is returned is in tail position, wherever the `return`
+            // is. Nothing after it runs, so it leaves the stack as it was as
+            // far as the rest of the code is concerned.
+            Statement::Return(r#return) => {
+                match &r#return.value {
+                    Some(value) => self.expression(value, true)?,
+                    None => self.emit(Opcode::Nil, r#return.span),
+                }
+                self.emit(Opcode::Return, r#return.span);
+                self.scope().height -= 1;
+            }
+            Statement::Destructure(declaration) => unsupported("destructuring", declaration.span)?,
+            Statement::Struct(r#struct) => unsupported("structs", r#struct.span)?,
+            Statement::Enum(r#enum) => unsupported("enums", r#enum.span)?,
+            Statement::Impl(r#impl) => unsupported("impls", r#impl.span)?,
+            Statement::Trait(r#trait) => unsupported("traits", r#trait.span)?,
+            Statement::Import(import) => unsupported("imports", import.span)?,
+            Statement::Macro(r#macro) => unsupported("macros", r#macro.span)?,
+        }
+        Ok(())
+    }
+
+    // Pops whatever the body of the innermost loop has put on the stack, for
+    // `break` and `continue`, giving back where the loop starts. The code
+    // after them never runs, so the compiler carries on as if nothing had
+    // been popped.
+    fn leave_loop(&mut self, span: Span) -> usize {
+        let scope = self.scope();
+        let r#loop = scope.loops.last().expect("the parser only accepts break and continue inside loops");
+        let (start, height) = (r#loop.start, r#loop.height);
+        let current = scope.height;
+        for _ in height..current {
+            self.emit(Opcode::Pop, span);
+        }
+        self.scope().height = current;
+        start
+    }
+
+    fn r#while(&mut self, r#while: &While) -> Result<(), CompileError> {
+        let (start, height) = (self.offset(), self.scope().height);
+        self.expression(&r#while.condition, false)?;
+        let exit = self.jump(Opcode::JumpIfFalse, r#while.condition.span())?;
+        self.scope().loops.push(Loop { start, height, breaks: Vec::new() });
+        self.expression(&r#while.body, false)?;
+        self.emit(Opcode::Pop, r#while.span);
+        self.back(start, r#while.span)?;
+        self.patch(exit, r#while.span)?;
+        self.end_loop(r#while.span)
+    }
+
+    fn end_loop(&mut self, span: Span) -> Result<(), CompileError> {
+        let r#loop = self.scope().loops.pop().expect("every loop is ended once");
+        for operand in r#loop.breaks {
+            self.patch(operand, span)?;
+        }
+        Ok(())
+    }
+
+    // The range and the next int to give are locals of their own, with
+    // names that can't be written, and the loop variable is another for each
+    // iteration
+    fn r#for(&mut self, r#for: &For) -> Result<(), CompileError> {
+        let start = self.begin_block();
+        self.expression(&r#for.iterable, false)?;
+        self.declare("");
+        self.emit(Opcode::Count, r#for.iterable.span());
+        self.declare("");
+        let (height, slot) = (self.scope().height, self.scope().height - 2);
+        let loop_start = self.offset();
+        self.emit_operand(Opcode::Iterate, slot, r#for.span)?;
+        let exit = self.offset();
+        self.operand(0, r#for.span)?;
+        self.scope().loops.push(Loop { start: loop_start, height, breaks: Vec::new() });
+        self.declare(&r#for.variable);
+        self.expression(&r#for.body, false)?;
+        self.emit(Opcode::Pop, r#for.span);
+        self.scope().locals.pop();
+        self.emit(Opcode::Pop, r#for.span);
+        self.back(loop_start, r#for.span)?;
+        // Nothing is pushed when there are no ints left, so the count of
+        // what's on the stack is already right here
+        self.patch(exit, r#for.span)?;
+        self.end_loop(r#for.span)?;
+        let scope = self.scope();
+        scope.depth -= 1;
+        scope.locals.truncate(start);
+        self.emit(Opcode::Pop, r#for.span);
+        self.emit(Opcode::Pop, r#for.span);
+        Ok(())
+    }
+
+    // A function inside another is a local like any other, declared before
+    // its body is compiled so that the body using the function's name is
+    // found to be a variable it can't use
+    fn declaration(&mut self, function: &Declaration) -> Result<(), CompileError> {
+        if self.scopes.len() == 1 && self.scopes[0].depth == 0 {
+            self.function(Some(&function.name), &function.parameters, &function.body, function.span)?;
+            return self.define(&function.name, function.span);
+        }
+        self.emit(Opcode::Nil, function.span);
+        self.declare(&function.name);
+        self.function(Some(&function.name), &function.parameters, &function.body, function.span)?;
+        let slot = self.scope().height - 2;
+        self.emit_operand(Opcode::SetLocal, slot, function.span)?;
+        self.emit(Opcode::Pop, function.span);
+        Ok(())
+    }
+
+    // Pushes the function as a constant
+    fn function(
+        &mut self,
+        name: Option<&str>,
+        parameters: &[String],
+        body: &Expression,
+        span: Span,
+    ) -> Result<(), CompileError> {
+        let locals = parameters.iter().enumerate().map(|(slot, name)| Local { name: name.clone(), slot }).collect();
+        self.scopes.push(Scope { locals, height: parameters.len(), ..Scope::default() });
+        self.expression(body, true)?;
+        self.emit(Opcode::Return, body.span());
+        let function = self.finish(name.map(str::to_string), parameters.len());
+        self.constant(Value::Function(Callable::compiled(function)), span)
+    }
+
+    // Pushes the value of the expression. When it's in tail position in a
+    // function, which is to say its value is what the function gives back, a
+    // call there replaces the one running rather than going inside it. That
+    // goes for whichever part of a block, `if`, conditional or `??` gives the
+    // value of it too.
+    fn expression(&mut self, expression: &Expression, tail: bool) -> Result<(), CompileError> {
+        match expression {
+            Expression::Binary(binary) => {
+                // Down the left side of a chain in a loop, so that a long one
+                // doesn't recurse once per operator
+                let mut chain = vec![binary];
+                while let Expression::Binary(left) = &*chain[chain.len() - 1].left {
+                    chain.push(left);
+                }
+                self.expression(&chain[chain.len() - 1].left, false)?;
+                for binary in chain.into_iter().rev() {
+                    self.expression(&binary.right, false)?;
+                    let operation = self.operation(&binary.operator, binary.span, binary.operator_span, binary.right.span());
+                    self.emit_operand(Opcode::Binary, operation, binary.span)?;
+                }
+            }
+            Expression::Unary(unary) => {
+                self.expression(&unary.right, false)?;
+                let opcode = match unary.operator {
+                    UnaryOperator::Minus => Opcode::Negate,
+                    UnaryOperator::Tilde => Opcode::Invert,
+                };
+                self.emit(opcode, unary.span);
+            }
+            Expression::Integer(integer) => self.constant(Value::Int(integer.value), integer.span)?,
+            Expression::Float(float) => self.constant(Value::Float(float.value), float.span)?,
+            Expression::Char(char) => self.constant(Value::Char(char.value), char.span)?,
+            Expression::Str(str) => self.constant(Value::Str(str.value.clone()), str.span)?,
+            Expression::Bool(bool) => self.emit(if bool.value { Opcode::True } else { Opcode::False }, bool.span),
+            Expression::Nil(nil) => self.emit(Opcode::Nil, nil.span),
+            Expression::Interpolation(interpolation) => {
+                for part in &interpolation.parts {
+                    self.expression(part, false)?;
+                }
+                self.emit_operand(Opcode::Interpolate, interpolation.parts.len(), interpolation.span)?;
+                self.collapse(interpolation.parts.len());
+            }
+            Expression::Variable(variable) => self.load(&variable.name, variable.span, Opcode::GetGlobal)?,
+            Expression::Assign(assign) => {
+                self.expression(&assign.value, false)?;
+                match self.resolve(&assign.name, assign.span)? {
+                    Name::Local(slot) => self.emit_operand(Opcode::SetLocal, slot, assign.span)?,
+                    Name::Global => {
+                        let index = self.name(&assign.name);
+                        self.emit_operand(Opcode::SetGlobal, index, assign.span)?;
+                    }
+                }
+            }
+            Expression::Block(block) => self.block(block, tail)?,
+            Expression::If(r#if) => {
+                let otherwise = self.condition(&r#if.condition)?;
+                self.expression(&r#if.then_branch, tail)?;
+                self.branch(otherwise, r#if.else_branch.as_deref(), r#if.span, tail)?;
+            }
+            Expression::Conditional(conditional) => {
+                let otherwise = self.condition(&conditional.condition)?;
+                self.expression(&conditional.then_branch, tail)?;
+                self.branch(otherwise, Some(&conditional.else_branch), conditional.span, tail)?;
+            }
+            Expression::Coalesce(coalesce) => {
+                self.expression(&coalesce.value, false)?;
+                let end = self.jump(Opcode::JumpIfNotNil, coalesce.span)?;
+                self.expression(&coalesce.fallback, tail)?;
+                self.patch(end, coalesce.span)?;
+            }
+            Expression::Range(range) => {
+                self.expression(&range.start, false)?;
+                self.expression(&range.end, false)?;
+                self.emit(Opcode::Range, range.span);
+                self.byte(usize::from(range.inclusive), range.span)?;
+            }
+            Expression::Call(call) => self.call(call, tail)?,
+            Expression::Lambda(lambda) => self.function(None, &lambda.parameters, &lambda.body, lambda.span)?,
+            Expression::Array(array) => {
+                self.elements(&array.elements)?;
+                self.emit_operand(Opcode::Array, array.elements.len(), array.span)?;
+                self.collapse(array.elements.len());
+            }
+            Expression::Tuple(tuple) => {
+                self.elements(&tuple.elements)?;
+                self.emit_operand(Opcode::Tuple, tuple.elements.len(), tuple.span)?;
+                self.collapse(tuple.elements.len());
+            }
+            Expression::TupleIndex(index) => {
+                self.expression(&index.tuple, false)?;
+                self.emit(Opcode::TupleIndex, index.span);
+                self.mark(index.tuple.span());
+                self.operand(index.position, index.span)?;
+            }
+            Expression::Index(index) => {
+                self.expression(&index.target, false)?;
+                self.expression(&index.index, false)?;
+                self.emit(Opcode::Index, index.index.span());
+            }
+            Expression::IndexAssign(assign) => self.index_assign(assign)?,
+            Expression::Match(r#match) => unsupported("match expressions", r#match.span)?,
+            Expression::Try(r#try) => unsupported("try expressions", r#try.span)?,
+            Expression::Map(map) => unsupported("maps", map.span)?,
+            Expression::StructLiteral(literal) => unsupported("structs", literal.span)?,
+            Expression::Field(field) => unsupported("fields and methods", field.span)?,
+            Expression::Spread(spread) => unsupported("spreads", spread.span)?,
+        }
+        Ok(())
+    }
+
+    fn elements(&mut self, elements: &[Expression]) -> Result<(), CompileError> {
+        for element in elements {
+            if let Expression::Spread(spread) = element {
+                unsupported("spreads", spread.span)?;
+            }
+            self.expression(element, false)?;
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, block: &Block, tail: bool) -> Result<(), CompileError> {
+        let start = self.begin_block();
+        for statement in &block.statements {
+            self.statement(statement)?;
+        }
+        match &block.value {
+            Some(value) => self.expression(value, tail)?,
+            None => self.emit(Opcode::Nil, block.span),
+        }
+        self.end_block(start, block.span)
+    }
+
+    // Gives back the jump to take when the condition is false
+    fn condition(&mut self, condition: &Expression) -> Result<usize, CompileError> {
+        self.expression(condition, false)?;
+        self.jump(Opcode::JumpIfFalse, condition.span())
+    }
+
+    // Jumps past the other branch at the end of the one just compiled, which
+    // leaves a value the other one leaves too
+    fn branch(&mut self, otherwise: usize, branch: Option<&Expression>, span: Span, tail: bool) -> Result<(), CompileError> {
+        let end = self.jump(Opcode::Jump, span)?;
+        self.scope().height -= 1;
+        self.patch(otherwise, span)?;
+        match branch {
+            Some(branch) => self.expression(branch, tail)?,
+            None => self.emit(Opcode::Nil, span),
+        }
+        self.patch(end, span)
+    }
+
+    // A name that isn't defined at all gets its own error, as it does for the
+    // evaluator
+    fn call(&mut self, call: &Call, tail: bool) -> Result<(), CompileError> {
+        match &*call.callee {
+            Expression::Variable(variable) => self.load(&variable.name, variable.span, Opcode::GetFunction)?,
+            Expression::Field(field) => unsupported("fields and methods", field.span)?,
+            callee => self.expression(callee, false)?,
+        }
+        self.elements(&call.arguments)?;
+        self.emit(if tail { Opcode::TailCall } else { Opcode::Call }, call.span);
+        self.mark(call.callee.span());
+        self.byte(call.arguments.len(), call.span)?;
+        self.collapse(call.arguments.len() + 1);
+        Ok(())
+    }
+
+    fn index_assign(&mut self, assign: &IndexAssign) -> Result<(), CompileError> {
+        self.expression(&assign.target, false)?;
+        self.expression(&assign.index, false)?;
+        self.expression(&assign.value, false)?;
+        let operation = match &assign.operator {
+            Some(operator) => self.operation(operator, assign.span, assign.span, assign.value.span()),
+            None => NO_OPERATION,
+        };
+        self.emit_operand(Opcode::SetIndex, operation, assign.index.span())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::lexer::get_tokens;
+    use crate::grammar::parser::get_program;
+
+    fn disassemble_source(input: &str) -> String {
+        disassemble(&compile(&get_program(get_tokens(input).unwrap()).unwrap()).unwrap().chunk)
+    }
+
+    #[test]
+    fn test_disassemble() -> Result<(), String> {
+        let expected = "\
+0000    1 Constant     0 \"n\"
+0003    | DefineGlobal 1 \"name\"
+0006    2 GetGlobal    2 \"name\"
+0009    | Constant     3 1
+0012    | Constant     4 2
+0015    | Binary       0 *
+0018    | Tuple        2
+0021    | Return
+";
+        assert_eq!(disassemble_source("let name = \"n\"\n(name, 1 * 2)"), expected);
+
+        let expected = "\
+0000    1 Constant     0 <fn down>
+0003    | DefineGlobal 1 \"down\"
+0006    2 GetFunction  2 \"down\"
+0009    | Constant     3 3
+0012    | Call         1
+0014    | Return
+
+<fn down>:
+0000    1 GetLocal     0
+0003    | Constant     0 0
+0006    | Binary       0 ==
+0009    | JumpIfFalse  4 -> 0016
+0012    | Nil
+0013    | Jump         14 -> 0030
+0016    | GetFunction  1 \"down\"
+0019    | GetLocal     0
+0022    | Constant     2 1
+0025    | Binary       1 -
+0028    | TailCall     1
+0030    | Return
+";
+        assert_eq!(disassemble_source("fn down(n) { n == 0 ? nil : down(n - 1) }\ndown(3)"), expected);
+
+        let output = disassemble_source("let i = 0\nwhile i < 3 { i += 1 }\nlet xs = [i]\nxs[0] = 5");
+        assert!(output.contains("| JumpIfFalse  "), "{}", output);
+        assert!(output.contains("| Loop         "), "{}", output);
+        assert!(output.lines().any(|line| line.ends_with("| SetIndex")), "{}", output);
+
+        Ok(())
+    }
+}
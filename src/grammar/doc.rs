@@ -0,0 +1,168 @@
+use std::fmt::Write;
+use crate::grammar::parser::{Function, Program, Signature, Statement};
+use crate::grammar::unparse::unparse_signature;
+
+// One heading of the documentation, with the `///` comments written above the
+// declaration it's for. Methods are a level below the impl or trait they're in.
+struct Item {
+    heading: String,
+    level: usize,
+    doc: Option<String>,
+}
+
+// Only the top level is documented, since anything declared inside a function
+// can't be used from outside it
+fn items(program: &Program) -> Vec<Item> {
+    let mut items = Vec::new();
+    for statement in &program.statements {
+        match statement {
+            Statement::Function(function) => items.push(function_item(function, 2)),
+            Statement::Struct(r#struct) => items.push(Item {
+                heading: format!("struct {} {{ {} }}", r#struct.name, r#struct.fields.join(", ")),
+                level: 2,
+                doc: r#struct.doc.clone(),
+            }),
+            Statement::Enum(r#enum) => {
+                let variants = r#enum.variants.iter()
+                    .map(|variant| match variant.fields.as_slice() {
+                        [] => variant.name.clone(),
+                        fields => format!("{}({})", variant.name, fields.join(", ")),
+                    })
+                    .collect::<Vec<_>>();
+                items.push(Item {
+                    heading: format!("enum {} {{ {} }}", r#enum.name, variants.join(", ")),
+                    level: 2,
+                    doc: r#enum.doc.clone(),
+                });
+            }
+            Statement::Trait(r#trait) => {
+                items.push(Item { heading: format!("trait {}", r#trait.name), level: 2, doc: r#trait.doc.clone() });
+                items.extend(r#trait.methods.iter().map(signature_item));
+            }
+            Statement::Impl(r#impl) => {
+                let heading = match &r#impl.r#trait {
+                    Some(r#trait) => format!("impl {} for {}", r#trait, r#impl.name),
+                    None => format!("impl {}", r#impl.name),
+                };
+                items.push(Item { heading, level: 2, doc: None });
+                items.extend(r#impl.methods.iter().map(|method| function_item(method, 3)));
+            }
+            _ => {}
+        }
+    }
+    items
+}
+
+fn function_item(function: &Function, level: usize) -> Item {
+    let signature = unparse_signature(
+        &function.name, &function.type_parameters, &function.parameters, &function.annotations, &function.return_type,
+    );
+    Item { heading: format!("fn {}", signature), level, doc: function.doc.clone() }
+}
+
+fn signature_item(signature: &Signature) -> Item {
+    let heading = unparse_signature(
+        &signature.name, &signature.type_parameters, &signature.parameters, &signature.annotations,
+        &signature.return_type,
+    );
+    Item { heading: format!("fn {}", heading), level: 3, doc: signature.doc.clone() }
+}
+
+pub fn to_markdown(program: &Program) -> String {
+    let mut output = String::new();
+    for item in items(program) {
+        if !output.is_empty() {
+            output.push('\n');
+        }
+        writeln!(output, "{} `{}`", "#".repeat(item.level), item.heading).unwrap();
+        if let Some(doc) = item.doc {
+            writeln!(output, "\n{}", doc).unwrap();
+        }
+    }
+    output
+}
+
+// The doc is split into paragraphs on blank lines, as Markdown would
+pub fn to_html(program: &Program) -> String {
+    let mut output = String::new();
+    for item in items(program) {
+        writeln!(output, "<h{0}><code>{1}</code></h{0}>", item.level, escape(&item.heading)).unwrap();
+        let doc = item.doc.unwrap_or_default();
+        let paragraphs = doc.split("\n\n").map(str::trim).filter(|paragraph| !paragraph.is_empty());
+        for paragraph in paragraphs {
+            writeln!(output, "<p>{}</p>", escape(paragraph)).unwrap();
+        }
+    }
+    output
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::lexer::get_tokens;
+    use crate::grammar::parser::get_program;
+
+    fn program(input: &str) -> Program {
+        get_program(get_tokens(input).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_markdown() -> Result<(), String> {
+        let input = "\
+/// Adds two numbers.
+///
+/// Works on floats too.
+fn add(a: Int, b: Int) -> Int { a + b }
+struct Point { x, y }
+/// Something with an area
+trait Shape {
+    /// How much room it takes up
+    fn area(s)
+}
+impl Shape for Point { fn area(p) { 0 } }
+let x = 1;";
+        assert_eq!(to_markdown(&program(input)), "\
+## `fn add(a: Int, b: Int) -> Int`
+
+Adds two numbers.
+
+Works on floats too.
+
+## `struct Point { x, y }`
+
+## `trait Shape`
+
+Something with an area
+
+### `fn area(s)`
+
+How much room it takes up
+
+## `impl Shape for Point`
+
+### `fn area(p)`
+");
+        Ok(())
+    }
+
+    #[test]
+    fn test_html() -> Result<(), String> {
+        let input = "\
+/// Returns `a` if a < b & \"b\" otherwise.
+///
+/// Second paragraph
+fn min<T>(a: T, b: T) -> T { if a < b { a } else { b } }
+enum Option { Some(value), None }";
+        assert_eq!(to_html(&program(input)), "\
+<h2><code>fn min&lt;T&gt;(a: T, b: T) -&gt; T</code></h2>
+<p>Returns `a` if a &lt; b &amp; &quot;b&quot; otherwise.</p>
+<p>Second paragraph</p>
+<h2><code>enum Option { Some(value), None }</code></h2>
+");
+        Ok(())
+    }
+}
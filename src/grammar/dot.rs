@@ -0,0 +1,425 @@
+use std::fmt::Write;
+use crate::grammar::parser::{
+    Array, Assign, Binary, BinaryOperator, Block, Bool, Break, Call, Char, Coalesce, Conditional, Continue, Destructure,
+    Enum, Expression, ExpressionStatement, Field, Float, For, Function, If, Impl, Import, Index, IndexAssign, Integer,
+    Interpolation, Lambda, Let, Macro, Map, Match, Nil, Program, Range, Return, Spread, Str, Struct, StructLiteral, Trait,
+    Try, Tuple, TupleIndex, Unary, Variable, While,
+};
+use crate::grammar::unparse::unparse_pattern;
+use crate::grammar::visit::{StatementVisitor, Visitor};
+
+// Writes out one DOT node per AST node, named after its id, and an edge to
+// each of its children
+struct DotWriter {
+    output: String,
+}
+
+impl DotWriter {
+    fn node(&mut self, id: u32, label: &str) {
+        let label = label.replace('\\', "\\\\").replace('"', "\\\"");
+        writeln!(self.output, "    n{} [label=\"{}\"];", id, label).unwrap();
+    }
+
+    fn edge(&mut self, parent: u32, child: &Expression) {
+        self.visit_expression(child);
+        self.link(parent, child.id().0);
+    }
+
+    fn link(&mut self, parent: u32, child: u32) {
+        writeln!(self.output, "    n{} -> n{};", parent, child).unwrap();
+    }
+}
+
+impl Visitor<()> for DotWriter {
+    // Goes down the left side of a chain of operators in a loop, writing the
+    // same lines as recursing would, so that a long chain doesn't overflow
+    fn visit_binary(&mut self, binary: &Binary) {
+        let mut chain = vec![binary];
+        self.node(binary.id.0, &binary.operator.to_string());
+        while let Expression::Binary(left) = &*chain[chain.len() - 1].left {
+            self.node(left.id.0, &left.operator.to_string());
+            chain.push(left);
+        }
+        let innermost = chain[chain.len() - 1];
+        self.edge(innermost.id.0, &innermost.left);
+        for (position, binary) in chain.iter().enumerate().rev() {
+            self.edge(binary.id.0, &binary.right);
+            if position > 0 {
+                self.link(chain[position - 1].id.0, binary.id.0);
+            }
+        }
+    }
+
+    fn visit_unary(&mut self, unary: &Unary) {
+        self.node(unary.id.0, &unary.operator.to_string());
+        self.edge(unary.id.0, &unary.right);
+    }
+
+    fn visit_integer(&mut self, integer: &Integer) {
+        self.node(integer.id.0, &integer.value.to_string());
+    }
+
+    fn visit_float(&mut self, float: &Float) {
+        self.node(float.id.0, &format!("{:?}", float.value));
+    }
+
+    fn visit_char(&mut self, char: &Char) {
+        self.node(char.id.0, &format!("{:?}", char.value));
+    }
+
+    fn visit_str(&mut self, str: &Str) {
+        self.node(str.id.0, &format!("{:?}", str.value));
+    }
+
+    fn visit_bool(&mut self, bool: &Bool) {
+        self.node(bool.id.0, &bool.value.to_string());
+    }
+
+    fn visit_nil(&mut self, nil: &Nil) {
+        self.node(nil.id.0, "nil");
+    }
+
+    fn visit_interpolation(&mut self, interpolation: &Interpolation) {
+        self.node(interpolation.id.0, "interpolation");
+        for part in &interpolation.parts {
+            self.edge(interpolation.id.0, part);
+        }
+    }
+
+    fn visit_variable(&mut self, variable: &Variable) {
+        self.node(variable.id.0, &variable.name);
+    }
+
+    fn visit_assign(&mut self, assign: &Assign) {
+        self.node(assign.id.0, &format!("{} =", assign.name));
+        self.edge(assign.id.0, &assign.value);
+    }
+
+    fn visit_block(&mut self, block: &Block) {
+        self.node(block.id.0, "block");
+        for statement in &block.statements {
+            self.visit_statement(statement);
+            writeln!(self.output, "    n{} -> n{};", block.id.0, statement.id().0).unwrap();
+        }
+        if let Some(value) = &block.value {
+            self.edge(block.id.0, value);
+        }
+    }
+
+    fn visit_if(&mut self, r#if: &If) {
+        self.node(r#if.id.0, "if");
+        self.edge(r#if.id.0, &r#if.condition);
+        self.edge(r#if.id.0, &r#if.then_branch);
+        if let Some(branch) = &r#if.else_branch {
+            self.edge(r#if.id.0, branch);
+        }
+    }
+
+    fn visit_range(&mut self, range: &Range) {
+        self.node(range.id.0, if range.inclusive { "..=" } else { ".." });
+        self.edge(range.id.0, &range.start);
+        self.edge(range.id.0, &range.end);
+    }
+
+    fn visit_call(&mut self, call: &Call) {
+        self.node(call.id.0, "call");
+        self.edge(call.id.0, &call.callee);
+        for argument in &call.arguments {
+            self.edge(call.id.0, argument);
+        }
+    }
+
+    fn visit_map(&mut self, map: &Map) {
+        self.node(map.id.0, "map");
+        for (key, value) in &map.entries {
+            self.edge(map.id.0, key);
+            self.edge(map.id.0, value);
+        }
+    }
+
+    fn visit_coalesce(&mut self, coalesce: &Coalesce) {
+        self.node(coalesce.id.0, "??");
+        self.edge(coalesce.id.0, &coalesce.value);
+        self.edge(coalesce.id.0, &coalesce.fallback);
+    }
+
+    fn visit_conditional(&mut self, conditional: &Conditional) {
+        self.node(conditional.id.0, "?:");
+        self.edge(conditional.id.0, &conditional.condition);
+        self.edge(conditional.id.0, &conditional.then_branch);
+        self.edge(conditional.id.0, &conditional.else_branch);
+    }
+
+    // Each arm gets a node of its own, labelled with its pattern, with edges
+    // to its guard and body
+    fn visit_match(&mut self, r#match: &Match) {
+        self.node(r#match.id.0, "match");
+        self.edge(r#match.id.0, &r#match.scrutinee);
+        for arm in &r#match.arms {
+            self.node(arm.id.0, &format!("{} =>", unparse_pattern(&arm.pattern)));
+            writeln!(self.output, "    n{} -> n{};", r#match.id.0, arm.id.0).unwrap();
+            if let Some(guard) = &arm.guard {
+                self.edge(arm.id.0, guard);
+            }
+            self.edge(arm.id.0, &arm.body);
+        }
+    }
+
+    fn visit_try(&mut self, r#try: &Try) {
+        self.node(r#try.id.0, &format!("try catch ({})", r#try.name));
+        self.edge(r#try.id.0, &r#try.body);
+        self.edge(r#try.id.0, &r#try.handler);
+    }
+
+    fn visit_tuple(&mut self, tuple: &Tuple) {
+        self.node(tuple.id.0, "()");
+        for element in &tuple.elements {
+            self.edge(tuple.id.0, element);
+        }
+    }
+
+    fn visit_tuple_index(&mut self, index: &TupleIndex) {
+        self.node(index.id.0, &format!(".{}", index.position));
+        self.edge(index.id.0, &index.tuple);
+    }
+
+    fn visit_index(&mut self, index: &Index) {
+        self.node(index.id.0, "index");
+        self.edge(index.id.0, &index.target);
+        self.edge(index.id.0, &index.index);
+    }
+
+    fn visit_index_assign(&mut self, assign: &IndexAssign) {
+        let operator = assign.operator.as_ref().map(BinaryOperator::to_string).unwrap_or_default();
+        self.node(assign.id.0, &format!("[] {}=", operator));
+        self.edge(assign.id.0, &assign.target);
+        self.edge(assign.id.0, &assign.index);
+        self.edge(assign.id.0, &assign.value);
+    }
+
+    fn visit_array(&mut self, array: &Array) {
+        self.node(array.id.0, "[]");
+        for element in &array.elements {
+            self.edge(array.id.0, element);
+        }
+    }
+
+    fn visit_lambda(&mut self, lambda: &Lambda) {
+        self.node(lambda.id.0, &format!("fn({})", lambda.parameters.join(", ")));
+        self.edge(lambda.id.0, &lambda.body);
+    }
+
+    // The field names go in the label, with an edge to each value in the
+    // same order
+    fn visit_struct_literal(&mut self, literal: &StructLiteral) {
+        let names = literal.fields.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>();
+        self.node(literal.id.0, &format!("{} {{ {} }}", literal.name, names.join(", ")));
+        for (_, value) in &literal.fields {
+            self.edge(literal.id.0, value);
+        }
+    }
+
+    fn visit_field(&mut self, field: &Field) {
+        self.node(field.id.0, &format!(".{}", field.field));
+        self.edge(field.id.0, &field.target);
+    }
+
+    fn visit_spread(&mut self, spread: &Spread) {
+        self.node(spread.id.0, "...");
+        self.edge(spread.id.0, &spread.value);
+    }
+}
+
+impl StatementVisitor<()> for DotWriter {
+    fn visit_expression_statement(&mut self, statement: &ExpressionStatement) {
+        self.node(statement.id.0, "expression statement");
+        self.edge(statement.id.0, &statement.expression);
+    }
+
+    fn visit_let(&mut self, declaration: &Let) {
+        self.node(declaration.id.0, &format!("let {}", declaration.name));
+        self.edge(declaration.id.0, &declaration.initializer);
+    }
+
+    fn visit_destructure(&mut self, declaration: &Destructure) {
+        self.node(declaration.id.0, &format!("let {}", unparse_pattern(&declaration.pattern)));
+        self.edge(declaration.id.0, &declaration.initializer);
+    }
+
+    fn visit_while(&mut self, r#while: &While) {
+        self.node(r#while.id.0, "while");
+        self.edge(r#while.id.0, &r#while.condition);
+        self.edge(r#while.id.0, &r#while.body);
+    }
+
+    fn visit_for(&mut self, r#for: &For) {
+        self.node(r#for.id.0, &format!("for {}", r#for.variable));
+        self.edge(r#for.id.0, &r#for.iterable);
+        self.edge(r#for.id.0, &r#for.body);
+    }
+
+    fn visit_break(&mut self, r#break: &Break) {
+        self.node(r#break.id.0, "break");
+    }
+
+    fn visit_continue(&mut self, r#continue: &Continue) {
+        self.node(r#continue.id.0, "continue");
+    }
+
+    fn visit_function(&mut self, function: &Function) {
+        self.node(function.id.0, &format!("fn {}({})", function.name, function.parameters.join(", ")));
+        self.edge(function.id.0, &function.body);
+    }
+
+    fn visit_return(&mut self, r#return: &Return) {
+        self.node(r#return.id.0, "return");
+        if let Some(value) = &r#return.value {
+            self.edge(r#return.id.0, value);
+        }
+    }
+
+    fn visit_struct(&mut self, r#struct: &Struct) {
+        self.node(r#struct.id.0, &format!("struct {} {{ {} }}", r#struct.name, r#struct.fields.join(", ")));
+    }
+
+    fn visit_enum(&mut self, r#enum: &Enum) {
+        let variants = r#enum.variants.iter().map(|variant| variant.name.as_str()).collect::<Vec<_>>();
+        self.node(r#enum.id.0, &format!("enum {} {{ {} }}", r#enum.name, variants.join(", ")));
+    }
+
+    fn visit_impl(&mut self, r#impl: &Impl) {
+        let label = match &r#impl.r#trait {
+            Some(r#trait) => format!("impl {} for {}", r#trait, r#impl.name),
+            None => format!("impl {}", r#impl.name),
+        };
+        self.node(r#impl.id.0, &label);
+        for method in &r#impl.methods {
+            self.visit_function(method);
+            writeln!(self.output, "    n{} -> n{};", r#impl.id.0, method.id.0).unwrap();
+        }
+    }
+
+    fn visit_import(&mut self, import: &Import) {
+        self.node(import.id.0, &format!("import {}", import.path));
+    }
+
+    fn visit_macro(&mut self, r#macro: &Macro) {
+        self.node(r#macro.id.0, &format!("macro {}({})", r#macro.name, r#macro.parameters.join(", ")));
+        self.edge(r#macro.id.0, &r#macro.body);
+    }
+
+    fn visit_trait(&mut self, r#trait: &Trait) {
+        let methods = r#trait.methods.iter().map(|method| method.name.as_str()).collect::<Vec<_>>();
+        self.node(r#trait.id.0, &format!("trait {} {{ {} }}", r#trait.name, methods.join(", ")));
+    }
+}
+
+// `ordering=out` keeps operands drawn left to right in source order
+pub fn to_dot(root: &Expression) -> String {
+    let mut writer = DotWriter {
+        output: String::from("digraph ast {\n    ordering=out;\n"),
+    };
+    writer.visit_expression(root);
+    writer.output.push_str("}\n");
+    writer.output
+}
+
+// Statements hang off a single `program` node in order
+pub fn program_to_dot(program: &Program) -> String {
+    let mut writer = DotWriter {
+        output: String::from("digraph ast {\n    ordering=out;\n    program [label=\"program\"];\n"),
+    };
+    for statement in &program.statements {
+        writer.visit_statement(statement);
+        writeln!(writer.output, "    program -> n{};", statement.id().0).unwrap();
+    }
+    writer.output.push_str("}\n");
+    writer.output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::lexer::get_tokens;
+    use crate::grammar::parser::{get_ast, get_program};
+
+    fn dot_source(input: &str) -> String {
+        let tokens = get_tokens(input).unwrap();
+        to_dot(&get_ast(tokens).unwrap())
+    }
+
+    #[test]
+    fn test_nesting() -> Result<(), String> {
+        assert_eq!(dot_source("2+3-1"), "\
+digraph ast {
+    ordering=out;
+    n4 [label=\"-\"];
+    n2 [label=\"+\"];
+    n0 [label=\"2\"];
+    n2 -> n0;
+    n1 [label=\"3\"];
+    n2 -> n1;
+    n4 -> n2;
+    n3 [label=\"1\"];
+    n4 -> n3;
+}
+");
+        // A long chain takes a node and an edge for each operator and operand
+        let dot = dot_source(&vec!["1"; 100_000].join(" + "));
+        assert_eq!(dot.lines().count(), 3 + 199_999 + 199_998);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_program() -> Result<(), String> {
+        let program = get_program(get_tokens("1; -2").unwrap()).unwrap();
+        assert_eq!(program_to_dot(&program), "\
+digraph ast {
+    ordering=out;
+    program [label=\"program\"];
+    n1 [label=\"expression statement\"];
+    n0 [label=\"1\"];
+    n1 -> n0;
+    program -> n1;
+    n4 [label=\"expression statement\"];
+    n3 [label=\"-\"];
+    n2 [label=\"2\"];
+    n3 -> n2;
+    n4 -> n3;
+    program -> n4;
+}
+");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_literal_labels() -> Result<(), String> {
+        let dot = dot_source("-\"a\\\"${'b' + 1.5}\"");
+        assert!(dot.contains("[label=\"-\"]"));
+        assert!(dot.contains("[label=\"interpolation\"]"));
+        assert!(dot.contains("[label=\"\\\"a\\\\\\\"\\\"\"]"));
+        assert!(dot.contains("[label=\"'b'\"]"));
+        assert!(dot.contains("[label=\"1.5\"]"));
+
+        let dot = dot_source("if true { 1 } else { 2 }");
+        assert!(dot.contains("n5 [label=\"if\"];"));
+        assert!(dot.contains("n0 [label=\"true\"];"));
+        assert!(dot.contains("n5 -> n0;\n"));
+        assert!(dot.contains("n5 -> n2;\n"));
+        assert!(dot.contains("n5 -> n4;\n"));
+
+        let dot = dot_source("[1, []]");
+        assert!(dot.contains("n2 [label=\"[]\"];"));
+        assert!(dot.contains("n2 -> n0;\n"));
+        assert!(dot.contains("n2 -> n1;\n"));
+
+        let dot = dot_source("P { x: 1, y: 2 }.x");
+        assert!(dot.contains("n2 [label=\"P { x, y }\"];"));
+        assert!(dot.contains("n3 [label=\".x\"];"));
+        assert!(dot.contains("n3 -> n2;\n"));
+
+        Ok(())
+    }
+}
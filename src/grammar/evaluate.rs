@@ -1,35 +1,4974 @@
-use crate::grammar::parser::{Binary, Expression, Integer, TermOperator};
-
-trait Evaluate<T> {
-    fn evaluate(&self) -> T;
-}
-
-impl Evaluate<i32> for Expression {
-    fn evaluate(&self) -> i32 {
-        match self {
-            Expression::Binary(b) => b.evaluate(),
-            Expression::Integer(i) => i.evaluate(),
-        }
-    }
-}
-
-impl Evaluate<i32> for Binary {
-    fn evaluate(&self) -> i32 {
-        let left = self.left.evaluate();
-        let right = self.right.evaluate();
-        match self.operator {
-            TermOperator::Plus => left + right,
-            TermOperator::Minus => left - right,
-        }
-    }
-}
-
-impl Evaluate<i32> for Integer {
-    fn evaluate(&self) -> i32 {
-        self.value
-    }
-}
-
-pub fn evaluate(root: &Expression) -> i32 {
-    root.evaluate()
-}
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::{cmp, fs, io, iter, mem};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::grammar::bigint::BigInt;
+use crate::grammar::compile::{self, CompileError};
+use crate::grammar::format;
+use crate::grammar::heap::{Heap, Trace};
+use crate::grammar::lexer::{get_tokens, LexerError, Span};
+use crate::grammar::math;
+use crate::grammar::parser::{
+    Array, Assign, Binary, Block, BinaryOperator, Bool, Break, Call, Char, Coalesce, Conditional, Continue, Destructure,
+    Enum, Expression, ExpressionStatement, Field, Float, For, Function, If, Impl, Import, Index, IndexAssign, Integer,
+    Interpolation, Lambda, Let, Macro, Map, Match, MatchArm, Nil, ParseError, Pattern, Program, Range, Return, Signature,
+    NodeId, Spread, Statement, Str, Struct, StructLiteral, Trait, Try, Tuple, TupleIndex, Unary, UnaryOperator, Variable,
+    While, get_ast, get_program,
+};
+use crate::grammar::random::Rng;
+use crate::grammar::rational::Rational;
+use crate::grammar::runtime::{EnumType, EnumValue, Instance, Key, Module, RangeValue, StructType, Type, Value};
+use crate::grammar::visit::{walk_expression, walk_statement, StatementVisitor, Visitor};
+
+impl Key {
+    fn new(value: Value, span: Span) -> Result<Key, RuntimeError> {
+        match value {
+            Value::Int(key) => Ok(Key::Int(key)),
+            Value::Str(key) => Ok(Key::Str(key)),
+            _ => Err(RuntimeError::InvalidKey { span }),
+        }
+    }
+}
+
+// A function as a value, made by running a declaration or a lambda or
+// provided as a builtin. Every copy of the value shares the same routine, and
+// two functions are only equal if they are copies of each other.
+#[derive(Clone)]
+pub struct Callable(Rc<Routine>);
+
+enum Routine {
+    Closure(Closure),
+    Builtin(Builtin),
+    Variadic(Builtin),
+    Native(Native),
+    Constructor(Constructor),
+    // Only the VM can run these, so to the evaluator they aren't callable
+    Compiled(compile::Function),
+}
+
+// The environment a function was made in stays alive for as long as the
+// function does, so the function can keep using its variables after the
+// call that made it has returned
+struct Closure {
+    name: Option<String>,
+    parameters: Vec<String>,
+    body: Expression,
+    environment: Rc<Environment>,
+}
+
+// A function that comes with the language rather than being written in it.
+// The arguments have already been checked against the arity. The evaluator
+// is there for the builtins that read or write something.
+#[derive(Clone, Copy)]
+struct Builtin {
+    name: &'static str,
+    arity: usize,
+    function: fn(&mut Evaluator, Vec<Value>, Span) -> Result<Value, RuntimeError>,
+}
+
+// A function the program embedding rat gave it with `Interpreter::register`.
+// It has no span to put its errors down to, so they get the call's.
+struct Native {
+    name: String,
+    arity: usize,
+    function: Box<NativeFunction>,
+}
+
+type NativeFunction = dyn Fn(&[Value]) -> Result<Value, RuntimeError>;
+
+// Makes a value of the variant of an enum out of one argument per field
+struct Constructor {
+    enum_name: String,
+    variant: String,
+    arity: usize,
+}
+
+const BUILTINS: [Builtin; 11] = [
+    Builtin { name: "len", arity: 1, function: len },
+    Builtin { name: "int", arity: 1, function: int },
+    Builtin { name: "float", arity: 1, function: float },
+    Builtin { name: "read_line", arity: 0, function: read_line },
+    Builtin { name: "parse_int", arity: 1, function: parse_int },
+    Builtin { name: "now", arity: 0, function: now },
+    Builtin { name: "elapsed", arity: 1, function: elapsed },
+    Builtin { name: "random", arity: 0, function: random },
+    Builtin { name: "random_int", arity: 2, function: random_int },
+    Builtin { name: "assert", arity: 1, function: assert },
+    Builtin { name: "assert_eq", arity: 2, function: assert_eq },
+];
+
+// Builtins that take any number of arguments after the ones their arity
+// counts
+const VARIADIC_BUILTINS: [Builtin; 3] = [
+    Builtin { name: "print", arity: 1, function: print },
+    Builtin { name: "println", arity: 1, function: println },
+    Builtin { name: "format", arity: 1, function: format },
+];
+
+// Only there with `EvalConfig::allow_fs`
+const FS_BUILTINS: [Builtin; 2] = [
+    Builtin { name: "read_file", arity: 1, function: read_file },
+    Builtin { name: "write_file", arity: 2, function: write_file },
+];
+
+// Only there with `EvalConfig::allow_sleep`
+const SLEEP: Builtin = Builtin { name: "sleep", arity: 1, function: sleep };
+
+// The methods of each type. A method is a builtin that gets the receiver as
+// its first argument, so the arity counts the receiver too.
+const METHODS: [(Type, Builtin); 24] = [
+    (Type::Array, Builtin { name: "len", arity: 1, function: len }),
+    (Type::Array, Builtin { name: "join", arity: 2, function: join }),
+    (Type::Array, Builtin { name: "map", arity: 2, function: map }),
+    (Type::Array, Builtin { name: "filter", arity: 2, function: filter }),
+    (Type::Array, Builtin { name: "reduce", arity: 3, function: reduce }),
+    (Type::Array, Builtin { name: "sort", arity: 1, function: sort }),
+    (Type::Array, Builtin { name: "reverse", arity: 1, function: reverse }),
+    (Type::Array, Builtin { name: "push", arity: 2, function: push }),
+    (Type::Array, Builtin { name: "pop", arity: 1, function: pop }),
+    (Type::Array, Builtin { name: "contains", arity: 2, function: contains_element }),
+    (Type::Tuple, Builtin { name: "len", arity: 1, function: len }),
+    (Type::Range, Builtin { name: "len", arity: 1, function: len }),
+    (Type::Map, Builtin { name: "len", arity: 1, function: len }),
+    (Type::Map, Builtin { name: "keys", arity: 1, function: keys }),
+    (Type::Str, Builtin { name: "len", arity: 1, function: len }),
+    (Type::Str, Builtin { name: "upper", arity: 1, function: upper }),
+    (Type::Str, Builtin { name: "lower", arity: 1, function: lower }),
+    (Type::Str, Builtin { name: "trim", arity: 1, function: trim }),
+    (Type::Str, Builtin { name: "chars", arity: 1, function: chars }),
+    (Type::Str, Builtin { name: "split", arity: 2, function: split }),
+    (Type::Str, Builtin { name: "contains", arity: 2, function: contains_part }),
+    (Type::Str, Builtin { name: "replace", arity: 3, function: replace }),
+    (Type::Rational, Builtin { name: "numerator", arity: 1, function: numerator }),
+    (Type::Rational, Builtin { name: "denominator", arity: 1, function: denominator }),
+];
+
+// The number of elements in an array or tuple, entries in a map, ints in a
+// range or characters in a string
+fn len(_: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let length = match &arguments[0] {
+        Value::Array(elements) => elements.borrow().len(),
+        Value::Tuple(elements) => elements.len(),
+        Value::Map(entries) => entries.borrow().len(),
+        Value::Range(range) => range.len(),
+        Value::Str(value) => value.chars().count(),
+        _ => return Err(RuntimeError::TypeMismatch { span }),
+    };
+    i64::try_from(length).map(Value::Int).map_err(|_| RuntimeError::Overflow { span })
+}
+
+// Rounds toward zero, and only gives back a big int when the config says to
+// promote ints that are too large
+fn int(evaluator: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    match &arguments[0] {
+        value @ (Value::Int(_) | Value::BigInt(_)) => Ok(value.clone()),
+        Value::Rational(value) => Ok(Value::Int(value.truncate())),
+        Value::Float(value) => {
+            let value = value.trunc();
+            let limit = 2_f64.powi(63);
+            if (-limit..limit).contains(&value) {
+                Ok(Value::Int(value as i64))
+            } else if value.is_finite() && evaluator.config.overflow == OverflowMode::Promote {
+                Ok(Value::BigInt(BigInt::parse(&format!("{:.0}", value)).unwrap()))
+            } else {
+                Err(RuntimeError::Overflow { span })
+            }
+        }
+        _ => Err(RuntimeError::TypeMismatch { span }),
+    }
+}
+
+fn float(_: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    to_float(&arguments[0]).map(Value::Float).ok_or(RuntimeError::TypeMismatch { span })
+}
+
+// The keys of a map as an array, in the same order the map prints them in
+fn keys(evaluator: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let Value::Map(entries) = &arguments[0] else {
+        return Err(RuntimeError::TypeMismatch { span });
+    };
+    let mut keys = entries.borrow().keys().cloned().collect::<Vec<_>>();
+    keys.sort();
+    let keys = keys.into_iter().map(|key| match key {
+        Key::Int(key) => Value::Int(key),
+        Key::Str(key) => Value::Str(key),
+    });
+    Ok(evaluator.share(Value::array(keys.collect())))
+}
+
+// The denominator is always positive, so the sign is on the numerator
+fn numerator(_: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    match &arguments[0] {
+        Value::Rational(value) => Ok(Value::Int(value.numerator())),
+        _ => Err(RuntimeError::TypeMismatch { span }),
+    }
+}
+
+fn denominator(_: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    match &arguments[0] {
+        Value::Rational(value) => Ok(Value::Int(value.denominator())),
+        _ => Err(RuntimeError::TypeMismatch { span }),
+    }
+}
+
+fn upper(_: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    match &arguments[0] {
+        Value::Str(value) => Ok(Value::Str(value.to_uppercase())),
+        _ => Err(RuntimeError::TypeMismatch { span }),
+    }
+}
+
+fn lower(_: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    match &arguments[0] {
+        Value::Str(value) => Ok(Value::Str(value.to_lowercase())),
+        _ => Err(RuntimeError::TypeMismatch { span }),
+    }
+}
+
+// Without whitespace at either end, going by what Unicode counts as
+// whitespace
+fn trim(_: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    match &arguments[0] {
+        Value::Str(value) => Ok(Value::Str(value.trim().to_string())),
+        _ => Err(RuntimeError::TypeMismatch { span }),
+    }
+}
+
+fn chars(evaluator: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    match &arguments[0] {
+        Value::Str(value) => Ok(evaluator.share(Value::array(value.chars().map(Value::Char).collect()))),
+        _ => Err(RuntimeError::TypeMismatch { span }),
+    }
+}
+
+// The parts between each occurrence of the separator, which can be a string
+// or a char. An empty separator splits the string into its characters.
+fn split(evaluator: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let Value::Str(value) = &arguments[0] else {
+        return Err(RuntimeError::TypeMismatch { span });
+    };
+    let parts = match &arguments[1] {
+        Value::Str(separator) if separator.is_empty() => value.chars().map(String::from).collect::<Vec<_>>(),
+        Value::Str(separator) => value.split(separator.as_str()).map(str::to_string).collect(),
+        Value::Char(separator) => value.split(*separator).map(str::to_string).collect(),
+        _ => return Err(RuntimeError::TypeMismatch { span }),
+    };
+    Ok(evaluator.share(Value::array(parts.into_iter().map(Value::Str).collect())))
+}
+
+// The elements written out the same as by interpolation, with the separator
+// between each of them
+fn join(_: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let (Value::Array(elements), Value::Str(separator)) = (&arguments[0], &arguments[1]) else {
+        return Err(RuntimeError::TypeMismatch { span });
+    };
+    let parts = elements.borrow().iter().map(Value::to_string).collect::<Vec<_>>();
+    Ok(Value::Str(parts.join(separator)))
+}
+
+// Whether the string has the string or char somewhere in it
+fn contains_part(_: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    match (&arguments[0], &arguments[1]) {
+        (Value::Str(value), Value::Str(part)) => Ok(Value::Bool(value.contains(part.as_str()))),
+        (Value::Str(value), Value::Char(part)) => Ok(Value::Bool(value.contains(*part))),
+        _ => Err(RuntimeError::TypeMismatch { span }),
+    }
+}
+
+// Every occurrence of the string or char replaced with the other string
+fn replace(_: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    match (&arguments[0], &arguments[1], &arguments[2]) {
+        (Value::Str(value), Value::Str(from), Value::Str(to)) => Ok(Value::Str(value.replace(from.as_str(), to))),
+        (Value::Str(value), Value::Char(from), Value::Str(to)) => Ok(Value::Str(value.replace(*from, to))),
+        _ => Err(RuntimeError::TypeMismatch { span }),
+    }
+}
+
+// The elements of the array a method was called on, copied so that the
+// function it was given can change the array without changing what the
+// method goes through
+fn elements_and_function(arguments: &[Value], span: Span) -> Result<(Vec<Value>, Callable), RuntimeError> {
+    match (&arguments[0], &arguments[1]) {
+        (Value::Array(elements), Value::Function(function)) => Ok((elements.borrow().clone(), function.clone())),
+        _ => Err(RuntimeError::TypeMismatch { span }),
+    }
+}
+
+// A new array of what the function gives back for each element
+fn map(evaluator: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let (elements, function) = elements_and_function(&arguments, span)?;
+    let mapped = elements.into_iter()
+        .map(|element| evaluator.invoke(&function, vec![element], span))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(evaluator.share(Value::array(mapped)))
+}
+
+// A new array of the elements the function gives back true for. It has to
+// give back a bool.
+fn filter(evaluator: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let (elements, function) = elements_and_function(&arguments, span)?;
+    let mut kept = Vec::new();
+    for element in elements {
+        match evaluator.invoke(&function, vec![element.clone()], span)? {
+            Value::Bool(true) => kept.push(element),
+            Value::Bool(false) => {}
+            _ => return Err(RuntimeError::TypeMismatch { span }),
+        }
+    }
+    Ok(evaluator.share(Value::array(kept)))
+}
+
+// Starts from the initial value and calls the function with what it has so
+// far and the next element, giving back what that makes once every element
+// has been through it
+fn reduce(evaluator: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let (elements, function) = elements_and_function(&arguments, span)?;
+    elements.into_iter().try_fold(arguments[2].clone(), |accumulator, element| {
+        evaluator.invoke(&function, vec![accumulator, element], span)
+    })
+}
+
+// A new array in the order `<` puts the elements in, keeping equal ones in
+// the order they were in. Every element has to be comparable with the others.
+fn sort(evaluator: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let Value::Array(elements) = &arguments[0] else {
+        return Err(RuntimeError::TypeMismatch { span });
+    };
+    let mut sorted = elements.borrow().clone();
+    let mut comparable = true;
+    sorted.sort_by(|a, b| match (compare(&BinaryOperator::Less, a, b), compare(&BinaryOperator::Less, b, a)) {
+        (Some(true), _) => cmp::Ordering::Less,
+        (_, Some(true)) => cmp::Ordering::Greater,
+        (Some(false), Some(false)) => cmp::Ordering::Equal,
+        _ => {
+            comparable = false;
+            cmp::Ordering::Equal
+        }
+    });
+    if !comparable {
+        return Err(RuntimeError::TypeMismatch { span });
+    }
+    Ok(evaluator.share(Value::array(sorted)))
+}
+
+// A new array with the elements the other way round
+fn reverse(evaluator: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let Value::Array(elements) = &arguments[0] else {
+        return Err(RuntimeError::TypeMismatch { span });
+    };
+    let reversed = elements.borrow().iter().rev().cloned().collect();
+    Ok(evaluator.share(Value::array(reversed)))
+}
+
+// Adds the value to the end of the array itself, which every variable holding
+// the array sees
+fn push(_: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let Value::Array(elements) = &arguments[0] else {
+        return Err(RuntimeError::TypeMismatch { span });
+    };
+    elements.borrow_mut().push(arguments[1].clone());
+    Ok(Value::Nil)
+}
+
+// Takes the last element off the array itself, giving back nil if it's empty
+fn pop(_: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let Value::Array(elements) = &arguments[0] else {
+        return Err(RuntimeError::TypeMismatch { span });
+    };
+    let last = elements.borrow_mut().pop();
+    Ok(last.unwrap_or(Value::Nil))
+}
+
+// The same as `value in array`
+fn contains_element(_: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    match &arguments[0] {
+        Value::Array(elements) => Ok(Value::Bool(elements.borrow().contains(&arguments[1]))),
+        _ => Err(RuntimeError::TypeMismatch { span }),
+    }
+}
+
+// Writes the value the way it's displayed, flushing straight away so that
+// what's written without a line break still shows up before anything else.
+// Given more than one value, it writes what `format` would make of them.
+fn print(evaluator: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let text = printed(&arguments, span)?;
+    write!(evaluator.output, "{}", text)
+        .and_then(|_| evaluator.output.flush())
+        .map_err(|error| RuntimeError::OutputFailed { message: error.to_string(), span })?;
+    Ok(Value::Nil)
+}
+
+// Leaves out the line break at the end, giving back nil once there's nothing
+// left to read
+fn read_line(evaluator: &mut Evaluator, _: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let mut line = String::new();
+    let read = evaluator.input.read_line(&mut line)
+        .map_err(|error| RuntimeError::InputFailed { message: error.to_string(), span })?;
+    if read == 0 {
+        return Ok(Value::Nil);
+    }
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+    Ok(Value::Str(line))
+}
+
+// Nil when the string isn't a whole number, so `parse_int(s) ?? 0` gives a
+// default. Whitespace around the number is fine.
+fn parse_int(_: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    match &arguments[0] {
+        Value::Str(value) => Ok(value.trim().parse().map_or(Value::Nil, Value::Int)),
+        _ => Err(RuntimeError::TypeMismatch { span }),
+    }
+}
+
+// The whole file as a string. The path is relative to the interpreter's
+// directory, the same as `import` paths.
+fn read_file(evaluator: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let Value::Str(path) = &arguments[0] else {
+        return Err(RuntimeError::TypeMismatch { span });
+    };
+    fs::read_to_string(evaluator.directory.join(path))
+        .map(Value::Str)
+        .map_err(|error| RuntimeError::FileFailed { path: path.clone(), kind: error.kind(), span })
+}
+
+// Makes the file hold just the string, creating it if it isn't there
+fn write_file(evaluator: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let (Value::Str(path), Value::Str(contents)) = (&arguments[0], &arguments[1]) else {
+        return Err(RuntimeError::TypeMismatch { span });
+    };
+    fs::write(evaluator.directory.join(path), contents)
+        .map_err(|error| RuntimeError::FileFailed { path: path.clone(), kind: error.kind(), span })?;
+    Ok(Value::Nil)
+}
+
+// How many milliseconds have passed since the interpreter was made, as a
+// float. Only the difference between two of them means anything.
+fn now(evaluator: &mut Evaluator, _: Vec<Value>, _: Span) -> Result<Value, RuntimeError> {
+    Ok(Value::Float(evaluator.milliseconds()))
+}
+
+// How many milliseconds have passed since `now` gave `start`
+fn elapsed(evaluator: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let Value::Float(start) = arguments[0] else {
+        return Err(RuntimeError::TypeMismatch { span });
+    };
+    Ok(Value::Float(evaluator.milliseconds() - start))
+}
+
+// Waits for an int number of milliseconds, or none if it's negative. A
+// sleep that would go past the interpreter's deadline wakes up when it
+// passes and stops there.
+fn sleep(evaluator: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let Value::Int(milliseconds) = arguments[0] else {
+        return Err(RuntimeError::TypeMismatch { span });
+    };
+    let duration = Duration::from_millis(u64::try_from(milliseconds).unwrap_or(0));
+    match evaluator.deadline {
+        Some(deadline) if Instant::now() + duration >= deadline => {
+            thread::sleep(deadline.saturating_duration_since(Instant::now()));
+            Err(RuntimeError::TimedOut { span })
+        }
+        _ => {
+            thread::sleep(duration);
+            Ok(Value::Nil)
+        }
+    }
+}
+
+// A float that's at least 0 and less than 1
+fn random(evaluator: &mut Evaluator, _: Vec<Value>, _: Span) -> Result<Value, RuntimeError> {
+    Ok(Value::Float(evaluator.rng.next_f64()))
+}
+
+// An int that's at least `low` and less than `high`, the same as the range
+// `low..high`
+fn random_int(evaluator: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let [Value::Int(low), Value::Int(high)] = arguments[..] else {
+        return Err(RuntimeError::TypeMismatch { span });
+    };
+    if low >= high {
+        return Err(RuntimeError::EmptyRange { low, high, span });
+    }
+    Ok(Value::Int(evaluator.rng.next_in(low, high)))
+}
+
+fn assert(_: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    match arguments[0] {
+        Value::Bool(true) => Ok(Value::Nil),
+        Value::Bool(false) => Err(RuntimeError::AssertionFailed { span }),
+        _ => Err(RuntimeError::NonBooleanCondition { span }),
+    }
+}
+
+// Equal the same way `==` finds them, `__eq__` methods included
+fn assert_eq(evaluator: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let [left, right] = <[Value; 2]>::try_from(arguments).map_err(|_| RuntimeError::TypeMismatch { span })?;
+    let equal = evaluator.operate(&BinaryOperator::EqualEqual, left.clone(), right.clone(), span, span, span);
+    match equal.map_err(|unwind| unwind.into_error(span))? {
+        Value::Bool(true) => Ok(Value::Nil),
+        Value::Bool(false) => Err(RuntimeError::NotEqual { left: Box::new(left), right: Box::new(right), span }),
+        _ => Err(RuntimeError::TypeMismatch { span }),
+    }
+}
+
+fn println(evaluator: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let text = printed(&arguments, span)?;
+    writeln!(evaluator.output, "{}", text)
+        .and_then(|_| evaluator.output.flush())
+        .map_err(|error| RuntimeError::OutputFailed { message: error.to_string(), span })?;
+    Ok(Value::Nil)
+}
+
+// A value on its own is printed as it is, even a string with braces in it
+fn printed(arguments: &[Value], span: Span) -> Result<String, RuntimeError> {
+    match arguments {
+        [value] => Ok(value.to_string()),
+        _ => formatted(arguments, span),
+    }
+}
+
+// A format string followed by the values that go in its placeholders
+fn formatted(arguments: &[Value], span: Span) -> Result<String, RuntimeError> {
+    match arguments {
+        [Value::Str(template), values @ ..] => format::fill(template, values).map_err(|error| error.at(span)),
+        [] => Err(RuntimeError::TypeMismatch { span }),
+        _ => Err(RuntimeError::InvalidFormat { message: "the first argument has to be a format string".to_string(), span }),
+    }
+}
+
+fn format(_: &mut Evaluator, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    formatted(&arguments, span).map(Value::Str)
+}
+
+impl Callable {
+    // A function written in Rust that checks it's called with `arity`
+    // arguments before running
+    pub fn native(name: &str, arity: usize, function: impl Fn(&[Value]) -> Result<Value, RuntimeError> + 'static) -> Callable {
+        Callable(Rc::new(Routine::Native(Native { name: name.to_string(), arity, function: Box::new(function) })))
+    }
+
+    pub fn compiled(function: compile::Function) -> Callable {
+        Callable(Rc::new(Routine::Compiled(function)))
+    }
+
+    pub fn as_compiled(&self) -> Option<&compile::Function> {
+        match &*self.0 {
+            Routine::Compiled(function) => Some(function),
+            _ => None,
+        }
+    }
+
+    // The same address the heap sees
+    pub fn address(&self) -> usize {
+        Rc::as_ptr(&self.0) as *const () as usize
+    }
+
+    // The fewest arguments it can be called with, which is the only number
+    // it can be called with unless it's variadic
+    fn arity(&self) -> usize {
+        match &*self.0 {
+            Routine::Closure(closure) => closure.parameters.len(),
+            Routine::Builtin(builtin) | Routine::Variadic(builtin) => builtin.arity,
+            Routine::Native(native) => native.arity,
+            Routine::Constructor(constructor) => constructor.arity,
+            Routine::Compiled(function) => function.arity,
+        }
+    }
+
+    fn accepts(&self, count: usize) -> bool {
+        match &*self.0 {
+            Routine::Variadic(builtin) => count >= builtin.arity,
+            _ => count == self.arity(),
+        }
+    }
+}
+
+impl PartialEq for Callable {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Display for Callable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &*self.0 {
+            Routine::Closure(Closure { name: Some(name), .. }) => write!(f, "<fn {}>", name),
+            Routine::Closure(Closure { name: None, .. }) => f.write_str("<fn>"),
+            Routine::Builtin(builtin) | Routine::Variadic(builtin) => write!(f, "<fn {}>", builtin.name),
+            Routine::Native(native) => write!(f, "<fn {}>", native.name),
+            Routine::Constructor(constructor) => write!(f, "<fn {}.{}>", constructor.enum_name, constructor.variant),
+            Routine::Compiled(compile::Function { name: Some(name), .. }) => write!(f, "<fn {}>", name),
+            Routine::Compiled(compile::Function { name: None, .. }) => f.write_str("<fn>"),
+        }
+    }
+}
+
+// The environment may well contain the function itself, so this can't show
+// any more than the name without going round in circles
+impl Debug for Callable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+// The variables of the globals, of one function call or of one block, along
+// with the environment around it for looking up everything else: the one a
+// function was made in, or the one a block is in. A named function is stored
+// in the environment it keeps alive, so it's up to the heap to free the two.
+#[derive(Default)]
+struct Environment {
+    variables: RefCell<HashMap<String, Value>>,
+    parent: Option<Rc<Environment>>,
+}
+
+impl Environment {
+    fn get(&self, name: &str) -> Option<Value> {
+        match self.variables.borrow().get(name) {
+            Some(value) => Some(value.clone()),
+            None => self.parent.as_ref()?.get(name),
+        }
+    }
+
+    // Changes the innermost variable called `name`, giving back whether there
+    // was one
+    fn assign(&self, name: &str, value: Value) -> bool {
+        match self.variables.borrow_mut().get_mut(name) {
+            Some(variable) => {
+                *variable = value;
+                true
+            }
+            None => self.parent.as_ref().is_some_and(|parent| parent.assign(name, value)),
+        }
+    }
+
+    fn define(&self, name: String, value: Value) -> Option<Value> {
+        self.variables.borrow_mut().insert(name, value)
+    }
+}
+
+// Emptying an environment leaves its parent, since that's never part of the
+// cycle it's in
+impl Trace for Environment {
+    fn trace(&self, visit: &mut dyn FnMut(usize)) -> bool {
+        if let Some(parent) = &self.parent {
+            visit(Rc::as_ptr(parent) as *const () as usize);
+        }
+        self.variables.trace(visit)
+    }
+
+    fn clear(&self) {
+        self.variables.clear();
+    }
+}
+
+// A closure can only be part of a cycle through the environment it holds,
+// which is where the cycle is broken instead
+impl Trace for Routine {
+    fn trace(&self, visit: &mut dyn FnMut(usize)) -> bool {
+        if let Routine::Closure(closure) = self {
+            visit(Rc::as_ptr(&closure.environment) as *const () as usize);
+        }
+        true
+    }
+
+    fn clear(&self) {}
+}
+
+// Every error has the span of the node it came from, which for an operator is
+// the whole operation and for a builtin is the call it was made by
+#[derive(Debug)]
+pub enum RuntimeError {
+    // The span is that of the divisor
+    DivisionByZero { span: Span },
+    NegativeExponent { span: Span },
+    Overflow { span: Span },
+    TypeMismatch { span: Span },
+    // A binary operator doesn't take operands of these types. The span is
+    // that of the operator.
+    TypeError { operator: BinaryOperator, left: Type, right: Type, span: Span },
+    // A builtin was given an argument of a type it doesn't take, where it
+    // only takes what `expected` describes
+    ArgumentType { function: &'static str, expected: &'static str, found: Type, span: Span },
+    // Nothing called `name` is in scope. The span is that of the use of the
+    // name, or of the whole declaration using it.
+    UndefinedVariable { name: String, span: Span },
+    // The span is that of the condition
+    NonBooleanCondition { span: Span },
+    // A call's callee is a name with nothing called that in scope. The span
+    // is that of the name.
+    UndefinedFunction { name: String, span: Span },
+    // The span is that of the callee
+    NotCallable { span: Span },
+    ArityMismatch { expected: usize, found: usize, span: Span },
+    // The span is that of the index or key expression, or of the whole
+    // `tuple.position`
+    IndexOutOfBounds { index: i64, length: usize, span: Span },
+    NonIntegerIndex { span: Span },
+    MissingKey { key: Key, span: Span },
+    InvalidKey { span: Span },
+    // `random_int` was given bounds with no int between them. The span is
+    // that of the call.
+    EmptyRange { low: i64, high: i64, span: Span },
+    // `assert` was given false, or `assert_eq` two values that aren't equal.
+    // The span is that of the call.
+    AssertionFailed { span: Span },
+    NotEqual { left: Box<Value>, right: Box<Value>, span: Span },
+    // The format string given to `format` or `print` doesn't fit the values
+    // given with it, for the reason in `message`. The span is that of the
+    // call.
+    InvalidFormat { message: String, span: Span },
+    // The span is that of the whole `match`
+    NoMatchingArm { span: Span },
+    // The span is that of the struct literal or the whole `target.field`
+    UnknownField { name: String, span: Span },
+    MissingField { name: String, span: Span },
+    // The span is that of the whole `Enum.Variant`
+    UnknownVariant { name: String, span: Span },
+    // The receiver's type has no method with the name. The span is that of
+    // the whole `receiver.method`.
+    UnknownMethod { name: String, span: Span },
+    // An `impl` of a trait is missing one of the trait's methods. The span is
+    // that of the whole `impl`.
+    MissingMethod { name: String, span: Span },
+    // Nil was negated or inverted. The span is that of the whole operation.
+    // Nil with a binary operator other than `==`, `!=` and `in` is a
+    // `TypeError` like any other type it doesn't take.
+    NilOperand { span: Span },
+    // The value in a `let` doesn't have the shape of its pattern. The span is
+    // that of the pattern.
+    PatternMismatch { span: Span },
+    // A `break` or `continue` outside a loop, a `return` outside a function
+    // or a spread anywhere but an array literal or a call's arguments. The
+    // parser doesn't accept any of them, so only a syntax tree made some other
+    // way can have one. The span is that of the statement or spread.
+    Misplaced { construct: &'static str, allowed: &'static str, span: Span },
+    // What the VM was given to run needs something only the evaluator can do,
+    // such as a loop over an iterator
+    Unsupported { construct: &'static str, span: Span },
+    // The path is the one `import` was given, and the span is that of the
+    // whole `import`
+    ModuleNotFound { path: String, span: Span },
+    // The module, or one it imports, imports a module that's still being run.
+    // The cycle is the canonical path of every module in it, starting and
+    // ending with the one imported again, and the span is that of the
+    // `import` closing it.
+    ImportCycle { cycle: Vec<PathBuf>, span: Span },
+    // The module couldn't be lexed or parsed, for the reason in `message`
+    InvalidModule { path: String, message: String, span: Span },
+    // A call would have made more than `limit` calls run at once. The span
+    // is that of the call.
+    RecursionLimitExceeded { limit: usize, span: Span },
+    // Writing to the output failed, for the reason in `message`
+    OutputFailed { message: String, span: Span },
+    // Reading the input failed, for the reason in `message`
+    InputFailed { message: String, span: Span },
+    // Reading or writing the file at `path` failed, for the reason in
+    // `kind`. That's kept rather than the whole message so that this error is
+    // no bigger than the others.
+    FileFailed { path: String, kind: io::ErrorKind, span: Span },
+    // Evaluating the expression or running the statement at the span would
+    // have taken more than the `limit` steps the config allows
+    BudgetExhausted { limit: u64, span: Span },
+    // The flag given to `Interpreter::set_cancel` was set. The span is that
+    // of whatever was about to be evaluated when it was noticed.
+    Cancelled { span: Span },
+    // The deadline given to `Interpreter::set_deadline` passed, with the span
+    // of whatever was about to be evaluated when it was noticed
+    TimedOut { span: Span },
+}
+
+impl RuntimeError {
+    pub fn span(&self) -> Span {
+        match self {
+            RuntimeError::DivisionByZero { span }
+            | RuntimeError::NegativeExponent { span }
+            | RuntimeError::Overflow { span }
+            | RuntimeError::TypeMismatch { span }
+            | RuntimeError::TypeError { span, .. }
+            | RuntimeError::ArgumentType { span, .. }
+            | RuntimeError::UndefinedVariable { span, .. }
+            | RuntimeError::NonBooleanCondition { span }
+            | RuntimeError::UndefinedFunction { span, .. }
+            | RuntimeError::NotCallable { span }
+            | RuntimeError::ArityMismatch { span, .. }
+            | RuntimeError::IndexOutOfBounds { span, .. }
+            | RuntimeError::NonIntegerIndex { span }
+            | RuntimeError::MissingKey { span, .. }
+            | RuntimeError::InvalidKey { span }
+            | RuntimeError::EmptyRange { span, .. }
+            | RuntimeError::AssertionFailed { span }
+            | RuntimeError::NotEqual { span, .. }
+            | RuntimeError::InvalidFormat { span, .. }
+            | RuntimeError::NoMatchingArm { span }
+            | RuntimeError::UnknownField { span, .. }
+            | RuntimeError::MissingField { span, .. }
+            | RuntimeError::UnknownVariant { span, .. }
+            | RuntimeError::UnknownMethod { span, .. }
+            | RuntimeError::MissingMethod { span, .. }
+            | RuntimeError::NilOperand { span }
+            | RuntimeError::PatternMismatch { span }
+            | RuntimeError::Misplaced { span, .. }
+            | RuntimeError::Unsupported { span, .. }
+            | RuntimeError::ModuleNotFound { span, .. }
+            | RuntimeError::ImportCycle { span, .. }
+            | RuntimeError::InvalidModule { span, .. }
+            | RuntimeError::RecursionLimitExceeded { span, .. }
+            | RuntimeError::OutputFailed { span, .. }
+            | RuntimeError::InputFailed { span, .. }
+            | RuntimeError::FileFailed { span, .. }
+            | RuntimeError::BudgetExhausted { span, .. }
+            | RuntimeError::Cancelled { span }
+            | RuntimeError::TimedOut { span } => *span,
+        }
+    }
+
+    fn span_mut(&mut self) -> &mut Span {
+        match self {
+            RuntimeError::DivisionByZero { span }
+            | RuntimeError::NegativeExponent { span }
+            | RuntimeError::Overflow { span }
+            | RuntimeError::TypeMismatch { span }
+            | RuntimeError::TypeError { span, .. }
+            | RuntimeError::ArgumentType { span, .. }
+            | RuntimeError::UndefinedVariable { span, .. }
+            | RuntimeError::NonBooleanCondition { span }
+            | RuntimeError::UndefinedFunction { span, .. }
+            | RuntimeError::NotCallable { span }
+            | RuntimeError::ArityMismatch { span, .. }
+            | RuntimeError::IndexOutOfBounds { span, .. }
+            | RuntimeError::NonIntegerIndex { span }
+            | RuntimeError::MissingKey { span, .. }
+            | RuntimeError::InvalidKey { span }
+            | RuntimeError::EmptyRange { span, .. }
+            | RuntimeError::AssertionFailed { span }
+            | RuntimeError::NotEqual { span, .. }
+            | RuntimeError::InvalidFormat { span, .. }
+            | RuntimeError::NoMatchingArm { span }
+            | RuntimeError::UnknownField { span, .. }
+            | RuntimeError::MissingField { span, .. }
+            | RuntimeError::UnknownVariant { span, .. }
+            | RuntimeError::UnknownMethod { span, .. }
+            | RuntimeError::MissingMethod { span, .. }
+            | RuntimeError::NilOperand { span }
+            | RuntimeError::PatternMismatch { span }
+            | RuntimeError::Misplaced { span, .. }
+            | RuntimeError::Unsupported { span, .. }
+            | RuntimeError::ModuleNotFound { span, .. }
+            | RuntimeError::ImportCycle { span, .. }
+            | RuntimeError::InvalidModule { span, .. }
+            | RuntimeError::RecursionLimitExceeded { span, .. }
+            | RuntimeError::OutputFailed { span, .. }
+            | RuntimeError::InputFailed { span, .. }
+            | RuntimeError::FileFailed { span, .. }
+            | RuntimeError::BudgetExhausted { span, .. }
+            | RuntimeError::Cancelled { span }
+            | RuntimeError::TimedOut { span } => span,
+        }
+    }
+
+    // The same error, put down to `span` instead
+    fn at(mut self, span: Span) -> RuntimeError {
+        *self.span_mut() = span;
+        self
+    }
+
+    // What went wrong, without where
+    pub fn message(&self) -> String {
+        match self {
+            RuntimeError::DivisionByZero { .. } => "Division by zero".to_string(),
+            RuntimeError::NegativeExponent { .. } => "An int can't be raised to a negative power".to_string(),
+            RuntimeError::Overflow { .. } => "The result is too large for an int".to_string(),
+            RuntimeError::TypeMismatch { .. } => "A value has the wrong type for what it's used for".to_string(),
+            RuntimeError::TypeError { operator, left, right, .. } => {
+                format!("Can't apply '{}' to {} and {}", operator, left, right)
+            }
+            RuntimeError::ArgumentType { function, expected, found, .. } => {
+                format!("Can't pass {} to '{}', which takes {}", found, function, expected)
+            }
+            RuntimeError::UndefinedVariable { name, .. } => format!("Undefined variable '{}'", name),
+            RuntimeError::NonBooleanCondition { .. } => "A condition has to be a bool".to_string(),
+            RuntimeError::UndefinedFunction { name, .. } => format!("Undefined function '{}'", name),
+            RuntimeError::NotCallable { .. } => "Only functions can be called".to_string(),
+            RuntimeError::ArityMismatch { expected, found, .. } => {
+                format!("Expected {} arguments but was given {}", expected, found)
+            }
+            RuntimeError::IndexOutOfBounds { index, length, .. } => {
+                format!("Index {} is out of bounds for a length of {}", index, length)
+            }
+            RuntimeError::NonIntegerIndex { .. } => "An index has to be an int".to_string(),
+            RuntimeError::MissingKey { key, .. } => format!("The map has no key {}", key),
+            RuntimeError::InvalidKey { .. } => "A map key has to be an int or a string".to_string(),
+            RuntimeError::EmptyRange { low, high, .. } => format!("There's no int from {} up to {}", low, high),
+            RuntimeError::AssertionFailed { .. } => "Assertion failed".to_string(),
+            RuntimeError::NotEqual { left, right, .. } => format!("Assertion failed: {} != {}", left, right),
+            RuntimeError::InvalidFormat { message, .. } => format!("The format string can't be used: {}", message),
+            RuntimeError::NoMatchingArm { .. } => "No arm of the match matches the value".to_string(),
+            RuntimeError::UnknownField { name, .. } => format!("There is no field '{}'", name),
+            RuntimeError::MissingField { name, .. } => format!("Field '{}' isn't given", name),
+            RuntimeError::UnknownVariant { name, .. } => format!("There is no variant '{}'", name),
+            RuntimeError::UnknownMethod { name, .. } => format!("There is no method '{}'", name),
+            RuntimeError::MissingMethod { name, .. } => format!("Method '{}' of the trait isn't given", name),
+            RuntimeError::NilOperand { .. } => "Nil can only be compared with '==' and '!='".to_string(),
+            RuntimeError::PatternMismatch { .. } => "The value doesn't match the pattern".to_string(),
+            RuntimeError::Misplaced { construct, allowed, .. } => format!("{} can only be used {}", construct, allowed),
+            RuntimeError::Unsupported { construct, .. } => format!("The VM can't run {}", construct),
+            RuntimeError::ModuleNotFound { path, .. } => format!("There is no module at '{}'", path),
+            RuntimeError::ImportCycle { cycle, .. } => {
+                let cycle = cycle.iter().map(|path| path.display().to_string()).collect::<Vec<_>>();
+                format!("Modules import each other in a cycle: {}", cycle.join(" -> "))
+            }
+            RuntimeError::InvalidModule { path, message, .. } => format!("Module '{}' is invalid: {}", path, message),
+            RuntimeError::RecursionLimitExceeded { limit, .. } => {
+                format!("Recursion went more than {} calls deep", limit)
+            }
+            RuntimeError::OutputFailed { message, .. } => format!("Couldn't write the output: {}", message),
+            RuntimeError::InputFailed { message, .. } => format!("Couldn't read the input: {}", message),
+            RuntimeError::FileFailed { path, kind, .. } => format!("Couldn't use the file '{}': {}", path, kind),
+            RuntimeError::BudgetExhausted { limit, .. } => format!("Ran out of steps after {}", limit),
+            RuntimeError::Cancelled { .. } => "The evaluation was cancelled".to_string(),
+            RuntimeError::TimedOut { .. } => "The evaluation ran past its deadline".to_string(),
+        }
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let span = self.span();
+        write!(f, "{} at line {}, character {}", self.message(), span.line, span.character)
+    }
+}
+
+impl Error for RuntimeError {}
+
+// Whatever stops source code from giving back a value, from scanning it to
+// running it
+#[derive(Debug)]
+pub enum EvalError {
+    Lexer(LexerError),
+    Parse(ParseError),
+    Runtime(RuntimeError),
+    Compile(CompileError),
+}
+
+impl Display for EvalError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EvalError::Lexer(error) => Display::fmt(error, f),
+            EvalError::Parse(error) => Display::fmt(error, f),
+            EvalError::Runtime(error) => Display::fmt(error, f),
+            EvalError::Compile(error) => Display::fmt(error, f),
+        }
+    }
+}
+
+impl Error for EvalError {}
+
+impl From<LexerError> for EvalError {
+    fn from(error: LexerError) -> Self {
+        EvalError::Lexer(error)
+    }
+}
+
+impl From<ParseError> for EvalError {
+    fn from(error: ParseError) -> Self {
+        EvalError::Parse(error)
+    }
+}
+
+impl From<RuntimeError> for EvalError {
+    fn from(error: RuntimeError) -> Self {
+        EvalError::Runtime(error)
+    }
+}
+
+impl From<CompileError> for EvalError {
+    fn from(error: CompileError) -> Self {
+        EvalError::Compile(error)
+    }
+}
+
+// Whatever stops the evaluator from carrying on with the next node. Only
+// errors get out of it, since the parser makes sure every `break` and
+// `continue` is inside a loop to catch it and every `return` is inside a
+// function.
+#[derive(Debug)]
+enum Unwind {
+    Error(RuntimeError),
+    // These have the span of the statement, for when there's no loop for
+    // them to get out of
+    Break(Span),
+    Continue(Span),
+    Return(Value),
+    // A call in tail position, which the call of the function it's in makes
+    // in its place once its environment is gone. The span is that of the call.
+    TailCall(Callable, Vec<Value>, Span),
+}
+
+impl From<RuntimeError> for Unwind {
+    fn from(error: RuntimeError) -> Self {
+        Unwind::Error(error)
+    }
+}
+
+impl Unwind {
+    // A `return` that gets this far wasn't in a function, and is put down to
+    // `span`, that of whatever it came out of
+    fn into_error(self, span: Span) -> RuntimeError {
+        match self {
+            Unwind::Error(error) => error,
+            Unwind::Break(span) => misplaced("'break'", "inside a loop", span),
+            Unwind::Continue(span) => misplaced("'continue'", "inside a loop", span),
+            Unwind::Return(_) | Unwind::TailCall(..) => misplaced("'return'", "inside a function", span),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct EvalConfig {
+    pub overflow: OverflowMode,
+    // How many calls of functions written in rat can be running at once
+    // before the evaluator gives up instead of overflowing the stack. The
+    // default fits in a main thread's stack even in debug builds.
+    pub max_call_depth: usize,
+    // How many expressions and statements can be evaluated before the
+    // evaluator gives up, so that something untrusted can't run forever.
+    // Every `evaluate` or `execute` starts out with all of them. There's no
+    // limit by default.
+    pub fuel: Option<u64>,
+    // Whether programs get `read_file` and `write_file`, which they don't
+    // by default
+    pub allow_fs: bool,
+    // Whether programs get `sleep`, which they don't by default either
+    pub allow_sleep: bool,
+}
+
+impl Default for EvalConfig {
+    fn default() -> Self {
+        EvalConfig {
+            overflow: OverflowMode::default(),
+            max_call_depth: 200,
+            fuel: None,
+            allow_fs: false,
+            allow_sleep: false,
+        }
+    }
+}
+
+// What int arithmetic does when its result is too large for an int. Only
+// `+`, `-`, `*`, `/`, `**` and negation can overflow.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowMode {
+    // Stops with `RuntimeError::Overflow`
+    #[default]
+    Checked,
+    // Wraps around past the other end, the same as two's complement
+    Wrapping,
+    // Stays at the largest or smallest int
+    Saturating,
+    // Carries on with an int of any size, which goes back to being an i64
+    // once a result fits in one again
+    Promote,
+}
+
+impl OverflowMode {
+    fn apply(
+        self,
+        (left, right): (i64, i64),
+        checked: fn(i64, i64) -> Option<i64>,
+        wrapping: fn(i64, i64) -> i64,
+        saturating: fn(i64, i64) -> i64,
+        span: Span,
+    ) -> Result<i64, RuntimeError> {
+        match self {
+            // `integer` redoes an operation that overflowed with big ints
+            // when it's promoting
+            OverflowMode::Checked | OverflowMode::Promote => checked(left, right).ok_or(RuntimeError::Overflow { span }),
+            OverflowMode::Wrapping => Ok(wrapping(left, right)),
+            OverflowMode::Saturating => Ok(saturating(left, right)),
+        }
+    }
+}
+
+// What's left to do in a chain of binary operators: evaluating an operand, or
+// applying an operator to the two values on top of the stack. The expression
+// of an operator nested in the chain is kept for telling the observer.
+enum Work<'a> {
+    Evaluate(&'a Expression),
+    Operate(&'a Binary, Option<&'a Expression>),
+}
+
+// Told about every expression the interpreter evaluates, for debuggers,
+// profilers and the like. An expression that doesn't give a value, because
+// of an error or because it breaks out of a loop or returns, is entered but
+// never exited.
+pub trait EvalObserver {
+    fn on_enter_node(&mut self, _expression: &Expression) {}
+    fn on_exit_node(&mut self, _expression: &Expression, _value: &Value) {}
+}
+
+// Code can see the variables of the environment it is running in and of
+// every environment that one was made in, but not those of whatever called it
+struct Evaluator {
+    config: EvalConfig,
+    // Where `print` and `println` write to
+    output: Box<dyn Write>,
+    // Where `read_line` reads from
+    input: Box<dyn BufRead>,
+    // How many calls of closures are running
+    depth: usize,
+    // How many more steps can be taken, if there's a limit
+    fuel: Option<u64>,
+    // How many steps have been taken in this run
+    steps: u64,
+    // What another thread can set to stop the evaluation, and when it has to
+    // stop by
+    cancel: Option<Arc<AtomicBool>>,
+    deadline: Option<Instant>,
+    // What `now` counts from
+    started: Instant,
+    // Where `random` and `random_int` get their numbers
+    rng: Rng,
+    observer: Option<Box<dyn EvalObserver>>,
+    // The values of constant arithmetic, by the id of its operator, while
+    // evaluating a `Memoized`
+    memo: Option<HashMap<NodeId, Value>>,
+    environment: Rc<Environment>,
+    methods: HashMap<Type, HashMap<&'static str, Builtin>>,
+    // The methods `impl` gave structs and enums, by the name of the type
+    impls: HashMap<String, HashMap<String, Callable>>,
+    // The method signatures of every trait declared, by the trait's name
+    traits: HashMap<String, Vec<Signature>>,
+    // Where the file being run is, which `import` paths are relative to
+    directory: PathBuf,
+    // Every module imported so far, and the ones still being run, by their
+    // canonical path. The file being run, if it's known, is the first of
+    // those still being run.
+    modules: HashMap<PathBuf, Value>,
+    loading: Vec<PathBuf>,
+    // The functions registered by the program embedding rat, which modules
+    // get along with the builtins
+    natives: Vec<(String, Callable)>,
+    // Everything made while running that could end up in a cycle
+    heap: Heap,
+}
+
+// The builtins are globals like any other, so they can be shadowed. Methods
+// aren't variables at all, so nothing can shadow them.
+impl Default for Evaluator {
+    fn default() -> Evaluator {
+        let mut methods = HashMap::<_, HashMap<_, _>>::new();
+        for (r#type, method) in METHODS {
+            methods.entry(r#type).or_default().insert(method.name, method);
+        }
+        Evaluator {
+            config: EvalConfig::default(),
+            output: Box::new(io::stdout()),
+            input: Box::new(BufReader::new(io::stdin())),
+            depth: 0,
+            fuel: None,
+            steps: 0,
+            cancel: None,
+            deadline: None,
+            started: Instant::now(),
+            rng: Rng::from_time(),
+            observer: None,
+            memo: None,
+            environment: Rc::new(builtins(&[], &EvalConfig::default())),
+            methods,
+            impls: HashMap::new(),
+            traits: HashMap::new(),
+            directory: PathBuf::from("."),
+            modules: HashMap::new(),
+            loading: Vec::new(),
+            natives: Vec::new(),
+            heap: Heap::default(),
+        }
+    }
+}
+
+// Whatever the evaluator was keeping alive is let go of first, so that only
+// what's still reachable from outside it survives the last collection
+impl Drop for Evaluator {
+    fn drop(&mut self) {
+        self.environment = Rc::default();
+        self.impls.clear();
+        self.modules.clear();
+        self.natives.clear();
+        self.heap.collect();
+    }
+}
+
+// The builtins that need a capability are only there when the config gives
+// it, so that nothing can get at them otherwise
+fn builtins(natives: &[(String, Callable)], config: &EvalConfig) -> Environment {
+    let globals = Environment::default();
+    let files = FS_BUILTINS.iter().filter(|_| config.allow_fs);
+    let sleep = iter::once(&SLEEP).filter(|_| config.allow_sleep);
+    for &builtin in BUILTINS.iter().chain(files).chain(sleep) {
+        globals.define(builtin.name.to_string(), Value::Function(Callable(Rc::new(Routine::Builtin(builtin)))));
+    }
+    for builtin in VARIADIC_BUILTINS {
+        globals.define(builtin.name.to_string(), Value::Function(Callable(Rc::new(Routine::Variadic(builtin)))));
+    }
+    globals.define("math".to_string(), math::module());
+    for (name, native) in natives {
+        globals.define(name.clone(), Value::Function(native.clone()));
+    }
+    globals
+}
+
+impl Visitor<Result<Value, Unwind>> for Evaluator {
+    fn visit_expression(&mut self, expression: &Expression) -> Result<Value, Unwind> {
+        self.step(expression.span())?;
+        if self.observer.is_some() {
+            return self.observed(expression);
+        }
+        walk_expression(self, expression)
+    }
+
+    fn visit_binary(&mut self, binary: &Binary) -> Result<Value, Unwind> {
+        let nested = matches!(*binary.left, Expression::Binary(_)) || matches!(*binary.right, Expression::Binary(_));
+        if nested || self.memo.is_some() {
+            return self.chain(binary);
+        }
+        let left = self.visit_expression(&binary.left)?;
+        let right = self.visit_expression(&binary.right)?;
+        self.operate(&binary.operator, left, right, binary.span, binary.operator_span, binary.right.span())
+    }
+
+    fn visit_unary(&mut self, unary: &Unary) -> Result<Value, Unwind> {
+        let right = self.visit_expression(&unary.right)?;
+        let name = match unary.operator {
+            UnaryOperator::Minus => "__neg__",
+            UnaryOperator::Tilde => "__invert__",
+        };
+        if let Some(method) = self.user_method(&right, name) {
+            return self.call(&method, vec![right], unary.span);
+        }
+        Ok(negate(&unary.operator, right, self.config.overflow, unary.span)?)
+    }
+
+    fn visit_integer(&mut self, integer: &Integer) -> Result<Value, Unwind> {
+        Ok(Value::Int(integer.value))
+    }
+
+    fn visit_float(&mut self, float: &Float) -> Result<Value, Unwind> {
+        Ok(Value::Float(float.value))
+    }
+
+    fn visit_char(&mut self, char: &Char) -> Result<Value, Unwind> {
+        Ok(Value::Char(char.value))
+    }
+
+    fn visit_str(&mut self, str: &Str) -> Result<Value, Unwind> {
+        Ok(Value::Str(str.value.clone()))
+    }
+
+    fn visit_bool(&mut self, bool: &Bool) -> Result<Value, Unwind> {
+        Ok(Value::Bool(bool.value))
+    }
+
+    fn visit_nil(&mut self, _: &Nil) -> Result<Value, Unwind> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_interpolation(&mut self, interpolation: &Interpolation) -> Result<Value, Unwind> {
+        let mut result = String::new();
+        for part in &interpolation.parts {
+            result.push_str(&self.visit_expression(part)?.to_string());
+        }
+        Ok(Value::Str(result))
+    }
+
+    fn visit_variable(&mut self, variable: &Variable) -> Result<Value, Unwind> {
+        self.environment.get(&variable.name).ok_or_else(|| undefined(&variable.name, variable.span).into())
+    }
+
+    fn visit_block(&mut self, block: &Block) -> Result<Value, Unwind> {
+        self.block(block, false)
+    }
+
+    fn visit_if(&mut self, r#if: &If) -> Result<Value, Unwind> {
+        self.r#if(r#if, false)
+    }
+
+    fn visit_conditional(&mut self, conditional: &Conditional) -> Result<Value, Unwind> {
+        self.conditional(conditional, false)
+    }
+
+    fn visit_coalesce(&mut self, coalesce: &Coalesce) -> Result<Value, Unwind> {
+        self.coalesce(coalesce, false)
+    }
+
+    // A `break`, `continue` or `return` in the body carries on past the `try`
+    fn visit_try(&mut self, r#try: &Try) -> Result<Value, Unwind> {
+        match self.visit_expression(&r#try.body) {
+            Err(Unwind::Error(error)) if catchable(&error) => {
+                let variables = HashMap::from([(r#try.name.clone(), Value::Str(error.message()))]);
+                self.scoped(variables, |evaluator| evaluator.visit_expression(&r#try.handler))
+            }
+            result => result,
+        }
+    }
+
+    fn visit_match(&mut self, r#match: &Match) -> Result<Value, Unwind> {
+        self.r#match(r#match, false)
+    }
+
+    fn visit_range(&mut self, range: &Range) -> Result<Value, Unwind> {
+        let bounds = (self.visit_expression(&range.start)?, self.visit_expression(&range.end)?);
+        let (Value::Int(start), Value::Int(end)) = bounds else {
+            return Err(RuntimeError::TypeMismatch { span: range.span }.into());
+        };
+        Ok(Value::Range(RangeValue { start, end, inclusive: range.inclusive }))
+    }
+
+    // A name that isn't defined at all gets its own error, rather than the
+    // one for reading an undefined variable. Calling a field of anything but
+    // an enum or a struct with that field calls a method of its type instead.
+    fn visit_call(&mut self, call: &Call) -> Result<Value, Unwind> {
+        let (function, arguments) = self.callee(call)?;
+        self.call(&function, arguments, call.span)
+    }
+
+    fn visit_lambda(&mut self, lambda: &Lambda) -> Result<Value, Unwind> {
+        Ok(Value::Function(self.closure(None, &lambda.parameters, &lambda.body)))
+    }
+
+    fn visit_array(&mut self, array: &Array) -> Result<Value, Unwind> {
+        let elements = self.elements(&array.elements)?;
+        Ok(self.share(Value::array(elements)))
+    }
+
+    // A key that comes up more than once ends up with the last value given
+    fn visit_map(&mut self, map: &Map) -> Result<Value, Unwind> {
+        let mut entries = HashMap::new();
+        for (key, value) in &map.entries {
+            let key = Key::new(self.visit_expression(key)?, key.span())?;
+            entries.insert(key, self.visit_expression(value)?);
+        }
+        Ok(self.share(Value::map(entries)))
+    }
+
+    fn visit_tuple(&mut self, tuple: &Tuple) -> Result<Value, Unwind> {
+        let elements = tuple.elements.iter()
+            .map(|element| self.visit_expression(element))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Value::Tuple(elements))
+    }
+
+    fn visit_tuple_index(&mut self, index: &TupleIndex) -> Result<Value, Unwind> {
+        let Value::Tuple(mut elements) = self.visit_expression(&index.tuple)? else {
+            return Err(RuntimeError::TypeMismatch { span: index.tuple.span() }.into());
+        };
+        if index.position >= elements.len() {
+            let (length, span) = (elements.len(), index.span);
+            return Err(RuntimeError::IndexOutOfBounds { index: index.position as i64, length, span }.into());
+        }
+        Ok(elements.swap_remove(index.position))
+    }
+
+    // Every field has to be given, in any order. A field that comes up more
+    // than once ends up with the last value given.
+    fn visit_struct_literal(&mut self, literal: &StructLiteral) -> Result<Value, Unwind> {
+        let span = literal.span;
+        let r#type = self.environment.get(&literal.name).ok_or_else(|| undefined(&literal.name, span))?;
+        let Value::StructType(r#type) = r#type else {
+            return Err(RuntimeError::TypeMismatch { span }.into());
+        };
+
+        let mut values = vec![None; r#type.fields.len()];
+        for (name, value) in &literal.fields {
+            let Some(position) = r#type.fields.iter().position(|field| field == name) else {
+                return Err(RuntimeError::UnknownField { name: name.clone(), span: literal.span }.into());
+            };
+            values[position] = Some(self.visit_expression(value)?);
+        }
+
+        let fields = r#type.fields.into_iter().zip(values)
+            .map(|(name, value)| match value {
+                Some(value) => Ok((name, value)),
+                None => Err(RuntimeError::MissingField { name, span: literal.span }),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(self.share(Value::instance(Instance { name: r#type.name, fields })))
+    }
+
+    // The fields of an instance, or the variants of an enum
+    fn visit_field(&mut self, field: &Field) -> Result<Value, Unwind> {
+        let target = self.visit_expression(&field.target)?;
+        self.member(field, target)
+    }
+
+    // Array literals and calls take their spreads apart themselves
+    fn visit_spread(&mut self, spread: &Spread) -> Result<Value, Unwind> {
+        Err(misplaced("A spread", "in an array literal or the arguments of a call", spread.span).into())
+    }
+
+    // Indexing an array with a range gives a new array of the elements the
+    // range counts through
+    fn visit_index(&mut self, index: &Index) -> Result<Value, Unwind> {
+        let target = self.visit_expression(&index.target)?;
+        let position = self.visit_expression(&index.index)?;
+        let slice = matches!((&target, &position), (Value::Array(_), Value::Range(_)));
+        let value = subscript(&target, position, index.index.span())?;
+        Ok(if slice { self.share(value) } else { value })
+    }
+
+    // Arrays and maps are references, so the element is changed in the array
+    // or map itself, and everything else referring to it sees the change.
+    // Assigning to a key that isn't in a map adds it, unless the assignment
+    // needs the value that was already there.
+    fn visit_index_assign(&mut self, assign: &IndexAssign) -> Result<Value, Unwind> {
+        let target = self.visit_expression(&assign.target)?;
+        let (position, span) = (self.visit_expression(&assign.index)?, assign.index.span());
+        let mut value = self.visit_expression(&assign.value)?;
+        if let Some(operator) = &assign.operator {
+            let current = element(&target, position.clone(), span, |element| element.clone())?;
+            value = self.operate(operator, current, value, assign.span, assign.span, assign.value.span())?;
+        }
+        insert(&target, position, span, value.clone())?;
+        Ok(value)
+    }
+
+    // Only variables that have already been declared can be assigned to
+    fn visit_assign(&mut self, assign: &Assign) -> Result<Value, Unwind> {
+        let value = self.visit_expression(&assign.value)?;
+        if self.environment.assign(&assign.name, value.clone()) {
+            Ok(value)
+        } else {
+            Err(undefined(&assign.name, assign.span).into())
+        }
+    }
+}
+
+// Statements give back the value they produced, if any
+impl StatementVisitor<Result<Option<Value>, Unwind>> for Evaluator {
+    fn visit_statement(&mut self, statement: &Statement) -> Result<Option<Value>, Unwind> {
+        self.step(statement.span())?;
+        walk_statement(self, statement)
+    }
+
+    fn visit_expression_statement(&mut self, statement: &ExpressionStatement) -> Result<Option<Value>, Unwind> {
+        self.visit_expression(&statement.expression).map(Some)
+    }
+
+    fn visit_let(&mut self, declaration: &Let) -> Result<Option<Value>, Unwind> {
+        let value = self.visit_expression(&declaration.initializer)?;
+        self.environment.define(declaration.name.clone(), value);
+        Ok(None)
+    }
+
+    fn visit_destructure(&mut self, declaration: &Destructure) -> Result<Option<Value>, Unwind> {
+        let value = self.visit_expression(&declaration.initializer)?;
+        let Some(variables) = self.bindings(&declaration.pattern, &value)? else {
+            return Err(RuntimeError::PatternMismatch { span: declaration.pattern.span() }.into());
+        };
+        for (name, value) in variables {
+            self.environment.define(name, value);
+        }
+        Ok(None)
+    }
+
+    fn visit_while(&mut self, r#while: &While) -> Result<Option<Value>, Unwind> {
+        while self.condition(&r#while.condition)? {
+            if !self.loop_body(&r#while.body)? {
+                break;
+            }
+        }
+        Ok(None)
+    }
+
+    // The range is evaluated once, before the first iteration. Every
+    // iteration has a scope of its own with the loop variable in it, so a
+    // closure made in one keeps the value it had then.
+    // A range gives its ints in order, an array or tuple its elements and a
+    // string its chars. An array is looked at once, so what the body does
+    // to it doesn't change what the loop goes over. Anything else is an
+    // iterator, which gives one value each time it's asked for the next and
+    // nil once there are no more, so it can go on forever: a function called
+    // without arguments, or a struct instance or enum value with a `next`
+    // method. Nothing is asked for until the loop is ready for it.
+    fn visit_for(&mut self, r#for: &For) -> Result<Option<Value>, Unwind> {
+        match self.visit_expression(&r#for.iterable)? {
+            Value::Range(range) => self.each(r#for, range.values().map(Value::Int))?,
+            Value::Array(elements) => {
+                let elements = elements.borrow().clone();
+                self.each(r#for, elements)?
+            }
+            Value::Tuple(elements) => self.each(r#for, elements)?,
+            Value::Str(string) => self.each(r#for, string.chars().map(Value::Char))?,
+            iterator => self.iterate(r#for, iterator)?,
+        }
+        Ok(None)
+    }
+
+    fn visit_break(&mut self, r#break: &Break) -> Result<Option<Value>, Unwind> {
+        Err(Unwind::Break(r#break.span))
+    }
+
+    fn visit_continue(&mut self, r#continue: &Continue) -> Result<Option<Value>, Unwind> {
+        Err(Unwind::Continue(r#continue.span))
+    }
+
+    // The function is defined in the environment it captures, so it can call
+    // itself
+    fn visit_function(&mut self, function: &Function) -> Result<Option<Value>, Unwind> {
+        let value = self.closure(Some(&function.name), &function.parameters, &function.body);
+        self.environment.define(function.name.clone(), Value::Function(value));
+        Ok(None)
+    }
+
+    // Whatever is returned is in tail position, wherever the `return` is
+    fn visit_return(&mut self, r#return: &Return) -> Result<Option<Value>, Unwind> {
+        let value = match &r#return.value {
+            Some(value) => self.tail(value)?,
+            None => Value::Nil,
+        };
+        Err(Unwind::Return(value))
+    }
+
+    fn visit_struct(&mut self, r#struct: &Struct) -> Result<Option<Value>, Unwind> {
+        let r#type = StructType { name: r#struct.name.clone(), fields: r#struct.fields.clone() };
+        self.environment.define(r#struct.name.clone(), Value::StructType(r#type));
+        Ok(None)
+    }
+
+    fn visit_enum(&mut self, r#enum: &Enum) -> Result<Option<Value>, Unwind> {
+        let variants = r#enum.variants.iter()
+            .map(|variant| {
+                let (enum_name, name) = (r#enum.name.clone(), variant.name.clone());
+                let value = match variant.fields.len() {
+                    0 => Value::Enum(EnumValue { enum_name, variant: name, values: Vec::new() }),
+                    arity => {
+                        let constructor = Constructor { enum_name, variant: name, arity };
+                        Value::Function(Callable(Rc::new(Routine::Constructor(constructor))))
+                    }
+                };
+                (variant.name.clone(), value)
+            })
+            .collect();
+        self.environment.define(r#enum.name.clone(), Value::EnumType(EnumType { name: r#enum.name.clone(), variants }));
+        Ok(None)
+    }
+
+    // The methods are closures over the environment the `impl` is in, the
+    // same as functions declared there. A method given again replaces the
+    // one the type already had.
+    fn visit_impl(&mut self, r#impl: &Impl) -> Result<Option<Value>, Unwind> {
+        match self.environment.get(&r#impl.name) {
+            Some(Value::StructType(_) | Value::EnumType(_)) => {}
+            Some(_) => return Err(RuntimeError::TypeMismatch { span: r#impl.span }.into()),
+            None => return Err(undefined(&r#impl.name, r#impl.span).into()),
+        }
+        if let Some(r#trait) = &r#impl.r#trait {
+            self.check_trait(r#trait, r#impl)?;
+        }
+        for method in &r#impl.methods {
+            let name = format!("{}.{}", r#impl.name, method.name);
+            let closure = self.closure(Some(&name), &method.parameters, &method.body);
+            self.impls.entry(r#impl.name.clone()).or_default().insert(method.name.clone(), closure);
+        }
+        Ok(None)
+    }
+
+    // A module is only run the first time it's imported. Importing it again
+    // binds the same module without running anything.
+    fn visit_import(&mut self, import: &Import) -> Result<Option<Value>, Unwind> {
+        let (path, span) = (self.directory.join(&import.path), import.span);
+        let Ok(path) = path.canonicalize() else {
+            return Err(RuntimeError::ModuleNotFound { path: import.path.clone(), span }.into());
+        };
+        if let Some(start) = self.loading.iter().position(|loading| *loading == path) {
+            let cycle = self.loading[start..].iter().cloned().chain([path]).collect();
+            return Err(RuntimeError::ImportCycle { cycle, span }.into());
+        }
+        let module = match self.modules.get(&path) {
+            Some(module) => module.clone(),
+            None => {
+                let module = self.load(import, &path)?;
+                self.modules.insert(path, module.clone());
+                module
+            }
+        };
+        self.environment.define(import.name.clone(), module);
+        Ok(None)
+    }
+
+    // Every use of a macro has already been replaced by `expand_macros`, so
+    // declaring one does nothing
+    fn visit_macro(&mut self, _: &Macro) -> Result<Option<Value>, Unwind> {
+        Ok(None)
+    }
+
+    // Traits live apart from variables, like the methods of an `impl`, and a
+    // trait declared again replaces the old one for the `impl`s after it
+    fn visit_trait(&mut self, r#trait: &Trait) -> Result<Option<Value>, Unwind> {
+        self.traits.insert(r#trait.name.clone(), r#trait.methods.clone());
+        Ok(None)
+    }
+}
+
+impl Evaluator {
+    // Runs the module at `path` with globals of its own, which only have the
+    // builtins in them to begin with. What the `impl`s and traits in it
+    // declare isn't kept to the module, the same as everywhere else.
+    fn load(&mut self, import: &Import, path: &Path) -> Result<Value, Unwind> {
+        let invalid = |message: String| RuntimeError::InvalidModule { path: import.path.clone(), message, span: import.span };
+        let source = fs::read_to_string(path).map_err(|error| invalid(error.to_string()))?;
+        let tokens = get_tokens(&source).map_err(|error| invalid(error.to_string()))?;
+        let program = get_program(tokens).map_err(|error| invalid(error.to_string()))?;
+
+        let globals = self.environment(HashMap::new(), Rc::new(builtins(&self.natives, &self.config)));
+        let environment = mem::replace(&mut self.environment, Rc::clone(&globals));
+        let directory = mem::replace(&mut self.directory, path.parent().unwrap_or(Path::new(".")).to_path_buf());
+        self.loading.push(path.to_path_buf());
+        let result = program.statements.iter().try_for_each(|statement| self.visit_statement(statement).map(drop));
+        self.loading.pop();
+        self.directory = directory;
+        self.environment = environment;
+        result?;
+
+        let mut members = globals.variables.borrow().iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect::<Vec<_>>();
+        members.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(Value::Module(Module { name: import.name.clone(), members }))
+    }
+
+    // An `impl` of a trait has to give exactly the methods the trait declares,
+    // each with as many parameters as its signature
+    fn check_trait(&self, r#trait: &str, r#impl: &Impl) -> Result<(), Unwind> {
+        let signatures = self.traits.get(r#trait).ok_or_else(|| undefined(r#trait, r#impl.span))?;
+        for signature in signatures {
+            let Some(method) = r#impl.methods.iter().find(|method| method.name == signature.name) else {
+                return Err(RuntimeError::MissingMethod { name: signature.name.clone(), span: r#impl.span }.into());
+            };
+            if method.parameters.len() != signature.parameters.len() {
+                let (expected, found) = (signature.parameters.len(), method.parameters.len());
+                return Err(RuntimeError::ArityMismatch { expected, found, span: method.span }.into());
+            }
+        }
+        match r#impl.methods.iter().find(|method| signatures.iter().all(|signature| signature.name != method.name)) {
+            Some(method) => Err(RuntimeError::UnknownMethod { name: method.name.clone(), span: method.span }.into()),
+            None => Ok(()),
+        }
+    }
+
+    // The field of an instance or the variant of an enum that `field` names
+    fn member(&self, field: &Field, target: Value) -> Result<Value, Unwind> {
+        let (name, span) = (field.field.clone(), field.span);
+        let (members, error) = match target {
+            Value::Struct(instance) => (instance.borrow().fields.clone(), RuntimeError::UnknownField { name, span }),
+            Value::EnumType(r#type) => (r#type.variants, RuntimeError::UnknownVariant { name, span }),
+            Value::Module(module) => (module.members, RuntimeError::UnknownField { name, span }),
+            _ => return Err(RuntimeError::TypeMismatch { span }.into()),
+        };
+        members.into_iter()
+            .find_map(|(name, value)| (name == field.field).then_some(value))
+            .ok_or(error.into())
+    }
+
+    // The function a call calls and the arguments it calls it with
+    fn callee(&mut self, call: &Call) -> Result<(Callable, Vec<Value>), Unwind> {
+        let callee = match &*call.callee {
+            Expression::Variable(variable) if self.environment.get(&variable.name).is_none() => {
+                return Err(RuntimeError::UndefinedFunction { name: variable.name.clone(), span: variable.span }.into());
+            }
+            Expression::Field(field) => match self.visit_expression(&field.target)? {
+                target @ (Value::EnumType(_) | Value::Module(_)) => self.member(field, target)?,
+                Value::Struct(instance) if instance.borrow().fields.iter().any(|(name, _)| *name == field.field) => {
+                    self.member(field, Value::Struct(instance))?
+                }
+                receiver => return self.method(field, receiver, &call.arguments, call.span),
+            },
+            callee => self.visit_expression(callee)?,
+        };
+        let function = match callee {
+            Value::Function(function) if function.as_compiled().is_none() => function,
+            _ => return Err(RuntimeError::NotCallable { span: call.callee.span() }.into()),
+        };
+        Ok((function, self.elements(&call.arguments)?))
+    }
+
+    // The methods an `impl` gave the receiver's type come before the builtin
+    // ones. The receiver goes in front of the arguments, but isn't counted by
+    // the arity an arity mismatch reports.
+    fn method(
+        &mut self,
+        method: &Field,
+        receiver: Value,
+        arguments: &[Expression],
+        span: Span,
+    ) -> Result<(Callable, Vec<Value>), Unwind> {
+        let function = match self.user_method(&receiver, &method.field) {
+            Some(function) => function,
+            None => {
+                let builtin = self.methods.get(&receiver.type_of()).and_then(|methods| methods.get(method.field.as_str()));
+                let Some(&builtin) = builtin else {
+                    return Err(RuntimeError::UnknownMethod { name: method.field.clone(), span: method.span }.into());
+                };
+                Callable(Rc::new(Routine::Builtin(builtin)))
+            }
+        };
+        let arguments = iter::once(receiver).chain(self.elements(arguments)?).collect::<Vec<_>>();
+        if !function.accepts(arguments.len()) {
+            let (expected, found) = (function.arity() - 1, arguments.len() - 1);
+            return Err(RuntimeError::ArityMismatch { expected, found, span }.into());
+        }
+        Ok((function, arguments))
+    }
+
+    fn closure(&mut self, name: Option<&str>, parameters: &[String], body: &Expression) -> Callable {
+        let routine = Rc::new(Routine::Closure(Closure {
+            name: name.map(str::to_string),
+            parameters: parameters.to_vec(),
+            body: body.clone(),
+            environment: Rc::clone(&self.environment),
+        }));
+        self.heap.track_object(&routine);
+        Callable(routine)
+    }
+
+    // Calls a function for a builtin, which only errors can get out of
+    fn invoke(&mut self, function: &Callable, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+        if function.as_compiled().is_some() {
+            return Err(RuntimeError::NotCallable { span });
+        }
+        self.call(function, arguments, span).map_err(|unwind| unwind.into_error(span))
+    }
+
+    // Keeps track of an array, map or struct instance that was just made
+    fn share(&mut self, value: Value) -> Value {
+        self.heap.track(&value);
+        value
+    }
+
+    // An environment inside `parent` with `variables` in it
+    fn environment(&mut self, variables: HashMap<String, Value>, parent: Rc<Environment>) -> Rc<Environment> {
+        let environment = Rc::new(Environment { variables: RefCell::new(variables), parent: Some(parent) });
+        self.heap.track_object(&environment);
+        environment
+    }
+
+    // The method an `impl` gave the type of a struct instance or an enum value
+    fn user_method(&self, receiver: &Value, name: &str) -> Option<Callable> {
+        let type_name = match receiver {
+            Value::Struct(instance) => instance.borrow().name.clone(),
+            Value::Enum(value) => value.enum_name.clone(),
+            _ => return None,
+        };
+        self.impls.get(&type_name)?.get(name).cloned()
+    }
+
+    // A struct or enum value on the left of an operator gets its method for
+    // the operator called instead, if its type has one. `!=` uses the method
+    // for `==`, which has to give back a bool. `span` is that of the whole
+    // operation, `operator_span` that of just the operator and `divisor` that
+    // of the right operand.
+    fn operate(
+        &mut self,
+        operator: &BinaryOperator,
+        left: Value,
+        right: Value,
+        span: Span,
+        operator_span: Span,
+        divisor: Span,
+    ) -> Result<Value, Unwind> {
+        let method = operator_method(operator).and_then(|name| self.user_method(&left, name));
+        let Some(method) = method else {
+            return Ok(apply(operator, left, right, self.config.overflow, span, operator_span, divisor)?);
+        };
+        match (operator, self.call(&method, vec![left, right], span)?) {
+            (BinaryOperator::BangEqual, Value::Bool(equal)) => Ok(Value::Bool(!equal)),
+            (BinaryOperator::BangEqual, _) => Err(RuntimeError::TypeMismatch { span }.into()),
+            (_, result) => Ok(result),
+        }
+    }
+
+    // The arguments are bound to the parameters in a fresh environment inside
+    // the one the function was made in, and the caller's environment is put
+    // back once the body has been evaluated. The span is that of whatever made
+    // the call, which errors from a builtin are put down to.
+    //
+    // A call in tail position in the body is made here once the body is done,
+    // rather than inside it, so that a function recursing in tail position
+    // runs in constant space and never reaches the limit on call depth.
+    fn call(&mut self, function: &Callable, arguments: Vec<Value>, span: Span) -> Result<Value, Unwind> {
+        let (mut function, mut arguments, mut span) = (function.clone(), arguments, span);
+        loop {
+            if !function.accepts(arguments.len()) {
+                let (expected, found) = (function.arity(), arguments.len());
+                return Err(RuntimeError::ArityMismatch { expected, found, span }.into());
+            }
+            let Routine::Closure(closure) = &*function.0 else {
+                return self.call_routine(&function.0, arguments, span);
+            };
+
+            if self.depth == self.config.max_call_depth {
+                return Err(RuntimeError::RecursionLimitExceeded { limit: self.config.max_call_depth, span }.into());
+            }
+
+            let variables = closure.parameters.iter().cloned().zip(arguments).collect();
+            let environment = self.environment(variables, Rc::clone(&closure.environment));
+            let caller = mem::replace(&mut self.environment, environment);
+            self.depth += 1;
+            let result = self.tail(&closure.body);
+            self.depth -= 1;
+            self.environment = caller;
+            match result {
+                Err(Unwind::Return(value)) => return Ok(value),
+                Err(Unwind::TailCall(callee, values, call)) => (function, arguments, span) = (callee, values, call),
+                result => return result,
+            }
+        }
+    }
+
+    // Calls anything but a closure, which `call` runs itself. This is kept out
+    // of `call` so that its frame stays small for deep recursion. Only the VM
+    // can run a compiled function, so the evaluator can't call one it's given.
+    fn call_routine(&mut self, routine: &Routine, arguments: Vec<Value>, span: Span) -> Result<Value, Unwind> {
+        match routine {
+            Routine::Builtin(builtin) | Routine::Variadic(builtin) => Ok((builtin.function)(self, arguments, span)?),
+            Routine::Native(native) => Ok((native.function)(&arguments).map_err(|error| error.at(span))?),
+            Routine::Constructor(constructor) => {
+                let (enum_name, variant) = (constructor.enum_name.clone(), constructor.variant.clone());
+                Ok(Value::Enum(EnumValue { enum_name, variant, values: arguments }))
+            }
+            Routine::Closure(_) | Routine::Compiled(_) => Err(RuntimeError::NotCallable { span }.into()),
+        }
+    }
+
+    // Evaluates an expression in tail position, whose value is what the
+    // function it's in gives back, so a call there can be left to `call`.
+    // Whichever part of a block, `if`, conditional, `??` or `match` it gives
+    // the value of is in tail position too.
+    //
+    // With an observer, nothing is left to `call`, so that it sees every call
+    // exit with its value.
+    fn tail(&mut self, expression: &Expression) -> Result<Value, Unwind> {
+        if self.observer.is_some() {
+            return self.visit_expression(expression);
+        }
+        match expression {
+            Expression::Call(call) => {
+                let (function, arguments) = self.callee(call)?;
+                Err(Unwind::TailCall(function, arguments, call.span))
+            }
+            Expression::Block(block) => self.block(block, true),
+            Expression::If(r#if) => self.r#if(r#if, true),
+            Expression::Conditional(conditional) => self.conditional(conditional, true),
+            Expression::Coalesce(coalesce) => self.coalesce(coalesce, true),
+            Expression::Match(r#match) => self.r#match(r#match, true),
+            expression => self.visit_expression(expression),
+        }
+    }
+
+    fn value(&mut self, expression: &Expression, tail: bool) -> Result<Value, Unwind> {
+        if tail {
+            self.tail(expression)
+        } else {
+            self.visit_expression(expression)
+        }
+    }
+
+    // What a block declares is gone once it's done, and shadows what's
+    // declared outside it until then
+    fn block(&mut self, block: &Block, tail: bool) -> Result<Value, Unwind> {
+        self.scoped(HashMap::new(), |evaluator| {
+            for statement in &block.statements {
+                evaluator.visit_statement(statement)?;
+            }
+            match &block.value {
+                Some(value) => evaluator.value(value, tail),
+                None => Ok(Value::Nil),
+            }
+        })
+    }
+
+    fn r#if(&mut self, r#if: &If, tail: bool) -> Result<Value, Unwind> {
+        if self.condition(&r#if.condition)? {
+            self.value(&r#if.then_branch, tail)
+        } else {
+            match &r#if.else_branch {
+                Some(branch) => self.value(branch, tail),
+                None => Ok(Value::Nil),
+            }
+        }
+    }
+
+    fn conditional(&mut self, conditional: &Conditional, tail: bool) -> Result<Value, Unwind> {
+        if self.condition(&conditional.condition)? {
+            self.value(&conditional.then_branch, tail)
+        } else {
+            self.value(&conditional.else_branch, tail)
+        }
+    }
+
+    fn coalesce(&mut self, coalesce: &Coalesce, tail: bool) -> Result<Value, Unwind> {
+        match self.visit_expression(&coalesce.value)? {
+            Value::Nil => self.value(&coalesce.fallback, tail),
+            value => Ok(value),
+        }
+    }
+
+    // The variables bound by an arm's pattern are only visible to its guard
+    // and body
+    fn r#match(&mut self, r#match: &Match, tail: bool) -> Result<Value, Unwind> {
+        let value = self.visit_expression(&r#match.scrutinee)?;
+        for arm in &r#match.arms {
+            let Some(variables) = self.bindings(&arm.pattern, &value)? else {
+                continue;
+            };
+
+            if let Some(value) = self.scoped(variables, |evaluator| evaluator.arm(arm, tail))? {
+                return Ok(value);
+            }
+        }
+        Err(RuntimeError::NoMatchingArm { span: r#match.span }.into())
+    }
+
+    // Runs `run` in a new scope inside the current one, starting out with
+    // `variables` in it, and goes back to the current one afterwards
+    fn scoped<T>(&mut self, variables: HashMap<String, Value>, run: impl FnOnce(&mut Self) -> T) -> T {
+        let scope = self.environment(variables, Rc::clone(&self.environment));
+        let outer = mem::replace(&mut self.environment, scope);
+        let result = run(self);
+        self.environment = outer;
+        result
+    }
+
+    // Runs the body of a `for` loop with each of `values` until it breaks
+    fn each(&mut self, r#for: &For, values: impl IntoIterator<Item = Value>) -> Result<(), Unwind> {
+        for value in values {
+            let variables = HashMap::from([(r#for.variable.clone(), value)]);
+            if !self.scoped(variables, |evaluator| evaluator.loop_body(&r#for.body))? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // Runs the body of a `for` loop with each value `iterator` gives
+    fn iterate(&mut self, r#for: &For, iterator: Value) -> Result<(), Unwind> {
+        let span = r#for.iterable.span();
+        let (next, arguments) = match &iterator {
+            Value::Function(function) if function.as_compiled().is_none() => (function.clone(), vec![]),
+            _ => match self.user_method(&iterator, "next") {
+                Some(method) => (method, vec![iterator]),
+                None => return Err(RuntimeError::TypeMismatch { span }.into()),
+            },
+        };
+
+        loop {
+            let value = self.call(&next, arguments.clone(), span)?;
+            if value == Value::Nil {
+                return Ok(());
+            }
+            let variables = HashMap::from([(r#for.variable.clone(), value)]);
+            if !self.scoped(variables, |evaluator| evaluator.loop_body(&r#for.body))? {
+                return Ok(());
+            }
+        }
+    }
+
+    // Runs one iteration of a loop, giving back whether the loop should go
+    // on to the next one
+    fn loop_body(&mut self, body: &Expression) -> Result<bool, Unwind> {
+        match self.visit_expression(body) {
+            Ok(_) | Err(Unwind::Continue(_)) => Ok(true),
+            Err(Unwind::Break(_)) => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+
+    // The variables a pattern binds, if `value` matches it. A variant binds
+    // whatever the patterns of its fields do.
+    fn bindings(&mut self, pattern: &Pattern, value: &Value) -> Result<Option<HashMap<String, Value>>, Unwind> {
+        Ok(match pattern {
+            Pattern::Wildcard(_) => Some(HashMap::new()),
+            Pattern::Binding(name, _) => Some(HashMap::from([(name.clone(), value.clone())])),
+            Pattern::Literal(literal) => (self.visit_expression(literal)? == *value).then(HashMap::new),
+            Pattern::Variant(pattern) => {
+                let Value::Enum(value) = value else {
+                    return Ok(None);
+                };
+                if value.enum_name != pattern.enum_name
+                    || value.variant != pattern.variant
+                    || value.values.len() != pattern.fields.len()
+                {
+                    return Ok(None);
+                }
+                self.all_bindings(&pattern.fields, &value.values)?
+            }
+            Pattern::Tuple(pattern) => match value {
+                Value::Tuple(values) if values.len() == pattern.elements.len() => {
+                    self.all_bindings(&pattern.elements, values)?
+                }
+                _ => None,
+            },
+            Pattern::Array(pattern) => {
+                let Value::Array(values) = value else {
+                    return Ok(None);
+                };
+                let values = values.borrow().clone();
+                let count = pattern.elements.len();
+                if values.len() < count || pattern.rest.is_none() && values.len() > count {
+                    return Ok(None);
+                }
+
+                let mut variables = self.all_bindings(&pattern.elements, &values[..count])?;
+                if let (Some(variables), Some(rest)) = (&mut variables, &pattern.rest) {
+                    let rest_values = self.share(Value::array(values[count..].to_vec()));
+                    if let Some(bound) = self.bindings(rest, &rest_values)? {
+                        variables.extend(bound);
+                    }
+                }
+                variables
+            }
+        })
+    }
+
+    // The values of the elements of an array literal or the arguments of a
+    // call, with the elements of each spread array in its place
+    fn elements(&mut self, expressions: &[Expression]) -> Result<Vec<Value>, Unwind> {
+        let mut values = Vec::new();
+        for expression in expressions {
+            let Expression::Spread(spread) = expression else {
+                values.push(self.visit_expression(expression)?);
+                continue;
+            };
+            match self.visit_expression(&spread.value)? {
+                Value::Array(elements) => values.extend(elements.borrow().iter().cloned()),
+                _ => return Err(RuntimeError::TypeMismatch { span: spread.span }.into()),
+            }
+        }
+        Ok(values)
+    }
+
+    // The variables bound by matching each value against the pattern in the
+    // same position, or nothing if any of them doesn't match
+    fn all_bindings(
+        &mut self,
+        patterns: &[Pattern],
+        values: &[Value]
+    ) -> Result<Option<HashMap<String, Value>>, Unwind> {
+        let mut variables = HashMap::new();
+        for (pattern, value) in patterns.iter().zip(values) {
+            match self.bindings(pattern, value)? {
+                Some(bound) => variables.extend(bound),
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(variables))
+    }
+
+    // The value of the arm's body, or nothing if its guard turns it down
+    fn arm(&mut self, arm: &MatchArm, tail: bool) -> Result<Option<Value>, Unwind> {
+        if let Some(guard) = &arm.guard {
+            if !self.condition(guard)? {
+                return Ok(None);
+            }
+        }
+        self.value(&arm.body, tail).map(Some)
+    }
+
+    fn condition(&mut self, condition: &Expression) -> Result<bool, Unwind> {
+        match self.visit_expression(condition)? {
+            Value::Bool(value) => Ok(value),
+            _ => Err(RuntimeError::NonBooleanCondition { span: condition.span() }.into()),
+        }
+    }
+
+    // Operators nested in the operands of another are worked through with a
+    // stack of their own rather than by recursing, so that a chain of them as
+    // long as the parser can be configured to allow doesn't run out of native
+    // stack. Each one is still stepped and observed like any other expression.
+    //
+    // Alongside each value is whether it's constant, which the value of an
+    // operator is when both its operands are. Those are remembered while
+    // evaluating a `Memoized`, so that the next time round their operands
+    // don't have to be evaluated at all.
+    fn chain(&mut self, binary: &Binary) -> Result<Value, Unwind> {
+        if let Some(value) = self.remembered(binary.id) {
+            return Ok(value);
+        }
+        let mut work = vec![Work::Operate(binary, None), Work::Evaluate(&binary.right), Work::Evaluate(&binary.left)];
+        let mut values = Vec::new();
+        while let Some(next) = work.pop() {
+            match next {
+                Work::Evaluate(expression @ Expression::Binary(binary)) => {
+                    if let Some(value) = self.remembered(binary.id) {
+                        values.push((value, true));
+                        continue;
+                    }
+                    self.step(binary.span)?;
+                    if let Some(observer) = &mut self.observer {
+                        observer.on_enter_node(expression);
+                    }
+                    let operands = [Work::Evaluate(&binary.right), Work::Evaluate(&binary.left)];
+                    work.extend(iter::once(Work::Operate(binary, Some(expression))).chain(operands));
+                }
+                Work::Evaluate(expression) => values.push((self.visit_expression(expression)?, constant(expression))),
+                Work::Operate(binary, expression) => {
+                    let ((right, right_constant), (left, left_constant)) = (values.pop().unwrap(), values.pop().unwrap());
+                    let (span, operator_span, divisor) = (binary.span, binary.operator_span, binary.right.span());
+                    let value = self.operate(&binary.operator, left, right, span, operator_span, divisor)?;
+                    if let (Some(observer), Some(expression)) = (&mut self.observer, expression) {
+                        observer.on_exit_node(expression, &value);
+                    }
+                    if let (Some(memo), true) = (self.memo(), left_constant && right_constant) {
+                        memo.insert(binary.id, value.clone());
+                    }
+                    values.push((value, left_constant && right_constant));
+                }
+            }
+        }
+        Ok(values.pop().unwrap().0)
+    }
+
+    fn remembered(&mut self, id: NodeId) -> Option<Value> {
+        self.memo()?.get(&id).cloned()
+    }
+
+    // Only what's outside every function is remembered, since a function
+    // called could have been parsed from something else, where the same ids
+    // are different nodes
+    fn memo(&mut self) -> Option<&mut HashMap<NodeId, Value>> {
+        self.memo.as_mut().filter(|_| self.depth == 0)
+    }
+
+    // Evaluates `expression`, telling the observer about it
+    fn observed(&mut self, expression: &Expression) -> Result<Value, Unwind> {
+        if let Some(observer) = &mut self.observer {
+            observer.on_enter_node(expression);
+        }
+        let result = walk_expression(self, expression);
+        if let (Some(observer), Ok(value)) = (&mut self.observer, &result) {
+            observer.on_exit_node(expression, value);
+        }
+        result
+    }
+
+    // Gets ready for a run, which gets the whole budget of steps
+    fn start(&mut self) {
+        self.fuel = self.config.fuel;
+        self.steps = 0;
+    }
+
+    // Since the evaluator was made
+    fn milliseconds(&self) -> f64 {
+        self.started.elapsed().as_secs_f64() * 1000.0
+    }
+
+    // Called once for every expression and statement evaluated, which is
+    // where the budget of steps they can take is checked. The span is that of
+    // the one about to be evaluated.
+    //
+    // Asking for the time takes long enough that the deadline is only checked
+    // every so many steps, starting with the first.
+    fn step(&mut self, span: Span) -> Result<(), Unwind> {
+        match &mut self.fuel {
+            Some(0) => {
+                let limit = self.config.fuel.unwrap_or_default();
+                return Err(RuntimeError::BudgetExhausted { limit, span }.into());
+            }
+            Some(fuel) => *fuel -= 1,
+            None => {}
+        }
+        if self.cancel.as_ref().is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+            return Err(RuntimeError::Cancelled { span }.into());
+        }
+        if self.steps.is_multiple_of(DEADLINE_INTERVAL) && self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(RuntimeError::TimedOut { span }.into());
+        }
+        self.steps += 1;
+        Ok(())
+    }
+}
+
+fn misplaced(construct: &'static str, allowed: &'static str, span: Span) -> RuntimeError {
+    RuntimeError::Misplaced { construct, allowed, span }
+}
+
+fn undefined(name: &str, span: Span) -> RuntimeError {
+    RuntimeError::UndefinedVariable { name: name.to_string(), span }
+}
+
+// Whether a `try` can catch `error`, which it can unless the evaluation ran
+// out of steps or time or was cancelled, since those have to stop the program
+// whatever it does
+fn catchable(error: &RuntimeError) -> bool {
+    !matches!(error, RuntimeError::BudgetExhausted { .. } | RuntimeError::Cancelled { .. } | RuntimeError::TimedOut { .. })
+}
+
+// Whether `expression` is a literal that always evaluates to the same value,
+// which no operator can change. Arrays, maps and structs aren't, since each
+// evaluation makes a new one.
+fn constant(expression: &Expression) -> bool {
+    match expression {
+        Expression::Integer(_) | Expression::Float(_) | Expression::Char(_) | Expression::Str(_) => true,
+        Expression::Bool(_) | Expression::Nil(_) => true,
+        Expression::Unary(unary) => constant(&unary.right),
+        _ => false,
+    }
+}
+
+// Dividing the smallest int by -1 overflows, but the remainder is just 0.
+// Dividing by zero is put down to the divisor rather than the whole operation.
+fn int_binary(
+    operator: &BinaryOperator,
+    left: i64,
+    right: i64,
+    overflow: OverflowMode,
+    span: Span,
+    divisor: Span,
+) -> Result<i64, RuntimeError> {
+    let operands = (left, right);
+    match operator {
+        BinaryOperator::Plus => overflow.apply(operands, i64::checked_add, i64::wrapping_add, i64::saturating_add, span),
+        BinaryOperator::Minus => overflow.apply(operands, i64::checked_sub, i64::wrapping_sub, i64::saturating_sub, span),
+        BinaryOperator::Star => overflow.apply(operands, i64::checked_mul, i64::wrapping_mul, i64::saturating_mul, span),
+        BinaryOperator::Slash if right == 0 => Err(RuntimeError::DivisionByZero { span: divisor }),
+        BinaryOperator::Slash => overflow.apply(operands, i64::checked_div, i64::wrapping_div, i64::saturating_div, span),
+        BinaryOperator::Percent if right == 0 => Err(RuntimeError::DivisionByZero { span: divisor }),
+        BinaryOperator::Percent => Ok(left.wrapping_rem(right)),
+        BinaryOperator::StarStar => power(left, right, overflow, span),
+        BinaryOperator::Ampersand => Ok(left & right),
+        BinaryOperator::Pipe => Ok(left | right),
+        BinaryOperator::Caret => Ok(left ^ right),
+        BinaryOperator::EqualEqual
+        | BinaryOperator::BangEqual
+        | BinaryOperator::Less
+        | BinaryOperator::LessEqual
+        | BinaryOperator::Greater
+        | BinaryOperator::GreaterEqual
+        | BinaryOperator::In => unreachable!("comparisons are evaluated before arithmetic"),
+    }
+}
+
+fn float_binary(operator: &BinaryOperator, left: f64, right: f64) -> f64 {
+    match operator {
+        BinaryOperator::Plus => left + right,
+        BinaryOperator::Minus => left - right,
+        BinaryOperator::Star => left * right,
+        BinaryOperator::Slash => left / right,
+        BinaryOperator::Percent => left % right,
+        BinaryOperator::StarStar => left.powf(right),
+        BinaryOperator::Ampersand | BinaryOperator::Pipe | BinaryOperator::Caret => {
+            unreachable!("only ints can be operands of bitwise operators")
+        }
+        BinaryOperator::EqualEqual
+        | BinaryOperator::BangEqual
+        | BinaryOperator::Less
+        | BinaryOperator::LessEqual
+        | BinaryOperator::Greater
+        | BinaryOperator::GreaterEqual
+        | BinaryOperator::In => unreachable!("comparisons are evaluated before arithmetic"),
+    }
+}
+
+// Ordering is only defined between two values of the same type. A NaN is
+// unordered, so every comparison against one is false. Nil can only be
+// compared for equality, and `span` is that of the whole operation it was an
+// operand of. Operands of types the operator doesn't take are reported at
+// `operator_span`, along with what the types were before any were promoted.
+pub fn apply(
+    operator: &BinaryOperator,
+    left: Value,
+    right: Value,
+    overflow: OverflowMode,
+    span: Span,
+    operator_span: Span,
+    divisor: Span,
+) -> Result<Value, RuntimeError> {
+    let (left_type, right_type) = (left.type_of(), right.type_of());
+    let mismatch = || RuntimeError::TypeError {
+        operator: operator.clone(),
+        left: left_type,
+        right: right_type,
+        span: operator_span,
+    };
+    // A float with an int or rational makes that a float too, so mixed
+    // arithmetic and comparisons are done the same as between floats
+    let (left, right) = match (&left, &right) {
+        (Value::Float(_), other) | (other, Value::Float(_)) if to_float(other).is_some() => {
+            (Value::Float(to_float(&left).unwrap()), Value::Float(to_float(&right).unwrap()))
+        }
+        _ => (left, right),
+    };
+    match (operator, left, right) {
+        // Otherwise values of different types are never equal
+        (BinaryOperator::EqualEqual, left, right) => Ok(Value::Bool(left == right)),
+        (BinaryOperator::BangEqual, left, right) => Ok(Value::Bool(left != right)),
+        (BinaryOperator::In, value, collection) => Ok(Value::Bool(contains(&collection, &value).ok_or_else(mismatch)?)),
+        (
+            operator @ (BinaryOperator::Less
+            | BinaryOperator::LessEqual
+            | BinaryOperator::Greater
+            | BinaryOperator::GreaterEqual),
+            left,
+            right,
+        ) => Ok(Value::Bool(compare(operator, &left, &right).ok_or_else(mismatch)?)),
+        (BinaryOperator::Ampersand | BinaryOperator::Pipe | BinaryOperator::Caret, left, right)
+            if !matches!(left, Value::Int(_) | Value::BigInt(_)) || !matches!(right, Value::Int(_) | Value::BigInt(_)) =>
+        {
+            Err(mismatch())
+        }
+        (_, left @ (Value::Int(_) | Value::BigInt(_)), right @ (Value::Int(_) | Value::BigInt(_))) => {
+            integer(operator, left, right, overflow, span, divisor)
+        }
+        (_, left @ (Value::Int(_) | Value::Rational(_)), right @ (Value::Int(_) | Value::Rational(_))) => {
+            fraction(operator, exact(&left), exact(&right), span, divisor)
+        }
+        (_, Value::Float(left), Value::Float(right)) => Ok(Value::Float(float_binary(operator, left, right))),
+        _ => Err(mismatch()),
+    }
+}
+
+// What `-` or `~` gives for a value that isn't a struct or enum with a method
+// for it. The span is that of the whole operation.
+pub fn negate(operator: &UnaryOperator, right: Value, overflow: OverflowMode, span: Span) -> Result<Value, RuntimeError> {
+    match (operator, right) {
+        (_, Value::Nil) => Err(RuntimeError::NilOperand { span }),
+        // Negating is taking away from zero, overflow and all
+        (UnaryOperator::Minus, right @ (Value::Int(_) | Value::BigInt(_))) => {
+            integer(&BinaryOperator::Minus, Value::Int(0), right, overflow, span, span)
+        }
+        (UnaryOperator::Minus, Value::Rational(right)) => fraction(&BinaryOperator::Minus, Rational::from(0), right, span, span),
+        (UnaryOperator::Minus, Value::Float(right)) => Ok(Value::Float(-right)),
+        (UnaryOperator::Tilde, Value::Int(right)) => Ok(Value::Int(!right)),
+        (UnaryOperator::Tilde, Value::BigInt(right)) => Ok(narrow(!&right)),
+        _ => Err(RuntimeError::TypeMismatch { span }),
+    }
+}
+
+// What indexing `target` with `position` gives. The span is that of the
+// index.
+pub fn subscript(target: &Value, position: Value, span: Span) -> Result<Value, RuntimeError> {
+    match (target, &position) {
+        (Value::Array(elements), Value::Range(range)) => {
+            let elements = elements.borrow();
+            Ok(Value::array(elements[slice(elements.len(), range, span)?].to_vec()))
+        }
+        (Value::Str(value), _) => character(value, position, span),
+        _ => element(target, position, span, |element| element.clone()),
+    }
+}
+
+// Runs `run` on the element of an array at `index`, which has to be an
+// integer from zero up to but not including the length, or on the entry of a
+// map
+fn element<T>(target: &Value, index: Value, span: Span, run: impl FnOnce(&mut Value) -> T) -> Result<T, RuntimeError> {
+    match target {
+        Value::Array(elements) => {
+            let Value::Int(index) = index else {
+                return Err(RuntimeError::NonIntegerIndex { span });
+            };
+            let mut elements = elements.borrow_mut();
+            let length = elements.len();
+            usize::try_from(index).ok()
+                .and_then(|position| elements.get_mut(position))
+                .map(run)
+                .ok_or(RuntimeError::IndexOutOfBounds { index, length, span })
+        }
+        Value::Map(entries) => {
+            let key = Key::new(index, span)?;
+            match entries.borrow_mut().get_mut(&key) {
+                Some(value) => Ok(run(value)),
+                None => Err(RuntimeError::MissingKey { key, span }),
+            }
+        }
+        _ => Err(RuntimeError::TypeMismatch { span }),
+    }
+}
+
+// Stores `value` as the element of an array at `index`, or as the entry of a
+// map, adding it if there wasn't one
+pub fn insert(target: &Value, index: Value, span: Span, value: Value) -> Result<(), RuntimeError> {
+    if let Value::Map(entries) = target {
+        entries.borrow_mut().insert(Key::new(index, span)?, value);
+        return Ok(());
+    }
+    element(target, index, span, |element| *element = value)
+}
+
+// The name of the method that overloads an operator. `in` has the collection
+// on its right, so it can't be overloaded by the type on its left.
+fn operator_method(operator: &BinaryOperator) -> Option<&'static str> {
+    Some(match operator {
+        BinaryOperator::Plus => "__add__",
+        BinaryOperator::Minus => "__sub__",
+        BinaryOperator::Star => "__mul__",
+        BinaryOperator::Slash => "__div__",
+        BinaryOperator::Percent => "__mod__",
+        BinaryOperator::StarStar => "__pow__",
+        BinaryOperator::Ampersand => "__and__",
+        BinaryOperator::Pipe => "__or__",
+        BinaryOperator::Caret => "__xor__",
+        BinaryOperator::EqualEqual | BinaryOperator::BangEqual => "__eq__",
+        BinaryOperator::Less => "__lt__",
+        BinaryOperator::LessEqual => "__le__",
+        BinaryOperator::Greater => "__gt__",
+        BinaryOperator::GreaterEqual => "__ge__",
+        BinaryOperator::In => return None,
+    })
+}
+
+// The positions from the start of `range` up to its end, both of which have
+// to be inside something `length` long. A range that ends before it starts
+// gives no positions at all.
+fn slice(length: usize, range: &RangeValue, span: Span) -> Result<std::ops::Range<usize>, RuntimeError> {
+    let Some(start) = usize::try_from(range.start).ok().filter(|start| *start <= length) else {
+        return Err(RuntimeError::IndexOutOfBounds { index: range.start, length, span });
+    };
+    let Some(end) = usize::try_from(range.end_exclusive()).ok().filter(|end| *end <= length) else {
+        return Err(RuntimeError::IndexOutOfBounds { index: range.end, length, span });
+    };
+    Ok(start..end.max(start))
+}
+
+// Strings are indexed by character rather than by byte, so an int gives the
+// character at that position and a range gives the characters it counts
+// through as a new string
+fn character(value: &str, index: Value, span: Span) -> Result<Value, RuntimeError> {
+    let length = value.chars().count();
+    match index {
+        Value::Int(index) => usize::try_from(index).ok()
+            .and_then(|position| value.chars().nth(position))
+            .map(Value::Char)
+            .ok_or(RuntimeError::IndexOutOfBounds { index, length, span }),
+        Value::Range(range) => {
+            let positions = slice(length, &range, span)?;
+            Ok(Value::Str(value.chars().skip(positions.start).take(positions.len()).collect()))
+        }
+        _ => Err(RuntimeError::NonIntegerIndex { span }),
+    }
+}
+
+// Whether `value` is one of the ints of a range, an element of an array or a
+// key of a map. A value of the wrong type is never in any of them. Nothing if
+// `collection` isn't any of those.
+fn contains(collection: &Value, value: &Value) -> Option<bool> {
+    match collection {
+        Value::Range(range) => Some(matches!(value, Value::Int(value) if range.contains(*value))),
+        Value::Array(elements) => Some(elements.borrow().contains(value)),
+        Value::Map(entries) => Some(match value {
+            Value::Int(key) => entries.borrow().contains_key(&Key::Int(*key)),
+            Value::Str(key) => entries.borrow().contains_key(&Key::Str(key.clone())),
+            _ => false,
+        }),
+        _ => None,
+    }
+}
+
+// Nothing if the values can't be ordered against each other
+pub fn compare(operator: &BinaryOperator, left: &Value, right: &Value) -> Option<bool> {
+    let ordering = match (left, right) {
+        (Value::Int(left), Value::Int(right)) => left.partial_cmp(right),
+        (Value::Int(_) | Value::BigInt(_), Value::Int(_) | Value::BigInt(_)) => widen(left).partial_cmp(&widen(right)),
+        (Value::Int(_) | Value::Rational(_), Value::Int(_) | Value::Rational(_)) => exact(left).partial_cmp(&exact(right)),
+        (Value::Float(left), Value::Float(right)) => left.partial_cmp(right),
+        (Value::Char(left), Value::Char(right)) => left.partial_cmp(right),
+        (Value::Str(left), Value::Str(right)) => left.partial_cmp(right),
+        _ => return None,
+    };
+    let Some(ordering) = ordering else {
+        return Some(false);
+    };
+
+    Some(match operator {
+        BinaryOperator::Less => ordering.is_lt(),
+        BinaryOperator::LessEqual => ordering.is_le(),
+        BinaryOperator::Greater => ordering.is_gt(),
+        _ => ordering.is_ge(),
+    })
+}
+
+// Ints too large for an i64 only come about when the config says to promote
+// them, but once there is one, arithmetic with it is exact whatever the mode
+fn integer(
+    operator: &BinaryOperator,
+    left: Value,
+    right: Value,
+    overflow: OverflowMode,
+    span: Span,
+    divisor: Span,
+) -> Result<Value, RuntimeError> {
+    if let (BinaryOperator::Slash, Value::Int(left), Value::Int(right)) = (operator, &left, &right) {
+        if *right != 0 && left.wrapping_rem(*right) != 0 {
+            let quotient = Rational::new((*left).into(), (*right).into());
+            return quotient.map(Value::Rational).ok_or(RuntimeError::Overflow { span });
+        }
+    }
+    if let (Value::Int(left), Value::Int(right)) = (&left, &right) {
+        match int_binary(operator, *left, *right, overflow, span, divisor) {
+            Err(RuntimeError::Overflow { .. }) if overflow == OverflowMode::Promote => {}
+            result => return result.map(Value::Int),
+        }
+    }
+    let (left, right) = (widen(&left), widen(&right));
+    let result = match operator {
+        BinaryOperator::Plus => &left + &right,
+        BinaryOperator::Minus => &left - &right,
+        BinaryOperator::Star => &left * &right,
+        BinaryOperator::Slash | BinaryOperator::Percent => {
+            let Some((quotient, remainder)) = left.div_rem(&right) else {
+                return Err(RuntimeError::DivisionByZero { span: divisor });
+            };
+            match operator {
+                // A rational is only ever made of i64s
+                BinaryOperator::Slash if !remainder.is_zero() => return Err(RuntimeError::Overflow { span }),
+                BinaryOperator::Slash => quotient,
+                _ => remainder,
+            }
+        }
+        BinaryOperator::StarStar => big_power(&left, &right, span)?,
+        BinaryOperator::Ampersand => &left & &right,
+        BinaryOperator::Pipe => &left | &right,
+        BinaryOperator::Caret => &left ^ &right,
+        BinaryOperator::EqualEqual
+        | BinaryOperator::BangEqual
+        | BinaryOperator::Less
+        | BinaryOperator::LessEqual
+        | BinaryOperator::Greater
+        | BinaryOperator::GreaterEqual
+        | BinaryOperator::In => unreachable!("comparisons are evaluated before arithmetic"),
+    };
+    Ok(narrow(result))
+}
+
+// The nearest float to a number of any type, which for a big int too large
+// for one is an infinity
+fn to_float(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int(value) => Some(*value as f64),
+        Value::BigInt(value) => Some(value.to_f64()),
+        Value::Rational(value) => Some(value.to_f64()),
+        Value::Float(value) => Some(*value),
+        _ => None,
+    }
+}
+
+fn widen(value: &Value) -> BigInt {
+    match value {
+        Value::Int(value) => BigInt::from(*value),
+        Value::BigInt(value) => value.clone(),
+        _ => unreachable!("only ints are widened"),
+    }
+}
+
+// Back to an i64 if it fits in one, so that equal ints are always stored the
+// same way
+fn narrow(value: BigInt) -> Value {
+    match value.to_i64() {
+        Some(value) => Value::Int(value),
+        None => Value::BigInt(value),
+    }
+}
+
+// Arithmetic on rationals is always checked, whatever the config says about
+// ints, since a rational that wrapped around would be nonsense
+fn fraction(
+    operator: &BinaryOperator,
+    left: Rational,
+    right: Rational,
+    span: Span,
+    divisor: Span,
+) -> Result<Value, RuntimeError> {
+    let result = match operator {
+        BinaryOperator::Plus => left.checked_add(&right),
+        BinaryOperator::Minus => left.checked_sub(&right),
+        BinaryOperator::Star => left.checked_mul(&right),
+        BinaryOperator::Slash | BinaryOperator::Percent if right.is_zero() => {
+            return Err(RuntimeError::DivisionByZero { span: divisor });
+        }
+        BinaryOperator::Slash => left.checked_div(&right),
+        BinaryOperator::Percent => left.checked_rem(&right),
+        // Only to a whole power, which can be negative
+        BinaryOperator::StarStar => match right.to_integer() {
+            Some(exponent) if exponent < 0 && left.is_zero() => return Err(RuntimeError::DivisionByZero { span }),
+            Some(exponent) => left.checked_pow(exponent),
+            None => return Err(RuntimeError::TypeMismatch { span }),
+        },
+        BinaryOperator::Ampersand | BinaryOperator::Pipe | BinaryOperator::Caret => {
+            unreachable!("only ints can be operands of bitwise operators")
+        }
+        BinaryOperator::EqualEqual
+        | BinaryOperator::BangEqual
+        | BinaryOperator::Less
+        | BinaryOperator::LessEqual
+        | BinaryOperator::Greater
+        | BinaryOperator::GreaterEqual
+        | BinaryOperator::In => unreachable!("comparisons are evaluated before arithmetic"),
+    };
+    let result = result.ok_or(RuntimeError::Overflow { span })?;
+    Ok(result.to_integer().map_or(Value::Rational(result), Value::Int))
+}
+
+fn exact(value: &Value) -> Rational {
+    match value {
+        Value::Int(value) => Rational::from(*value),
+        Value::Rational(value) => *value,
+        _ => unreachable!("only ints and rationals are exact"),
+    }
+}
+
+// A result with more bits than this is an overflow even when promoting, since
+// working it out would take far too long
+const MAX_POWER_BITS: u64 = 1 << 18;
+
+// How many steps are taken between checks of the deadline
+const DEADLINE_INTERVAL: u64 = 1024;
+
+fn big_power(base: &BigInt, exponent: &BigInt, span: Span) -> Result<BigInt, RuntimeError> {
+    if exponent.is_negative() {
+        return Err(RuntimeError::NegativeExponent { span });
+    }
+    // Zero, one and minus one never grow however many times they're
+    // multiplied, so only whether the exponent is zero or odd matters
+    if base.bits() <= 1 {
+        let odd = !(exponent & &BigInt::from(1)).is_zero();
+        return Ok(base.pow(if exponent.is_zero() { 0 } else if odd { 1 } else { 2 }));
+    }
+    match exponent.to_i64() {
+        Some(exponent) if base.bits().saturating_mul(exponent as u64) <= MAX_POWER_BITS => Ok(base.pow(exponent as u64)),
+        _ => Err(RuntimeError::Overflow { span }),
+    }
+}
+
+// Exponentiation by squaring, with every multiplication overflowing the way
+// the config says. The base is only squared again if it's going to be used,
+// so a result that fits never overflows along the way.
+fn power(base: i64, exponent: i64, overflow: OverflowMode, span: Span) -> Result<i64, RuntimeError> {
+    if exponent < 0 {
+        return Err(RuntimeError::NegativeExponent { span });
+    }
+    let multiply = |left, right| overflow.apply((left, right), i64::checked_mul, i64::wrapping_mul, i64::saturating_mul, span);
+    let (mut result, mut base, mut exponent) = (1, base, exponent);
+    loop {
+        if exponent & 1 == 1 {
+            result = multiply(result, base)?;
+        }
+        exponent >>= 1;
+        if exponent == 0 {
+            return Ok(result);
+        }
+        base = multiply(base, base)?;
+    }
+}
+
+pub fn evaluate(root: &Expression) -> Result<Value, RuntimeError> {
+    evaluate_with_config(root, EvalConfig::default())
+}
+
+pub fn evaluate_with_config(root: &Expression, config: EvalConfig) -> Result<Value, RuntimeError> {
+    Interpreter::new(config).evaluate(root)
+}
+
+// Evaluates the expression in `source` with `variables` as globals, for using
+// rat to work out formulas
+pub fn eval_with_vars(source: &str, variables: &HashMap<String, Value>) -> Result<Value, EvalError> {
+    let root = get_ast(get_tokens(source)?)?;
+    let mut interpreter = Interpreter::default();
+    for (name, value) in variables {
+        interpreter.define(name, value.clone());
+    }
+    Ok(interpreter.evaluate(&root)?)
+}
+
+// Runs every statement in order, giving back the value of the last one.
+// Modules are imported relative to the current directory.
+pub fn execute(program: &Program) -> Result<Option<Value>, RuntimeError> {
+    execute_in(program, Path::new("."))
+}
+
+// Like `execute`, for the program in the file at `path`
+pub fn execute_file(program: &Program, path: &Path) -> Result<Option<Value>, RuntimeError> {
+    let mut interpreter = Interpreter::new(EvalConfig::default());
+    interpreter.set_file(path);
+    interpreter.execute(program)
+}
+
+// Like `execute`, for a program from a file in `directory`
+pub fn execute_in(program: &Program, directory: &Path) -> Result<Option<Value>, RuntimeError> {
+    execute_with_config(program, directory, EvalConfig::default())
+}
+
+pub fn execute_with_config(
+    program: &Program,
+    directory: &Path,
+    config: EvalConfig,
+) -> Result<Option<Value>, RuntimeError> {
+    let mut interpreter = Interpreter::new(config);
+    interpreter.set_directory(directory);
+    interpreter.execute(program)
+}
+
+// An expression to be evaluated over and over, like a formula run again
+// each time its variables change. The value of arithmetic that only has
+// literals in it, like `2 ** 10` in `x * 2 ** 10`, is remembered the first
+// time round, so later evaluations only do what depends on something else.
+// Nothing in what's remembered is stepped through or told to an observer.
+// What's inside a function isn't remembered.
+pub struct Memoized {
+    expression: Expression,
+    values: HashMap<NodeId, Value>,
+    // What the values were worked out with, since that changes them
+    overflow: OverflowMode,
+}
+
+impl Memoized {
+    pub fn new(expression: Expression) -> Memoized {
+        Memoized { expression, values: HashMap::new(), overflow: OverflowMode::default() }
+    }
+
+    pub fn expression(&self) -> &Expression {
+        &self.expression
+    }
+
+    // How many values are remembered
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+// For embedding rat. Everything run by the same interpreter shares its
+// globals, so a program can use what one run before it declared.
+pub struct Interpreter {
+    evaluator: Evaluator,
+}
+
+impl Interpreter {
+    pub fn new(config: EvalConfig) -> Interpreter {
+        let mut evaluator = Evaluator::default();
+        evaluator.config = config;
+        evaluator.environment = Rc::new(builtins(&[], &config));
+        Interpreter { evaluator }
+    }
+
+    // Where `print` and `println` write to, which is stdout to begin with
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.evaluator.output = output;
+    }
+
+    // Makes `value` a global called `name`, in place of any there already is
+    pub fn define(&mut self, name: &str, value: Value) {
+        self.evaluator.environment.define(name.to_string(), value);
+    }
+
+    // Makes `function` a global called `name`, one that checks it's called
+    // with `arity` arguments before running it. An argument of the wrong type
+    // can be reported with `RuntimeError::TypeMismatch { span: Span::default() }`,
+    // since the error gets the span of the call whatever span it has.
+    pub fn register(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: impl Fn(&[Value]) -> Result<Value, RuntimeError> + 'static,
+    ) {
+        let native = Callable::native(name, arity, function);
+        self.evaluator.environment.define(name.to_string(), Value::Function(native.clone()));
+        self.evaluator.natives.push((name.to_string(), native));
+    }
+
+    // Where `read_line` reads from, which is stdin to begin with
+    pub fn set_input(&mut self, input: Box<dyn BufRead>) {
+        self.evaluator.input = input;
+    }
+
+    // What `import` paths are relative to, which is the current directory to
+    // begin with
+    pub fn set_directory(&mut self, directory: &Path) {
+        self.evaluator.directory = directory.to_path_buf();
+    }
+
+    // The file the programs being run are from. Imports are relative to its
+    // directory, and a module importing it back is a cycle rather than running
+    // it a second time.
+    pub fn set_file(&mut self, path: &Path) {
+        self.set_directory(path.parent().unwrap_or(Path::new(".")));
+        self.evaluator.loading = path.canonicalize().into_iter().collect();
+    }
+
+    // Frees whatever is only being kept alive by a cycle, giving back how many
+    // arrays, maps, instances, environments and functions were cleared to do
+    // it. This happens by itself every so often, and when the interpreter is
+    // dropped.
+    pub fn collect(&mut self) -> usize {
+        self.evaluator.heap.collect()
+    }
+
+    // Whenever `cancel` is set, whatever is being evaluated stops with
+    // `RuntimeError::Cancelled`. The interpreter never clears it, so that's
+    // up to whoever set it before anything else can be run.
+    pub fn set_cancel(&mut self, cancel: Arc<AtomicBool>) {
+        self.evaluator.cancel = Some(cancel);
+    }
+
+    // Once `deadline` has passed, whatever is being evaluated stops with
+    // `RuntimeError::TimedOut`
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.evaluator.deadline = deadline;
+    }
+
+    // Starts `random` and `random_int` over from `seed`, so that they give
+    // the same numbers every time. They're seeded from the clock otherwise.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.evaluator.rng = Rng::new(seed);
+    }
+
+    // What gets told about every expression evaluated from now on, if
+    // anything. Calls in tail position are made the same as any other while
+    // there is one, so recursing in tail position can reach the limit on call
+    // depth.
+    pub fn set_observer(&mut self, observer: Option<Box<dyn EvalObserver>>) {
+        self.evaluator.observer = observer;
+    }
+
+    pub fn evaluate(&mut self, root: &Expression) -> Result<Value, RuntimeError> {
+        self.evaluator.start();
+        self.evaluator.visit_expression(root).map_err(|unwind| unwind.into_error(root.span()))
+    }
+
+    // Evaluates the expression `memoized` holds, using what it remembers from
+    // evaluating it before and remembering more
+    pub fn evaluate_memoized(&mut self, memoized: &mut Memoized) -> Result<Value, RuntimeError> {
+        if memoized.overflow != self.evaluator.config.overflow {
+            memoized.values.clear();
+            memoized.overflow = self.evaluator.config.overflow;
+        }
+        self.evaluator.memo = Some(mem::take(&mut memoized.values));
+        let result = self.evaluate(&memoized.expression);
+        memoized.values = self.evaluator.memo.take().unwrap_or_default();
+        result
+    }
+
+    // The global called `name`, if there is one
+    pub fn global(&self, name: &str) -> Option<Value> {
+        self.evaluator.environment.get(name)
+    }
+
+    // Calls `function` with `arguments`, putting errors from a builtin down to
+    // `span`
+    pub fn call(&mut self, function: &Callable, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+        self.evaluator.invoke(function, arguments, span)
+    }
+
+    // Gets ready for a run of something the interpreter doesn't run itself,
+    // such as the VM's, which gets the whole budget of steps
+    pub fn start(&mut self) {
+        self.evaluator.start();
+    }
+
+    // Takes one of the steps the config allows, failing once there are none
+    // left, the flag given to `set_cancel` is set or the deadline has passed
+    pub fn step(&mut self, span: Span) -> Result<(), RuntimeError> {
+        self.evaluator.step(span).map_err(|unwind| unwind.into_error(span))
+    }
+
+    // Runs every statement in order, giving back the value of the last one
+    pub fn execute(&mut self, program: &Program) -> Result<Option<Value>, RuntimeError> {
+        self.evaluator.start();
+        let mut result = None;
+        for statement in &program.statements {
+            result = self.evaluator.visit_statement(statement).map_err(|unwind| unwind.into_error(statement.span()))?;
+        }
+        Ok(result)
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Interpreter {
+        Interpreter::new(EvalConfig::default())
+    }
+}
+
+// Evaluates `input` as a single expression, for the tests here and in the
+// modules evaluating uses
+#[cfg(test)]
+pub(crate) fn evaluate_source(input: &str) -> Result<Value, RuntimeError> {
+    let tokens = get_tokens(input).unwrap();
+    evaluate(&get_ast(tokens).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::lexer::get_tokens;
+    use crate::grammar::parser::{get_ast, get_program};
+    use crate::grammar::unparse::unparse;
+
+    #[test]
+    fn test_precedence() -> Result<(), String> {
+        assert_eq!(evaluate_source("2+3*4").unwrap(), Value::Int(14));
+        assert_eq!(evaluate_source("2*3+4").unwrap(), Value::Int(10));
+        assert_eq!(evaluate_source("20-6/3").unwrap(), Value::Int(18));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unary_and_grouping() -> Result<(), String> {
+        assert_eq!(evaluate_source("-3+5").unwrap(), Value::Int(2));
+        assert_eq!(evaluate_source("--3").unwrap(), Value::Int(3));
+        assert_eq!(evaluate_source("(2+3)*4").unwrap(), Value::Int(20));
+        assert_eq!(evaluate_source("2*(3-(4+1))").unwrap(), Value::Int(-4));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_exponent() -> Result<(), String> {
+        assert_eq!(evaluate_source("2**10").unwrap(), Value::Int(1024));
+        assert_eq!(evaluate_source("2**3**2").unwrap(), Value::Int(512));
+        assert_eq!(evaluate_source("-2**2").unwrap(), Value::Int(-4));
+        assert_eq!(evaluate_source("(-2)**2").unwrap(), Value::Int(4));
+        assert_eq!(evaluate_source("3*2**2").unwrap(), Value::Int(12));
+        assert_eq!(evaluate_source("7**0").unwrap(), Value::Int(1));
+        assert_eq!(evaluate_source("2**40 + 2147483647").unwrap(), Value::Int(1_101_659_111_423));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_exponent_errors() -> Result<(), String> {
+        assert!(matches!(evaluate_source("2**-1"), Err(RuntimeError::NegativeExponent { .. })));
+        assert!(matches!(evaluate_source("2**63"), Err(RuntimeError::Overflow { .. })));
+        assert!(matches!(evaluate_source("10**100"), Err(RuntimeError::Overflow { .. })));
+        assert_eq!(evaluate_source("(-2)**63").unwrap(), Value::Int(i64::MIN));
+        assert_eq!(evaluate_source("1**10000000000 + (-1)**10000000001").unwrap(), Value::Int(0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_overflow_modes() -> Result<(), String> {
+        let run = |input: &str, overflow: OverflowMode| {
+            let config = EvalConfig { overflow, ..EvalConfig::default() };
+            evaluate_with_config(&get_ast(get_tokens(input).unwrap()).unwrap(), config)
+        };
+        let max = "9223372036854775807";
+        let min = "(-9223372036854775807 - 1)";
+
+        for input in [format!("{} + 1", max), format!("{} - 1", min), format!("{} * 2", max), format!("-{}", min)] {
+            assert!(matches!(run(&input, OverflowMode::Checked), Err(RuntimeError::Overflow { .. })), "{}", input);
+        }
+        assert!(matches!(run(&format!("{} / -1", min), OverflowMode::Checked), Err(RuntimeError::Overflow { .. })));
+        assert_eq!(run(&format!("{} % -1", min), OverflowMode::Checked).unwrap(), Value::Int(0));
+        assert!(matches!(evaluate_source(&format!("{} + 1", max)), Err(RuntimeError::Overflow { .. })));
+
+        assert_eq!(run(&format!("{} + 1", max), OverflowMode::Wrapping).unwrap(), Value::Int(i64::MIN));
+        assert_eq!(run(&format!("{} - 1", min), OverflowMode::Wrapping).unwrap(), Value::Int(i64::MAX));
+        assert_eq!(run(&format!("-{}", min), OverflowMode::Wrapping).unwrap(), Value::Int(i64::MIN));
+        assert_eq!(run("2**64 + 3**41", OverflowMode::Wrapping).unwrap(), Value::Int(3_i64.wrapping_pow(41)));
+
+        assert_eq!(run(&format!("{} + 1", max), OverflowMode::Saturating).unwrap(), Value::Int(i64::MAX));
+        assert_eq!(run(&format!("{} * 2", min), OverflowMode::Saturating).unwrap(), Value::Int(i64::MIN));
+        assert_eq!(run(&format!("-{}", min), OverflowMode::Saturating).unwrap(), Value::Int(i64::MAX));
+        assert_eq!(run("(-3)**41", OverflowMode::Saturating).unwrap(), Value::Int(i64::MIN));
+        assert_eq!(run("(-3)**40", OverflowMode::Saturating).unwrap(), Value::Int(i64::MAX));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rationals() -> Result<(), String> {
+        let run = |input: &str| evaluate_source(input).map(|value| value.to_string());
+
+        assert_eq!(run("1/3 + 1/6").unwrap(), "1/2");
+        assert_eq!(evaluate_source("1/3 + 1/6").unwrap(), Value::Rational(Rational::new(1, 2).unwrap()));
+        assert_eq!(run("6/4").unwrap(), "3/2");
+        assert_eq!(run("-6/4").unwrap(), "-3/2");
+        assert_eq!(run("6/-4").unwrap(), "-3/2");
+        assert_eq!(run("-(1/2)").unwrap(), "-1/2");
+        // A whole number is an int again
+        assert_eq!(evaluate_source("1/3 * 3").unwrap(), Value::Int(1));
+        assert_eq!(evaluate_source("1/2 + 1/2 == 1").unwrap(), Value::Bool(true));
+        assert_eq!(evaluate_source("8/4").unwrap(), Value::Int(2));
+        assert_eq!(run("(1/2) / (3/4)").unwrap(), "2/3");
+        assert_eq!(run("(2/3) - 1").unwrap(), "-1/3");
+        assert_eq!(run("(7/2) % 2").unwrap(), "3/2");
+        assert_eq!(run("(2/3)**3").unwrap(), "8/27");
+        assert_eq!(run("(2/3)**-2").unwrap(), "9/4");
+        assert_eq!(run("(1/3 < 1/2, 1/2 < 1, 3/2 >= 1, 2/4 == 1/2, 1/2 == 1)").unwrap(), "(true, true, true, true, false)");
+        assert_eq!(run("(1/2).numerator() + (1/2).denominator()").unwrap(), "3");
+        assert_eq!(run("(-3/6).numerator()").unwrap(), "-1");
+
+        assert!(matches!(evaluate_source("(1/2) / 0"), Err(RuntimeError::DivisionByZero { .. })));
+        assert!(matches!(evaluate_source("(1/2) % 0"), Err(RuntimeError::DivisionByZero { .. })));
+        assert!(matches!(evaluate_source("0 ** -1"), Err(RuntimeError::NegativeExponent { .. })));
+        assert!(matches!(evaluate_source("(1/2) ** (1/2)"), Err(RuntimeError::TypeMismatch { .. })));
+        assert!(matches!(evaluate_source("(1/2) + 'a'"), Err(RuntimeError::TypeError { .. })));
+        assert!(matches!(evaluate_source("(1/2) & 1"), Err(RuntimeError::TypeError { .. })));
+        assert!(matches!(evaluate_source("(1/3) ** 100"), Err(RuntimeError::Overflow { .. })));
+        assert!(matches!(evaluate_source("[1, 2][1/2]"), Err(RuntimeError::NonIntegerIndex { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_coercion() -> Result<(), String> {
+        let run = |input: &str| match evaluate_source(input) {
+            Ok(value) => value.to_string(),
+            Err(RuntimeError::TypeMismatch { .. } | RuntimeError::TypeError { .. }) => "mismatch".to_string(),
+            Err(error) => panic!("Unexpected error {:?} from {}", error, input),
+        };
+
+        // Each operator between 7 and 2, as int and int, int and float, float
+        // and int, float and float, and rational and float
+        for (operator, expected) in [
+            ("+", ["9", "9.0", "9.0", "9.0", "5.5"]),
+            ("-", ["5", "5.0", "5.0", "5.0", "1.5"]),
+            ("*", ["14", "14.0", "14.0", "14.0", "7.0"]),
+            ("/", ["7/2", "3.5", "3.5", "3.5", "1.75"]),
+            ("%", ["1", "1.0", "1.0", "1.0", "1.5"]),
+            ("**", ["49", "49.0", "49.0", "49.0", "12.25"]),
+            ("&", ["2", "mismatch", "mismatch", "mismatch", "mismatch"]),
+            ("|", ["7", "mismatch", "mismatch", "mismatch", "mismatch"]),
+            ("^", ["5", "mismatch", "mismatch", "mismatch", "mismatch"]),
+            ("==", ["false", "false", "false", "false", "false"]),
+            ("!=", ["true", "true", "true", "true", "true"]),
+            ("<", ["false", "false", "false", "false", "false"]),
+            ("<=", ["false", "false", "false", "false", "false"]),
+            (">", ["true", "true", "true", "true", "true"]),
+            (">=", ["true", "true", "true", "true", "true"]),
+        ] {
+            let operands = [("7", "2"), ("7", "2.0"), ("7.0", "2"), ("7.0", "2.0"), ("(7/2)", "2.0")];
+            for ((left, right), expected) in operands.into_iter().zip(expected) {
+                assert_eq!(run(&format!("{} {} {}", left, operator, right)), expected, "{} {} {}", left, operator, right);
+            }
+        }
+        assert_eq!(run("1 + 2.5"), "3.5");
+        assert_eq!(run("3 / 2"), "3/2");
+        assert_eq!(run("2 == 2.0"), "true");
+        assert_eq!(run("1/2 == 0.5"), "true");
+        assert_eq!(run("1/3 == 0.3333333333333333"), "true");
+        assert_eq!(run("(2 < 2.5, 2.5 < 3, 1/2 > 0.25)"), "(true, true, true)");
+        assert_eq!(run("1 < 0.0 / 0.0"), "false");
+        // Only operators compare numbers of different types like this
+        assert_eq!(run("[1] == [1.0]"), "false");
+        assert_eq!(run("1 in [1.0]"), "false");
+
+        assert_eq!(run("int(7)"), "7");
+        assert_eq!(run("int(3.99)"), "3");
+        assert_eq!(run("int(-3.99)"), "-3");
+        assert_eq!(run("int(7/2)"), "3");
+        assert_eq!(run("int(-7/2)"), "-3");
+        assert_eq!(run("int(-9223372036854775808.0)"), "-9223372036854775808");
+        assert_eq!(run("float(7)"), "7.0");
+        assert_eq!(run("float(7/2)"), "3.5");
+        assert_eq!(run("float(2.5)"), "2.5");
+        assert_eq!(run("int(\"7\")"), "mismatch");
+        assert_eq!(run("float(nil)"), "mismatch");
+        for input in ["int(9223372036854775808.0)", "int(1.0 / 0.0)", "int(0.0 / 0.0)"] {
+            assert!(matches!(evaluate_source(input), Err(RuntimeError::Overflow { .. })), "{}", input);
+        }
+        let config = EvalConfig { overflow: OverflowMode::Promote, ..EvalConfig::default() };
+        let promoted = evaluate_with_config(&get_ast(get_tokens("int(1e20)").unwrap()).unwrap(), config).unwrap();
+        assert_eq!(promoted.to_string(), "100000000000000000000");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_promotion() -> Result<(), String> {
+        let run = |input: &str| {
+            let config = EvalConfig { overflow: OverflowMode::Promote, ..EvalConfig::default() };
+            execute_with_config(&get_program(get_tokens(input).unwrap()).unwrap(), Path::new("."), config)
+                .map(|value| value.unwrap().to_string())
+        };
+        let max = "9223372036854775807";
+        let min = "(-9223372036854775807 - 1)";
+
+        assert_eq!(run(&format!("{} + 1", max)).unwrap(), "9223372036854775808");
+        assert_eq!(run(&format!("{} - 1", min)).unwrap(), "-9223372036854775809");
+        assert_eq!(run(&format!("-{}", min)).unwrap(), "9223372036854775808");
+        assert_eq!(run(&format!("{} / -1", min)).unwrap(), "9223372036854775808");
+        assert_eq!(run("2**100").unwrap(), "1267650600228229401496703205376");
+        assert_eq!(run("(-3)**41").unwrap(), "-36472996377170786403");
+        let factorial = "fn factorial(n) { if n <= 1 { 1 } else { n * factorial(n - 1) } }\nfactorial(30)";
+        assert_eq!(run(factorial).unwrap(), "265252859812191058636308480000000");
+
+        // A result that fits goes back to being an i64
+        assert_eq!(run(&format!("({} + 1) - 1", max)).unwrap(), max);
+        assert_eq!(run(&format!("({} + 1) - 1 == {}", max, max)).unwrap(), "true");
+        assert_eq!(run("2**64 / 2**60").unwrap(), "16");
+        assert_eq!(run("2**64 % 10").unwrap(), "6");
+        assert_eq!(run("-(2**64) % 10").unwrap(), "-6");
+        assert_eq!(run("2**64 == 2**64").unwrap(), "true");
+        assert_eq!(run("(2**64 > 2**63, 2**64 > 1, -(2**64) < -1, 1 < 2**64)").unwrap(), "(true, true, true, true)");
+        assert_eq!(run("(2**64 | 1, 2**64 & 1, ~(2**64))").unwrap(), "(18446744073709551617, 0, -18446744073709551617)");
+        assert_eq!(run("(1**(2**64), 1 + 1)").unwrap(), "(1, 2)");
+
+        assert!(matches!(run("2**64 / 0"), Err(RuntimeError::DivisionByZero { .. })));
+        assert!(matches!(run("2**64 / 3"), Err(RuntimeError::Overflow { .. })));
+        assert_eq!(run("2**64 + 1.0").unwrap(), "1.8446744073709552e19");
+        assert!(matches!(run("2**64 + 'a'"), Err(RuntimeError::TypeError { .. })));
+        assert!(matches!(run("(2**64)**(2**64)"), Err(RuntimeError::Overflow { .. })));
+        assert!(matches!(run("2**10000000"), Err(RuntimeError::Overflow { .. })));
+        assert!(matches!(run("[1, 2][2**64]"), Err(RuntimeError::NonIntegerIndex { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bitwise() -> Result<(), String> {
+        assert_eq!(evaluate_source("12&10").unwrap(), Value::Int(8));
+        assert_eq!(evaluate_source("12|10").unwrap(), Value::Int(14));
+        assert_eq!(evaluate_source("12^10").unwrap(), Value::Int(6));
+        assert_eq!(evaluate_source("~0").unwrap(), Value::Int(-1));
+        assert_eq!(evaluate_source("~5+1").unwrap(), Value::Int(-5));
+        assert_eq!(evaluate_source("1|2^3&6").unwrap(), Value::Int(1));
+        assert_eq!(evaluate_source("1+2&3").unwrap(), Value::Int(3));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_literals() -> Result<(), String> {
+        assert_eq!(evaluate_source("2.5").unwrap(), Value::Float(2.5));
+        assert_eq!(evaluate_source("'x'").unwrap(), Value::Char('x'));
+        assert_eq!(evaluate_source("\"rat\"").unwrap(), Value::Str("rat".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_float_arithmetic() -> Result<(), String> {
+        assert_eq!(evaluate_source("1.5+2.25*2.0").unwrap(), Value::Float(6.0));
+        assert_eq!(evaluate_source("-1e3/4.0").unwrap(), Value::Float(-250.0));
+        assert_eq!(evaluate_source("2.0**0.5").unwrap(), Value::Float(2.0_f64.sqrt()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_type_mismatch() -> Result<(), String> {
+        assert!(matches!(evaluate_source("1+'a'"), Err(RuntimeError::TypeError { .. })));
+        assert!(matches!(evaluate_source("1.0&2.0"), Err(RuntimeError::TypeError { .. })));
+        assert!(matches!(evaluate_source("-\"rat\""), Err(RuntimeError::TypeMismatch { .. })));
+        assert!(matches!(evaluate_source("'a'*3"), Err(RuntimeError::TypeError { .. })));
+
+        let error = evaluate_source("true + \"a\"").unwrap_err();
+        let RuntimeError::TypeError { operator: BinaryOperator::Plus, left: Type::Bool, right: Type::Str, span } = error else {
+            panic!("Expected a type error but got {:?}", error);
+        };
+        assert_eq!((span.line, span.character, span.end - span.start), (1, 6, 1));
+        assert_eq!(error.to_string(), "Can't apply '+' to bool and str at line 1, character 6");
+        let error = evaluate_source("[1] < 2.5").unwrap_err();
+        assert_eq!(error.message(), "Can't apply '<' to array and float");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_interpolation() -> Result<(), String> {
+        assert_eq!(evaluate_source("\"sum is ${2+3}\"").unwrap(), Value::Str("sum is 5".to_string()));
+        assert_eq!(
+            evaluate_source("\"${1} ${'+'} ${1.0} = ${\"<${1+1}>\"}!\"").unwrap(),
+            Value::Str("1 + 1.0 = <2>!".to_string())
+        );
+        assert!(matches!(evaluate_source("\"${1/0}\""), Err(RuntimeError::DivisionByZero { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_modulo() -> Result<(), String> {
+        assert_eq!(evaluate_source("7%3").unwrap(), Value::Int(1));
+        assert_eq!(evaluate_source("1+7%3*2").unwrap(), Value::Int(3));
+        assert_eq!(evaluate_source("0-7%3").unwrap(), Value::Int(-1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_spans() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+        let span = |input: &str| {
+            let span = execute_source(input).unwrap_err().span();
+            (span.line, span.character, input[span.start..span.end].to_string())
+        };
+
+        assert_eq!(span("let a = 1\nlet b = a + true"), (2, 11, "+".to_string()));
+        assert_eq!(span("let a = 1\nif a { 2 }"), (2, 4, "a".to_string()));
+        assert_eq!(span("let a = [1] + len(2)"), (1, 15, "len(2)".to_string()));
+        assert_eq!(span("let a = 1; a(2)"), (1, 12, "a".to_string()));
+        assert_eq!(span("fn f(x) { -x }\nf(\"s\")"), (1, 11, "-x".to_string()));
+        assert_eq!(span("let a = b"), (1, 9, "b".to_string()));
+
+        let error = execute_source("let a = 1\nlet b = a / 0").unwrap_err();
+        assert_eq!(error.to_string(), "Division by zero at line 2, character 13");
+        let error = execute_source("fn f(x) { x }\nf()").unwrap_err();
+        assert_eq!(error.message(), "Expected 1 arguments but was given 0");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_division_by_zero() -> Result<(), String> {
+        assert!(matches!(evaluate_source("5%0"), Err(RuntimeError::DivisionByZero { .. })));
+        assert!(matches!(evaluate_source("5/0"), Err(RuntimeError::DivisionByZero { .. })));
+        assert!(matches!(evaluate_source("1+5%0*3"), Err(RuntimeError::DivisionByZero { .. })));
+
+        // The error points at the divisor, wherever the division is
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+        let divisor = |input: &str| match execute_source(input) {
+            Err(RuntimeError::DivisionByZero { span }) => input[span.start..span.end].to_string(),
+            result => panic!("Expected division by zero, got {:?}", result),
+        };
+        assert_eq!(divisor("let a = 10 / (2 - 2)"), "2 - 2");
+        assert_eq!(divisor("let z = 0\nlet a = 1 + 7 % z * 3"), "z");
+        assert_eq!(divisor("let a = [4]\na[0] /= 2 * 0"), "2 * 0");
+        assert_eq!(divisor("fn f(x) { 1 / x }\nf(0)"), "x");
+        assert_eq!(divisor("let x = 5\nx /= 1 - 1"), "1 - 1");
+        assert_eq!(divisor("(-9223372036854775807 - 1) % (4 - 2 * 2)"), "4 - 2 * 2");
+        match execute_source("let a = 1\nlet b = [2,\n  a / (a - 1)]") {
+            Err(error @ RuntimeError::DivisionByZero { .. }) => {
+                assert_eq!(error.to_string(), "Division by zero at line 3, character 8");
+            }
+            result => panic!("Expected division by zero, got {:?}", result),
+        }
+        // It can be caught, and floats divide by zero to infinity instead
+        let program = "let e = try { 1 + 2 / 0 } catch (e) { e }\n[e, 1.0 / 0.0]";
+        assert_eq!(execute_source(program).unwrap().unwrap().to_string(), "[Division by zero, inf]");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_execute_program() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        assert_eq!(execute_source("1 + 1; 2 * 3\n\"done\"").unwrap(), Some(Value::Str("done".to_string())));
+        assert_eq!(execute_source("").unwrap(), None);
+        assert!(matches!(execute_source("1\n1 / 0\n2"), Err(RuntimeError::DivisionByZero { .. })));
+
+        assert_eq!(execute_source("let x = 2 + 3\nlet y = x * 2; y - x").unwrap(), Some(Value::Int(5)));
+        assert_eq!(execute_source("let x = 1").unwrap(), None);
+        assert_eq!(execute_source("let x = 1; let x = \"${x}!\"; x").unwrap(), Some(Value::Str("1!".to_string())));
+        assert!(matches!(execute_source("let x = y"), Err(RuntimeError::UndefinedVariable { name, .. }) if name == "y"));
+        let error = execute_source("let x = 1\nlet y = x + zed").unwrap_err();
+        assert_eq!(error.to_string(), "Undefined variable 'zed' at line 2, character 13");
+
+        assert_eq!(execute_source("let x = 1; x = x + 1; x * 10").unwrap(), Some(Value::Int(20)));
+        assert_eq!(execute_source("let x = 1; let y = 2; x = y = 3; x + y").unwrap(), Some(Value::Int(6)));
+        assert_eq!(execute_source("let x = 1; 10 + (x = 5)").unwrap(), Some(Value::Int(15)));
+        assert!(matches!(execute_source("x = 1"), Err(RuntimeError::UndefinedVariable { name, .. }) if name == "x"));
+
+        assert_eq!(execute_source("let x = { let y = 2; y * 3 }; x").unwrap(), Some(Value::Int(6)));
+        assert_eq!(execute_source("{ 1; }").unwrap(), Some(Value::Nil));
+        assert_eq!(execute_source("let x = 1\n{\n  x += 1\n  x += 1\n}\nx").unwrap(), Some(Value::Int(3)));
+        assert!(matches!(execute_source("{} + 1"), Err(RuntimeError::TypeError { left: Type::Nil, right: Type::Int, .. })));
+
+        assert_eq!(execute_source("let x = 10; x += 5; x -= 1; x *= 3; x /= 2; x").unwrap(), Some(Value::Int(21)));
+        assert_eq!(execute_source("let s = \"a\"; s = \"${s}b\"; s").unwrap(), Some(Value::Str("ab".to_string())));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_environment() -> Result<(), String> {
+        let globals = Rc::new(Environment::default());
+        globals.define("x".to_string(), Value::Int(1));
+        let inner = Environment { parent: Some(Rc::clone(&globals)), ..Environment::default() };
+        assert_eq!(inner.get("x"), Some(Value::Int(1)));
+        // Assigning changes the variable where it was defined
+        assert!(inner.assign("x", Value::Int(2)));
+        assert_eq!(globals.get("x"), Some(Value::Int(2)));
+        // Defining it again inside shadows it there and leaves the outer one alone
+        assert_eq!(inner.define("x".to_string(), Value::Int(3)), None);
+        assert_eq!(globals.define("x".to_string(), Value::Int(4)), Some(Value::Int(2)));
+        assert_eq!((inner.get("x"), globals.get("x")), (Some(Value::Int(3)), Some(Value::Int(4))));
+        assert!(!inner.assign("y", Value::Nil));
+        assert_eq!(inner.get("y"), None);
+
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+        let program = "let x = 1\nlet y = { let x = 10; x + 1 }\nfn f() { x = x + y }\nf()\nx";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(12)));
+
+        // A variable is gone once the block it was defined in ends
+        let undefined = |input: &str| match execute_source(input) {
+            Err(error @ RuntimeError::UndefinedVariable { .. }) => error.to_string(),
+            result => panic!("Expected an undefined variable, got {:?}", result),
+        };
+        assert_eq!(undefined("{ let inner = 1 }\ninner"), "Undefined variable 'inner' at line 2, character 1");
+        assert_eq!(undefined("fn f() { count += 1 }\nf()"), "Undefined variable 'count' at line 1, character 10");
+        assert_eq!(undefined("fn f(a) { a + b }\nf(1)"), "Undefined variable 'b' at line 1, character 15");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_while() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        let program = "let i = 0\nlet total = 0\nwhile i < 5 {\n  i += 1\n  total += i\n}\ntotal";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(15)));
+        assert_eq!(execute_source("let x = 1; while false { x = 1 / 0 }; x").unwrap(), Some(Value::Int(1)));
+        assert_eq!(execute_source("let x = 1; while x < 100 { x *= 2 }").unwrap(), None);
+        // The condition is checked again before every iteration
+        assert_eq!(execute_source("let x = 0; while { x += 1; x < 3 } {}; x").unwrap(), Some(Value::Int(3)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_for() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        assert_eq!(execute_source("let total = 0\nfor i in 0..5 {\n  total += i\n}\ntotal").unwrap(), Some(Value::Int(10)));
+        assert_eq!(execute_source("let n = 0; for i in 3..1 { n += 1 }; n").unwrap(), Some(Value::Int(0)));
+        // Changing the loop variable doesn't change how many times the loop runs
+        assert_eq!(execute_source("let n = 0; for i in 0..3 { i = 10; n += 1 }; n").unwrap(), Some(Value::Int(3)));
+        let program = "let product = 1; let n = 4; for i in 1..n + 1 { product *= i }; product";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(24)));
+
+        assert_eq!(execute_source("let i = \"outer\"; for i in 0..2 {}; i").unwrap(), Some(Value::Str("outer".to_string())));
+        assert!(matches!(execute_source("for i in 0..2 {}; i"), Err(RuntimeError::UndefinedVariable { .. })));
+        assert!(matches!(execute_source("for i in 0..2.0 {}"), Err(RuntimeError::TypeMismatch { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_break_and_continue() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        let program = "let i = 0\nwhile true {\n  i += 1\n  if i == 5 { break }\n}\ni";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(5)));
+        let program = "let odd = 0\nfor i in 0..10 {\n  if i % 2 == 0 { continue }\n  odd += i\n}\nodd";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(25)));
+
+        // Only the innermost loop is stopped
+        let program = "let n = 0; for i in 0..3 { for j in 0..3 { if j == 1 { break }; n += 1 } }; n";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(3)));
+        assert_eq!(execute_source("let i = 7; for i in 0..3 { break }; i").unwrap(), Some(Value::Int(7)));
+        assert!(matches!(execute_source("while true { 1 / 0; break }"), Err(RuntimeError::DivisionByZero { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_iterators() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        // Only as many values are made as the loop asks for
+        let program = "fn naturals() {\n  let n = -1\n  fn() { n += 1; n }\n}\nlet total = 0\n\
+            for x in naturals() {\n  if x == 5 { break }\n  total += x\n}\ntotal";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(10)));
+        let program = "fn countdown(n) { fn() { n == 0 ? nil : { n -= 1; n + 1 } } }\n\
+            let seen = []; for x in countdown(3) { seen = [...seen, x] }; seen";
+        assert_eq!(execute_source(program).unwrap().unwrap().to_string(), "[3, 2, 1]");
+
+        // A struct instance with a `next` method keeps what it needs in its fields
+        let program = "struct Evens { state }\nimpl Evens { fn next(e) { let s = e.state; s[0] += 2; s[0] > 6 ? nil : s[0] } }\n\
+            let seen = []; for x in Evens { state: [0] } { seen = [...seen, x] }; seen";
+        assert_eq!(execute_source(program).unwrap().unwrap().to_string(), "[2, 4, 6]");
+
+        // Arrays, tuples and strings give what's in them
+        let program = "let seen = []\nfor x in [1, \"a\", [2]] { seen = [...seen, x] }\nfor c in \"añ\" { seen = [...seen, c] }\n\
+            for x in (true, nil) { seen = [...seen, x] }\nseen";
+        assert_eq!(execute_source(program).unwrap().unwrap().to_string(), "[1, a, [2], a, ñ, true, nil]");
+        let program = "let total = 0\nfor x in [1, 2, 3, 4] {\n  if x == 2 { continue }\n  if x == 4 { break }\n  total += x\n}\ntotal";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(4)));
+        // Changing the array in the loop doesn't change what it goes over
+        let program = "let xs = [1, 2]\nlet count = 0\nfor x in xs { xs.push(x); count += 1 }\n(count, xs)";
+        assert_eq!(execute_source(program).unwrap().unwrap().to_string(), "(2, [1, 2, 1, 2])");
+        assert_eq!(execute_source("let n = 0; for x in [] { n += 1 }; for c in \"\" { n += 1 }; n").unwrap(), Some(Value::Int(0)));
+
+        assert!(matches!(execute_source("for x in 5 {}"), Err(RuntimeError::TypeMismatch { .. })));
+        assert!(matches!(execute_source("for x in fn(a) { a } {}"), Err(RuntimeError::ArityMismatch { .. })));
+        assert!(matches!(execute_source("struct S { a }\nfor x in S { a: 1 } {}"), Err(RuntimeError::TypeMismatch { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_functions() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        assert_eq!(execute_source("fn add(a, b) { a + b }\nadd(1, 2) * add(3, 4)").unwrap(), Some(Value::Int(21)));
+        assert_eq!(execute_source("fn answer() { 42 }; answer()").unwrap(), Some(Value::Int(42)));
+        assert_eq!(execute_source("fn nothing() {}; nothing()").unwrap(), Some(Value::Nil));
+        assert_eq!(execute_source("fn f() {}; f").unwrap().unwrap().to_string(), "<fn f>");
+
+        let program = "fn fib(n) {\n  if n < 2 { n } else { fib(n - 1) + fib(n - 2) }\n}\nfib(15)";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(610)));
+        let program = "fn twice(f, x) { f(f(x)) }\nfn inc(x) { x + 1 }\ntwice(inc, 5)";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(7)));
+
+        // Locals are thrown away after the call, but globals can be changed
+        let program = "let count = 0\nfn bump(by) { let step = by; count += step }\nbump(2); bump(3); count";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(5)));
+        assert!(matches!(execute_source("fn f(a) { let b = a }; f(1); b"), Err(RuntimeError::UndefinedVariable { .. })));
+        assert!(matches!(execute_source("fn f() { x }; fn g(x) { f() }; g(1)"), Err(RuntimeError::UndefinedVariable { .. })));
+        assert_eq!(execute_source("let a = 1; fn f(a) { a = a * 10; a }; f(5) + a").unwrap(), Some(Value::Int(51)));
+
+        // Types aren't checked, so a generic function takes anything
+        let program = "fn id<T>(x: T) -> T { x }\n(id(1), id(\"a\"), id([true]))";
+        assert_eq!(execute_source(program).unwrap().unwrap().to_string(), "(1, a, [true])");
+        assert_eq!(execute_source("fn f(x: Int) -> Int { x }; f(1.5)").unwrap(), Some(Value::Float(1.5)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_return() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        let program = "fn find(limit) {\n  for i in 0..limit {\n    if i * i > 50 { return i }\n  }\n  -1\n}\nfind(100) + find(3)";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(7)));
+        assert_eq!(execute_source("fn f() { return; 1 }; f()").unwrap(), Some(Value::Nil));
+        // Only the innermost call returns
+        let program = "fn inner() { return 1; 2 }\nfn outer() { let x = inner(); x + 10 }\nouter()";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(11)));
+        assert!(matches!(execute_source("fn f() { return 1 / 0 }; f()"), Err(RuntimeError::DivisionByZero { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_arrays() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        let expected = Value::array(vec![Value::Int(3), Value::array(vec![Value::Str("a".to_string())]), Value::array(vec![])]);
+        assert_eq!(execute_source("[1 + 2, [\"a\"], []]").unwrap(), Some(expected));
+        assert_eq!(execute_source("[1, [2.5, 'c'], true]").unwrap().unwrap().to_string(), "[1, [2.5, c], true]");
+        assert_eq!(execute_source("[1, [2]] == [1, [2]]").unwrap(), Some(Value::Bool(true)));
+        assert_eq!(execute_source("[1, 2] != [2, 1]").unwrap(), Some(Value::Bool(true)));
+
+        assert_eq!(execute_source("len([1, [2, 3], 4])").unwrap(), Some(Value::Int(3)));
+        assert_eq!(execute_source("len([])").unwrap(), Some(Value::Int(0)));
+        assert_eq!(execute_source("len(\"héllo\")").unwrap(), Some(Value::Int(5)));
+        assert_eq!(execute_source("len").unwrap().unwrap().to_string(), "<fn len>");
+        assert_eq!(execute_source("let len = 3; len").unwrap(), Some(Value::Int(3)));
+
+        assert!(matches!(execute_source("len(1)"), Err(RuntimeError::TypeMismatch { .. })));
+        assert!(matches!(execute_source("len()"), Err(RuntimeError::ArityMismatch { expected: 1, found: 0, .. })));
+        assert!(matches!(execute_source("[1] < [2]"), Err(RuntimeError::TypeError { .. })));
+        assert!(matches!(execute_source("[1] + [2]"), Err(RuntimeError::TypeError { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_indexing() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        assert_eq!(execute_source("let xs = [10, 20, 30]; xs[0] + xs[1 + 1]").unwrap(), Some(Value::Int(40)));
+        assert_eq!(execute_source("[[1, 2], [3, 4]][1][0]").unwrap(), Some(Value::Int(3)));
+        assert_eq!(execute_source("fn f() { [1, 2] }; f()[1]").unwrap(), Some(Value::Int(2)));
+
+        let program = "let xs = [1, 2, 3]; let ys = xs; xs[0] = 9; xs[2] += 10; [xs, ys]";
+        assert_eq!(execute_source(program).unwrap().unwrap().to_string(), "[[9, 2, 13], [9, 2, 13]]");
+        let program = "let grid = [[0, 0], [0, 0]]; for i in 0..2 { grid[i][1 - i] = i + 1 }; grid";
+        assert_eq!(execute_source(program).unwrap().unwrap().to_string(), "[[0, 1], [2, 0]]");
+        assert_eq!(execute_source("let xs = [1]; xs[0] = 5").unwrap(), Some(Value::Int(5)));
+
+        // The index is only evaluated once by a compound assignment
+        let program = "let n = 0; fn next() { n += 1; n - 1 }; let xs = [1, 2]; xs[next()] *= 5; [xs, n]";
+        assert_eq!(execute_source(program).unwrap().unwrap().to_string(), "[[5, 2], 1]");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_errors() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        let input = "let xs = [1, 2]\nxs[1 + 1]";
+        match execute_source(input) {
+            Err(RuntimeError::IndexOutOfBounds { index, length, span }) => {
+                assert_eq!((index, length), (2, 2));
+                assert_eq!(&input[span.start..span.end], "1 + 1");
+                assert_eq!((span.line, span.character), (2, 4));
+            }
+            result => panic!("Expected the index to be out of bounds, got {:?}", result)
+        }
+        assert!(matches!(execute_source("[1][-1]"), Err(RuntimeError::IndexOutOfBounds { index: -1, length: 1, .. })));
+        assert!(matches!(execute_source("[][0]"), Err(RuntimeError::IndexOutOfBounds { index: 0, length: 0, .. })));
+        assert!(matches!(execute_source("let xs = [[1]]; xs[0][1] = 2"), Err(RuntimeError::IndexOutOfBounds { index: 1, .. })));
+
+        let input = "let xs = [1]; xs[true] = 2";
+        match execute_source(input) {
+            Err(RuntimeError::NonIntegerIndex { span }) => assert_eq!(&input[span.start..span.end], "true"),
+            result => panic!("Expected the index to be rejected, got {:?}", result)
+        }
+        assert!(matches!(execute_source("[1][0.0]"), Err(RuntimeError::NonIntegerIndex { .. })));
+
+        assert!(matches!(execute_source("1[0]"), Err(RuntimeError::TypeMismatch { .. })));
+        assert!(matches!(execute_source("let x = 1; x[0] = 2"), Err(RuntimeError::TypeMismatch { .. })));
+        assert!(matches!(execute_source("xs[0] = 1"), Err(RuntimeError::UndefinedVariable { name, .. }) if name == "xs"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tuples() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        let expected = Value::Tuple(vec![Value::Int(1), Value::Str("two".to_string()), Value::Float(3.0)]);
+        assert_eq!(execute_source("(1, \"two\", 3.0)").unwrap(), Some(expected));
+        assert_eq!(execute_source("((1,), (), (2, [3]))").unwrap().unwrap().to_string(), "((1,), (), (2, [3]))");
+
+        let program = "fn divide(a, b) { ((a - a % b) / b, a % b) }\nlet result = divide(17, 5)\nresult.0 * 10 + result.1";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(32)));
+        assert_eq!(execute_source("((1, (2, 3)), 4).0.1.0").unwrap(), Some(Value::Int(2)));
+        assert_eq!(execute_source("(1, 'a') == (1, 'a')").unwrap(), Some(Value::Bool(true)));
+        assert_eq!(execute_source("(1, 2) == [1, 2]").unwrap(), Some(Value::Bool(false)));
+        assert_eq!(execute_source("len((1, 2, 3))").unwrap(), Some(Value::Int(3)));
+
+        let input = "let t = (1, 2)\nt.2";
+        match execute_source(input) {
+            Err(RuntimeError::IndexOutOfBounds { index: 2, length: 2, span }) => assert_eq!(&input[span.start..span.end], "t.2"),
+            result => panic!("Expected the position to be out of bounds, got {:?}", result)
+        }
+        assert!(matches!(execute_source("[1, 2].0"), Err(RuntimeError::TypeMismatch { .. })));
+        assert!(matches!(execute_source("(1, 2)[0]"), Err(RuntimeError::TypeMismatch { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_maps() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        let program = "let rat = { \"name\": \"rat\", \"age\": 1 }\nrat[\"age\"] += 1\nrat[\"tail\"] = true\nrat";
+        assert_eq!(execute_source(program).unwrap().unwrap().to_string(), "{age: 2, name: rat, tail: true}");
+        assert_eq!(execute_source("{ 2: \"b\", 1: \"a\" }[1]").unwrap(), Some(Value::Str("a".to_string())));
+        assert_eq!(execute_source("{ 1: 'a', 1: 'b' }").unwrap().unwrap().to_string(), "{1: b}");
+        assert_eq!(execute_source("let m = {:}; m[0] = [1]; m[0][0] = 2; m").unwrap().unwrap().to_string(), "{0: [2]}");
+        assert_eq!(execute_source("len({ 1: 1, \"1\": 1 })").unwrap(), Some(Value::Int(2)));
+        assert_eq!(execute_source("{ 1: 2, 3: 4 } == { 3: 4, 1: 2 }").unwrap(), Some(Value::Bool(true)));
+        assert_eq!(execute_source("let a = {:}; let b = a; b[1] = 1; len(a)").unwrap(), Some(Value::Int(1)));
+
+        let input = "let m = { \"a\": 1 }\nm[\"b\"]";
+        match execute_source(input) {
+            Err(RuntimeError::MissingKey { key, span }) => {
+                assert_eq!(key, Key::Str("b".to_string()));
+                assert_eq!(&input[span.start..span.end], "\"b\"");
+            }
+            result => panic!("Expected the key to be missing, got {:?}", result)
+        }
+        assert!(matches!(execute_source("let m = {:}; m[1] += 1"), Err(RuntimeError::MissingKey { key: Key::Int(1), .. })));
+        assert!(matches!(execute_source("{ 1.5: 1 }"), Err(RuntimeError::InvalidKey { .. })));
+        assert!(matches!(execute_source("let m = {:}; m[[1]] = 1"), Err(RuntimeError::InvalidKey { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_references() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+        let run = |input: &str| execute_source(input).unwrap().unwrap().to_string();
+
+        // Functions change what they're passed, rather than a copy of it
+        assert_eq!(run("fn push(xs, x) { xs[len(xs) - 1] = x }\nlet xs = [0, 0]; push(xs, 5); xs"), "[0, 5]");
+        assert_eq!(run("let m = { 1: [0] }; let inner = m[1]; inner[0] = 2; m"), "{1: [2]}");
+        assert_eq!(run("let xs = [1, 2]; let ys = xs[0..2]; ys[0] = 9; xs"), "[1, 2]");
+        assert_eq!(run("let a = [1]; let b = [a, a]; a[0] = 3; b"), "[[3], [3]]");
+        assert_eq!(run("let a = [1]; let b = [1]; a == b"), "true");
+
+        // A value inside itself
+        assert_eq!(run("let a = [1, 2]; a[1] = a; a"), "[1, [...]]");
+        assert_eq!(run("let m = {:}; m[\"self\"] = m; m"), "{self: {...}}");
+        assert_eq!(run("let a = [1]; a[0] = a; a[0][0][0] == a"), "true");
+        assert_eq!(run("let a = [1]; a[0] = a; let b = [1]; b[0] = b; a == b"), "true");
+        assert_eq!(run("let a = [1, 1]; a[0] = a; let b = [1, 2]; b[0] = b; a == b"), "false");
+        assert_eq!(run("let a = [0]; a[0] = a; a in a"), "true");
+
+        // Only cycles nothing else can reach are freed
+        let mut interpreter = Interpreter::default();
+        let program = |input: &str| get_program(get_tokens(input).unwrap()).unwrap();
+        interpreter.execute(&program("for i in 0..10 { let a = [i]; a[0] = a }")).unwrap();
+        interpreter.execute(&program("let kept = [0]; kept[0] = kept")).unwrap();
+        assert_eq!(interpreter.collect(), 10);
+        assert_eq!(interpreter.collect(), 0);
+        // Each function is stored in the environment it keeps alive
+        interpreter.execute(&program("for i in 0..3 { fn f() { i } }")).unwrap();
+        assert_eq!(interpreter.collect(), 9);
+        assert_eq!(interpreter.execute(&program("kept[0][0] == kept")).unwrap(), Some(Value::Bool(true)));
+        // Without waiting to be asked once there are enough of them
+        interpreter.execute(&program("for i in 0..5000 { let a = [i]; a[0] = a }")).unwrap();
+        assert!(interpreter.evaluator.heap.len() < 2000);
+
+        // Nor is what the embedding program still has once the interpreter
+        // is gone
+        let kept = interpreter.execute(&program("kept")).unwrap().unwrap();
+        drop(interpreter);
+        assert_eq!(kept.to_string(), "[[...]]");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_closures() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        assert_eq!(execute_source("let inc = fn(x) { x + 1 }; inc(41)").unwrap(), Some(Value::Int(42)));
+        assert_eq!(execute_source("fn(a, b) { a * b }(6, 7)").unwrap(), Some(Value::Int(42)));
+        assert_eq!(execute_source("fn() {}").unwrap().unwrap().to_string(), "<fn>");
+
+        let program = "fn adder(n) { fn(x) { x + n } }\nlet add2 = adder(2)\nlet add10 = adder(10)\nadd2(1) + add10(1)";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(14)));
+
+        // Captured variables are shared rather than copied, so changes stick
+        // between calls and are seen by everything that captured them
+        let program = "fn counter() {\n  let n = 0\n  fn() { n += 1; n }\n}\nlet c = counter()\nc(); c()\nlet d = counter()\nd(); c()";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(3)));
+        let program = "let x = 1; let get = fn() { x }; x = 2; get()";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(2)));
+
+        let program = "fn map3(f) { f(1) + f(2) + f(3) }\nlet scale = 10\nmap3(fn(x) { x * scale })";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(60)));
+        let program = "fn compose(f, g) { fn(x) { f(g(x)) } }\ncompose(fn(x) { x * 2 }, fn(x) { x + 1 })(4)";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(10)));
+
+        assert_eq!(execute_source("let f = fn() { 1 }; let g = f; f == g").unwrap(), Some(Value::Bool(true)));
+        assert_eq!(execute_source("fn() { 1 } == fn() { 1 }").unwrap(), Some(Value::Bool(false)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scopes() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        // A `let` in a block shadows the outer variable until the block ends,
+        // while assigning changes the outer one
+        let program = "let x = 1; let inner = { let x = 2; x }; [inner, x]";
+        assert_eq!(execute_source(program).unwrap().unwrap().to_string(), "[2, 1]");
+        assert_eq!(execute_source("let x = 1; { x = 2 }; x").unwrap(), Some(Value::Int(2)));
+        assert!(matches!(execute_source("{ let y = 1 }; y"), Err(RuntimeError::UndefinedVariable { name, .. }) if name == "y"));
+        assert!(matches!(execute_source("if true { let y = 1 }; y"), Err(RuntimeError::UndefinedVariable { .. })));
+        assert!(matches!(execute_source("fn f() {}; { fn g() {} }; g()"), Err(RuntimeError::UndefinedFunction { .. })));
+
+        // A variable can be declared again in the same scope, and the new one
+        // is what a closure made before then sees
+        let program = "let x = 1; let f = fn() { x }; let x = 2; f()";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(2)));
+        let program = "let x = 1; let f = { let x = 10; fn() { x } }; x = 2; f()";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(10)));
+
+        // Every iteration of a loop starts with a fresh scope
+        let program = "let n = 0; while n < 3 { let seen = n; n += 1 }; n";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(3)));
+        let program = "let fs = []; for i in 0..3 { fs = [...fs, fn() { i }] }; [fs[0](), fs[2]()]";
+        assert_eq!(execute_source(program).unwrap().unwrap().to_string(), "[0, 2]");
+        assert!(matches!(execute_source("while true { let y = 1; break }; y"), Err(RuntimeError::UndefinedVariable { .. })));
+
+        // A function body is a scope inside the one the function was made in,
+        // and parameters shadow variables outside it
+        let program = "let x = 1; fn f(x) { let y = x * 2; y }; [f(5), x]";
+        assert_eq!(execute_source(program).unwrap().unwrap().to_string(), "[10, 1]");
+        assert!(matches!(execute_source("fn f() { let y = 1 }; f(); y"), Err(RuntimeError::UndefinedVariable { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fuel() -> Result<(), String> {
+        let run = |input: &str, fuel: u64| {
+            let config = EvalConfig { fuel: Some(fuel), ..EvalConfig::default() };
+            execute_with_config(&get_program(get_tokens(input).unwrap()).unwrap(), Path::new("."), config)
+        };
+
+        // The statement, the addition and both of its operands
+        assert_eq!(run("1 + 2", 4).unwrap(), Some(Value::Int(3)));
+        match run("1 + 2", 3) {
+            Err(RuntimeError::BudgetExhausted { limit, span }) => {
+                assert_eq!(limit, 3);
+                assert_eq!((span.line, span.character), (1, 5));
+            }
+            result => panic!("Expected to run out of steps, got {:?}", result),
+        }
+        assert!(matches!(run("while true {}", 1000), Err(RuntimeError::BudgetExhausted { limit: 1000, .. })));
+        assert!(matches!(run("fn f() { f() }\nf()", 1000), Err(RuntimeError::BudgetExhausted { .. })));
+        assert!(matches!(run("let i = 0; while i < 1000 { i += 1 }", 1000), Err(RuntimeError::BudgetExhausted { .. })));
+        assert_eq!(run("let i = 0; while i < 10 { i += 1 }; i", 1000).unwrap(), Some(Value::Int(10)));
+        let error = run("for i in 0..10 {}", 10).unwrap_err();
+        assert_eq!(error.message(), "Ran out of steps after 10");
+
+        // Every run gets the whole budget again
+        let mut interpreter = Interpreter::new(EvalConfig { fuel: Some(20), ..EvalConfig::default() });
+        let program = get_program(get_tokens("let x = 1 + 2 * 3").unwrap()).unwrap();
+        for _ in 0..5 {
+            assert_eq!(interpreter.execute(&program).unwrap(), None);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cancellation() -> Result<(), String> {
+        let program = |input: &str| get_program(get_tokens(input).unwrap()).unwrap();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let mut interpreter = Interpreter::default();
+        interpreter.set_cancel(Arc::clone(&cancel));
+        assert_eq!(interpreter.execute(&program("1 + 2")).unwrap(), Some(Value::Int(3)));
+
+        // Set from another thread while the loop is running
+        let setter = Arc::clone(&cancel);
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            setter.store(true, Ordering::Relaxed);
+        });
+        match interpreter.execute(&program("let i = 0\nwhile true { i += 1 }")) {
+            Err(RuntimeError::Cancelled { span }) => assert_eq!(span.line, 2),
+            result => panic!("Expected to be cancelled, got {:?}", result),
+        }
+        thread.join().unwrap();
+        assert!(matches!(interpreter.execute(&program("1")), Err(RuntimeError::Cancelled { .. })));
+        cancel.store(false, Ordering::Relaxed);
+        assert_eq!(interpreter.execute(&program("i > 0")).unwrap(), Some(Value::Bool(true)));
+
+        let mut interpreter = Interpreter::default();
+        interpreter.set_deadline(Some(Instant::now() + std::time::Duration::from_millis(50)));
+        let error = interpreter.execute(&program("while true {}")).unwrap_err();
+        assert!(matches!(error, RuntimeError::TimedOut { .. }));
+        assert_eq!(error.message(), "The evaluation ran past its deadline");
+        // It's checked before the first step
+        assert!(matches!(interpreter.execute(&program("1")), Err(RuntimeError::TimedOut { .. })));
+        interpreter.set_deadline(None);
+        assert_eq!(interpreter.execute(&program("1")).unwrap(), Some(Value::Int(1)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_observer() -> Result<(), String> {
+        struct Recorder(Rc<RefCell<Vec<String>>>);
+        impl EvalObserver for Recorder {
+            fn on_enter_node(&mut self, expression: &Expression) {
+                self.0.borrow_mut().push(format!("enter {}", unparse(expression)));
+            }
+
+            fn on_exit_node(&mut self, expression: &Expression, value: &Value) {
+                self.0.borrow_mut().push(format!("exit {} = {}", unparse(expression), value));
+            }
+        }
+
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut interpreter = Interpreter::default();
+        interpreter.set_observer(Some(Box::new(Recorder(Rc::clone(&log)))));
+        let mut run = |input: &str| {
+            log.borrow_mut().clear();
+            let result = interpreter.execute(&get_program(get_tokens(input).unwrap()).unwrap());
+            (result, log.borrow().clone())
+        };
+
+        let (result, log) = run("1 + 2 * 3");
+        assert_eq!(result.unwrap(), Some(Value::Int(7)));
+        assert_eq!(log, [
+            "enter 1 + 2 * 3", "enter 1", "exit 1 = 1", "enter 2 * 3", "enter 2", "exit 2 = 2", "enter 3", "exit 3 = 3",
+            "exit 2 * 3 = 6", "exit 1 + 2 * 3 = 7",
+        ]);
+
+        // Calls in tail position exit with their values too
+        let (_, log) = run("fn f(n) { n == 0 ? \"done\" : f(n - 1) }\nf(2)");
+        let calls = log.iter().filter(|line| line.starts_with("exit f(")).collect::<Vec<_>>();
+        assert_eq!(calls, ["exit f(n - 1) = done", "exit f(n - 1) = done", "exit f(2) = done"]);
+
+        // Nothing exits on the way out of an error
+        let (result, log) = run("1 + len(2)");
+        assert!(result.is_err());
+        assert_eq!(log.last().unwrap(), "exit 2 = 2");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_long_chains() -> Result<(), String> {
+        let parse = |input: &str| get_ast(get_tokens(input).unwrap()).unwrap();
+
+        let sum = parse(&vec!["1"; 100_000].join(" + "));
+        assert_eq!(evaluate(&sum).unwrap(), Value::Int(100_000));
+        let alternating = parse(&format!("0{}", " - 1 + 2".repeat(50_000)));
+        assert_eq!(evaluate(&alternating).unwrap(), Value::Int(50_000));
+        // Errors still point at the operator they came from
+        let mismatch = parse(&format!("{} + 'a'", vec!["1"; 50_000].join(" * ")));
+        match evaluate(&mismatch) {
+            Err(RuntimeError::TypeError { left: Type::Int, right: Type::Char, span, .. }) => {
+                assert_eq!(span.character, 50_000 * 4 - 1);
+            }
+            result => panic!("Expected a type error, got {:?}", result),
+        }
+        let config = EvalConfig { fuel: Some(1000), ..EvalConfig::default() };
+        assert!(matches!(evaluate_with_config(&sum, config), Err(RuntimeError::BudgetExhausted { .. })));
+        assert_eq!(evaluate(&alternating.clone()).unwrap(), Value::Int(50_000));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_memoized() -> Result<(), String> {
+        struct Counter(Rc<RefCell<usize>>);
+        impl EvalObserver for Counter {
+            fn on_enter_node(&mut self, _: &Expression) {
+                *self.0.borrow_mut() += 1;
+            }
+        }
+
+        let count = Rc::new(RefCell::new(0));
+        let mut interpreter = Interpreter::default();
+        interpreter.set_observer(Some(Box::new(Counter(Rc::clone(&count)))));
+        let mut formula = Memoized::new(get_ast(get_tokens("x * (2 ** 10 + -3 * 4) + len([1, 2])").unwrap()).unwrap());
+        let mut run = |x: i64| {
+            *count.borrow_mut() = 0;
+            interpreter.define("x", Value::Int(x));
+            let value = interpreter.evaluate_memoized(&mut formula).unwrap();
+            (value, *count.borrow(), formula.len())
+        };
+
+        assert_eq!(run(1), (Value::Int(1014), 16, 3));
+        // Only what has `x` in it and the call are evaluated again
+        assert_eq!(run(2), (Value::Int(2026), 8, 3));
+        assert_eq!(run(3), (Value::Int(3038), 8, 3));
+
+        // Nothing is remembered from an error, or from inside a function
+        let mut interpreter = Interpreter::default();
+        interpreter.execute(&get_program(get_tokens("fn f() { 1 + 2 }").unwrap()).unwrap()).map_err(|e| e.to_string())?;
+        let mut failing = Memoized::new(get_ast(get_tokens("f() + 1 / 0").unwrap()).unwrap());
+        assert!(matches!(interpreter.evaluate_memoized(&mut failing), Err(RuntimeError::DivisionByZero { .. })));
+        assert!(failing.is_empty());
+
+        // What's remembered is worked out again if the overflow mode changes
+        let mut large = Memoized::new(get_ast(get_tokens("9223372036854775807 + 1").unwrap()).unwrap());
+        let mut interpreter = Interpreter::new(EvalConfig { overflow: OverflowMode::Wrapping, ..EvalConfig::default() });
+        assert_eq!(interpreter.evaluate_memoized(&mut large).unwrap(), Value::Int(i64::MIN));
+        let mut interpreter = Interpreter::default();
+        assert!(matches!(interpreter.evaluate_memoized(&mut large), Err(RuntimeError::Overflow { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_recursion_limit() -> Result<(), String> {
+        let run = |input: &str, max_call_depth: usize| {
+            let config = EvalConfig { max_call_depth, ..EvalConfig::default() };
+            execute_with_config(&get_program(get_tokens(input).unwrap()).unwrap(), Path::new("."), config)
+        };
+        let countdown = |n: usize| format!("fn f(n) {{ if n == 0 {{ 0 }} else {{ 1 + f(n - 1) }} }}\nf({})", n);
+
+        // The first call counts too
+        assert_eq!(run(&countdown(49), 50).unwrap(), Some(Value::Int(49)));
+        match run(&countdown(50), 50) {
+            Err(RuntimeError::RecursionLimitExceeded { limit, span }) => {
+                assert_eq!(limit, 50);
+                assert_eq!((span.line, span.character), (1, 38));
+            }
+            result => panic!("Expected the recursion limit to be exceeded, got {:?}", result),
+        }
+        // Calls that have returned don't count
+        let program = "fn f(n) { if n == 0 { 0 } else { 1 + f(n - 1) } }\nf(40) + f(40)";
+        assert_eq!(run(program, 50).unwrap(), Some(Value::Int(80)));
+        let program = "fn f() { 1 + f() }\nf()";
+        assert!(matches!(run(program, 50), Err(RuntimeError::RecursionLimitExceeded { .. })));
+        let program = "struct P { x }\nimpl P { fn down(p) { -P { x: p.x - 1 }.down() } }\nP { x: 1 }.down()";
+        assert!(matches!(run(program, 50), Err(RuntimeError::RecursionLimitExceeded { .. })));
+
+        // The default limit is sized for a main thread's stack, which test
+        // threads don't get
+        std::thread::Builder::new()
+            .stack_size(8 * 1024 * 1024)
+            .spawn(move || {
+                let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+                assert!(execute_source(&countdown(150)).is_ok());
+                assert!(matches!(execute_source("fn f() { [f()] }\nf()"), Err(RuntimeError::RecursionLimitExceeded { .. })));
+                let program = "fn f(n) { { { { if n == 0 { 0 } else { [(1 + f(n - 1))][0] } } } } }\nf(1000)";
+                assert!(matches!(execute_source(program), Err(RuntimeError::RecursionLimitExceeded { .. })));
+            })
+            .unwrap()
+            .join()
+            .map_err(|_| "Recursing past the limit overflowed the stack".to_string())?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_misplaced() -> Result<(), String> {
+        // The parser never makes these, but a syntax tree built some other way can
+        let span = Span { start: 4, end: 9, line: 2, character: 3 };
+        let run = |statement: Statement| execute(&Program { statements: vec![statement] }).unwrap_err();
+        let error = run(Statement::Break(Break { span, id: NodeId(0) }));
+        assert!(matches!(error, RuntimeError::Misplaced { construct: "'break'", .. }));
+        assert_eq!(error.to_string(), "'break' can only be used inside a loop at line 2, character 3");
+        let error = run(Statement::Continue(Continue { span, id: NodeId(0) }));
+        assert_eq!(error.message(), "'continue' can only be used inside a loop");
+        let error = run(Statement::Return(Return { value: None, span, id: NodeId(0) }));
+        assert_eq!(error.to_string(), "'return' can only be used inside a function at line 2, character 3");
+
+        let mut program = get_program(get_tokens("let xs = [1]\n[...xs]").unwrap()).unwrap();
+        let statement = program.statements.pop();
+        let Some(Statement::Expression(ExpressionStatement { expression: Expression::Array(array), .. })) = statement else {
+            panic!("Expected an array literal");
+        };
+        let spread = array.elements.into_iter().next().unwrap();
+        let span = spread.span();
+        program.statements.push(Statement::Expression(ExpressionStatement { expression: spread, span, id: NodeId(0) }));
+        let error = execute(&program).unwrap_err();
+        assert_eq!(error.to_string(), "A spread can only be used in an array literal or the arguments of a call at line 2, character 2");
+
+        // A compiled function is for the VM to run
+        let function = Callable::compiled(compile::compile(&get_program(get_tokens("1").unwrap()).unwrap()).unwrap());
+        let mut interpreter = Interpreter::default();
+        assert!(matches!(interpreter.call(&function, vec![], span), Err(RuntimeError::NotCallable { span: error }) if error == span));
+        interpreter.define("f", Value::Function(function));
+        let program = get_program(get_tokens("f()").unwrap()).unwrap();
+        assert!(matches!(interpreter.execute(&program), Err(RuntimeError::NotCallable { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tail_calls() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        // Far more calls than the limit on call depth, in a test thread's stack
+        let program = "fn sum(n, total) { if n == 0 { total } else { sum(n - 1, total + n) } }\nsum(100000, 0)";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(5000050000)));
+        let program = "fn even(n) { n == 0 ? true : odd(n - 1) }\nfn odd(n) { n == 0 ? false : even(n - 1) }\neven(10001)";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Bool(false)));
+        let program = "fn count(n) { match n { 0 => \"done\", _ => { let m = n - 1; count(m) } } }\ncount(10000)";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Str("done".to_string())));
+        let program = "fn count(n) { while true { if n == 0 { return 0 }; return count(n - 1) } }\ncount(10000)";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(0)));
+        let program = "fn first(n) { (n == 0 ? 1 : nil) ?? first(n - 1) }\nfirst(10000)";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(1)));
+        let program = "struct C { n }\nimpl C { fn down(c) { c.n == 0 ? c : C { n: c.n - 1 }.down() } }\nC { n: 10000 }.down().n";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(0)));
+
+        // A call whose value is used afterwards isn't in tail position
+        let program = "fn f(n) { n == 0 ? 0 : 1 + f(n - 1) }\nf(10000)";
+        assert!(matches!(execute_source(program), Err(RuntimeError::RecursionLimitExceeded { .. })));
+        let program = "fn f(n) { n == 0 ? [] : [f(n - 1)] }\nf(10000)";
+        assert!(matches!(execute_source(program), Err(RuntimeError::RecursionLimitExceeded { .. })));
+
+        // The scopes around a tail call are gone by the time it's made, but
+        // what it's called with was worked out before then
+        let program = "let x = \"global\"\nfn get() { x }\nfn f() { let x = \"local\"; get() }\nf()";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Str("global".to_string())));
+        let program = "fn id(v) { v }\nfn f() { let x = 5; id(x + 1) }\nf()";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(6)));
+        let program = "fn g(a, b) { a }\nfn f() { g(1) }\nf()";
+        match execute_source(program) {
+            Err(RuntimeError::ArityMismatch { expected: 2, found: 1, span }) => assert_eq!((span.line, span.character), (2, 10)),
+            result => panic!("Expected an arity mismatch, got {:?}", result),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_call_errors() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        match execute_source("let x = 1\nmissing(x)") {
+            Err(error @ RuntimeError::UndefinedFunction { .. }) => {
+                assert_eq!(error.to_string(), "Undefined function 'missing' at line 2, character 1");
+            }
+            result => panic!("Expected an undefined function, got {:?}", result),
+        }
+        assert!(matches!(execute_source("let x = 1; x()"), Err(RuntimeError::NotCallable { .. })));
+        assert!(matches!(execute_source("\"f\"()"), Err(RuntimeError::NotCallable { .. })));
+        assert!(matches!(
+            execute_source("fn f(a, b) { a }; f(1)"),
+            Err(RuntimeError::ArityMismatch { expected: 2, found: 1, .. })
+        ));
+        assert!(matches!(execute_source("fn f() { 1 / 0 }; f()"), Err(RuntimeError::DivisionByZero { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_comparisons() -> Result<(), String> {
+        assert_eq!(evaluate_source("1 < 2").unwrap(), Value::Bool(true));
+        assert_eq!(evaluate_source("2 <= 1 + 1").unwrap(), Value::Bool(true));
+        assert_eq!(evaluate_source("1.5 > 2.5").unwrap(), Value::Bool(false));
+        assert_eq!(evaluate_source("'b' >= 'a'").unwrap(), Value::Bool(true));
+        assert_eq!(evaluate_source("\"abc\" < \"abd\"").unwrap(), Value::Bool(true));
+        assert_eq!(evaluate_source("0.0 / 0.0 < 1.0").unwrap(), Value::Bool(false));
+        assert_eq!(evaluate_source("1 < 2 == true").unwrap(), Value::Bool(true));
+        assert_eq!(evaluate_source("1 == 1.0").unwrap(), Value::Bool(true));
+        assert_eq!(evaluate_source("1 == 'a'").unwrap(), Value::Bool(false));
+        assert_eq!(evaluate_source("\"a\" != \"b\"").unwrap(), Value::Bool(true));
+        assert_eq!(evaluate_source("1 < 2.0").unwrap(), Value::Bool(true));
+        assert!(matches!(evaluate_source("1 < \"2\""), Err(RuntimeError::TypeError { .. })));
+        assert!(matches!(evaluate_source("true < false"), Err(RuntimeError::TypeError { .. })));
+        assert!(matches!(evaluate_source("true + 1"), Err(RuntimeError::TypeError { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_if() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        assert_eq!(evaluate_source("if 1 < 2 { 10 } else { 20 }").unwrap(), Value::Int(10));
+        assert_eq!(evaluate_source("if false { 10 } else if true { 20 } else { 30 }").unwrap(), Value::Int(20));
+        assert_eq!(evaluate_source("if false { 10 }").unwrap(), Value::Nil);
+        assert_eq!(evaluate_source("1 + if true { 2 } else { 3 }").unwrap(), Value::Int(3));
+
+        let program = "let x = 5\nlet sign = 0\nif x < 0 {\n  sign = -1\n} else if x > 0 {\n  sign = 1\n}\nsign";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(1)));
+        // Only the branch that is taken gets evaluated
+        assert_eq!(execute_source("let x = 1; if true { x } else { 1 / 0 }").unwrap(), Some(Value::Int(1)));
+
+        assert!(matches!(evaluate_source("if 1 { 2 }"), Err(RuntimeError::NonBooleanCondition { .. })));
+        assert!(matches!(execute_source("let x = 0; while x { x = 1 }"), Err(RuntimeError::NonBooleanCondition { .. })));
+        assert!(matches!(evaluate_source("if \"true\" { 2 } else { 3 }"), Err(RuntimeError::NonBooleanCondition { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_match() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        let program = "fn describe(x) {\n  match x {\n    0 => \"zero\",\n    -1 => \"minus one\",\n    \
+            n if n > 100 => \"big\",\n    n if n < 0 => \"negative ${-n}\",\n    _ => \"other\",\n  }\n}\n\
+            [describe(0), describe(-1), describe(500), describe(-7), describe(5)]";
+        assert_eq!(execute_source(program).unwrap().unwrap().to_string(), "[zero, minus one, big, negative 7, other]");
+
+        assert_eq!(evaluate_source("match 'b' { 'a' => 1, 'b' => 2 }").unwrap(), Value::Int(2));
+        assert_eq!(evaluate_source("match \"rat\" { \"cat\" => 1, \"rat\" => 2 }").unwrap(), Value::Int(2));
+        assert_eq!(evaluate_source("match 1 < 2 { false => 0, true => 1 }").unwrap(), Value::Int(1));
+        assert_eq!(evaluate_source("match 2.5 { -2.5 => 0, 2.5 => 1 }").unwrap(), Value::Int(1));
+        // Values of different types never match, the same as with `==`
+        assert_eq!(evaluate_source("match 1 { 1.0 => 0, '1' => 0, _ => 1 }").unwrap(), Value::Int(1));
+
+        // Only the first arm that matches is evaluated
+        assert_eq!(execute_source("let x = 0; match 1 { _ => x = 1, _ => x = 2 }; x").unwrap(), Some(Value::Int(1)));
+        // Bindings are only visible inside their arm and shadow what is outside
+        let program = "let n = 1; let f = match 5 { n => fn() { n } }; [f(), n]";
+        assert_eq!(execute_source(program).unwrap().unwrap().to_string(), "[5, 1]");
+        assert!(matches!(execute_source("match 5 { n => n }; n"), Err(RuntimeError::UndefinedVariable { .. })));
+        assert_eq!(execute_source("let total = 0; match 5 { n => total += n }; total").unwrap(), Some(Value::Int(5)));
+
+        let input = "let x = 3\nmatch x { 1 => 1, n if n > 5 => 2 }";
+        match execute_source(input) {
+            Err(RuntimeError::NoMatchingArm { span }) => assert_eq!(&input[span.start..span.end], "match x { 1 => 1, n if n > 5 => 2 }"),
+            result => panic!("Expected no arm to match, got {:?}", result)
+        }
+        assert!(matches!(evaluate_source("match 1 { n if n => 1 }"), Err(RuntimeError::NonBooleanCondition { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_try() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        assert_eq!(evaluate_source("try { 6 / 3 } catch (e) { 0 }").unwrap(), Value::Int(2));
+        assert_eq!(evaluate_source("try { 1 / 0 } catch (e) { e }").unwrap(), Value::Str("Division by zero".to_string()));
+        let program = "fn safe(x) { try { 10 / x } catch (e) { nil } }\n[safe(5), safe(0)]";
+        assert_eq!(execute_source(program).unwrap().unwrap().to_string(), "[2, nil]");
+
+        // Whatever the body set up is gone by the time the handler runs
+        let program = "fn deep(n) { 1 + deep(n + 1) }\nlet x = 1\nlet e = try { let x = 2; deep(0) } catch (e) { e }\n[x, e, deep]";
+        assert_eq!(execute_source(program).unwrap().unwrap().to_string(), "[1, Recursion went more than 200 calls deep, <fn deep>]");
+        assert_eq!(execute_source("fn deep(n) { 1 + deep(n + 1) }\ntry { deep(0) } catch (e) { 1 }\ntry { deep(0) } catch (e) { 2 }").unwrap(), Some(Value::Int(2)));
+        assert!(matches!(execute_source("try { 1 } catch (e) { 2 }; e"), Err(RuntimeError::UndefinedVariable { .. })));
+
+        // Errors in the handler aren't caught, and nor is leaving a loop
+        assert!(matches!(evaluate_source("try { 1 / 0 } catch (e) { e + 1 }"), Err(RuntimeError::TypeError { .. })));
+        let program = "let n = 0\nwhile true { n += 1; try { if n == 3 { break }; [][0] } catch (e) { continue } }\nn";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(3)));
+
+        // Running out of steps still stops the program
+        let program = get_program(get_tokens("try { while true {} } catch (e) { 1 }").unwrap()).unwrap();
+        let config = EvalConfig { fuel: Some(100), ..EvalConfig::default() };
+        assert!(matches!(execute_with_config(&program, Path::new("."), config), Err(RuntimeError::BudgetExhausted { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_structs() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        let program = "struct Point { x, y }\nlet p = Point { y: 2, x: 1 }\n[p.x, p.y, p.x + p.y]";
+        assert_eq!(execute_source(program).unwrap().unwrap().to_string(), "[1, 2, 3]");
+        assert_eq!(execute_source("struct P { x, y }; P { y: 2, x: 1 }").unwrap().unwrap().to_string(), "P { x: 1, y: 2 }");
+        assert_eq!(execute_source("struct P { x }; P").unwrap().unwrap().to_string(), "<struct P>");
+        assert_eq!(execute_source("struct P { x }; P { x: 1, x: 2 }.x").unwrap(), Some(Value::Int(2)));
+        assert_eq!(execute_source("struct P { x }; P { x: [1] } == P { x: [1] }").unwrap(), Some(Value::Bool(true)));
+        assert_eq!(execute_source("struct P { x }; P { x: 1 } == P { x: 2 }").unwrap(), Some(Value::Bool(false)));
+        // Fields can hold anything, including other structs
+        let program = "struct Line { from, to }; struct P { x, y }\n\
+            let line = Line { from: P { x: 0, y: 0 }, to: P { x: 3, y: 4 } }\nline.to.x * line.to.y";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(12)));
+        // Instances are values, so changing the variable they came from
+        // doesn't change them
+        let program = "struct P { x }; let x = 1; let p = P { x: x }; x = 2; p.x";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(1)));
+
+        let input = "struct P { x }\nP { x: 1, y: 2 }";
+        match execute_source(input) {
+            Err(RuntimeError::UnknownField { name, span }) => {
+                assert_eq!(name, "y");
+                assert_eq!(&input[span.start..span.end], "P { x: 1, y: 2 }");
+            }
+            result => panic!("Expected an unknown field, got {:?}", result)
+        }
+        let input = "struct P { x }\nlet p = P { x: 1 }\np.z + 1";
+        match execute_source(input) {
+            Err(RuntimeError::UnknownField { name, span }) => {
+                assert_eq!(name, "z");
+                assert_eq!(&input[span.start..span.end], "p.z");
+            }
+            result => panic!("Expected an unknown field, got {:?}", result)
+        }
+        assert!(matches!(execute_source("struct P { x, y }; P { x: 1 }"), Err(RuntimeError::MissingField { name, .. }) if name == "y"));
+        assert!(matches!(execute_source("P { x: 1 }"), Err(RuntimeError::UndefinedVariable { .. })));
+        assert!(matches!(execute_source("let P = 1; P { x: 1 }"), Err(RuntimeError::TypeMismatch { .. })));
+        assert!(matches!(execute_source("let t = (1,); t.x"), Err(RuntimeError::TypeMismatch { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_enums() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        let program = "enum Shape { Circle(r), Rect(w, h), Empty }\n\
+            fn area(shape) {\n  match shape {\n    Shape.Circle(r) => 3 * r * r,\n    Shape.Rect(w, h) => w * h,\n    \
+            Shape.Empty => 0,\n  }\n}\n\
+            [area(Shape.Circle(2)), area(Shape.Rect(3, 4)), area(Shape.Empty)]";
+        assert_eq!(execute_source(program).unwrap().unwrap().to_string(), "[12, 12, 0]");
+
+        let declaration = "enum Option { Some(value), None }\n";
+        let run = |input: &str| execute_source(&format!("{}{}", declaration, input));
+        assert_eq!(run("Option.Some((1, 'a'))").unwrap().unwrap().to_string(), "Option.Some((1, a))");
+        assert_eq!(run("[Option.None, Option.Some, Option]").unwrap().unwrap().to_string(), "[Option.None, <fn Option.Some>, <enum Option>]");
+        assert_eq!(run("Option.Some(1) == Option.Some(1)").unwrap(), Some(Value::Bool(true)));
+        assert_eq!(run("Option.Some(1) == Option.Some(2)").unwrap(), Some(Value::Bool(false)));
+        assert_eq!(run("Option.None == Option.None").unwrap(), Some(Value::Bool(true)));
+        assert_eq!(run("Option.Some == Option.Some").unwrap(), Some(Value::Bool(true)));
+        // Patterns nest, and literals in them have to be equal
+        let program = "fn f(o) { match o { Option.Some(Option.Some(0)) => \"zero\", Option.Some(Option.Some(n)) => n, \
+            Option.Some(_) => \"one\", _ => \"none\" } }\n\
+            [f(Option.Some(Option.Some(0))), f(Option.Some(Option.Some(5))), f(Option.Some(Option.None)), f(Option.None)]";
+        assert_eq!(run(program).unwrap().unwrap().to_string(), "[zero, 5, one, none]");
+        // A pattern only matches the variant of the enum it names, with the
+        // same number of fields
+        assert_eq!(run("enum Other { Some(value) }; match Other.Some(1) { Option.Some(x) => 0, _ => 1 }").unwrap(), Some(Value::Int(1)));
+        assert_eq!(run("match Option.Some(1) { Option.Some => 0, Option.Some(_, _) => 1, _ => 2 }").unwrap(), Some(Value::Int(2)));
+        assert_eq!(run("match 1 { Option.None => 0, _ => 1 }").unwrap(), Some(Value::Int(1)));
+
+        let input = format!("{}Option.Any(1)", declaration);
+        match execute_source(&input) {
+            Err(RuntimeError::UnknownVariant { name, span }) => {
+                assert_eq!(name, "Any");
+                assert_eq!(&input[span.start..span.end], "Option.Any");
+            }
+            result => panic!("Expected an unknown variant, got {:?}", result)
+        }
+        assert!(matches!(run("Option.Some(1, 2)"), Err(RuntimeError::ArityMismatch { expected: 1, found: 2, .. })));
+        assert!(matches!(run("Option.None()"), Err(RuntimeError::NotCallable { .. })));
+        assert!(matches!(run("Option.Some(1).value"), Err(RuntimeError::TypeMismatch { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_conditionals() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        assert_eq!(evaluate_source("1 < 2 ? 10 : 20").unwrap(), Value::Int(10));
+        assert_eq!(evaluate_source("1 > 2 ? 10 : 2 > 1 ? 20 : 30").unwrap(), Value::Int(20));
+        assert_eq!(evaluate_source("1 + (false ? 2 : 3) * 2").unwrap(), Value::Int(7));
+
+        let program = "fn abs(x) { x < 0 ? -x : x }\nabs(-4) + abs(3)";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(7)));
+        // Only the branch that is picked gets evaluated
+        assert_eq!(evaluate_source("true ? 1 : 1 / 0").unwrap(), Value::Int(1));
+        assert_eq!(execute_source("let x = 0; false ? x = 1 : 2; x").unwrap(), Some(Value::Int(0)));
+
+        assert!(matches!(evaluate_source("1 ? 2 : 3"), Err(RuntimeError::NonBooleanCondition { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_nil() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        assert_eq!(evaluate_source("nil").unwrap(), Value::Nil);
+        assert_eq!(evaluate_source("[nil, {}]").unwrap().to_string(), "[nil, nil]");
+        assert_eq!(evaluate_source("nil == nil").unwrap(), Value::Bool(true));
+        assert_eq!(evaluate_source("nil != 0").unwrap(), Value::Bool(true));
+        assert_eq!(evaluate_source("match nil { 0 => 1, nil => 2 }").unwrap(), Value::Int(2));
+
+        assert_eq!(evaluate_source("nil ?? 1").unwrap(), Value::Int(1));
+        assert_eq!(evaluate_source("0 ?? 1").unwrap(), Value::Int(0));
+        assert_eq!(evaluate_source("false ?? true").unwrap(), Value::Bool(false));
+        assert_eq!(evaluate_source("nil ?? nil ?? 3").unwrap(), Value::Int(3));
+        assert_eq!(evaluate_source("(nil ?? 2) * 5").unwrap(), Value::Int(10));
+        // The fallback is only evaluated when it is needed
+        assert_eq!(evaluate_source("1 ?? 1 / 0").unwrap(), Value::Int(1));
+        assert_eq!(execute_source("let x = 0; 5 ?? (x = 1); x").unwrap(), Some(Value::Int(0)));
+        let program = "let scores = { \"a\": 1, \"b\": nil }\n[scores[\"a\"] ?? 0, scores[\"b\"] ?? 0]";
+        assert_eq!(execute_source(program).unwrap().unwrap().to_string(), "[1, 0]");
+
+        // Nil takes no operator but `==`, `!=` and `in`, and the error is put
+        // down to the operator like for any other type
+        let input = "let x = nil\nx * 2 + 1";
+        match execute_source(input) {
+            Err(RuntimeError::TypeError { left: Type::Nil, right: Type::Int, span, .. }) => {
+                assert_eq!(&input[span.start..span.end], "*");
+            }
+            result => panic!("Expected a type error, got {:?}", result)
+        }
+        let error = evaluate_source("1 + nil").unwrap_err();
+        assert_eq!(error.to_string(), "Can't apply '+' to int and nil at line 1, character 3");
+        let error = evaluate_source("[1] + [2 < nil]").unwrap_err();
+        assert_eq!(error.to_string(), "Can't apply '<' to int and nil at line 1, character 10");
+        assert!(matches!(evaluate_source("-nil"), Err(RuntimeError::NilOperand { .. })));
+        assert!(matches!(execute_source("let x = nil; x += 1"), Err(RuntimeError::TypeError { left: Type::Nil, .. })));
+        assert!(matches!(execute_source("let xs = [nil]; xs[0] += 1"), Err(RuntimeError::TypeError { left: Type::Nil, .. })));
+        assert_eq!(evaluate_source("[nil == nil, 1 != nil, nil in [nil]]").unwrap().to_string(), "[true, true, true]");
+        assert!(matches!(evaluate_source("if nil { 1 }"), Err(RuntimeError::NonBooleanCondition { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ranges() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        assert_eq!(evaluate_source("0..10").unwrap(), Value::Range(RangeValue { start: 0, end: 10, inclusive: false }));
+        assert_eq!(evaluate_source("1 + 1..=2 * 3").unwrap().to_string(), "2..=6");
+        assert_eq!(evaluate_source("0..2 == 0..2").unwrap(), Value::Bool(true));
+        assert_eq!(evaluate_source("0..2 == 0..=1").unwrap(), Value::Bool(false));
+        assert_eq!(evaluate_source("[len(0..10), len(0..=10), len(5..2)]").unwrap().to_string(), "[10, 11, 0]");
+        assert_eq!(evaluate_source("len(9223372036854775806..=9223372036854775807)").unwrap(), Value::Int(2));
+        assert!(matches!(evaluate_source("len(-9223372036854775807 - 1..=9223372036854775807)"), Err(RuntimeError::Overflow { .. })));
+        assert!(matches!(evaluate_source("0..1.5"), Err(RuntimeError::TypeMismatch { .. })));
+
+        let program = "let r = 1..=3\nlet sum = 0\nfor i in r { sum += i }\nsum";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(6)));
+        let program = "let count = 0\nfor i in 3..0 { count += 1 }\ncount";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(0)));
+        assert!(matches!(execute_source("for x in 1.5 {}"), Err(RuntimeError::TypeMismatch { .. })));
+
+        assert_eq!(evaluate_source("[1, 2, 3, 4][1..3]").unwrap().to_string(), "[2, 3]");
+        assert_eq!(evaluate_source("[1, 2, 3, 4][1..=3]").unwrap().to_string(), "[2, 3, 4]");
+        assert_eq!(evaluate_source("[1, 2, 3][3..3]").unwrap().to_string(), "[]");
+        assert_eq!(evaluate_source("[1, 2, 3][2..1]").unwrap().to_string(), "[]");
+        let input = "[1, 2, 3][1..4]";
+        match evaluate_source(input) {
+            Err(RuntimeError::IndexOutOfBounds { index: 4, length: 3, span }) => assert_eq!(&input[span.start..span.end], "1..4"),
+            result => panic!("Expected an index out of bounds, got {:?}", result)
+        }
+        assert!(matches!(evaluate_source("[1, 2, 3][-1..2]"), Err(RuntimeError::IndexOutOfBounds { index: -1, .. })));
+        assert!(matches!(evaluate_source("[1, 2, 3][4..2]"), Err(RuntimeError::IndexOutOfBounds { index: 4, .. })));
+        assert!(matches!(evaluate_source("[1, 2, 3][0..=3]"), Err(RuntimeError::IndexOutOfBounds { index: 3, .. })));
+
+        assert_eq!(evaluate_source("5 in 0..10").unwrap(), Value::Bool(true));
+        assert_eq!(evaluate_source("10 in 0..10").unwrap(), Value::Bool(false));
+        assert_eq!(evaluate_source("10 in 0..=10").unwrap(), Value::Bool(true));
+        assert_eq!(evaluate_source("\"a\" in 0..10").unwrap(), Value::Bool(false));
+        assert_eq!(evaluate_source("2 in [1, 2, 3]").unwrap(), Value::Bool(true));
+        assert_eq!(evaluate_source("\"2\" in [1, 2, 3]").unwrap(), Value::Bool(false));
+        assert_eq!(evaluate_source("\"b\" in { \"a\": 1, \"b\": 2 }").unwrap(), Value::Bool(true));
+        assert_eq!(evaluate_source("1 in { \"a\": 1 }").unwrap(), Value::Bool(false));
+        assert_eq!(evaluate_source("[1] in { \"a\": 1 }").unwrap(), Value::Bool(false));
+        assert!(matches!(evaluate_source("1 in 1"), Err(RuntimeError::TypeError { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_destructuring() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        assert_eq!(execute_source("let (a, b) = (1, 2); a * 10 + b").unwrap(), Some(Value::Int(12)));
+        assert_eq!(execute_source("let [head, rest...] = [1, 2, 3]; (head, rest)").unwrap().unwrap().to_string(), "(1, [2, 3])");
+        assert_eq!(execute_source("let [x, rest...] = [1]; rest").unwrap().unwrap().to_string(), "[]");
+        assert_eq!(execute_source("let [a, _, c] = [1, 2, 3]; a + c").unwrap(), Some(Value::Int(4)));
+        assert_eq!(execute_source("let (a, [b, (c,)]) = (1, [2, (3,)]); a + b + c").unwrap(), Some(Value::Int(6)));
+        assert_eq!(execute_source("let [_...] = [1, 2]; let () = (); 0").unwrap(), Some(Value::Int(0)));
+        // The initializer is evaluated before any of the names are bound
+        assert_eq!(execute_source("let a = 1; let b = 2; let (a, b) = (b, a); a - b").unwrap(), Some(Value::Int(1)));
+        let program = "fn f() { let (x, y) = (1, 2); x + y }\nlet x = 10\nf() + x";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(13)));
+
+        let input = "let (a, b) = (1, 2, 3)";
+        match execute_source(input) {
+            Err(RuntimeError::PatternMismatch { span }) => assert_eq!(&input[span.start..span.end], "(a, b)"),
+            result => panic!("Expected a pattern mismatch, got {:?}", result)
+        }
+        assert!(matches!(execute_source("let [a, b] = [1]"), Err(RuntimeError::PatternMismatch { .. })));
+        assert!(matches!(execute_source("let [a, b...] = []"), Err(RuntimeError::PatternMismatch { .. })));
+        assert!(matches!(execute_source("let [a] = [1, 2]"), Err(RuntimeError::PatternMismatch { .. })));
+        assert!(matches!(execute_source("let (a, b) = [1, 2]"), Err(RuntimeError::PatternMismatch { .. })));
+        assert!(matches!(execute_source("let [a] = (1,)"), Err(RuntimeError::PatternMismatch { .. })));
+        assert!(matches!(execute_source("let (a, [b]) = (1, [])"), Err(RuntimeError::PatternMismatch { .. })));
+
+        assert_eq!(evaluate_source("match [1, 2, 3] { [] => 0, [x] => x, [x, rest...] => len(rest) }").unwrap(), Value::Int(2));
+        assert_eq!(evaluate_source("match (1, nil) { (x, nil) => x, _ => 0 }").unwrap(), Value::Int(1));
+        assert_eq!(evaluate_source("match (1, 2) { (x, 3) => x, [x, y] => x, (x, y) if x > y => x, _ => 9 }").unwrap(), Value::Int(9));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_spread() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        assert_eq!(execute_source("let xs = [2, 3]; [1, ...xs, 4, ...xs]").unwrap().unwrap().to_string(), "[1, 2, 3, 4, 2, 3]");
+        assert_eq!(evaluate_source("[...[], ...[[1]]]").unwrap().to_string(), "[[1]]");
+        assert_eq!(evaluate_source("len([...[1, 2], ...[3]])").unwrap(), Value::Int(3));
+        let program = "fn add(a, b, c) { a * 100 + b * 10 + c }\nlet args = [2, 3]\n[add(...args, 4), add(1, ...args), add(...[], 1, ...args)]";
+        assert_eq!(execute_source(program).unwrap().unwrap().to_string(), "[234, 123, 123]");
+        assert_eq!(evaluate_source("len(...[[1, 2]])").unwrap(), Value::Int(2));
+
+        // The arity is only known once the spread arrays have been evaluated
+        let program = "fn f(a, b) { a + b }\nf(...[1, 2, 3])";
+        assert!(matches!(execute_source(program), Err(RuntimeError::ArityMismatch { expected: 2, found: 3, .. })));
+        let program = "fn f(a, b) { a + b }\nf(1, ...[])";
+        assert!(matches!(execute_source(program), Err(RuntimeError::ArityMismatch { expected: 2, found: 1, .. })));
+        assert!(matches!(evaluate_source("[...(1, 2)]"), Err(RuntimeError::TypeMismatch { .. })));
+        assert!(matches!(evaluate_source("len(...\"ab\")"), Err(RuntimeError::TypeMismatch { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pipeline() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        let program = "fn double(x) { x * 2 }\nfn inc(x) { x + 1 }\n3 |> double |> inc";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(7)));
+        let program = "fn add(n) { fn(x) { x + n } }\n1 + 1 |> add(10) |> fn(x) { x * 3 }";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(36)));
+        assert_eq!(evaluate_source("[1, 2, 3] |> len").unwrap(), Value::Int(3));
+        assert!(matches!(evaluate_source("1 |> 2"), Err(RuntimeError::NotCallable { .. })));
+        assert!(matches!(execute_source("fn f(a, b) { a }\n1 |> f"), Err(RuntimeError::ArityMismatch { expected: 2, found: 1, .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_methods() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        assert_eq!(evaluate_source("[1, 2, 3].len()").unwrap(), Value::Int(3));
+        assert_eq!(evaluate_source("\"héllo\".len()").unwrap(), Value::Int(5));
+        assert_eq!(evaluate_source("(\"Hi\".upper(), \"Hi\".lower())").unwrap().to_string(), "(HI, hi)");
+        assert_eq!(evaluate_source("{ \"b\": 1, \"a\": 2 }.keys()").unwrap().to_string(), "[a, b]");
+        assert_eq!(evaluate_source("[(1, 2).len(), (0..=4).len()]").unwrap().to_string(), "[2, 5]");
+        assert_eq!(execute_source("let s = \"ab\"; s.upper().len()").unwrap(), Some(Value::Int(2)));
+        // Methods aren't variables, so a global with the same name doesn't get in the way
+        assert_eq!(execute_source("let len = 0; [1].len()").unwrap(), Some(Value::Int(1)));
+        // A field holding a function is still called like one
+        assert_eq!(execute_source("struct S { len }\nS { len: fn() { 7 } }.len()").unwrap(), Some(Value::Int(7)));
+        assert_eq!(execute_source("enum E { A(x) }\nE.A(1)").unwrap().unwrap().to_string(), "E.A(1)");
+
+        let input = "[1].upper()";
+        match evaluate_source(input) {
+            Err(RuntimeError::UnknownMethod { name, span }) => {
+                assert_eq!(name, "upper");
+                assert_eq!(&input[span.start..span.end], "[1].upper");
+            }
+            result => panic!("Expected an unknown method, got {:?}", result)
+        }
+        assert!(matches!(evaluate_source("1.5.len()"), Err(RuntimeError::UnknownMethod { .. })));
+        assert!(matches!(evaluate_source("\"a\".len(1)"), Err(RuntimeError::ArityMismatch { expected: 0, found: 1, .. })));
+        assert!(matches!(evaluate_source("[].len"), Err(RuntimeError::TypeMismatch { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_array_methods() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        assert_eq!(evaluate_source("[1, 2, 3].map(fn(x) { x * x })").unwrap().to_string(), "[1, 4, 9]");
+        assert_eq!(evaluate_source("[1, 2, 3, 4].filter(fn(x) { x % 2 == 0 })").unwrap().to_string(), "[2, 4]");
+        assert_eq!(evaluate_source("[1, 2, 3, 4].reduce(fn(total, x) { total + x }, 0)").unwrap(), Value::Int(10));
+        assert_eq!(evaluate_source("[].reduce(fn(total, x) { total + x }, 0)").unwrap(), Value::Int(0));
+        let program = "fn add(a, b) { a + b }\nlet offset = 10\n[1, 2].map(fn(x) { add(x, offset) }).reduce(add, 0)";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Int(23)));
+        assert_eq!(evaluate_source("[3, 1, 2].sort()").unwrap().to_string(), "[1, 2, 3]");
+        assert_eq!(evaluate_source("[\"b\", \"c\", \"a\"].sort().reverse()").unwrap().to_string(), "[c, b, a]");
+        assert_eq!(evaluate_source("[1 / 2, 1 / 3, 1].sort()").unwrap().to_string(), "[1/3, 1/2, 1]");
+        assert_eq!(evaluate_source("([1, 2].contains(2), [1, 2].contains(\"2\"))").unwrap().to_string(), "(true, false)");
+
+        // `sort`, `reverse`, `map` and `filter` make new arrays, while `push` and `pop` change the one they're called on
+        assert_eq!(execute_source("let xs = [2, 1]; let ys = xs.sort(); [xs, ys]").unwrap().unwrap().to_string(), "[[2, 1], [1, 2]]");
+        let program = "let xs = [1]; let ys = xs; xs.push(2); xs.push(3); let last = xs.pop(); [ys, last, [].pop()]";
+        assert_eq!(execute_source(program).unwrap().unwrap().to_string(), "[[1, 2], 3, nil]");
+        // The function going through the elements can change the array without changing what it goes through
+        let program = "let xs = [1, 2]; let seen = xs.map(fn(x) { xs.push(x); x }); [seen, xs]";
+        assert_eq!(execute_source(program).unwrap().unwrap().to_string(), "[[1, 2], [1, 2, 1, 2]]");
+
+        assert!(matches!(evaluate_source("[1].map(1)"), Err(RuntimeError::TypeMismatch { .. })));
+        assert!(matches!(evaluate_source("[1].filter(fn(x) { x })"), Err(RuntimeError::TypeMismatch { .. })));
+        assert!(matches!(evaluate_source("[1, \"a\"].sort()"), Err(RuntimeError::TypeMismatch { .. })));
+        assert!(matches!(evaluate_source("[1].map(fn(a, b) { a })"), Err(RuntimeError::ArityMismatch { .. })));
+        assert!(matches!(evaluate_source("[1, 0].map(fn(x) { 1 / x })"), Err(RuntimeError::DivisionByZero { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_string_methods() -> Result<(), String> {
+        assert_eq!(evaluate_source("\"  héllo\\n \".trim()").unwrap(), Value::Str("héllo".to_string()));
+        assert_eq!(evaluate_source("\"\u{3000}wide\u{3000}\".trim()").unwrap(), Value::Str("wide".to_string()));
+        assert_eq!(evaluate_source("(\"ÀB\".lower(), \"straße\".upper())").unwrap().to_string(), "(àb, STRASSE)");
+        assert_eq!(evaluate_source("\"a,b,,c\".split(\",\")").unwrap().to_string(), "[a, b, , c]");
+        assert_eq!(evaluate_source("\"a b\".split(' ').len()").unwrap(), Value::Int(2));
+        assert_eq!(evaluate_source("\"añ😀\".split(\"\")").unwrap().to_string(), "[a, ñ, 😀]");
+        assert_eq!(evaluate_source("\"añ😀\".chars()").unwrap(), Value::array(vec![Value::Char('a'), Value::Char('ñ'), Value::Char('😀')]));
+        assert_eq!(evaluate_source("[\"a\", 1, 'c'].join(\"-\")").unwrap(), Value::Str("a-1-c".to_string()));
+        assert_eq!(evaluate_source("[].join(\", \")").unwrap(), Value::Str(String::new()));
+        assert_eq!(evaluate_source("\"a-b-c\".split(\"-\").join(\"+\")").unwrap(), Value::Str("a+b+c".to_string()));
+        assert_eq!(evaluate_source("(\"rat\".contains(\"at\"), \"rat\".contains('z'), \"rat\".contains(\"\"))").unwrap().to_string(), "(true, false, true)");
+        assert_eq!(evaluate_source("\"a.b.c\".replace(\".\", \"::\")").unwrap(), Value::Str("a::b::c".to_string()));
+        assert_eq!(evaluate_source("\"naïve\".replace('ï', \"i\")").unwrap(), Value::Str("naive".to_string()));
+        // Characters are indexed by position rather than by byte
+        assert_eq!(evaluate_source("(\"añ😀\"[2], \"añ😀\"[1..3], \"añ😀\".len())").unwrap().to_string(), "(😀, ñ😀, 3)");
+
+        assert!(matches!(evaluate_source("\"a\".split(1)"), Err(RuntimeError::TypeMismatch { .. })));
+        assert!(matches!(evaluate_source("[1].join(1)"), Err(RuntimeError::TypeMismatch { .. })));
+        assert!(matches!(evaluate_source("\"a\".replace(\"a\", 'b')"), Err(RuntimeError::TypeMismatch { .. })));
+        assert!(matches!(evaluate_source("\"a\".trim(1)"), Err(RuntimeError::ArityMismatch { expected: 0, found: 1, .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_string_indexing() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        assert_eq!(evaluate_source("\"hello\"[0]").unwrap(), Value::Char('h'));
+        assert_eq!(evaluate_source("\"héllo\"[1]").unwrap(), Value::Char('é'));
+        assert_eq!(evaluate_source("\"日本語\"[2]").unwrap(), Value::Char('語'));
+        assert_eq!(evaluate_source("\"héllo\"[1..3]").unwrap(), Value::Str("él".to_string()));
+        assert_eq!(evaluate_source("\"héllo\"[1..=4]").unwrap(), Value::Str("éllo".to_string()));
+        assert_eq!(evaluate_source("\"abc\"[3..3]").unwrap(), Value::Str(String::new()));
+        assert_eq!(evaluate_source("\"abc\"[2..1]").unwrap(), Value::Str(String::new()));
+        assert_eq!(execute_source("let s = \"abc\"; s[0..len(s)]").unwrap(), Some(Value::Str("abc".to_string())));
+
+        let input = "\"héllo\"[5]";
+        match evaluate_source(input) {
+            Err(RuntimeError::IndexOutOfBounds { index: 5, length: 5, span }) => assert_eq!(&input[span.start..span.end], "5"),
+            result => panic!("Expected an index out of bounds, got {:?}", result)
+        }
+        assert!(matches!(evaluate_source("\"abc\"[-1]"), Err(RuntimeError::IndexOutOfBounds { index: -1, length: 3, .. })));
+        assert!(matches!(evaluate_source("\"abc\"[1..4]"), Err(RuntimeError::IndexOutOfBounds { index: 4, length: 3, .. })));
+        assert!(matches!(evaluate_source("\"abc\"[-1..2]"), Err(RuntimeError::IndexOutOfBounds { index: -1, .. })));
+        assert!(matches!(evaluate_source("\"\"[0]"), Err(RuntimeError::IndexOutOfBounds { index: 0, length: 0, .. })));
+        assert!(matches!(evaluate_source("\"abc\"[\"a\"]"), Err(RuntimeError::NonIntegerIndex { .. })));
+        // Strings can't be changed in place
+        assert!(matches!(execute_source("let s = \"abc\"; s[0] = 'x'"), Err(RuntimeError::TypeMismatch { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_operator_overloading() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+        let point = "\
+struct Point { x, y }
+impl Point {
+  fn __add__(a, b) { Point { x: a.x + b.x, y: a.y + b.y } }
+  fn __mul__(p, k) { Point { x: p.x * k, y: p.y * k } }
+  fn __neg__(p) { Point { x: -p.x, y: -p.y } }
+  fn __eq__(a, b) { a.x == b.x }
+  fn __lt__(a, b) { a.x < b.x }
+  fn norm(p) { p.x * p.x + p.y * p.y }
+}
+let a = Point { x: 1, y: 2 }
+let b = Point { x: 3, y: 4 }
+";
+        let run = |input: &str| execute_source(&format!("{}{}", point, input)).map(|value| value.unwrap().to_string());
+
+        assert_eq!(run("a + b").unwrap(), "Point { x: 4, y: 6 }");
+        assert_eq!(run("a + b * 2").unwrap(), "Point { x: 7, y: 10 }");
+        assert_eq!(run("-a").unwrap(), "Point { x: -1, y: -2 }");
+        assert_eq!(run("(a == Point { x: 1, y: 5 }, a != b, a < b)").unwrap(), "(true, true, true)");
+        assert_eq!(run("let p = [a]; p[0] += b; p[0]").unwrap(), "Point { x: 4, y: 6 }");
+        assert_eq!(run("(a + b).norm()").unwrap(), "52");
+        // Only the operand on the left decides which method is called
+        assert!(matches!(run("2 * a"), Err(RuntimeError::TypeError { .. })));
+        assert!(matches!(run("a - b"), Err(RuntimeError::TypeError { .. })));
+        assert!(matches!(run("a.scale(2)"), Err(RuntimeError::UnknownMethod { .. })));
+        assert!(matches!(run("a.norm(1)"), Err(RuntimeError::ArityMismatch { expected: 0, found: 1, .. })));
+
+        let program = "enum Sign { Plus, Minus }\nimpl Sign {\n  fn flip(s) { match s { Sign.Plus => Sign.Minus, _ => Sign.Plus } }\n}\nSign.Plus.flip().flip()";
+        assert_eq!(execute_source(program).unwrap().unwrap().to_string(), "Sign.Plus");
+        // Without an `__eq__`, values are still compared field by field
+        let program = "struct P { x }\nimpl P { fn __eq__(a, b) { 1 } }\nstruct Q { x }\n[Q { x: 1 } == Q { x: 1 }, P { x: 1 } == P { x: 2 }]";
+        assert_eq!(execute_source(program).unwrap().unwrap().to_string(), "[true, 1]");
+        let program = "struct P { x }\nimpl P { fn __eq__(a, b) { 1 } }\nP { x: 1 } != P { x: 1 }";
+        assert!(matches!(execute_source(program), Err(RuntimeError::TypeMismatch { .. })));
+        let program = "struct P { x }\nimpl P { fn __add__(a) { a } }\nP { x: 1 } + 1";
+        assert!(matches!(execute_source(program), Err(RuntimeError::ArityMismatch { expected: 1, found: 2, .. })));
+        assert!(matches!(execute_source("impl P { fn f(p) {} }"), Err(RuntimeError::UndefinedVariable { .. })));
+        assert!(matches!(execute_source("let P = 1\nimpl P { fn f(p) {} }"), Err(RuntimeError::TypeMismatch { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_traits() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+        let shapes = "\
+trait Shape {
+  fn area(s)
+  fn scale(s, k)
+}
+struct Square { side }
+struct Rect { w, h }
+impl Shape for Square {
+  fn area(s) { s.side * s.side }
+  fn scale(s, k) { Square { side: s.side * k } }
+}
+impl Shape for Rect {
+  fn area(r) { r.w * r.h }
+  fn scale(r, k) { Rect { w: r.w * k, h: r.h * k } }
+}
+";
+        let run = |input: &str| execute_source(&format!("{}{}", shapes, input)).map(|value| value.unwrap().to_string());
+
+        let program = "let shapes = [Square { side: 2 }, Rect { w: 2, h: 3 }]\nlet total = 0\nfor i in 0..2 { total += shapes[i].area() }\ntotal";
+        assert_eq!(run(program).unwrap(), "10");
+        assert_eq!(run("Rect { w: 1, h: 2 }.scale(3).area()").unwrap(), "18");
+        // Other methods can still be given with a plain `impl`
+        assert_eq!(run("impl Square { fn perimeter(s) { 4 * s.side } }\nSquare { side: 5 }.perimeter()").unwrap(), "20");
+
+        let run = |input: &str| execute_source(&format!("trait Shape {{ fn area(s) fn scale(s, k) }}\nstruct P {{ x }}\n{}", input));
+        let program = "impl Shape for P { fn area(p) { 0 } }";
+        match run(program) {
+            Err(RuntimeError::MissingMethod { name, span }) => {
+                assert_eq!(name, "scale");
+                assert_eq!((span.line, span.character), (3, 1));
+            }
+            result => panic!("Expected a missing method, got {:?}", result),
+        }
+        let program = "impl Shape for P { fn area(p) { 0 } fn scale(p) { p } }";
+        assert!(matches!(run(program), Err(RuntimeError::ArityMismatch { expected: 2, found: 1, .. })));
+        let program = "impl Shape for P { fn area(p) { 0 } fn scale(p, k) { p } fn extra(p) { p } }";
+        assert!(matches!(run(program), Err(RuntimeError::UnknownMethod { name, .. }) if name == "extra"));
+        assert!(matches!(run("impl Other for P { fn area(p) { 0 } }"), Err(RuntimeError::UndefinedVariable { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_modules() -> Result<(), String> {
+        let directory = std::env::temp_dir().join(format!("rat-modules-{}", std::process::id()));
+        fs::create_dir_all(directory.join("lib")).unwrap();
+        for (path, source) in [
+            ("math.rat", "let pi = 3\nfn square(x) { x * x }\nfn area(r) { pi * square(r) }"),
+            ("lib/twice.rat", "import \"../math.rat\"\nfn quad(x) { math.square(x) * math.square(x) }"),
+            ("a.rat", "import b"),
+            ("b.rat", "import a"),
+            ("bad.rat", "let = 1"),
+            ("failing.rat", "1 / 0"),
+        ] {
+            fs::write(directory.join(path), source).unwrap();
+        }
+        let run = |input: &str| execute_in(&get_program(get_tokens(input).unwrap()).unwrap(), &directory);
+
+        assert_eq!(run("import math\n(math.area(2), math.pi)").unwrap().unwrap().to_string(), "(12, 3)");
+        assert_eq!(run("import \"lib/twice.rat\"\ntwice.quad(2)").unwrap(), Some(Value::Int(16)));
+        assert_eq!(run("import math\nmath").unwrap().unwrap().to_string(), "<module math>");
+        // A module has globals of its own, and doesn't see those of its importer
+        assert_eq!(run("let pi = 4\nimport math\n(pi, math.area(1))").unwrap().unwrap().to_string(), "(4, 3)");
+        assert!(matches!(run("fn r() { 1 }\nimport math\nmath.r"), Err(RuntimeError::UnknownField { name, .. }) if name == "r"));
+        assert!(matches!(run("import math\nmath.len"), Err(RuntimeError::UnknownField { .. })));
+
+        match run("\nimport missing") {
+            Err(RuntimeError::ModuleNotFound { path, span }) => {
+                assert_eq!(path, "missing.rat");
+                assert_eq!((span.line, span.character), (2, 1));
+            }
+            result => panic!("Expected a missing module, got {:?}", result),
+        }
+        let [a, b] = ["a.rat", "b.rat"].map(|path| directory.join(path).canonicalize().unwrap());
+        assert!(matches!(run("import a"), Err(RuntimeError::ImportCycle { cycle, .. }) if cycle == [a.clone(), b, a]));
+        assert!(matches!(run("import bad"), Err(RuntimeError::InvalidModule { path, .. }) if path == "bad.rat"));
+        assert!(matches!(run("import failing"), Err(RuntimeError::DivisionByZero { .. })));
+
+        // The file being run is part of a cycle too, so it isn't run again
+        // before the cycle is found
+        let path = directory.join("c1.rat");
+        fs::write(&path, "println(\"c1 runs\")\nimport c2").unwrap();
+        fs::write(directory.join("c2.rat"), "import c1").unwrap();
+        let output = Captured::default();
+        let mut interpreter = Interpreter::new(EvalConfig::default());
+        interpreter.set_output(Box::new(output.clone()));
+        interpreter.set_file(&path);
+        let error = interpreter.execute(&get_program(get_tokens(&fs::read_to_string(&path).unwrap()).unwrap()).unwrap());
+        assert_eq!(String::from_utf8(output.0.borrow().clone()).unwrap(), "c1 runs\n");
+        let [c1, c2] = ["c1.rat", "c2.rat"].map(|path| directory.join(path).canonicalize().unwrap().display().to_string());
+        match error {
+            Err(error @ RuntimeError::ImportCycle { .. }) => {
+                assert_eq!(error.message(), format!("Modules import each other in a cycle: {} -> {} -> {}", c1, c2, c1));
+                assert_eq!((error.span().line, error.span().character), (1, 1));
+            }
+            result => panic!("Expected an import cycle, got {:?}", result),
+        }
+
+        fs::remove_dir_all(&directory).unwrap();
+        Ok(())
+    }
+
+    // Keeps what's written where the test can still get at it once the
+    // interpreter has it
+    #[derive(Clone, Default)]
+    struct Captured(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buffer)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct Broken;
+
+    impl Write for Broken {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "pipe closed"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_output() -> Result<(), String> {
+        let run = |input: &str| {
+            let captured = Captured::default();
+            let mut interpreter = Interpreter::default();
+            interpreter.set_output(Box::new(captured.clone()));
+            let result = interpreter.execute(&get_program(get_tokens(input).unwrap()).unwrap());
+            let output = String::from_utf8(captured.0.borrow().clone()).unwrap();
+            (result, output)
+        };
+
+        let (result, output) = run("println(1)");
+        assert_eq!((result.unwrap(), output.as_str()), (Some(Value::Nil), "1\n"));
+        assert_eq!(run("print(\"a\"); print([1, 2]); println(\"\"); println(1.5)").1, "a[1, 2]\n1.5\n");
+        assert_eq!(run("for i in 0..3 { print(i) }").1, "012");
+        // What was written before an error stays written
+        let (result, output) = run("println(\"before\"); 1 / 0; println(\"after\")");
+        assert!(matches!(result, Err(RuntimeError::DivisionByZero { .. })));
+        assert_eq!(output, "before\n");
+        assert!(matches!(run("print()").0, Err(RuntimeError::ArityMismatch { expected: 1, found: 0, .. })));
+        // More than one value is a format string and what goes in it
+        assert_eq!(run("let x = 2.5\nprintln(\"x = {:.2}, y = {}\", x, [1])\nprint(\"{:>3}|\", 7)").1, "x = 2.50, y = [1]\n  7|");
+        assert_eq!(run("println(\"{}\")").1, "{}\n");
+        let input = "let x = 1\nprintln(x, 2)";
+        match run(input).0 {
+            Err(error @ RuntimeError::InvalidFormat { .. }) => {
+                assert_eq!(error.message(), "The format string can't be used: the first argument has to be a format string");
+                assert_eq!(&input[error.span().start..error.span().end], "println(x, 2)");
+            }
+            result => panic!("Expected an invalid format, got {:?}", result),
+        }
+        assert_eq!(run("println(1)").1, "1\n");
+        assert!(matches!(run("println(\"{}\", 1, 2)").0, Err(RuntimeError::InvalidFormat { .. })));
+
+        // Globals carry over from one run to the next
+        let captured = Captured::default();
+        let mut interpreter = Interpreter::default();
+        interpreter.set_output(Box::new(captured.clone()));
+        interpreter.execute(&get_program(get_tokens("fn greet(name) { print(\"hi \"); println(name) }").unwrap()).unwrap())
+            .unwrap();
+        interpreter.execute(&get_program(get_tokens("greet(\"rat\")").unwrap()).unwrap()).unwrap();
+        assert_eq!(String::from_utf8(captured.0.borrow().clone()).unwrap(), "hi rat\n");
+
+        let mut interpreter = Interpreter::default();
+        interpreter.set_output(Box::new(Broken));
+        match interpreter.execute(&get_program(get_tokens("\n  println(1)").unwrap()).unwrap()) {
+            Err(error @ RuntimeError::OutputFailed { .. }) => {
+                assert_eq!(error.to_string(), "Couldn't write the output: pipe closed at line 2, character 3");
+            }
+            result => panic!("Expected the output to fail, got {:?}", result),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_input() -> Result<(), String> {
+        let run = |input: &str, source: &str| {
+            let mut interpreter = Interpreter::default();
+            interpreter.set_input(Box::new(io::Cursor::new(input.to_string())));
+            interpreter.execute(&get_program(get_tokens(source).unwrap()).unwrap())
+        };
+
+        assert_eq!(run("hello\nworld\n", "read_line()").unwrap(), Some(Value::Str("hello".to_string())));
+        assert_eq!(run("a\r\nb", "(read_line(), read_line(), read_line())").unwrap().unwrap().to_string(), "(a, b, nil)");
+        assert_eq!(run("", "read_line()").unwrap(), Some(Value::Nil));
+        assert_eq!(run("\n", "read_line()").unwrap(), Some(Value::Str(String::new())));
+        let source = "\
+let total = 0
+let line = read_line()
+while line != nil {
+    total = total + parse_int(line)
+    line = read_line()
+}
+total";
+        assert_eq!(run("1\n 20 \n300\n", source).unwrap(), Some(Value::Int(321)));
+
+        let parse = |input: &str| run("", input).unwrap();
+        assert_eq!(parse("parse_int(\"42\")"), Some(Value::Int(42)));
+        assert_eq!(parse("parse_int(\" -7\\n\")"), Some(Value::Int(-7)));
+        assert_eq!(parse("parse_int(\"4.2\")"), Some(Value::Nil));
+        assert_eq!(parse("parse_int(\"\")"), Some(Value::Nil));
+        assert_eq!(parse("parse_int(\"99999999999999999999\")"), Some(Value::Nil));
+        assert_eq!(parse("parse_int(\"x\") ?? 0"), Some(Value::Int(0)));
+        assert!(matches!(run("", "parse_int(1)"), Err(RuntimeError::TypeMismatch { .. })));
+        assert!(matches!(run("", "read_line(1)"), Err(RuntimeError::ArityMismatch { expected: 0, found: 1, .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_files() -> Result<(), String> {
+        let program = |input: &str| get_program(get_tokens(input).unwrap()).unwrap();
+        // Programs can't touch files unless they're allowed to
+        assert!(matches!(execute(&program("read_file(\"a.txt\")")), Err(RuntimeError::UndefinedFunction { .. })));
+
+        let directory = std::env::temp_dir().join(format!("rat-files-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let mut interpreter = Interpreter::new(EvalConfig { allow_fs: true, ..EvalConfig::default() });
+        interpreter.set_directory(&directory);
+        let mut run = |input: &str| interpreter.execute(&program(input));
+
+        assert_eq!(run("write_file(\"a.txt\", \"one\\ntwo\")").unwrap(), Some(Value::Nil));
+        assert_eq!(fs::read_to_string(directory.join("a.txt")).unwrap(), "one\ntwo");
+        assert_eq!(run("read_file(\"a.txt\").split(\"\\n\")").unwrap().unwrap().to_string(), "[one, two]");
+        assert_eq!(run("write_file(\"a.txt\", \"three\")\nread_file(\"a.txt\")").unwrap(), Some(Value::Str("three".to_string())));
+
+        let input = "let x = 1\nread_file(\"missing.txt\")";
+        match run(input) {
+            Err(error @ RuntimeError::FileFailed { .. }) => {
+                assert_eq!(error.message(), "Couldn't use the file 'missing.txt': entity not found");
+                let span = error.span();
+                assert_eq!(&input[span.start..span.end], "read_file(\"missing.txt\")");
+            }
+            result => panic!("Expected a file error, got {:?}", result),
+        }
+        assert_eq!(run("try { read_file(\"missing.txt\") } catch (e) { nil }").unwrap(), Some(Value::Nil));
+        assert!(matches!(run("write_file(\"no/such/dir.txt\", \"\")"), Err(RuntimeError::FileFailed { .. })));
+        assert!(matches!(run("read_file(1)"), Err(RuntimeError::TypeMismatch { .. })));
+        assert!(matches!(run("write_file(\"a.txt\", 1)"), Err(RuntimeError::TypeMismatch { .. })));
+
+        // Modules get them too
+        fs::write(directory.join("reader.rat"), "fn read() { read_file(\"a.txt\") }").unwrap();
+        assert_eq!(run("import reader\nreader.read()").unwrap(), Some(Value::Str("three".to_string())));
+        fs::remove_dir_all(&directory).unwrap();
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_time() -> Result<(), String> {
+        let program = |input: &str| get_program(get_tokens(input).unwrap()).unwrap();
+        let mut interpreter = Interpreter::default();
+        let mut run = |input: &str| interpreter.execute(&program(input));
+
+        assert!(matches!(run("now()").unwrap(), Some(Value::Float(now)) if now >= 0.0));
+        let input = "let start = now()\nlet i = 0\nwhile i < 100 { i += 1 }\nlet taken = elapsed(start)\n(taken >= 0.0, elapsed(start) >= taken)";
+        assert_eq!(run(input).unwrap().unwrap().to_string(), "(true, true)");
+        assert!(matches!(run("elapsed(1)"), Err(RuntimeError::TypeMismatch { .. })));
+        // Programs can't sleep unless they're allowed to
+        assert!(matches!(run("sleep(1)"), Err(RuntimeError::UndefinedFunction { .. })));
+
+        let mut interpreter = Interpreter::new(EvalConfig { allow_sleep: true, ..EvalConfig::default() });
+        assert_eq!(interpreter.execute(&program("let start = now()\nsleep(20)\nelapsed(start) >= 20.0")).unwrap(), Some(Value::Bool(true)));
+        assert_eq!(interpreter.execute(&program("sleep(-5)")).unwrap(), Some(Value::Nil));
+        assert!(matches!(interpreter.execute(&program("sleep(1.5)")), Err(RuntimeError::TypeMismatch { .. })));
+
+        // It doesn't sleep past the deadline
+        let start = Instant::now();
+        interpreter.set_deadline(Some(start + Duration::from_millis(20)));
+        assert!(matches!(interpreter.execute(&program("sleep(60000)")), Err(RuntimeError::TimedOut { .. })));
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_random() -> Result<(), String> {
+        let program = |input: &str| get_program(get_tokens(input).unwrap()).unwrap();
+        let input = "let rolls = []\nfor i in 0..100 { rolls.push(random_int(1, 7)) }\n(random(), rolls)";
+        let run = |seed: u64| {
+            let mut interpreter = Interpreter::default();
+            interpreter.set_seed(seed);
+            interpreter.execute(&program(input)).unwrap().unwrap()
+        };
+
+        // The same seed gives the same numbers
+        let value = run(1);
+        assert_eq!(value, run(1));
+        assert_ne!(value, run(2));
+        let Value::Tuple(values) = value else { panic!("Expected a tuple, got {:?}", value) };
+        assert!(matches!(values[0], Value::Float(number) if (0.0..1.0).contains(&number)));
+        let Value::Array(rolls) = &values[1] else { panic!("Expected an array, got {:?}", values[1]) };
+        assert!(rolls.borrow().iter().all(|roll| matches!(roll, Value::Int(1..=6))));
+
+        let mut interpreter = Interpreter::default();
+        assert_eq!(interpreter.execute(&program("random_int(-3, -2)")).unwrap(), Some(Value::Int(-3)));
+        let error = interpreter.execute(&program("random_int(3, 3)")).unwrap_err();
+        assert!(matches!(error, RuntimeError::EmptyRange { low: 3, high: 3, .. }));
+        assert_eq!(error.message(), "There's no int from 3 up to 3");
+        assert!(matches!(interpreter.execute(&program("random_int(1.0, 2)")), Err(RuntimeError::TypeMismatch { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_assert() -> Result<(), String> {
+        let execute_source = |input: &str| execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        let program = "struct P { x, y }\nimpl P { fn __eq__(a, b) { a.x == b.x } }\nassert(1 < 2)\nassert_eq(1 + 1, 2)\nassert_eq(2, 2.0)\nassert_eq(P { x: 1, y: 2 }, P { x: 1, y: 3 })";
+        assert_eq!(execute_source(program).unwrap(), Some(Value::Nil));
+
+        let input = "let x = 1\nassert(x > 1)";
+        let error = execute_source(input).unwrap_err();
+        assert!(matches!(error, RuntimeError::AssertionFailed { .. }));
+        assert_eq!(&input[error.span().start..error.span().end], "assert(x > 1)");
+        assert_eq!(error.message(), "Assertion failed");
+
+        let input = "let xs = [1, 2]\nassert_eq(xs, [1, 3])";
+        match execute_source(input) {
+            Err(error @ RuntimeError::NotEqual { .. }) => {
+                assert_eq!(error.message(), "Assertion failed: [1, 2] != [1, 3]");
+                assert_eq!(error.span().line, 2);
+                let RuntimeError::NotEqual { left, right, .. } = error else { unreachable!() };
+                assert_eq!((left.to_string(), right.to_string()), ("[1, 2]".to_string(), "[1, 3]".to_string()));
+            }
+            result => panic!("Expected the values not to be equal, got {:?}", result),
+        }
+        assert!(matches!(execute_source("assert(1)"), Err(RuntimeError::NonBooleanCondition { .. })));
+        assert!(matches!(execute_source("assert_eq(1)"), Err(RuntimeError::ArityMismatch { .. })));
+        assert_eq!(execute_source("try { assert(false) } catch (e) { e }").unwrap(), Some(Value::Str("Assertion failed".to_string())));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_format() -> Result<(), String> {
+        let evaluate_source = |input: &str| evaluate(&get_ast(get_tokens(input).unwrap()).unwrap());
+
+        assert_eq!(evaluate_source("format(\"x = {}, y = {:.2}\", 1, 2.5)").unwrap(), Value::Str("x = 1, y = 2.50".to_string()));
+        assert_eq!(evaluate_source("format(\"{:<6}|{:06.1}|{:^5}\", \"ab\", -1 / 3, true)").unwrap(), Value::Str("ab    |-000.3|true ".to_string()));
+        assert_eq!(evaluate_source("format(\"{{}}\")").unwrap(), Value::Str("{}".to_string()));
+        assert_eq!(evaluate_source("format").unwrap().to_string(), "<fn format>");
+
+        let input = "1 + format(\"{} {}\", 1)";
+        match evaluate_source(input) {
+            Err(error @ RuntimeError::InvalidFormat { .. }) => {
+                assert_eq!(error.message(), "The format string can't be used: there are more placeholders than values");
+                assert_eq!(&input[error.span().start..error.span().end], "format(\"{} {}\", 1)");
+            }
+            result => panic!("Expected an invalid format, got {:?}", result),
+        }
+        assert!(matches!(evaluate_source("format(1)"), Err(RuntimeError::InvalidFormat { .. })));
+        assert!(matches!(evaluate_source("format()"), Err(RuntimeError::ArityMismatch { expected: 1, found: 0, .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_register() -> Result<(), String> {
+        let mut interpreter = Interpreter::default();
+        interpreter.register("hypot", 2, |arguments: &[Value]| match arguments {
+            [Value::Float(a), Value::Float(b)] => Ok(Value::Float(a.hypot(*b))),
+            _ => Err(RuntimeError::TypeMismatch { span: Span::default() }),
+        });
+        let count = Rc::new(RefCell::new(0));
+        let counted = Rc::clone(&count);
+        interpreter.register("tick", 0, move |_: &[Value]| {
+            *counted.borrow_mut() += 1;
+            Ok(Value::Int(*counted.borrow()))
+        });
+        let mut run = |input: &str| interpreter.execute(&get_program(get_tokens(input).unwrap()).unwrap());
+
+        assert_eq!(run("hypot(3.0, 4.0)").unwrap(), Some(Value::Float(5.0)));
+        assert_eq!(run("let h = hypot; 2.0 |> fn(x) { h(x, 0.0) }").unwrap(), Some(Value::Float(2.0)));
+        assert_eq!(run("hypot").unwrap().unwrap().to_string(), "<fn hypot>");
+        assert_eq!(run("tick(); tick(); tick()").unwrap(), Some(Value::Int(3)));
+        assert_eq!(*count.borrow(), 3);
+        assert!(matches!(run("hypot(1.0)"), Err(RuntimeError::ArityMismatch { expected: 2, found: 1, .. })));
+        match run("\n  hypot(1, 2)") {
+            Err(RuntimeError::TypeMismatch { span }) => assert_eq!((span.line, span.character), (2, 3)),
+            result => panic!("Expected a type mismatch, got {:?}", result),
+        }
+        // Like a builtin, it can be shadowed
+        assert_eq!(run("fn tick() { 0 }\ntick()").unwrap(), Some(Value::Int(0)));
+
+        // Modules can call it too
+        let directory = std::env::temp_dir().join(format!("rat-register-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("geometry.rat"), "fn diagonal(side) { hypot(side, side) }").unwrap();
+        interpreter.set_directory(&directory);
+        let program = get_program(get_tokens("import geometry\ngeometry.diagonal(1.0) > 1.41").unwrap()).unwrap();
+        assert_eq!(interpreter.execute(&program).unwrap(), Some(Value::Bool(true)));
+        fs::remove_dir_all(&directory).unwrap();
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_eval_with_vars() -> Result<(), String> {
+        let variables = HashMap::from([
+            ("a".to_string(), Value::Int(1)),
+            ("b".to_string(), Value::Int(3)),
+            ("rate".to_string(), Value::Float(0.5)),
+            ("name".to_string(), Value::Str("rat".to_string())),
+            ("len".to_string(), Value::Int(10)),
+        ]);
+
+        assert_eq!(eval_with_vars("a + b * 2", &variables).unwrap(), Value::Int(7));
+        assert_eq!(eval_with_vars("rate * 4.0", &variables).unwrap(), Value::Float(2.0));
+        assert_eq!(eval_with_vars("if a < b { name } else { \"\" }", &variables).unwrap(), Value::Str("rat".to_string()));
+        assert_eq!(eval_with_vars("name.upper()", &variables).unwrap(), Value::Str("RAT".to_string()));
+        // The variables go in place of builtins called the same
+        assert_eq!(eval_with_vars("len + 1", &variables).unwrap(), Value::Int(11));
+        assert_eq!(eval_with_vars("2 * 3", &HashMap::new()).unwrap(), Value::Int(6));
+
+        assert!(matches!(eval_with_vars("a + c", &variables), Err(EvalError::Runtime(RuntimeError::UndefinedVariable { .. }))));
+        assert!(matches!(eval_with_vars("a / (b - 3)", &variables), Err(EvalError::Runtime(RuntimeError::DivisionByZero { .. }))));
+        assert!(matches!(eval_with_vars("a +", &variables), Err(EvalError::Parse(_))));
+        assert!(matches!(eval_with_vars("a $ b", &variables), Err(EvalError::Lexer(_))));
+        assert_eq!(
+            eval_with_vars("a + c", &variables).unwrap_err().to_string(),
+            "Undefined variable 'c' at line 1, character 5"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,214 @@
+use crate::grammar::evaluate::RuntimeError;
+use crate::grammar::lexer::Span;
+use crate::grammar::runtime::Value;
+
+// The widest a placeholder can make its value, and the most digits it can
+// put after the point, so that a format string can't use up all the memory
+const MAX_WIDTH: usize = 1 << 16;
+
+// Puts `values` in place of the placeholders in `template`, in order, which
+// is what `format` and `print` do. `{{` and `}}` are braces on their own. A
+// placeholder can say how its value is laid out after a colon, much the same
+// as in Rust:
+//
+//   {:8}    at least 8 characters wide, with numbers on the right and
+//           anything else on the left
+//   {:<8}   on the left, or with `>` on the right and `^` in the middle
+//   {:*^8}  filled out with `*` rather than spaces
+//   {:08}   a number filled out with zeros after its sign
+//   {:.2}   a number with 2 digits after the point
+pub fn fill(template: &str, values: &[Value]) -> Result<String, RuntimeError> {
+    let mut output = String::new();
+    let mut values = values.iter();
+    let mut characters = template.chars().peekable();
+    while let Some(character) = characters.next() {
+        match character {
+            '{' | '}' if characters.peek() == Some(&character) => {
+                characters.next();
+                output.push(character);
+            }
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match characters.next() {
+                        Some('}') => break,
+                        Some(character) => placeholder.push(character),
+                        None => return Err(invalid("a '{' isn't closed".to_string())),
+                    }
+                }
+                let spec = match placeholder.strip_prefix(':') {
+                    Some(spec) => Spec::parse(spec)?,
+                    None if placeholder.is_empty() => Spec::default(),
+                    None => return Err(invalid(format!("'{{{}}}' isn't a placeholder", placeholder))),
+                };
+                let value = values.next().ok_or_else(|| invalid("there are more placeholders than values".to_string()))?;
+                output.push_str(&spec.apply(value)?);
+            }
+            '}' => return Err(invalid("a '}' isn't opened".to_string())),
+            character => output.push(character),
+        }
+    }
+    match values.next() {
+        Some(_) => Err(invalid("there are more values than placeholders".to_string())),
+        None => Ok(output),
+    }
+}
+
+fn invalid(message: String) -> RuntimeError {
+    RuntimeError::InvalidFormat { message, span: Span::default() }
+}
+
+#[derive(Clone, Copy)]
+enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+// How a placeholder lays out its value
+struct Spec {
+    fill: char,
+    // Numbers go on the right and anything else on the left by default
+    align: Option<Align>,
+    zeros: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Default for Spec {
+    fn default() -> Spec {
+        Spec { fill: ' ', align: None, zeros: false, width: 0, precision: None }
+    }
+}
+
+fn align(character: char) -> Option<Align> {
+    match character {
+        '<' => Some(Align::Left),
+        '>' => Some(Align::Right),
+        '^' => Some(Align::Center),
+        _ => None,
+    }
+}
+
+impl Spec {
+    // What comes after the colon: a fill and an alignment, a `0`, a width and
+    // a precision, each of which can be left out
+    fn parse(spec: &str) -> Result<Spec, RuntimeError> {
+        let characters = spec.chars().collect::<Vec<_>>();
+        let mut result = Spec::default();
+        let mut position = match characters.as_slice() {
+            [fill, alignment, ..] if align(*alignment).is_some() => {
+                (result.fill, result.align) = (*fill, align(*alignment));
+                2
+            }
+            [alignment, ..] if align(*alignment).is_some() => {
+                result.align = align(*alignment);
+                1
+            }
+            _ => 0,
+        };
+        if characters.get(position) == Some(&'0') {
+            result.zeros = true;
+            position += 1;
+        }
+        let number = |position: &mut usize| {
+            let digits = characters[*position..].iter().take_while(|character| character.is_ascii_digit()).collect::<String>();
+            *position += digits.len();
+            match digits.parse::<usize>() {
+                Ok(number) if number <= MAX_WIDTH => Ok(Some(number)),
+                _ if digits.is_empty() => Ok(None),
+                _ => Err(invalid(format!("'{}' asks for too many characters", spec))),
+            }
+        };
+        result.width = number(&mut position)?.unwrap_or(0);
+        if characters.get(position) == Some(&'.') {
+            position += 1;
+            result.precision = number(&mut position)?;
+            if result.precision.is_none() {
+                return Err(invalid(format!("'{}' has no precision after its '.'", spec)));
+            }
+        }
+        if position < characters.len() {
+            return Err(invalid(format!("'{}' isn't a format", spec)));
+        }
+        Ok(result)
+    }
+
+    fn apply(&self, value: &Value) -> Result<String, RuntimeError> {
+        let number = to_float(value).is_some();
+        let text = match (self.precision, to_float(value)) {
+            (Some(precision), Some(float)) => format!("{:.*}", precision, float),
+            (Some(_), None) => return Err(invalid(format!("{} is given a precision, but only numbers can be", value))),
+            (None, _) => value.to_string(),
+        };
+        let padding = self.width.saturating_sub(text.chars().count());
+        if self.zeros {
+            if !number {
+                return Err(invalid(format!("{} is filled out with zeros, but only numbers can be", value)));
+            }
+            let sign = if text.starts_with('-') { 1 } else { 0 };
+            return Ok(format!("{}{}{}", &text[..sign], "0".repeat(padding), &text[sign..]));
+        }
+        let (before, after) = match self.align.unwrap_or(if number { Align::Right } else { Align::Left }) {
+            Align::Left => (0, padding),
+            Align::Right => (padding, 0),
+            Align::Center => (padding / 2, padding - padding / 2),
+        };
+        let fill = |count| self.fill.to_string().repeat(count);
+        Ok(format!("{}{}{}", fill(before), text, fill(after)))
+    }
+}
+
+fn to_float(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int(value) => Some(*value as f64),
+        Value::BigInt(value) => Some(value.to_f64()),
+        Value::Rational(value) => Some(value.to_f64()),
+        Value::Float(value) => Some(*value),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fill_in(template: &str, values: &[Value]) -> String {
+        fill(template, values).unwrap()
+    }
+
+    #[test]
+    fn test_fill() -> Result<(), String> {
+        let (int, float, string) = (Value::Int(42), Value::Float(1.23456), Value::Str("rat".to_string()));
+        assert_eq!(fill_in("x = {}, y = {}", &[int.clone(), string.clone()]), "x = 42, y = rat");
+        assert_eq!(fill_in("{{{}}}", &[Value::Int(42)]), "{42}");
+        assert_eq!(fill_in("no placeholders }}", &[]), "no placeholders }");
+        assert_eq!(fill_in("{:.2}|{:8.3}|{:<8.1}|", &[float.clone(), float.clone(), float.clone()]), "1.23|   1.235|1.2     |");
+        assert_eq!(fill_in("{:6}|{:6}|{:>6}|{:^7}|", &[int.clone(), string.clone(), string.clone(), string.clone()]), "    42|rat   |   rat|  rat  |");
+        assert_eq!(fill_in("{:*^9}|{:-<5}", &[string.clone(), int.clone()]), "***rat***|42---");
+        assert_eq!(fill_in("{:06.2}|{:05}|{:.1}", &[Value::Float(-2.5), int.clone(), Value::Int(7)]), "-02.50|00042|7.0");
+        assert_eq!(fill_in("{:2}", &[string]), "rat");
+        assert_eq!(fill_in("{}", &[float]), "1.23456");
+        assert_eq!(fill_in("{:4}", &[Value::array(vec![Value::Int(1)])]), "[1] ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_errors() -> Result<(), String> {
+        let message = |template: &str, values: &[Value]| fill(template, values).unwrap_err().message();
+        assert_eq!(message("{} {}", &[Value::Int(1)]), "The format string can't be used: there are more placeholders than values");
+        assert_eq!(message("{}", &[Value::Int(1), Value::Int(2)]), "The format string can't be used: there are more values than placeholders");
+        assert_eq!(message("{", &[Value::Int(1)]), "The format string can't be used: a '{' isn't closed");
+        assert_eq!(message("}", &[]), "The format string can't be used: a '}' isn't opened");
+        assert_eq!(message("{x}", &[Value::Int(1)]), "The format string can't be used: '{x}' isn't a placeholder");
+        assert_eq!(message("{:8x}", &[Value::Int(1)]), "The format string can't be used: '8x' isn't a format");
+        assert_eq!(message("{:.}", &[Value::Int(1)]), "The format string can't be used: '.' has no precision after its '.'");
+        assert_eq!(message("{:.2}", &[Value::Bool(true)]), "The format string can't be used: true is given a precision, but only numbers can be");
+        assert_eq!(message("{:05}", &[Value::Nil]), "The format string can't be used: nil is filled out with zeros, but only numbers can be");
+        assert_eq!(message("{:99999999}", &[Value::Int(1)]), "The format string can't be used: '99999999' asks for too many characters");
+        assert!(matches!(fill("{:.99999999999999999999}", &[Value::Int(1)]), Err(RuntimeError::InvalidFormat { .. })));
+
+        Ok(())
+    }
+}
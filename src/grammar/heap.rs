@@ -0,0 +1,256 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+use crate::grammar::runtime::{Instance, Value};
+
+// How many objects have to have been made before the first collection
+const INITIAL_THRESHOLD: usize = 1024;
+
+// Something on the heap that can hold references to other things on it:
+// the storage of arrays, maps and struct instances, environments and
+// closures
+pub trait Trace {
+    // Calls `visit` with the address of everything on the heap this holds a
+    // reference to, once for each reference. Gives back false if what it
+    // holds can't be looked at right now.
+    fn trace(&self, visit: &mut dyn FnMut(usize)) -> bool;
+
+    // Lets go of everything this holds, if it can
+    fn clear(&self);
+}
+
+// What arrays and maps, struct instances and environments hold
+pub trait Contents {
+    fn values(&self, visit: &mut dyn FnMut(&Value));
+    fn clear(&mut self);
+    // Moves every value out into `into`
+    fn drain(&mut self, into: &mut Vec<Value>);
+}
+
+impl Contents for Vec<Value> {
+    fn values(&self, visit: &mut dyn FnMut(&Value)) {
+        self.iter().for_each(visit);
+    }
+
+    fn clear(&mut self) {
+        Vec::clear(self);
+    }
+
+    fn drain(&mut self, into: &mut Vec<Value>) {
+        into.append(self);
+    }
+}
+
+impl<K> Contents for HashMap<K, Value> {
+    fn values(&self, visit: &mut dyn FnMut(&Value)) {
+        HashMap::values(self).for_each(visit);
+    }
+
+    fn clear(&mut self) {
+        HashMap::clear(self);
+    }
+
+    fn drain(&mut self, into: &mut Vec<Value>) {
+        into.extend(HashMap::drain(self).map(|(_, value)| value));
+    }
+}
+
+impl Contents for Instance {
+    fn values(&self, visit: &mut dyn FnMut(&Value)) {
+        self.fields.iter().for_each(|(_, value)| visit(value));
+    }
+
+    fn clear(&mut self) {
+        self.fields.clear();
+    }
+
+    fn drain(&mut self, into: &mut Vec<Value>) {
+        into.extend(self.fields.drain(..).map(|(_, value)| value));
+    }
+}
+
+impl<T: Contents> Trace for RefCell<T> {
+    fn trace(&self, visit: &mut dyn FnMut(usize)) -> bool {
+        let Ok(contents) = self.try_borrow() else {
+            return false;
+        };
+        contents.values(&mut |value| references(value, visit));
+        true
+    }
+
+    fn clear(&self) {
+        if let Ok(mut contents) = self.try_borrow_mut() {
+            contents.clear();
+        }
+    }
+}
+
+// Calls `visit` with the address of everything on the heap `value` holds a
+// reference to. Tuples, enum values and modules are held in place rather
+// than on the heap, so it's what's inside them that's visited.
+pub fn references(value: &Value, visit: &mut dyn FnMut(usize)) {
+    match value {
+        Value::Array(elements) => visit(elements.address()),
+        Value::Map(entries) => visit(entries.address()),
+        Value::Struct(instance) => visit(instance.address()),
+        Value::Function(function) => visit(function.address()),
+        Value::Tuple(values) => values.iter().for_each(|value| references(value, visit)),
+        Value::Enum(value) => value.values.iter().for_each(|value| references(value, visit)),
+        Value::EnumType(r#type) => r#type.variants.iter().for_each(|(_, value)| references(value, visit)),
+        Value::Module(module) => module.members.iter().for_each(|(_, value)| references(value, visit)),
+        _ => {}
+    }
+}
+
+// Everything the evaluator has put on the heap, which is reference counted.
+// That frees most things as soon as they're no longer used, but not cycles,
+// which keep themselves alive: an array stored in one of its own elements, or
+// a function stored in a variable of the environment it was made in.
+//
+// Every so often the heap looks for those. Whatever has more references to
+// it than there are from other things on the heap is used by something else,
+// like a variable the evaluator is running with or a value the embedding
+// program kept, so it's alive along with everything it can reach. Whatever
+// isn't reachable from any of those is garbage, and is cleared so that the
+// cycles it's in are broken and it's freed.
+pub struct Heap {
+    objects: Vec<Weak<dyn Trace>>,
+    // How many objects there can be before the next collection
+    threshold: usize,
+}
+
+impl Default for Heap {
+    fn default() -> Heap {
+        Heap { objects: Vec::new(), threshold: INITIAL_THRESHOLD }
+    }
+}
+
+impl Heap {
+    // Keeps track of the storage of an array, map or struct instance
+    pub fn track(&mut self, value: &Value) {
+        match value {
+            Value::Array(elements) => self.track_object(elements.inner()),
+            Value::Map(entries) => self.track_object(entries.inner()),
+            Value::Struct(instance) => self.track_object(instance.inner()),
+            _ => {}
+        }
+    }
+
+    pub fn track_object<T: Trace + 'static>(&mut self, object: &Rc<T>) {
+        let object: Weak<T> = Rc::downgrade(object);
+        self.objects.push(object);
+        if self.objects.len() >= self.threshold {
+            self.collect();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.iter().filter(|object| object.strong_count() > 0).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Frees every cycle nothing outside the heap can reach, giving back how
+    // many objects were cleared to do it
+    pub fn collect(&mut self) -> usize {
+        let objects = self.objects.iter().filter_map(Weak::upgrade).collect::<Vec<_>>();
+        let positions = objects.iter().enumerate()
+            .map(|(position, object)| (Rc::as_ptr(object) as *const () as usize, position))
+            .collect::<HashMap<_, _>>();
+
+        // The references each object has from the others, and the ones it
+        // holds to them
+        let mut internal = vec![0; objects.len()];
+        let mut held = vec![Vec::new(); objects.len()];
+        let mut alive = vec![false; objects.len()];
+        for (position, object) in objects.iter().enumerate() {
+            let traced = object.trace(&mut |address| {
+                if let Some(&other) = positions.get(&address) {
+                    internal[other] += 1;
+                    held[position].push(other);
+                }
+            });
+            // Whatever couldn't be looked at is in use
+            alive[position] = !traced;
+        }
+
+        // Going by the reference `objects` itself holds, too
+        let mut reachable = (0..objects.len())
+            .filter(|&position| alive[position] || Rc::strong_count(&objects[position]) - 1 > internal[position])
+            .collect::<Vec<_>>();
+        reachable.iter().for_each(|&position| alive[position] = true);
+        while let Some(position) = reachable.pop() {
+            for &other in &held[position] {
+                if !alive[other] {
+                    alive[other] = true;
+                    reachable.push(other);
+                }
+            }
+        }
+
+        let garbage = objects.iter().zip(&alive).filter(|(_, alive)| !**alive).map(|(object, _)| object).collect::<Vec<_>>();
+        garbage.iter().for_each(|object| object.clear());
+        let collected = garbage.len();
+
+        self.objects = objects.iter()
+            .filter(|object| Rc::strong_count(object) > 1)
+            .map(Rc::downgrade)
+            .collect();
+        self.threshold = INITIAL_THRESHOLD.max(self.objects.len() * 2);
+        collected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::runtime::Key;
+
+    #[test]
+    fn test_cycles() -> Result<(), String> {
+        let mut heap = Heap::default();
+        let array = Value::array(vec![Value::Int(1)]);
+        heap.track(&array);
+        let Value::Array(elements) = &array else { unreachable!() };
+        elements.borrow_mut().push(array.clone());
+        let weak = Rc::downgrade(elements.inner());
+
+        // Still in use, since `array` is a reference from outside the heap
+        assert_eq!(heap.collect(), 0);
+        assert_eq!(array.to_string(), "[1, [...]]");
+        assert_eq!(array, array.clone());
+
+        drop(array);
+        assert!(weak.upgrade().is_some());
+        assert_eq!(heap.collect(), 1);
+        assert!(weak.upgrade().is_none());
+        assert!(heap.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reachable() -> Result<(), String> {
+        let mut heap = Heap::default();
+        let (first, second) = (Value::array(vec![]), Value::map(HashMap::new()));
+        heap.track(&first);
+        heap.track(&second);
+        let (Value::Array(elements), Value::Map(entries)) = (&first, &second) else { unreachable!() };
+        elements.borrow_mut().push(Value::Tuple(vec![second.clone()]));
+        entries.borrow_mut().insert(Key::Int(0), first.clone());
+
+        // Reachable from `second`, which is still in use
+        drop(first);
+        assert_eq!(heap.collect(), 0);
+        assert_eq!(second.to_string(), "{0: [({...},)]}");
+        assert_eq!(heap.len(), 2);
+
+        drop(second);
+        assert_eq!(heap.collect(), 2);
+        assert_eq!(heap.len(), 0);
+
+        Ok(())
+    }
+}
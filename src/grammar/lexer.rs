@@ -1,314 +1,2455 @@
-use std::fmt::{Debug, Formatter};
-use std::rc::Rc;
-
-#[derive(Debug)]
-pub struct LiteralToken <T> {
-    pub literal: T,
-    pub lexeme: Lexeme,
-    pub line: u32,
-    pub character: u32,
-}
-
-#[derive(Debug)]
-pub struct NonLiteralToken {
-    pub lexeme: Lexeme,
-    pub line: u32,
-    pub character: u32,
-}
-
-#[derive(Debug)]
-pub struct UnexpectedToken {
-    pub lexeme: Lexeme,
-    pub line: u32,
-    pub character: u32,
-}
-
-#[derive(Debug)]
-pub enum LexerError {
-    UnexpectedToken(UnexpectedToken),
-}
-
-pub struct Lexeme {
-    input: Rc<Vec<char>>,
-    start: usize,
-    end: usize
-}
-
-impl Debug for Lexeme {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Lexeme")
-            .field("start", &self.start)
-            .field("end", &self.end)
-            .finish()
-    }
-}
-
-impl Lexeme {
-    pub fn get_value(&self) -> &[char] {
-        &(*self.input)[self.start..self.end]
-    }
-}
-
-#[derive(Debug)]
-pub enum TokenType {
-    Number(LiteralToken<i32>),
-    Plus(NonLiteralToken),
-    Minus(NonLiteralToken),
-    EOF(),
-}
-
-struct Scanner {
-    input: Rc<Vec<char>>,
-    start: usize,
-    current: usize,
-    line: u32,
-    character: u32,
-    tokens: Vec<TokenType>
-}
-
-impl Scanner {
-
-    fn scan(input: &str) -> Result<Vec<TokenType>, LexerError> {
-        let mut scanner = Scanner::new(input);
-        while !scanner.is_at_end() {
-            scanner.start = scanner.current;
-            scanner.scan_token()?;
-            scanner.character += (scanner.current - scanner.start) as u32;
-        }
-
-        scanner.tokens.push(TokenType::EOF());
-        Ok(scanner.tokens)
-    }
-
-    fn new(input: &str) -> Scanner {
-        Scanner {
-            input: Rc::new(input.chars().collect()),
-            start: 0,
-            current: 0,
-            line: 1,
-            character: 1,
-            tokens: Vec::new()
-        }
-    }
-
-    fn scan_token(&mut self) -> Result<(), LexerError> {
-        let c = self.advance().unwrap();
-        match c {
-            // Handle whitespace
-            ' ' => (),
-            '\r' => (),
-            '\t' => (),
-            '\n' => self.newline(),
-
-            // Simple tokens
-            '+' => self.add_plus_token(),
-            '-' => self.add_minus_token(),
-
-            // Longer tokens
-            c if c.is_digit(10) => self.number(),
-
-            // identifiers
-            'a'..='z' | 'A'..='Z' | '_' => {}
-
-            _ => return Err(self.unexpected_token_error()),
-        }
-
-        Ok(())
-    }
-
-    fn newline(&mut self) {
-        self.line += 1;
-        self.character = 1;
-        self.start = self.current;
-    }
-
-    fn number(&mut self) {
-        while self.peek().map_or(false, |c| c.is_digit(10)) {
-            self.advance();
-        }
-
-        self.add_number_token()
-    }
-
-    fn advance(&mut self) -> Option<char> {
-        if self.is_at_end() { return None; }
-
-        let result = self.input[self.current];
-        self.current += 1;
-        Some(result)
-    }
-
-    fn peek(&self) -> Option<char> {
-        if self.is_at_end() { return None; }
-        Some(self.input[self.current])
-    }
-
-    fn add_plus_token(&mut self) {
-        self.tokens.push(TokenType::Plus(NonLiteralToken {
-            lexeme: self.get_current_lexeme(),
-            line: self.line,
-            character: self.character,
-        }))
-    }
-
-    fn add_minus_token(&mut self) {
-        self.tokens.push(TokenType::Minus(NonLiteralToken {
-            lexeme: self.get_current_lexeme(),
-            line: self.line,
-            character: self.character,
-        }))
-    }
-
-    fn add_number_token(&mut self) {
-        let s = self.input[self.start..self.current].iter().collect::<String>();
-        let value = s.parse::<i32>().unwrap();
-        self.tokens.push(TokenType::Number(LiteralToken {
-            literal: value,
-            lexeme: self.get_current_lexeme(),
-            line: self.line,
-            character: self.character,
-        }));
-    }
-
-    fn unexpected_token_error(&self) -> LexerError {
-        LexerError::UnexpectedToken(UnexpectedToken {
-            lexeme: self.get_current_lexeme(),
-            line: self.line,
-            character: self.character,
-        })
-    }
-
-    fn get_current_lexeme(&self) -> Lexeme {
-        Lexeme {
-            input: self.input.clone(),
-            start: self.start,
-            end: self.current
-        }
-    }
-
-    fn is_at_end(&self) -> bool {
-        self.current >= self.input.len()
-    }
-}
-
-pub fn get_tokens(input: &str) -> Result<Vec<TokenType>, LexerError> {
-    Ok(Scanner::scan(input)?)
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    const TOKEN_WRONG_LITERAL: &str = "Token literal value is not correct";
-    const TOKEN_WRONG_LINE: &str = "Token is on wrong line";
-    const TOKEN_WRONG_CHARACTER: &str = "Token is marked by wrong character";
-    const TOKEN_WRONG_LEXEME: &str = "Token lexeme does not match";
-    const UNEXPECTED_TOKEN_MATCH: &str = "The expected token did not match";
-
-    #[test]
-    fn test_simple_addition() -> Result<(), String> {
-        let result = get_tokens("2+3").unwrap();
-
-        assert_number_token(&result[0], 2, 1, 1, "2");
-        assert_plus_token(&result[1], 1, 2);
-        assert_number_token(&result[2], 3, 1, 3, "3");
-        assert!(matches!(&result[3], TokenType::EOF()));
-        assert_eq!(result.len(), 4);
-
-        Ok(())
-    }
-
-    #[test]
-    fn test_with_whitespace() -> Result<(), String> {
-        let result = get_tokens("2 + \t\r\n3").unwrap();
-
-        assert_number_token(&result[0], 2, 1, 1, "2");
-        assert_plus_token(&result[1], 1, 3);
-        assert_number_token(&result[2], 3, 2, 1, "3");
-        assert!(matches!(&result[3], TokenType::EOF()));
-        assert_eq!(result.len(), 4);
-
-        Ok(())
-    }
-
-    #[test]
-    fn test_subtraction() -> Result<(), String> {
-        let result = get_tokens("-").unwrap();
-
-        match &result[0] {
-            TokenType::Minus(token) => {
-                assert_eq!(token.line, 1, "{}", TOKEN_WRONG_LINE);
-                assert_eq!(token.character, 1, "{}", TOKEN_WRONG_CHARACTER);
-                assert_eq!(token.lexeme.get_value(), str_to_char_slice("-"), "{}", TOKEN_WRONG_LEXEME);
-            }
-            _ => assert!(false, "{}", UNEXPECTED_TOKEN_MATCH)
-        }
-
-        assert!(matches!(&result[1], TokenType::EOF()));
-        assert_eq!(result.len(), 2);
-
-        Ok(())
-    }
-
-    #[test]
-    fn test_unexpected_token() -> Result<(), String> {
-        let result = get_tokens("`");
-        match result {
-            Err(LexerError::UnexpectedToken(token)) => {
-                assert_eq!(token.line, 1, "{}", TOKEN_WRONG_LINE);
-                assert_eq!(token.character, 1, "{}", TOKEN_WRONG_CHARACTER);
-                assert_eq!(token.lexeme.get_value(), str_to_char_slice("`"), "{}", TOKEN_WRONG_LEXEME)
-            },
-            _ => assert!(false, "{}", UNEXPECTED_TOKEN_MATCH)
-        }
-
-        Ok(())
-    }
-
-    #[test]
-    fn test_big_numbers() -> Result<(), String> {
-        let result = get_tokens("123 + 456").unwrap();
-
-        assert_number_token(&result[0], 123, 1, 1, "123");
-        assert_plus_token(&result[1], 1, 5);
-        assert_number_token(&result[2], 456, 1, 7, "456");
-        assert!(matches!(&result[3], TokenType::EOF()));
-        assert_eq!(result.len(), 4);
-
-        Ok(())
-    }
-
-    fn assert_number_token(
-        token_type: &TokenType,
-        literal: i32,
-        line: u32,
-        character: u32,
-        lexeme: &str
-    ) {
-        match token_type {
-            TokenType::Number(token) => {
-                assert_eq!(token.literal, literal, "{}", TOKEN_WRONG_LITERAL);
-                assert_eq!(token.line, line, "{}", TOKEN_WRONG_LINE);
-                assert_eq!(token.character, character, "{}", TOKEN_WRONG_CHARACTER);
-                assert_eq!(token.lexeme.get_value(), str_to_char_slice(lexeme), "{}", TOKEN_WRONG_LEXEME);
-            }
-            _ => assert!(false, "{}", UNEXPECTED_TOKEN_MATCH)
-        }
-    }
-
-    fn assert_plus_token(token_type: &TokenType, line: u32, character: u32) {
-        match token_type {
-            TokenType::Plus(token) => {
-                assert_eq!(token.line, line, "{}", TOKEN_WRONG_LINE);
-                assert_eq!(token.character, character, "{}", TOKEN_WRONG_CHARACTER);
-                assert_eq!(token.lexeme.get_value(), str_to_char_slice("+"), "{}", TOKEN_WRONG_LEXEME);
-            }
-            _ => assert!(false, "{}", UNEXPECTED_TOKEN_MATCH)
-        }
-    }
-
-    fn str_to_char_slice(s: &str) -> Vec<char> {
-        s.chars().collect()
-    }
-
-}
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::Arc;
+use unicode_ident::{is_xid_continue, is_xid_start};
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LiteralToken <T> {
+    pub literal: T,
+    pub lexeme: Lexeme,
+    pub line: u32,
+    pub character: u32,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NonLiteralToken {
+    pub lexeme: Lexeme,
+    pub line: u32,
+    pub character: u32,
+}
+
+impl<T> LiteralToken<T> {
+    pub fn span(&self) -> Span {
+        Span {
+            start: self.lexeme.start,
+            end: self.lexeme.end,
+            line: self.line,
+            character: self.character,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct UnexpectedToken {
+    pub lexeme: Lexeme,
+    pub line: u32,
+    pub character: u32,
+}
+
+#[derive(Debug)]
+pub struct MalformedExponent {
+    pub lexeme: Lexeme,
+    pub line: u32,
+    pub character: u32,
+}
+
+#[derive(Debug)]
+pub struct NumberTooLarge {
+    pub lexeme: Lexeme,
+    pub line: u32,
+    pub character: u32,
+}
+
+#[derive(Debug)]
+pub struct UnterminatedString {
+    pub lexeme: Lexeme,
+    pub line: u32,
+    pub character: u32,
+}
+
+#[derive(Debug)]
+pub struct UnterminatedChar {
+    pub lexeme: Lexeme,
+    pub line: u32,
+    pub character: u32,
+}
+
+#[derive(Debug)]
+pub struct EmptyChar {
+    pub lexeme: Lexeme,
+    pub line: u32,
+    pub character: u32,
+}
+
+#[derive(Debug)]
+pub struct InvalidEscape {
+    pub lexeme: Lexeme,
+    pub line: u32,
+    pub character: u32,
+}
+
+#[derive(Debug)]
+pub enum LexerError {
+    UnexpectedToken(UnexpectedToken),
+    MalformedExponent(MalformedExponent),
+    NumberTooLarge(NumberTooLarge),
+    UnterminatedString(UnterminatedString),
+    UnterminatedChar(UnterminatedChar),
+    EmptyChar(EmptyChar),
+    InvalidEscape(InvalidEscape),
+}
+
+impl Display for UnexpectedToken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unexpected character '{}' at line {}, character {}", self.lexeme, self.line, self.character)
+    }
+}
+
+impl Error for UnexpectedToken {}
+
+impl Display for MalformedExponent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Missing digits after the exponent in '{}' at line {}, character {}", self.lexeme, self.line, self.character)
+    }
+}
+
+impl Error for MalformedExponent {}
+
+impl Display for NumberTooLarge {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Number {} is too large at line {}, character {}", self.lexeme, self.line, self.character)
+    }
+}
+
+impl Error for NumberTooLarge {}
+
+impl Display for UnterminatedString {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unterminated string {} starting at line {}, character {}", self.lexeme, self.line, self.character)
+    }
+}
+
+impl Error for UnterminatedString {}
+
+impl Display for UnterminatedChar {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unterminated character literal {} at line {}, character {}", self.lexeme, self.line, self.character)
+    }
+}
+
+impl Error for UnterminatedChar {}
+
+impl Display for EmptyChar {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Empty character literal {} at line {}, character {}", self.lexeme, self.line, self.character)
+    }
+}
+
+impl Error for EmptyChar {}
+
+impl Display for InvalidEscape {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid escape sequence in {} at line {}, character {}", self.lexeme, self.line, self.character)
+    }
+}
+
+impl Error for InvalidEscape {}
+
+impl Display for LexerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LexerError::UnexpectedToken(error) => Display::fmt(error, f),
+            LexerError::MalformedExponent(error) => Display::fmt(error, f),
+            LexerError::NumberTooLarge(error) => Display::fmt(error, f),
+            LexerError::UnterminatedString(error) => Display::fmt(error, f),
+            LexerError::UnterminatedChar(error) => Display::fmt(error, f),
+            LexerError::EmptyChar(error) => Display::fmt(error, f),
+            LexerError::InvalidEscape(error) => Display::fmt(error, f),
+        }
+    }
+}
+
+impl Error for LexerError {}
+
+// A byte range into the source the token was scanned from
+#[derive(Clone)]
+pub struct Lexeme {
+    source: Arc<str>,
+    // Where `source` begins in the original input, which is only nonzero for
+    // lexemes that were deserialized with just their own text
+    offset: usize,
+    pub start: usize,
+    pub end: usize
+}
+
+impl Debug for Lexeme {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Lexeme")
+            .field("start", &self.start)
+            .field("end", &self.end)
+            .finish()
+    }
+}
+
+impl Display for Lexeme {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Lexeme {
+    pub fn as_str(&self) -> &str {
+        &self.source[self.start - self.offset..self.end - self.offset]
+    }
+}
+
+// Lexemes serialize as their range plus the text it covers, since the whole
+// source is shared between every token
+#[cfg(feature = "serde")]
+impl serde::Serialize for Lexeme {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut lexeme = serializer.serialize_struct("Lexeme", 3)?;
+        lexeme.serialize_field("start", &self.start)?;
+        lexeme.serialize_field("end", &self.end)?;
+        lexeme.serialize_field("text", self.as_str())?;
+        lexeme.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Lexeme {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(serde::Deserialize)]
+        struct Fields {
+            start: usize,
+            end: usize,
+            text: String,
+        }
+
+        let fields = Fields::deserialize(deserializer)?;
+        if fields.end.checked_sub(fields.start) != Some(fields.text.len()) {
+            return Err(serde::de::Error::custom("lexeme text does not match its range"));
+        }
+
+        Ok(Lexeme {
+            source: fields.text.into(),
+            offset: fields.start,
+            start: fields.start,
+            end: fields.end,
+        })
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[allow(clippy::upper_case_acronyms)]
+pub enum TokenType {
+    Number(LiteralToken<i64>),
+    Float(LiteralToken<f64>),
+    Char(LiteralToken<char>),
+    Str(LiteralToken<String>),
+    // The text pieces of an interpolated string such as `"a ${x} b ${y} c"`,
+    // which lexes as `InterpolationStart("a ")`, the tokens of `x`,
+    // `InterpolationMiddle(" b ")`, the tokens of `y` and then
+    // `InterpolationEnd(" c")`
+    InterpolationStart(LiteralToken<String>),
+    InterpolationMiddle(LiteralToken<String>),
+    InterpolationEnd(LiteralToken<String>),
+    // A `///` comment, which documents whatever is declared after it. Unlike
+    // other comments these are always produced, holding the text after the
+    // `///` and the space following it, if any.
+    DocComment(LiteralToken<String>),
+    Identifier(NonLiteralToken),
+    Plus(NonLiteralToken),
+    Minus(NonLiteralToken),
+    Star(NonLiteralToken),
+    StarStar(NonLiteralToken),
+    Slash(NonLiteralToken),
+    Percent(NonLiteralToken),
+    Ampersand(NonLiteralToken),
+    Pipe(NonLiteralToken),
+    PipeGreater(NonLiteralToken),
+    Caret(NonLiteralToken),
+    Tilde(NonLiteralToken),
+    LeftParen(NonLiteralToken),
+    RightParen(NonLiteralToken),
+    LeftBrace(NonLiteralToken),
+    RightBrace(NonLiteralToken),
+    LeftBracket(NonLiteralToken),
+    RightBracket(NonLiteralToken),
+    Semicolon(NonLiteralToken),
+    Comma(NonLiteralToken),
+    Colon(NonLiteralToken),
+    Question(NonLiteralToken),
+    QuestionQuestion(NonLiteralToken),
+    Equal(NonLiteralToken),
+    PlusEqual(NonLiteralToken),
+    MinusEqual(NonLiteralToken),
+    StarEqual(NonLiteralToken),
+    SlashEqual(NonLiteralToken),
+    EqualEqual(NonLiteralToken),
+    BangEqual(NonLiteralToken),
+    Less(NonLiteralToken),
+    LessEqual(NonLiteralToken),
+    Greater(NonLiteralToken),
+    GreaterEqual(NonLiteralToken),
+    FatArrow(NonLiteralToken),
+    Arrow(NonLiteralToken),
+    DotDot(NonLiteralToken),
+    DotDotEqual(NonLiteralToken),
+    DotDotDot(NonLiteralToken),
+    Dot(NonLiteralToken),
+
+    // Keywords
+    Let(NonLiteralToken),
+    If(NonLiteralToken),
+    Else(NonLiteralToken),
+    True(NonLiteralToken),
+    False(NonLiteralToken),
+    While(NonLiteralToken),
+    For(NonLiteralToken),
+    In(NonLiteralToken),
+    Break(NonLiteralToken),
+    Continue(NonLiteralToken),
+    Fn(NonLiteralToken),
+    Return(NonLiteralToken),
+    Match(NonLiteralToken),
+    Struct(NonLiteralToken),
+    Enum(NonLiteralToken),
+    Nil(NonLiteralToken),
+    Impl(NonLiteralToken),
+    Trait(NonLiteralToken),
+    Import(NonLiteralToken),
+    Macro(NonLiteralToken),
+    Try(NonLiteralToken),
+    Catch(NonLiteralToken),
+
+    // Trivia, only produced when the scanner is preserving it
+    Whitespace(NonLiteralToken),
+    Newline(NonLiteralToken),
+    Comment(NonLiteralToken),
+
+    EOF(NonLiteralToken),
+}
+
+// Where something came from in the source: the byte range it covers along
+// with the line and character that it starts at
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: u32,
+    pub character: u32,
+}
+
+impl Span {
+    // Covers everything from the start of this span to the end of `other`
+    pub fn to(&self, other: Span) -> Span {
+        Span {
+            end: other.end,
+            ..*self
+        }
+    }
+}
+
+impl TokenType {
+    pub fn span(&self) -> Span {
+        let (lexeme, line, character) = self.position();
+        Span {
+            start: lexeme.start,
+            end: lexeme.end,
+            line,
+            character,
+        }
+    }
+
+    pub fn lexeme(&self) -> &Lexeme {
+        self.position().0
+    }
+
+    pub fn line(&self) -> u32 {
+        self.position().1
+    }
+
+    pub fn character(&self) -> u32 {
+        self.position().2
+    }
+
+    fn position(&self) -> (&Lexeme, u32, u32) {
+        match self {
+            TokenType::Number(token) => (&token.lexeme, token.line, token.character),
+            TokenType::Float(token) => (&token.lexeme, token.line, token.character),
+            TokenType::Char(token) => (&token.lexeme, token.line, token.character),
+            TokenType::Str(token)
+            | TokenType::InterpolationStart(token)
+            | TokenType::InterpolationMiddle(token)
+            | TokenType::InterpolationEnd(token)
+            | TokenType::DocComment(token) => (&token.lexeme, token.line, token.character),
+            TokenType::Identifier(token)
+            | TokenType::Plus(token)
+            | TokenType::Minus(token)
+            | TokenType::Star(token)
+            | TokenType::StarStar(token)
+            | TokenType::Slash(token)
+            | TokenType::Percent(token)
+            | TokenType::Ampersand(token)
+            | TokenType::Pipe(token)
+            | TokenType::PipeGreater(token)
+            | TokenType::Caret(token)
+            | TokenType::Tilde(token)
+            | TokenType::LeftParen(token)
+            | TokenType::RightParen(token)
+            | TokenType::LeftBrace(token)
+            | TokenType::RightBrace(token)
+            | TokenType::LeftBracket(token)
+            | TokenType::RightBracket(token)
+            | TokenType::Semicolon(token)
+            | TokenType::Comma(token)
+            | TokenType::Colon(token)
+            | TokenType::Question(token)
+            | TokenType::QuestionQuestion(token)
+            | TokenType::Equal(token)
+            | TokenType::PlusEqual(token)
+            | TokenType::MinusEqual(token)
+            | TokenType::StarEqual(token)
+            | TokenType::SlashEqual(token)
+            | TokenType::EqualEqual(token)
+            | TokenType::BangEqual(token)
+            | TokenType::Less(token)
+            | TokenType::LessEqual(token)
+            | TokenType::Greater(token)
+            | TokenType::GreaterEqual(token)
+            | TokenType::DotDot(token)
+            | TokenType::DotDotEqual(token)
+            | TokenType::DotDotDot(token)
+            | TokenType::Dot(token)
+            | TokenType::FatArrow(token)
+            | TokenType::Arrow(token)
+            | TokenType::Let(token)
+            | TokenType::If(token)
+            | TokenType::Else(token)
+            | TokenType::True(token)
+            | TokenType::False(token)
+            | TokenType::While(token)
+            | TokenType::For(token)
+            | TokenType::In(token)
+            | TokenType::Break(token)
+            | TokenType::Continue(token)
+            | TokenType::Fn(token)
+            | TokenType::Return(token)
+            | TokenType::Match(token)
+            | TokenType::Struct(token)
+            | TokenType::Enum(token)
+            | TokenType::Nil(token)
+            | TokenType::Impl(token)
+            | TokenType::Trait(token)
+            | TokenType::Import(token)
+            | TokenType::Macro(token)
+            | TokenType::Try(token)
+            | TokenType::Catch(token)
+            | TokenType::Whitespace(token)
+            | TokenType::Newline(token)
+            | TokenType::Comment(token)
+            | TokenType::EOF(token) => (&token.lexeme, token.line, token.character),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ScannerConfig {
+    // Tabs move the character to the next multiple of this, plus one
+    pub tab_width: u32,
+    // Emit whitespace, newline and comment tokens instead of dropping them
+    pub preserve_trivia: bool,
+}
+
+impl Default for ScannerConfig {
+    fn default() -> Self {
+        ScannerConfig {
+            tab_width: 1,
+            preserve_trivia: false,
+        }
+    }
+}
+
+struct Scanner {
+    input: Arc<str>,
+    start: usize,
+    current: usize,
+    line: u32,
+    character: u32,
+    config: ScannerConfig,
+    // How many braces are open inside each interpolation that hasn't been
+    // closed yet, innermost last, so that a `}` only resumes the string once
+    // it isn't closing a block
+    interpolations: Vec<u32>,
+    // Whether the last thing scanned was a `.`, which makes a number straight
+    // after it a tuple position
+    after_dot: bool,
+    tokens: Vec<TokenType>
+}
+
+// Lexes the input on demand, one token per call to `next`. Errors are yielded
+// in place of the offending token and the stream picks up right after them.
+pub struct TokenStream {
+    scanner: Scanner,
+    finished: bool,
+}
+
+impl TokenStream {
+    pub fn new(input: &str) -> TokenStream {
+        TokenStream::with_config(input, ScannerConfig::default())
+    }
+
+    pub fn with_config(input: &str, config: ScannerConfig) -> TokenStream {
+        TokenStream {
+            scanner: Scanner::new(input, config),
+            finished: false,
+        }
+    }
+
+    // Also yields whitespace, newline and comment tokens, so that joining the
+    // lexemes of every token reproduces the input exactly
+    pub fn with_trivia(input: &str) -> TokenStream {
+        TokenStream::with_config(input, ScannerConfig {
+            preserve_trivia: true,
+            ..ScannerConfig::default()
+        })
+    }
+}
+
+impl Iterator for TokenStream {
+    type Item = Result<TokenType, LexerError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.scanner.is_at_end() {
+            if let Err(error) = self.scanner.scan_next() {
+                return Some(Err(error));
+            }
+            if let Some(token) = self.scanner.tokens.pop() {
+                return Some(Ok(token));
+            }
+        }
+
+        if self.finished { return None; }
+        self.finished = true;
+        Some(Ok(self.scanner.eof_token()))
+    }
+}
+
+impl Scanner {
+    fn scan_next(&mut self) -> Result<(), LexerError> {
+        self.start = self.current;
+        let result = self.scan_token();
+        self.after_dot = matches!(self.tokens.last(), Some(TokenType::Dot(_)));
+        self.advance_character(self.start);
+        result
+    }
+
+    fn new(input: &str, config: ScannerConfig) -> Scanner {
+        Scanner {
+            input: Arc::from(input),
+            start: 0,
+            current: 0,
+            line: 1,
+            character: 1,
+            config,
+            interpolations: Vec::new(),
+            after_dot: false,
+            tokens: Vec::new()
+        }
+    }
+
+    fn scan_token(&mut self) -> Result<(), LexerError> {
+        let c = self.advance().unwrap();
+        match c {
+            // Handle whitespace
+            '\r' if self.match_char('\n') => {
+                if self.config.preserve_trivia {
+                    self.add_non_literal_token(TokenType::Newline);
+                }
+                self.newline();
+            }
+            ' ' | '\r' | '\t' => self.whitespace(),
+            '\n' => {
+                if self.config.preserve_trivia {
+                    self.add_non_literal_token(TokenType::Newline);
+                }
+                self.newline();
+            }
+            // `////` and more is an ordinary comment
+            '/' if self.match_char('/') => {
+                if self.peek() == Some('/') && self.peek_next() != Some('/') {
+                    self.doc_comment();
+                } else {
+                    self.comment();
+                }
+            }
+            // A `#!` line at the very start of a script is for the shell
+            '#' if self.start == 0 && self.match_char('!') => self.comment(),
+
+            // Simple tokens
+            '+' if self.match_char('=') => self.add_plus_equal_token(),
+            '+' => self.add_plus_token(),
+            '-' if self.match_char('=') => self.add_minus_equal_token(),
+            '-' if self.match_char('>') => self.add_arrow_token(),
+            '-' => self.add_minus_token(),
+            '*' if self.match_char('*') => self.add_star_star_token(),
+            '*' if self.match_char('=') => self.add_star_equal_token(),
+            '*' => self.add_star_token(),
+            '/' if self.match_char('=') => self.add_slash_equal_token(),
+            '/' => self.add_slash_token(),
+            '%' => self.add_percent_token(),
+            '&' => self.add_ampersand_token(),
+            '|' if self.match_char('>') => self.add_pipe_greater_token(),
+            '|' => self.add_pipe_token(),
+            '^' => self.add_caret_token(),
+            '~' => self.add_tilde_token(),
+            '(' => self.add_left_paren_token(),
+            ')' => self.add_right_paren_token(),
+            '{' => {
+                if let Some(braces) = self.interpolations.last_mut() {
+                    *braces += 1;
+                }
+                self.add_left_brace_token();
+            }
+            '}' if self.interpolations.last() == Some(&0) => self.string(true)?,
+            '}' => {
+                if let Some(braces) = self.interpolations.last_mut() {
+                    *braces -= 1;
+                }
+                self.add_right_brace_token();
+            }
+            '[' => self.add_left_bracket_token(),
+            ']' => self.add_right_bracket_token(),
+            ';' => self.add_semicolon_token(),
+            ',' => self.add_comma_token(),
+            ':' => self.add_colon_token(),
+            '?' if self.match_char('?') => self.add_question_question_token(),
+            '?' => self.add_question_token(),
+            '=' if self.match_char('=') => self.add_equal_equal_token(),
+            '=' if self.match_char('>') => self.add_fat_arrow_token(),
+            '=' => self.add_equal_token(),
+            '!' if self.match_char('=') => self.add_bang_equal_token(),
+            '<' if self.match_char('=') => self.add_less_equal_token(),
+            '<' => self.add_less_token(),
+            '>' if self.match_char('=') => self.add_greater_equal_token(),
+            '>' => self.add_greater_token(),
+            '.' if self.match_char('.') => {
+                if self.match_char('=') {
+                    self.add_dot_dot_equal_token();
+                } else if self.match_char('.') {
+                    self.add_dot_dot_dot_token();
+                } else {
+                    self.add_dot_dot_token();
+                }
+            }
+            '.' => self.add_dot_token(),
+
+            // Longer tokens
+            c if c.is_ascii_digit() => self.number()?,
+            '\'' => self.char()?,
+            '"' => self.string(false)?,
+            'r' if matches!(self.peek(), Some('"') | Some('#')) => self.raw_string()?,
+
+            // identifiers
+            c if is_xid_start(c) || c == '_' => self.identifier(),
+
+            _ => return Err(self.unexpected_token_error()),
+        }
+
+        Ok(())
+    }
+
+    fn newline(&mut self) {
+        self.line += 1;
+        self.character = 1;
+        self.start = self.current;
+    }
+
+    fn whitespace(&mut self) {
+        loop {
+            match self.peek() {
+                Some(' ') | Some('\t') => (),
+                Some('\r') if self.peek_next() != Some('\n') => (),
+                _ => break,
+            }
+            self.advance();
+        }
+
+        if self.config.preserve_trivia {
+            self.add_non_literal_token(TokenType::Whitespace);
+        }
+    }
+
+    fn comment(&mut self) {
+        loop {
+            match self.peek() {
+                None | Some('\n') => break,
+                Some('\r') if self.peek_next() == Some('\n') => break,
+                _ => (),
+            }
+            self.advance();
+        }
+
+        if self.config.preserve_trivia {
+            self.add_non_literal_token(TokenType::Comment);
+        }
+    }
+
+    fn doc_comment(&mut self) {
+        self.advance();
+        loop {
+            match self.peek() {
+                None | Some('\n') => break,
+                Some('\r') if self.peek_next() == Some('\n') => break,
+                _ => (),
+            }
+            self.advance();
+        }
+
+        let text = &self.input[self.start + 3..self.current];
+        self.tokens.push(TokenType::DocComment(LiteralToken {
+            literal: text.strip_prefix(' ').unwrap_or(text).to_string(),
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn identifier(&mut self) {
+        while self.peek().is_some_and(is_xid_continue) {
+            self.advance();
+        }
+
+        match keyword(&self.input[self.start..self.current]) {
+            Some(token_type) => self.add_non_literal_token(token_type),
+            None => self.add_identifier_token(),
+        }
+    }
+
+    // Straight after a `.` the number is a tuple position, so `pair.0.1` is
+    // two positions rather than a float
+    fn number(&mut self) -> Result<(), LexerError> {
+        self.digits();
+        if self.after_dot {
+            return self.add_number_token();
+        }
+
+        let mut is_float = false;
+        if self.peek() == Some('.') && self.peek_next().is_some_and(|c| c.is_ascii_digit()) {
+            is_float = true;
+            self.advance();
+            self.digits();
+        }
+
+        if matches!(self.peek(), Some('e') | Some('E')) {
+            is_float = true;
+            self.advance();
+            if matches!(self.peek(), Some('+') | Some('-')) {
+                self.advance();
+            }
+            if !self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                return Err(self.malformed_exponent_error());
+            }
+            self.digits();
+        }
+
+        if is_float {
+            self.add_float_token();
+            Ok(())
+        } else {
+            self.add_number_token()
+        }
+    }
+
+    fn char(&mut self) -> Result<(), LexerError> {
+        let value = match self.peek() {
+            None | Some('\n') => return Err(self.unterminated_char_error()),
+            Some('\'') => {
+                self.advance();
+                return Err(self.empty_char_error());
+            }
+            Some('\\') => {
+                self.advance();
+                self.escape()?
+            }
+            Some(c) => {
+                self.advance();
+                c
+            }
+        };
+
+        if self.peek() != Some('\'') {
+            return Err(self.unterminated_char_error());
+        }
+        self.advance();
+
+        self.add_char_token(value);
+        Ok(())
+    }
+
+    // Scans string contents up to the closing `"` or the `${` of an
+    // interpolation, either from the opening quote or from the `}` that ends
+    // an interpolated expression
+    fn string(&mut self, after_interpolation: bool) -> Result<(), LexerError> {
+        let mut value = String::new();
+        let mut newlines = 0;
+        let mut line_start = None;
+
+        let interpolates = loop {
+            match self.advance() {
+                None => return Err(self.unterminated_string_error()),
+                Some('"') => break false,
+                Some('$') if self.match_char('{') => break true,
+                Some('\\') => value.push(self.escape()?),
+                Some('\n') => {
+                    newlines += 1;
+                    line_start = Some(self.current);
+                    value.push('\n');
+                }
+                Some(c) => value.push(c),
+            }
+        };
+
+        let token_type = match (after_interpolation, interpolates) {
+            (false, false) => TokenType::Str,
+            (false, true) => TokenType::InterpolationStart,
+            (true, true) => TokenType::InterpolationMiddle,
+            (true, false) => TokenType::InterpolationEnd,
+        };
+        if interpolates && !after_interpolation {
+            self.interpolations.push(0);
+        } else if !interpolates && after_interpolation {
+            self.interpolations.pop();
+        }
+
+        self.add_string_segment_token(token_type, value);
+        if let Some(line_start) = line_start {
+            self.skip_lines(newlines, line_start);
+        }
+        Ok(())
+    }
+
+    // Raw strings are delimited by `r"` and `"`, with any number of `#`s
+    // between the `r` and the quotes so that the contents can include `"`.
+    // Nothing inside of one is treated as an escape.
+    fn raw_string(&mut self) -> Result<(), LexerError> {
+        let mut hashes = 0;
+        while self.match_char('#') {
+            hashes += 1;
+        }
+        if !self.match_char('"') {
+            return Err(self.unexpected_token_error());
+        }
+
+        let contents_start = self.current;
+        let mut newlines = 0;
+        let mut line_start = None;
+
+        let contents_end = loop {
+            match self.advance() {
+                None => return Err(self.unterminated_string_error()),
+                Some('"') => {
+                    let contents_end = self.current - 1;
+                    if self.input[self.current..].chars().take_while(|c| *c == '#').count() >= hashes {
+                        self.current += hashes;
+                        break contents_end;
+                    }
+                }
+                Some('\n') => {
+                    newlines += 1;
+                    line_start = Some(self.current);
+                }
+                Some(_) => (),
+            }
+        };
+
+        self.add_string_segment_token(TokenType::Str, self.input[contents_start..contents_end].to_string());
+        if let Some(line_start) = line_start {
+            self.skip_lines(newlines, line_start);
+        }
+        Ok(())
+    }
+
+    // Tokens are positioned by where they start, so a token spanning several
+    // lines only moves the line and character on once it has been added
+    fn skip_lines(&mut self, newlines: u32, line_start: usize) {
+        self.line += newlines;
+        self.character = 1;
+        self.advance_character(line_start);
+        self.start = self.current;
+    }
+
+    // Moves the character past everything scanned since `from`
+    fn advance_character(&mut self, from: usize) {
+        for c in self.input[from..self.current].chars() {
+            if c == '\t' {
+                let tab_width = self.config.tab_width.max(1);
+                self.character = ((self.character - 1) / tab_width + 1) * tab_width + 1;
+            } else {
+                self.character += 1;
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<char, LexerError> {
+        let value = match self.advance() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('0') => '\0',
+            Some('\\') => '\\',
+            Some('\'') => '\'',
+            Some('"') => '"',
+            Some('$') => '$',
+            Some('u') => return self.unicode_escape(),
+            _ => return Err(self.invalid_escape_error()),
+        };
+
+        Ok(value)
+    }
+
+    // Handles the `{1F600}` part of a `\u{1F600}` escape
+    fn unicode_escape(&mut self) -> Result<char, LexerError> {
+        if self.advance() != Some('{') {
+            return Err(self.invalid_escape_error());
+        }
+
+        let digits_start = self.current;
+        while self.peek().is_some_and(|c| c.is_ascii_hexdigit()) {
+            self.advance();
+        }
+        let digits_end = self.current;
+
+        if self.advance() != Some('}') {
+            return Err(self.invalid_escape_error());
+        }
+
+        u32::from_str_radix(&self.input[digits_start..digits_end], 16).ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| self.invalid_escape_error())
+    }
+
+    fn digits(&mut self) {
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.advance();
+        }
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        if self.is_at_end() { return None; }
+
+        let result = self.peek()?;
+        self.current += result.len_utf8();
+        Some(result)
+    }
+
+    fn match_char(&mut self, expected: char) -> bool {
+        if self.peek() != Some(expected) { return false; }
+
+        self.current += expected.len_utf8();
+        true
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.current..].chars().next()
+    }
+
+    fn peek_next(&self) -> Option<char> {
+        self.input[self.current..].chars().nth(1)
+    }
+
+    // The end of input is an empty lexeme just past the last character
+    fn eof_token(&mut self) -> TokenType {
+        self.start = self.current;
+        TokenType::EOF(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        })
+    }
+
+    fn add_plus_token(&mut self) {
+        self.tokens.push(TokenType::Plus(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_minus_token(&mut self) {
+        self.tokens.push(TokenType::Minus(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_star_token(&mut self) {
+        self.tokens.push(TokenType::Star(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_star_star_token(&mut self) {
+        self.tokens.push(TokenType::StarStar(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_slash_token(&mut self) {
+        self.tokens.push(TokenType::Slash(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_percent_token(&mut self) {
+        self.tokens.push(TokenType::Percent(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_ampersand_token(&mut self) {
+        self.tokens.push(TokenType::Ampersand(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_pipe_token(&mut self) {
+        self.tokens.push(TokenType::Pipe(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_pipe_greater_token(&mut self) {
+        self.tokens.push(TokenType::PipeGreater(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_caret_token(&mut self) {
+        self.tokens.push(TokenType::Caret(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_tilde_token(&mut self) {
+        self.tokens.push(TokenType::Tilde(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_left_paren_token(&mut self) {
+        self.tokens.push(TokenType::LeftParen(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_right_paren_token(&mut self) {
+        self.tokens.push(TokenType::RightParen(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_left_brace_token(&mut self) {
+        self.tokens.push(TokenType::LeftBrace(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_right_brace_token(&mut self) {
+        self.tokens.push(TokenType::RightBrace(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_semicolon_token(&mut self) {
+        self.tokens.push(TokenType::Semicolon(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_left_bracket_token(&mut self) {
+        self.tokens.push(TokenType::LeftBracket(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_right_bracket_token(&mut self) {
+        self.tokens.push(TokenType::RightBracket(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_comma_token(&mut self) {
+        self.tokens.push(TokenType::Comma(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_colon_token(&mut self) {
+        self.tokens.push(TokenType::Colon(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_question_token(&mut self) {
+        self.tokens.push(TokenType::Question(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_equal_token(&mut self) {
+        self.tokens.push(TokenType::Equal(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_plus_equal_token(&mut self) {
+        self.tokens.push(TokenType::PlusEqual(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_minus_equal_token(&mut self) {
+        self.tokens.push(TokenType::MinusEqual(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_star_equal_token(&mut self) {
+        self.tokens.push(TokenType::StarEqual(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_slash_equal_token(&mut self) {
+        self.tokens.push(TokenType::SlashEqual(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_equal_equal_token(&mut self) {
+        self.tokens.push(TokenType::EqualEqual(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_bang_equal_token(&mut self) {
+        self.tokens.push(TokenType::BangEqual(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_less_token(&mut self) {
+        self.tokens.push(TokenType::Less(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_less_equal_token(&mut self) {
+        self.tokens.push(TokenType::LessEqual(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_greater_token(&mut self) {
+        self.tokens.push(TokenType::Greater(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_greater_equal_token(&mut self) {
+        self.tokens.push(TokenType::GreaterEqual(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_dot_dot_token(&mut self) {
+        self.tokens.push(TokenType::DotDot(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_dot_dot_equal_token(&mut self) {
+        self.tokens.push(TokenType::DotDotEqual(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_dot_dot_dot_token(&mut self) {
+        self.tokens.push(TokenType::DotDotDot(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_question_question_token(&mut self) {
+        self.tokens.push(TokenType::QuestionQuestion(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_arrow_token(&mut self) {
+        self.tokens.push(TokenType::Arrow(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_fat_arrow_token(&mut self) {
+        self.tokens.push(TokenType::FatArrow(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_dot_token(&mut self) {
+        self.tokens.push(TokenType::Dot(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_string_segment_token(&mut self, token_type: fn(LiteralToken<String>) -> TokenType, value: String) {
+        self.tokens.push(token_type(LiteralToken {
+            literal: value,
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }));
+    }
+
+    fn add_identifier_token(&mut self) {
+        self.tokens.push(TokenType::Identifier(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_non_literal_token(&mut self, token_type: fn(NonLiteralToken) -> TokenType) {
+        self.tokens.push(token_type(NonLiteralToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }))
+    }
+
+    fn add_number_token(&mut self) -> Result<(), LexerError> {
+        let value = self.input[self.start..self.current].parse::<i64>().map_err(|_| self.number_too_large_error())?;
+        self.tokens.push(TokenType::Number(LiteralToken {
+            literal: value,
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }));
+        Ok(())
+    }
+
+    fn add_float_token(&mut self) {
+        let value = self.input[self.start..self.current].parse::<f64>().unwrap();
+        self.tokens.push(TokenType::Float(LiteralToken {
+            literal: value,
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }));
+    }
+
+    fn add_char_token(&mut self, value: char) {
+        self.tokens.push(TokenType::Char(LiteralToken {
+            literal: value,
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        }));
+    }
+
+    fn unexpected_token_error(&self) -> LexerError {
+        LexerError::UnexpectedToken(UnexpectedToken {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        })
+    }
+
+    fn malformed_exponent_error(&self) -> LexerError {
+        LexerError::MalformedExponent(MalformedExponent {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        })
+    }
+
+    fn number_too_large_error(&self) -> LexerError {
+        LexerError::NumberTooLarge(NumberTooLarge {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        })
+    }
+
+    fn unterminated_string_error(&self) -> LexerError {
+        LexerError::UnterminatedString(UnterminatedString {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        })
+    }
+
+    fn unterminated_char_error(&self) -> LexerError {
+        LexerError::UnterminatedChar(UnterminatedChar {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        })
+    }
+
+    fn empty_char_error(&self) -> LexerError {
+        LexerError::EmptyChar(EmptyChar {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        })
+    }
+
+    fn invalid_escape_error(&self) -> LexerError {
+        LexerError::InvalidEscape(InvalidEscape {
+            lexeme: self.get_current_lexeme(),
+            line: self.line,
+            character: self.character,
+        })
+    }
+
+    fn get_current_lexeme(&self) -> Lexeme {
+        Lexeme {
+            source: self.input.clone(),
+            offset: 0,
+            start: self.start,
+            end: self.current
+        }
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.current >= self.input.len()
+    }
+}
+
+// Words that are reserved by the language and lex as their own tokens
+// instead of as identifiers
+fn keyword(text: &str) -> Option<fn(NonLiteralToken) -> TokenType> {
+    match text {
+        "let" => Some(TokenType::Let),
+        "if" => Some(TokenType::If),
+        "else" => Some(TokenType::Else),
+        "true" => Some(TokenType::True),
+        "false" => Some(TokenType::False),
+        "while" => Some(TokenType::While),
+        "for" => Some(TokenType::For),
+        "in" => Some(TokenType::In),
+        "break" => Some(TokenType::Break),
+        "continue" => Some(TokenType::Continue),
+        "fn" => Some(TokenType::Fn),
+        "return" => Some(TokenType::Return),
+        "match" => Some(TokenType::Match),
+        "struct" => Some(TokenType::Struct),
+        "enum" => Some(TokenType::Enum),
+        "nil" => Some(TokenType::Nil),
+        "impl" => Some(TokenType::Impl),
+        "trait" => Some(TokenType::Trait),
+        "import" => Some(TokenType::Import),
+        "macro" => Some(TokenType::Macro),
+        "try" => Some(TokenType::Try),
+        "catch" => Some(TokenType::Catch),
+        _ => None,
+    }
+}
+
+pub fn get_tokens(input: &str) -> Result<Vec<TokenType>, LexerError> {
+    TokenStream::new(input).collect()
+}
+
+pub fn get_tokens_with_config(input: &str, config: ScannerConfig) -> Result<Vec<TokenType>, LexerError> {
+    TokenStream::with_config(input, config).collect()
+}
+
+pub fn get_tokens_with_trivia(input: &str) -> Result<Vec<TokenType>, LexerError> {
+    TokenStream::with_trivia(input).collect()
+}
+
+// Like `get_tokens`, but skips past anything it can't lex and keeps going so
+// that every error in the input is reported in one pass
+pub fn get_tokens_resilient(input: &str) -> (Vec<TokenType>, Vec<LexerError>) {
+    let mut tokens = Vec::new();
+    let mut errors = Vec::new();
+    for result in TokenStream::new(input) {
+        match result {
+            Ok(token) => tokens.push(token),
+            Err(error) => errors.push(error),
+        }
+    }
+    (tokens, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN_WRONG_LITERAL: &str = "Token literal value is not correct";
+    const TOKEN_WRONG_LINE: &str = "Token is on wrong line";
+    const TOKEN_WRONG_CHARACTER: &str = "Token is marked by wrong character";
+    const TOKEN_WRONG_LEXEME: &str = "Token lexeme does not match";
+    const UNEXPECTED_TOKEN_MATCH: &str = "The expected token did not match";
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() -> Result<(), String> {
+        let tokens = get_tokens("1 + \"rat\"").unwrap();
+        let json = serde_json::to_string(&tokens).map_err(|e| e.to_string())?;
+        let tokens: Vec<TokenType> = serde_json::from_str(&json).map_err(|e| e.to_string())?;
+
+        match &tokens[2] {
+            TokenType::Str(token) => {
+                assert_eq!(token.literal, "rat", "{}", TOKEN_WRONG_LITERAL);
+                assert_eq!(token.lexeme.as_str(), "\"rat\"", "{}", TOKEN_WRONG_LEXEME);
+                assert_eq!((token.lexeme.start, token.lexeme.end), (4, 9), "{}", TOKEN_WRONG_LEXEME);
+            }
+            _ => panic!("{}", UNEXPECTED_TOKEN_MATCH)
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_simple_addition() -> Result<(), String> {
+        let result = get_tokens("2+3").unwrap();
+
+        assert_number_token(&result[0], 2, 1, 1, "2");
+        assert_plus_token(&result[1], 1, 2);
+        assert_number_token(&result[2], 3, 1, 3, "3");
+        assert!(matches!(&result[3], TokenType::EOF(_)));
+        assert_eq!(result.len(), 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_whitespace() -> Result<(), String> {
+        let result = get_tokens("2 + \t\r\n3").unwrap();
+
+        assert_number_token(&result[0], 2, 1, 1, "2");
+        assert_plus_token(&result[1], 1, 3);
+        assert_number_token(&result[2], 3, 2, 1, "3");
+        assert!(matches!(&result[3], TokenType::EOF(_)));
+        assert_eq!(result.len(), 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_crlf_line_endings() -> Result<(), String> {
+        let result = get_tokens("1 +\r\n2\r\n\r\n  + 3\r\n").unwrap();
+
+        assert_number_token(&result[0], 1, 1, 1, "1");
+        assert_plus_token(&result[1], 1, 3);
+        assert_number_token(&result[2], 2, 2, 1, "2");
+        assert_plus_token(&result[3], 4, 3);
+        assert_number_token(&result[4], 3, 4, 5, "3");
+        assert!(matches!(&result[5], TokenType::EOF(token) if token.line == 5 && token.character == 1));
+        assert_eq!(result.len(), 6);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_crlf_trivia() -> Result<(), String> {
+        let result = get_tokens_with_trivia("// one\r\n").unwrap();
+        assert!(matches!(&result[0], TokenType::Comment(token) if token.lexeme.as_str() == "// one"));
+        assert!(matches!(&result[1], TokenType::Newline(token) if token.lexeme.as_str() == "\r\n"));
+
+        let result = get_tokens_with_trivia("1 \r\n\r2").unwrap();
+
+        match &result[..] {
+            [
+                TokenType::Number(_),
+                TokenType::Whitespace(whitespace),
+                TokenType::Newline(newline),
+                TokenType::Whitespace(carriage_return),
+                TokenType::Number(two),
+                TokenType::EOF(_),
+            ] => {
+                assert_eq!(whitespace.lexeme.as_str(), " ", "{}", TOKEN_WRONG_LEXEME);
+                assert_eq!(newline.lexeme.as_str(), "\r\n", "{}", TOKEN_WRONG_LEXEME);
+                assert_eq!((newline.line, newline.character), (1, 3), "{}", TOKEN_WRONG_CHARACTER);
+                assert_eq!(carriage_return.lexeme.as_str(), "\r", "{}", TOKEN_WRONG_LEXEME);
+                assert_eq!((two.line, two.character), (2, 2), "{}", TOKEN_WRONG_CHARACTER);
+            }
+            _ => panic!("{}", UNEXPECTED_TOKEN_MATCH)
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_crlf_in_strings() -> Result<(), String> {
+        let result = get_tokens("\"a\r\nb\" 1").unwrap();
+
+        assert_string_token(&result[0], "a\r\nb", 1, 1, "\"a\r\nb\"");
+        assert_number_token(&result[1], 1, 2, 4, "1");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_subtraction() -> Result<(), String> {
+        let result = get_tokens("-").unwrap();
+
+        match &result[0] {
+            TokenType::Minus(token) => {
+                assert_eq!(token.line, 1, "{}", TOKEN_WRONG_LINE);
+                assert_eq!(token.character, 1, "{}", TOKEN_WRONG_CHARACTER);
+                assert_eq!(token.lexeme.as_str(), "-", "{}", TOKEN_WRONG_LEXEME);
+            }
+            _ => panic!("{}", UNEXPECTED_TOKEN_MATCH)
+        }
+
+        assert!(matches!(&result[1], TokenType::EOF(_)));
+        assert_eq!(result.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_factor_operators() -> Result<(), String> {
+        let result = get_tokens("6*4/2%5").unwrap();
+
+        assert!(matches!(&result[1], TokenType::Star(token) if token.character == 2));
+        assert!(matches!(&result[3], TokenType::Slash(token) if token.character == 4));
+        assert!(matches!(&result[5], TokenType::Percent(token) if token.character == 6));
+        assert!(matches!(&result[7], TokenType::EOF(_)));
+        assert_eq!(result.len(), 8);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_exponent_and_parens() -> Result<(), String> {
+        let result = get_tokens("(2**3)*4").unwrap();
+
+        assert!(matches!(&result[0], TokenType::LeftParen(token) if token.character == 1));
+        match &result[2] {
+            TokenType::StarStar(token) => {
+                assert_eq!(token.character, 3, "{}", TOKEN_WRONG_CHARACTER);
+                assert_eq!(token.lexeme.as_str(), "**", "{}", TOKEN_WRONG_LEXEME);
+            }
+            _ => panic!("{}", UNEXPECTED_TOKEN_MATCH)
+        }
+        assert!(matches!(&result[4], TokenType::RightParen(token) if token.character == 6));
+        assert!(matches!(&result[5], TokenType::Star(token) if token.character == 7));
+        assert!(matches!(&result[7], TokenType::EOF(_)));
+        assert_eq!(result.len(), 8);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bitwise_operators() -> Result<(), String> {
+        let result = get_tokens("~1&2|3^4").unwrap();
+
+        assert!(matches!(&result[0], TokenType::Tilde(token) if token.character == 1));
+        assert!(matches!(&result[2], TokenType::Ampersand(token) if token.character == 3));
+        assert!(matches!(&result[4], TokenType::Pipe(token) if token.character == 5));
+        assert!(matches!(&result[6], TokenType::Caret(token) if token.character == 7));
+        assert!(matches!(&result[8], TokenType::EOF(_)));
+        assert_eq!(result.len(), 9);
+
+        let result = get_tokens("x|>f | >").unwrap();
+        assert!(matches!(&result[1], TokenType::PipeGreater(token) if token.character == 2));
+        assert!(matches!(&result[3], TokenType::Pipe(_)));
+        assert!(matches!(&result[4], TokenType::Greater(_)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_eof_position() -> Result<(), String> {
+        let result = get_tokens("1 +\n 22 ").unwrap();
+
+        match &result[3] {
+            TokenType::EOF(token) => {
+                assert_eq!(token.line, 2, "{}", TOKEN_WRONG_LINE);
+                assert_eq!(token.character, 5, "{}", TOKEN_WRONG_CHARACTER);
+                assert_eq!((token.lexeme.start, token.lexeme.end), (8, 8));
+                assert_eq!(token.lexeme.as_str(), "", "{}", TOKEN_WRONG_LEXEME);
+            }
+            _ => panic!("{}", UNEXPECTED_TOKEN_MATCH)
+        }
+
+        let result = get_tokens("").unwrap();
+        assert!(matches!(&result[..], [TokenType::EOF(token)] if token.line == 1 && token.character == 1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unexpected_token() -> Result<(), String> {
+        let result = get_tokens("`");
+        match result {
+            Err(LexerError::UnexpectedToken(token)) => {
+                assert_eq!(token.line, 1, "{}", TOKEN_WRONG_LINE);
+                assert_eq!(token.character, 1, "{}", TOKEN_WRONG_CHARACTER);
+                assert_eq!(token.lexeme.as_str(), "`", "{}", TOKEN_WRONG_LEXEME)
+            },
+            _ => panic!("{}", UNEXPECTED_TOKEN_MATCH)
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resilient_collects_all_errors() -> Result<(), String> {
+        let (tokens, errors) = get_tokens_resilient("1 ` 2\n$ + 1e");
+
+        assert_number_token(&tokens[0], 1, 1, 1, "1");
+        assert_number_token(&tokens[1], 2, 1, 5, "2");
+        assert_plus_token(&tokens[2], 2, 3);
+        assert!(matches!(&tokens[3], TokenType::EOF(_)));
+        assert_eq!(tokens.len(), 4);
+
+        match &errors[..] {
+            [
+                LexerError::UnexpectedToken(first),
+                LexerError::UnexpectedToken(second),
+                LexerError::MalformedExponent(third),
+            ] => {
+                assert_eq!((first.line, first.character), (1, 3), "{}", TOKEN_WRONG_CHARACTER);
+                assert_eq!(first.lexeme.as_str(), "`", "{}", TOKEN_WRONG_LEXEME);
+                assert_eq!((second.line, second.character), (2, 1), "{}", TOKEN_WRONG_CHARACTER);
+                assert_eq!(second.lexeme.as_str(), "$", "{}", TOKEN_WRONG_LEXEME);
+                assert_eq!((third.line, third.character), (2, 5), "{}", TOKEN_WRONG_CHARACTER);
+                assert_eq!(third.lexeme.as_str(), "1e", "{}", TOKEN_WRONG_LEXEME);
+            }
+            _ => panic!("{}", UNEXPECTED_TOKEN_MATCH)
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resilient_without_errors() -> Result<(), String> {
+        let (tokens, errors) = get_tokens_resilient("2+3");
+
+        assert_eq!(tokens.len(), 4);
+        assert!(errors.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_token_stream_is_lazy() -> Result<(), String> {
+        let mut stream = TokenStream::new("1 + ` 2");
+
+        assert_number_token(&stream.next().unwrap().unwrap(), 1, 1, 1, "1");
+        assert_plus_token(&stream.next().unwrap().unwrap(), 1, 3);
+        assert!(matches!(stream.next(), Some(Err(LexerError::UnexpectedToken(_)))));
+        assert_number_token(&stream.next().unwrap().unwrap(), 2, 1, 7, "2");
+        assert!(matches!(stream.next(), Some(Ok(TokenType::EOF(_)))));
+        assert!(stream.next().is_none());
+        assert!(stream.next().is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_token_stream_of_empty_input() -> Result<(), String> {
+        let tokens = TokenStream::new("  \n ").collect::<Vec<_>>();
+
+        assert!(matches!(&tokens[..], [Ok(TokenType::EOF(_))]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lexemes_are_byte_spans() -> Result<(), String> {
+        let result = get_tokens("'π' + 10").unwrap();
+
+        match (&result[0], &result[2]) {
+            (TokenType::Char(char), TokenType::Number(number)) => {
+                assert_eq!((char.lexeme.start, char.lexeme.end), (0, 4));
+                assert_eq!(number.character, 7, "{}", TOKEN_WRONG_CHARACTER);
+                assert_eq!((number.lexeme.start, number.lexeme.end), (7, 9));
+                assert_eq!(number.lexeme.as_str(), "10", "{}", TOKEN_WRONG_LEXEME);
+            }
+            _ => panic!("{}", UNEXPECTED_TOKEN_MATCH)
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lexeme_display() -> Result<(), String> {
+        let result = get_tokens("12 ** 'x'").unwrap();
+
+        match &result[..] {
+            [TokenType::Number(number), TokenType::StarStar(star_star), TokenType::Char(char), TokenType::EOF(_)] => {
+                assert_eq!(number.lexeme.to_string(), "12", "{}", TOKEN_WRONG_LEXEME);
+                assert_eq!(format!("<{}>", star_star.lexeme), "<**>", "{}", TOKEN_WRONG_LEXEME);
+                assert_eq!(char.lexeme.as_str(), "'x'", "{}", TOKEN_WRONG_LEXEME);
+            }
+            _ => panic!("{}", UNEXPECTED_TOKEN_MATCH)
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tab_width() -> Result<(), String> {
+        let config = ScannerConfig { tab_width: 4, ..ScannerConfig::default() };
+        let result = get_tokens_with_config("\t1\n  \t+\t\t2 \t3", config).unwrap();
+
+        assert_number_token(&result[0], 1, 1, 5, "1");
+        assert_plus_token(&result[1], 2, 5);
+        assert_number_token(&result[2], 2, 2, 13, "2");
+        assert_number_token(&result[3], 3, 2, 17, "3");
+
+        let result = get_tokens("\t1\t+").unwrap();
+        assert_number_token(&result[0], 1, 1, 2, "1");
+        assert_plus_token(&result[1], 1, 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tab_width_in_multiline_string() -> Result<(), String> {
+        let config = ScannerConfig { tab_width: 8, ..ScannerConfig::default() };
+        let result = get_tokens_with_config("\"a\n\tb\" 1", config).unwrap();
+
+        assert_number_token(&result[1], 1, 2, 12, "1");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_comments_are_skipped() -> Result<(), String> {
+        let result = get_tokens("1 // one\n// nothing here\n+ 2 //").unwrap();
+
+        assert_number_token(&result[0], 1, 1, 1, "1");
+        assert_plus_token(&result[1], 3, 1);
+        assert_number_token(&result[2], 2, 3, 3, "2");
+        assert!(matches!(&result[3], TokenType::EOF(_)));
+        assert_eq!(result.len(), 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_shebang() -> Result<(), String> {
+        let result = get_tokens("#!/usr/bin/env rat-lang\n1 + 2").unwrap();
+
+        assert_number_token(&result[0], 1, 2, 1, "1");
+        assert_plus_token(&result[1], 2, 3);
+        assert_number_token(&result[2], 2, 2, 5, "2");
+        assert!(matches!(&result[3], TokenType::EOF(_)));
+        assert_eq!(result.len(), 4);
+
+        let result = get_tokens_with_trivia("#!rat\n1").unwrap();
+        assert!(matches!(&result[0], TokenType::Comment(token) if token.lexeme.as_str() == "#!rat"));
+
+        assert!(matches!(get_tokens(" #!rat"), Err(LexerError::UnexpectedToken(_))));
+        assert!(matches!(get_tokens("1\n#!rat"), Err(LexerError::UnexpectedToken(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_doc_comments() -> Result<(), String> {
+        let result = get_tokens("/// Adds one.\n///\n///  Indented\r\nfn // plain\n//// also plain\nx").unwrap();
+        let docs = result.iter()
+            .filter_map(|token| match token {
+                TokenType::DocComment(doc) => Some((doc.literal.as_str(), doc.line, doc.lexeme.as_str())),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(docs, [("Adds one.", 1, "/// Adds one."), ("", 2, "///"), (" Indented", 3, "///  Indented")]);
+        assert!(matches!(&result[3], TokenType::Fn(token) if token.line == 4));
+        assert!(matches!(&result[4], TokenType::Identifier(token) if token.line == 6));
+        assert_eq!(result.len(), 6);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_trivia() -> Result<(), String> {
+        let result = get_tokens_with_trivia("1 \t+ // sum\n2").unwrap();
+
+        assert_number_token(&result[0], 1, 1, 1, "1");
+        match &result[1..5] {
+            [
+                TokenType::Whitespace(whitespace),
+                TokenType::Plus(_),
+                TokenType::Whitespace(_),
+                TokenType::Comment(comment),
+            ] => {
+                assert_eq!(whitespace.lexeme.as_str(), " \t", "{}", TOKEN_WRONG_LEXEME);
+                assert_eq!(whitespace.character, 2, "{}", TOKEN_WRONG_CHARACTER);
+                assert_eq!(comment.lexeme.as_str(), "// sum", "{}", TOKEN_WRONG_LEXEME);
+                assert_eq!(comment.character, 6, "{}", TOKEN_WRONG_CHARACTER);
+            }
+            _ => panic!("{}", UNEXPECTED_TOKEN_MATCH)
+        }
+        match &result[5] {
+            TokenType::Newline(newline) => {
+                assert_eq!(newline.line, 1, "{}", TOKEN_WRONG_LINE);
+                assert_eq!(newline.character, 12, "{}", TOKEN_WRONG_CHARACTER);
+            }
+            _ => panic!("{}", UNEXPECTED_TOKEN_MATCH)
+        }
+        assert_number_token(&result[6], 2, 2, 1, "2");
+        assert!(matches!(&result[7], TokenType::EOF(_)));
+        assert_eq!(result.len(), 8);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_braces() -> Result<(), String> {
+        let result = get_tokens("{ 1 }").unwrap();
+        assert!(matches!(&result[0], TokenType::LeftBrace(token) if token.character == 1));
+        assert!(matches!(&result[2], TokenType::RightBrace(token) if token.character == 5));
+
+        // Braces inside of an interpolation have to close before it does
+        let result = get_tokens("\"a${ {1} }b\" }").unwrap();
+        assert!(matches!(&result[0], TokenType::InterpolationStart(_)));
+        assert!(matches!(&result[1], TokenType::LeftBrace(token) if token.character == 6));
+        assert!(matches!(&result[3], TokenType::RightBrace(token) if token.character == 8));
+        match &result[4] {
+            TokenType::InterpolationEnd(token) => assert_eq!(token.literal, "b", "{}", TOKEN_WRONG_LITERAL),
+            _ => panic!("{}", UNEXPECTED_TOKEN_MATCH)
+        }
+        assert!(matches!(&result[5], TokenType::RightBrace(token) if token.character == 14));
+        assert_eq!(result.len(), 7);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_semicolon() -> Result<(), String> {
+        let result = get_tokens("1;2 ;").unwrap();
+
+        assert!(matches!(&result[1], TokenType::Semicolon(token) if token.character == 2));
+        assert!(matches!(&result[3], TokenType::Semicolon(token) if token.character == 5));
+        assert_eq!(result.len(), 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_trivia_reconstructs_source() -> Result<(), String> {
+        let input = "  (1+2) ** 3\r\n\t// done \n-'\\n'  % 4.5e1\n";
+        let result = get_tokens_with_trivia(input).unwrap();
+
+        let reconstructed = result.iter()
+            .filter_map(|token| match token {
+                TokenType::EOF(_) => None,
+                TokenType::Number(token) => Some(token.lexeme.to_string()),
+                TokenType::Float(token) => Some(token.lexeme.to_string()),
+                TokenType::Char(token) => Some(token.lexeme.to_string()),
+                TokenType::Plus(token) | TokenType::Minus(token) | TokenType::StarStar(token)
+                | TokenType::Percent(token) | TokenType::LeftParen(token)
+                | TokenType::RightParen(token) | TokenType::Whitespace(token)
+                | TokenType::Newline(token) | TokenType::Comment(token) => Some(token.lexeme.to_string()),
+                _ => panic!("{}", UNEXPECTED_TOKEN_MATCH)
+            })
+            .collect::<String>();
+        assert_eq!(reconstructed, input);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_strings() -> Result<(), String> {
+        let result = get_tokens("\"rat\" \"tab\\there \\\"quoted\\\"\" \"\"").unwrap();
+
+        assert_string_token(&result[0], "rat", 1, 1, "\"rat\"");
+        assert_string_token(&result[1], "tab\there \"quoted\"", 1, 7, "\"tab\\there \\\"quoted\\\"\"");
+        assert_string_token(&result[2], "", 1, 30, "\"\"");
+        assert!(matches!(&result[3], TokenType::EOF(_)));
+        assert_eq!(result.len(), 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_raw_strings() -> Result<(), String> {
+        let result = get_tokens("r\"C:\\temp\\n\" r#\"say \"hi\"\"# r##\"a\"#b\"## r").unwrap();
+
+        assert_string_token(&result[0], "C:\\temp\\n", 1, 1, "r\"C:\\temp\\n\"");
+        assert_string_token(&result[1], "say \"hi\"", 1, 14, "r#\"say \"hi\"\"#");
+        assert_string_token(&result[2], "a\"#b", 1, 28, "r##\"a\"#b\"##");
+        assert_identifier_token(&result[3], 1, 40, "r");
+        assert!(matches!(&result[4], TokenType::EOF(_)));
+        assert_eq!(result.len(), 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_multiline_strings() -> Result<(), String> {
+        let result = get_tokens("1 \"a\nbc\" 2\nr\"\n\n\"3").unwrap();
+
+        assert_number_token(&result[0], 1, 1, 1, "1");
+        assert_string_token(&result[1], "a\nbc", 1, 3, "\"a\nbc\"");
+        assert_number_token(&result[2], 2, 2, 5, "2");
+        assert_string_token(&result[3], "\n\n", 3, 1, "r\"\n\n\"");
+        assert_number_token(&result[4], 3, 5, 2, "3");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_strings() -> Result<(), String> {
+        assert!(matches!(get_tokens("\"abc"), Err(LexerError::UnterminatedString(_))));
+        assert!(matches!(get_tokens("r#\"abc\""), Err(LexerError::UnterminatedString(_))));
+        assert!(matches!(get_tokens("\"\\q\""), Err(LexerError::InvalidEscape(_))));
+        assert!(matches!(get_tokens("r#abc"), Err(LexerError::UnexpectedToken(_))));
+
+        match get_tokens("1 + \"abc\ndef") {
+            Err(LexerError::UnterminatedString(token)) => {
+                assert_eq!(token.line, 1, "{}", TOKEN_WRONG_LINE);
+                assert_eq!(token.character, 5, "{}", TOKEN_WRONG_CHARACTER);
+                assert_eq!(token.lexeme.as_str(), "\"abc\ndef", "{}", TOKEN_WRONG_LEXEME)
+            },
+            _ => panic!("{}", UNEXPECTED_TOKEN_MATCH)
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_interpolation() -> Result<(), String> {
+        let result = get_tokens("\"sum is ${1+2}!\"").unwrap();
+
+        match &result[..] {
+            [
+                TokenType::InterpolationStart(start),
+                TokenType::Number(_),
+                TokenType::Plus(_),
+                TokenType::Number(_),
+                TokenType::InterpolationEnd(end),
+                TokenType::EOF(_),
+            ] => {
+                assert_eq!(start.literal, "sum is ", "{}", TOKEN_WRONG_LITERAL);
+                assert_eq!(start.lexeme.as_str(), "\"sum is ${", "{}", TOKEN_WRONG_LEXEME);
+                assert_eq!(end.literal, "!", "{}", TOKEN_WRONG_LITERAL);
+                assert_eq!(end.lexeme.as_str(), "}!\"", "{}", TOKEN_WRONG_LEXEME);
+                assert_eq!(end.character, 14, "{}", TOKEN_WRONG_CHARACTER);
+            }
+            _ => panic!("{}", UNEXPECTED_TOKEN_MATCH)
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_interpolation_segments() -> Result<(), String> {
+        let result = get_tokens("\"${1} and ${\"in ${2}\"}\" \"\\${3}\"").unwrap();
+
+        match &result[..] {
+            [
+                TokenType::InterpolationStart(start),
+                TokenType::Number(_),
+                TokenType::InterpolationMiddle(middle),
+                TokenType::InterpolationStart(inner_start),
+                TokenType::Number(_),
+                TokenType::InterpolationEnd(inner_end),
+                TokenType::InterpolationEnd(end),
+                TokenType::Str(escaped),
+                TokenType::EOF(_),
+            ] => {
+                assert_eq!(start.literal, "", "{}", TOKEN_WRONG_LITERAL);
+                assert_eq!(middle.literal, " and ", "{}", TOKEN_WRONG_LITERAL);
+                assert_eq!(middle.lexeme.as_str(), "} and ${", "{}", TOKEN_WRONG_LEXEME);
+                assert_eq!(inner_start.literal, "in ", "{}", TOKEN_WRONG_LITERAL);
+                assert_eq!(inner_end.literal, "", "{}", TOKEN_WRONG_LITERAL);
+                assert_eq!(end.literal, "", "{}", TOKEN_WRONG_LITERAL);
+                assert_eq!(escaped.literal, "${3}", "{}", TOKEN_WRONG_LITERAL);
+            }
+            _ => panic!("{}", UNEXPECTED_TOKEN_MATCH)
+        }
+
+        // Outside of an interpolation a `}` just closes a block
+        assert!(matches!(&get_tokens("1 }").unwrap()[1], TokenType::RightBrace(_)));
+        assert!(matches!(get_tokens("\"${1}"), Err(LexerError::UnterminatedString(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_identifiers() -> Result<(), String> {
+        let result = get_tokens("foo _bar baz_2 x1").unwrap();
+
+        assert_identifier_token(&result[0], 1, 1, "foo");
+        assert_identifier_token(&result[1], 1, 5, "_bar");
+        assert_identifier_token(&result[2], 1, 10, "baz_2");
+        assert_identifier_token(&result[3], 1, 16, "x1");
+        assert!(matches!(&result[4], TokenType::EOF(_)));
+        assert_eq!(result.len(), 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_keywords() -> Result<(), String> {
+        let result = get_tokens("let letter = lets if else true false iffy while for in break continue fn return match struct enum nil impl trait import macro try catch").unwrap();
+
+        assert!(matches!(&result[0], TokenType::Let(token) if token.character == 1));
+        assert_identifier_token(&result[1], 1, 5, "letter");
+        assert!(matches!(&result[2], TokenType::Equal(token) if token.character == 12));
+        assert_identifier_token(&result[3], 1, 14, "lets");
+        assert!(matches!(&result[4], TokenType::If(_)));
+        assert!(matches!(&result[5], TokenType::Else(_)));
+        assert!(matches!(&result[6], TokenType::True(_)));
+        assert!(matches!(&result[7], TokenType::False(_)));
+        assert_identifier_token(&result[8], 1, 38, "iffy");
+        assert!(matches!(&result[9], TokenType::While(_)));
+        assert!(matches!(&result[10], TokenType::For(_)));
+        assert!(matches!(&result[11], TokenType::In(_)));
+        assert!(matches!(&result[12], TokenType::Break(_)));
+        assert!(matches!(&result[13], TokenType::Continue(_)));
+        assert!(matches!(&result[14], TokenType::Fn(_)));
+        assert!(matches!(&result[15], TokenType::Return(_)));
+        assert!(matches!(&result[16], TokenType::Match(_)));
+        assert!(matches!(&result[17], TokenType::Struct(_)));
+        assert!(matches!(&result[18], TokenType::Enum(_)));
+        assert!(matches!(&result[19], TokenType::Nil(_)));
+        assert!(matches!(&result[20], TokenType::Impl(_)));
+        assert!(matches!(&result[21], TokenType::Trait(_)));
+        assert!(matches!(&result[22], TokenType::Import(_)));
+        assert!(matches!(&result[23], TokenType::Macro(_)));
+        assert!(matches!(&result[24], TokenType::Try(_)));
+        assert!(matches!(&result[25], TokenType::Catch(_)));
+        assert_eq!(result.len(), 27);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compound_assignment() -> Result<(), String> {
+        let result = get_tokens("+= -= *= /= **= // =").unwrap();
+
+        assert!(matches!(&result[0], TokenType::PlusEqual(token) if token.lexeme.as_str() == "+="));
+        assert!(matches!(&result[1], TokenType::MinusEqual(token) if token.character == 4));
+        assert!(matches!(&result[2], TokenType::StarEqual(token) if token.character == 7));
+        assert!(matches!(&result[3], TokenType::SlashEqual(token) if token.character == 10));
+        assert!(matches!(&result[4], TokenType::StarStar(_)));
+        assert!(matches!(&result[5], TokenType::Equal(token) if token.character == 15));
+        assert!(matches!(&result[6], TokenType::EOF(_)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_comparison_operators() -> Result<(), String> {
+        let result = get_tokens("== != < <= > >= = !").unwrap_err();
+        assert!(matches!(result, LexerError::UnexpectedToken(token) if token.character == 19));
+
+        let result = get_tokens("a==b!=c<d<=e>f>=g=h").unwrap();
+        assert!(matches!(&result[1], TokenType::EqualEqual(token) if token.character == 2));
+        assert!(matches!(&result[3], TokenType::BangEqual(token) if token.character == 5));
+        assert!(matches!(&result[5], TokenType::Less(token) if token.character == 8));
+        assert!(matches!(&result[7], TokenType::LessEqual(token) if token.character == 10));
+        assert!(matches!(&result[9], TokenType::Greater(token) if token.character == 13));
+        assert!(matches!(&result[11], TokenType::GreaterEqual(token) if token.character == 15));
+        assert!(matches!(&result[13], TokenType::Equal(token) if token.character == 18));
+        assert_eq!(result.len(), 16);
+
+        let result = get_tokens("_ => a >= b = > c").unwrap();
+        assert!(matches!(&result[1], TokenType::FatArrow(token) if token.character == 3));
+        assert!(matches!(&result[3], TokenType::GreaterEqual(_)));
+        assert!(matches!(&result[5], TokenType::Equal(_)));
+        assert!(matches!(&result[6], TokenType::Greater(_)));
+
+        let result = get_tokens("f() -> T - > x").unwrap();
+        assert!(matches!(&result[3], TokenType::Arrow(token) if token.character == 5));
+        assert!(matches!(&result[5], TokenType::Minus(_)));
+        assert!(matches!(&result[6], TokenType::Greater(_)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ranges() -> Result<(), String> {
+        let result = get_tokens("0..10 1.5..2").unwrap();
+        assert!(matches!(&result[0], TokenType::Number(token) if token.literal == 0));
+        assert!(matches!(&result[1], TokenType::DotDot(token) if token.character == 2));
+        assert!(matches!(&result[2], TokenType::Number(token) if token.literal == 10));
+        assert!(matches!(&result[3], TokenType::Float(token) if token.literal == 1.5));
+        assert!(matches!(&result[4], TokenType::DotDot(token) if token.character == 10));
+        assert_eq!(result.len(), 7);
+
+        let result = get_tokens("0..=10 .. =").unwrap();
+        assert!(matches!(&result[1], TokenType::DotDotEqual(token) if token.character == 2));
+        assert!(matches!(&result[2], TokenType::Number(token) if token.literal == 10));
+        assert!(matches!(&result[3], TokenType::DotDot(_)));
+        assert!(matches!(&result[4], TokenType::Equal(_)));
+
+        let result = get_tokens("[x, rest...] .. .").unwrap();
+        assert!(matches!(&result[4], TokenType::DotDotDot(token) if token.character == 9));
+        assert!(matches!(&result[6], TokenType::DotDot(_)));
+        assert!(matches!(&result[7], TokenType::Dot(_)));
+
+        let result = get_tokens("pair.0.1 1 .5").unwrap();
+        assert!(matches!(&result[1], TokenType::Dot(token) if token.character == 5));
+        assert!(matches!(&result[2], TokenType::Number(token) if token.literal == 0));
+        assert!(matches!(&result[3], TokenType::Dot(token) if token.character == 7));
+        assert!(matches!(&result[4], TokenType::Number(token) if token.literal == 1));
+        assert!(matches!(&result[5], TokenType::Number(token) if token.literal == 1));
+        assert!(matches!(&result[7], TokenType::Number(token) if token.literal == 5));
+        assert_eq!(result.len(), 9);
+        assert!(matches!(&get_tokens("f(a, b)").unwrap()[3], TokenType::Comma(token) if token.character == 4));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_brackets() -> Result<(), String> {
+        let result = get_tokens("[1, [2]]").unwrap();
+        assert!(matches!(&result[0], TokenType::LeftBracket(token) if token.character == 1));
+        assert!(matches!(&result[3], TokenType::LeftBracket(token) if token.character == 5));
+        assert!(matches!(&result[5], TokenType::RightBracket(token) if token.character == 7));
+        assert!(matches!(&result[6], TokenType::RightBracket(token) if token.character == 8));
+        assert_eq!(result.len(), 8);
+
+        assert!(matches!(&get_tokens("{a: 1}").unwrap()[2], TokenType::Colon(token) if token.character == 3));
+        assert!(matches!(&get_tokens("a?b").unwrap()[1], TokenType::Question(token) if token.character == 2));
+        assert!(matches!(&get_tokens("a??b").unwrap()[1], TokenType::QuestionQuestion(token) if token.character == 2));
+        assert!(matches!(&get_tokens("a? ?b").unwrap()[2], TokenType::Question(token) if token.character == 4));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unicode_identifiers() -> Result<(), String> {
+        let result = get_tokens("número + π*2\n日本語 ñ").unwrap();
+
+        assert_identifier_token(&result[0], 1, 1, "número");
+        assert_plus_token(&result[1], 1, 8);
+        assert_identifier_token(&result[2], 1, 10, "π");
+        assert!(matches!(&result[3], TokenType::Star(token) if token.character == 11));
+        assert_number_token(&result[4], 2, 1, 12, "2");
+        assert_identifier_token(&result[5], 2, 1, "日本語");
+        assert_identifier_token(&result[6], 2, 5, "ñ");
+        assert!(matches!(&result[7], TokenType::EOF(_)));
+        assert_eq!(result.len(), 8);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_identifiers_follow_xid_rules() -> Result<(), String> {
+        // Digits and combining marks can continue an identifier but not start one
+        let result = get_tokens("e\u{301}1").unwrap();
+        assert_identifier_token(&result[0], 1, 1, "e\u{301}1");
+        assert_eq!(result.len(), 2);
+
+        assert!(matches!(get_tokens("\u{301}e"), Err(LexerError::UnexpectedToken(_))));
+        assert!(matches!(get_tokens("a€"), Err(LexerError::UnexpectedToken(token)) if token.character == 2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_messages() -> Result<(), String> {
+        let error = get_tokens("1 +\n  `").unwrap_err();
+        assert_eq!(error.to_string(), "Unexpected character '`' at line 2, character 3");
+
+        let error = get_tokens("2.5e+").unwrap_err();
+        assert_eq!(error.to_string(), "Missing digits after the exponent in '2.5e+' at line 1, character 1");
+
+        let error = get_tokens("  99999999999999999999").unwrap_err();
+        assert_eq!(error.to_string(), "Number 99999999999999999999 is too large at line 1, character 3");
+
+        let error = get_tokens("'\\z'").unwrap_err();
+        assert_eq!(error.to_string(), "Invalid escape sequence in '\\z at line 1, character 1");
+
+        let boxed: Box<dyn Error> = Box::new(get_tokens("\"abc").unwrap_err());
+        assert_eq!(boxed.to_string(), "Unterminated string \"abc starting at line 1, character 1");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_big_numbers() -> Result<(), String> {
+        let result = get_tokens("123 + 456").unwrap();
+
+        assert_number_token(&result[0], 123, 1, 1, "123");
+        assert_plus_token(&result[1], 1, 5);
+        assert_number_token(&result[2], 456, 1, 7, "456");
+        assert!(matches!(&result[3], TokenType::EOF(_)));
+        assert_eq!(result.len(), 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_floats() -> Result<(), String> {
+        let result = get_tokens("2.5 + 10").unwrap();
+
+        assert_float_token(&result[0], 2.5, 1, 1, "2.5");
+        assert_plus_token(&result[1], 1, 5);
+        assert_number_token(&result[2], 10, 1, 7, "10");
+        assert!(matches!(&result[3], TokenType::EOF(_)));
+        assert_eq!(result.len(), 4);
+
+        let result = get_tokens("1.").unwrap();
+        assert_number_token(&result[0], 1, 1, 1, "1");
+        assert!(matches!(&result[1], TokenType::Dot(token) if token.character == 2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scientific_notation() -> Result<(), String> {
+        let result = get_tokens("1e9 2.5e-3 6.02E23 1e+2").unwrap();
+
+        assert_float_token(&result[0], 1e9, 1, 1, "1e9");
+        assert_float_token(&result[1], 2.5e-3, 1, 5, "2.5e-3");
+        assert_float_token(&result[2], 6.02e23, 1, 12, "6.02E23");
+        assert_float_token(&result[3], 1e2, 1, 20, "1e+2");
+        assert!(matches!(&result[4], TokenType::EOF(_)));
+        assert_eq!(result.len(), 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_exponent() -> Result<(), String> {
+        for (input, lexeme) in [("1e", "1e"), ("2.5e-", "2.5e-"), ("3E+ 4", "3E+")] {
+            match get_tokens(input) {
+                Err(LexerError::MalformedExponent(token)) => {
+                    assert_eq!(token.line, 1, "{}", TOKEN_WRONG_LINE);
+                    assert_eq!(token.character, 1, "{}", TOKEN_WRONG_CHARACTER);
+                    assert_eq!(token.lexeme.as_str(), lexeme, "{}", TOKEN_WRONG_LEXEME)
+                },
+                _ => panic!("{}", UNEXPECTED_TOKEN_MATCH)
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_number_too_large() -> Result<(), String> {
+        let result = get_tokens("9223372036854775807").unwrap();
+        assert_number_token(&result[0], i64::MAX, 1, 1, "9223372036854775807");
+
+        match get_tokens("1 +\n 99999999999999999999") {
+            Err(LexerError::NumberTooLarge(token)) => {
+                assert_eq!(token.line, 2, "{}", TOKEN_WRONG_LINE);
+                assert_eq!(token.character, 2, "{}", TOKEN_WRONG_CHARACTER);
+                assert_eq!(token.lexeme.as_str(), "99999999999999999999", "{}", TOKEN_WRONG_LEXEME)
+            },
+            _ => panic!("{}", UNEXPECTED_TOKEN_MATCH)
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_chars() -> Result<(), String> {
+        let result = get_tokens("'a' '\\n' '\\'' '\\u{3c0}' 'π'").unwrap();
+
+        assert_char_token(&result[0], 'a', 1, 1, "'a'");
+        assert_char_token(&result[1], '\n', 1, 5, "'\\n'");
+        assert_char_token(&result[2], '\'', 1, 10, "'\\''");
+        assert_char_token(&result[3], 'π', 1, 15, "'\\u{3c0}'");
+        assert_char_token(&result[4], 'π', 1, 25, "'π'");
+        assert!(matches!(&result[5], TokenType::EOF(_)));
+        assert_eq!(result.len(), 6);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_chars() -> Result<(), String> {
+        assert!(matches!(get_tokens("'a"), Err(LexerError::UnterminatedChar(_))));
+        assert!(matches!(get_tokens("'ab'"), Err(LexerError::UnterminatedChar(_))));
+        assert!(matches!(get_tokens("'\n'"), Err(LexerError::UnterminatedChar(_))));
+        assert!(matches!(get_tokens("''"), Err(LexerError::EmptyChar(_))));
+        assert!(matches!(get_tokens("'\\q'"), Err(LexerError::InvalidEscape(_))));
+        assert!(matches!(get_tokens("'\\u{110000}'"), Err(LexerError::InvalidEscape(_))));
+
+        match get_tokens("'\\u{zz}'") {
+            Err(LexerError::InvalidEscape(token)) => {
+                assert_eq!(token.line, 1, "{}", TOKEN_WRONG_LINE);
+                assert_eq!(token.character, 1, "{}", TOKEN_WRONG_CHARACTER);
+                assert_eq!(token.lexeme.as_str(), "'\\u{z", "{}", TOKEN_WRONG_LEXEME)
+            },
+            _ => panic!("{}", UNEXPECTED_TOKEN_MATCH)
+        }
+
+        Ok(())
+    }
+
+    fn assert_number_token(
+        token_type: &TokenType,
+        literal: i64,
+        line: u32,
+        character: u32,
+        lexeme: &str
+    ) {
+        match token_type {
+            TokenType::Number(token) => {
+                assert_eq!(token.literal, literal, "{}", TOKEN_WRONG_LITERAL);
+                assert_eq!(token.line, line, "{}", TOKEN_WRONG_LINE);
+                assert_eq!(token.character, character, "{}", TOKEN_WRONG_CHARACTER);
+                assert_eq!(token.lexeme.as_str(), lexeme, "{}", TOKEN_WRONG_LEXEME);
+            }
+            _ => panic!("{}", UNEXPECTED_TOKEN_MATCH)
+        }
+    }
+
+    fn assert_float_token(
+        token_type: &TokenType,
+        literal: f64,
+        line: u32,
+        character: u32,
+        lexeme: &str
+    ) {
+        match token_type {
+            TokenType::Float(token) => {
+                assert_eq!(token.literal, literal, "{}", TOKEN_WRONG_LITERAL);
+                assert_eq!(token.line, line, "{}", TOKEN_WRONG_LINE);
+                assert_eq!(token.character, character, "{}", TOKEN_WRONG_CHARACTER);
+                assert_eq!(token.lexeme.as_str(), lexeme, "{}", TOKEN_WRONG_LEXEME);
+            }
+            _ => panic!("{}", UNEXPECTED_TOKEN_MATCH)
+        }
+    }
+
+    fn assert_char_token(
+        token_type: &TokenType,
+        literal: char,
+        line: u32,
+        character: u32,
+        lexeme: &str
+    ) {
+        match token_type {
+            TokenType::Char(token) => {
+                assert_eq!(token.literal, literal, "{}", TOKEN_WRONG_LITERAL);
+                assert_eq!(token.line, line, "{}", TOKEN_WRONG_LINE);
+                assert_eq!(token.character, character, "{}", TOKEN_WRONG_CHARACTER);
+                assert_eq!(token.lexeme.as_str(), lexeme, "{}", TOKEN_WRONG_LEXEME);
+            }
+            _ => panic!("{}", UNEXPECTED_TOKEN_MATCH)
+        }
+    }
+
+    fn assert_string_token(
+        token_type: &TokenType,
+        literal: &str,
+        line: u32,
+        character: u32,
+        lexeme: &str
+    ) {
+        match token_type {
+            TokenType::Str(token) => {
+                assert_eq!(token.literal, literal, "{}", TOKEN_WRONG_LITERAL);
+                assert_eq!(token.line, line, "{}", TOKEN_WRONG_LINE);
+                assert_eq!(token.character, character, "{}", TOKEN_WRONG_CHARACTER);
+                assert_eq!(token.lexeme.as_str(), lexeme, "{}", TOKEN_WRONG_LEXEME);
+            }
+            _ => panic!("{}", UNEXPECTED_TOKEN_MATCH)
+        }
+    }
+
+    fn assert_identifier_token(token_type: &TokenType, line: u32, character: u32, lexeme: &str) {
+        match token_type {
+            TokenType::Identifier(token) => {
+                assert_eq!(token.line, line, "{}", TOKEN_WRONG_LINE);
+                assert_eq!(token.character, character, "{}", TOKEN_WRONG_CHARACTER);
+                assert_eq!(token.lexeme.as_str(), lexeme, "{}", TOKEN_WRONG_LEXEME);
+            }
+            _ => panic!("{}", UNEXPECTED_TOKEN_MATCH)
+        }
+    }
+
+    fn assert_plus_token(token_type: &TokenType, line: u32, character: u32) {
+        match token_type {
+            TokenType::Plus(token) => {
+                assert_eq!(token.line, line, "{}", TOKEN_WRONG_LINE);
+                assert_eq!(token.character, character, "{}", TOKEN_WRONG_CHARACTER);
+                assert_eq!(token.lexeme.as_str(), "+", "{}", TOKEN_WRONG_LEXEME);
+            }
+            _ => panic!("{}", UNEXPECTED_TOKEN_MATCH)
+        }
+    }
+
+}
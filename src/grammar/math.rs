@@ -0,0 +1,201 @@
+use std::f64::consts;
+use crate::grammar::evaluate::{apply, Callable, OverflowMode, RuntimeError};
+use crate::grammar::lexer::Span;
+use crate::grammar::parser::BinaryOperator;
+use crate::grammar::rational::Rational;
+use crate::grammar::runtime::{Module, Value};
+
+type Function = fn(&[Value]) -> Result<Value, RuntimeError>;
+
+const FUNCTIONS: [(&str, usize, Function); 8] = [
+    ("abs", 1, abs),
+    ("ceil", 1, ceil),
+    ("floor", 1, floor),
+    ("gcd", 2, gcd),
+    ("max", 2, max),
+    ("min", 2, min),
+    ("pow", 2, pow),
+    ("sqrt", 1, sqrt),
+];
+
+// The `math` module every program can use without importing it. Its
+// functions are natives, so they put their errors down to the call.
+pub fn module() -> Value {
+    let mut members = FUNCTIONS.iter()
+        .map(|&(name, arity, function)| (name.to_string(), Value::Function(Callable::native(name, arity, function))))
+        .chain([("E".to_string(), Value::Float(consts::E)), ("PI".to_string(), Value::Float(consts::PI))])
+        .collect::<Vec<_>>();
+    members.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Value::Module(Module { name: "math".to_string(), members })
+}
+
+// `argument` was given to `function`, which only takes what `expected` says
+fn mismatch(function: &'static str, expected: &'static str, argument: &Value) -> RuntimeError {
+    RuntimeError::ArgumentType { function, expected, found: argument.type_of(), span: Span::default() }
+}
+
+fn overflow() -> RuntimeError {
+    RuntimeError::Overflow { span: Span::default() }
+}
+
+fn to_float(function: &'static str, value: &Value) -> Result<f64, RuntimeError> {
+    match value {
+        Value::Int(value) => Ok(*value as f64),
+        Value::BigInt(value) => Ok(value.to_f64()),
+        Value::Rational(value) => Ok(value.to_f64()),
+        Value::Float(value) => Ok(*value),
+        value => Err(mismatch(function, "a number", value)),
+    }
+}
+
+fn abs(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    match &arguments[0] {
+        Value::Int(value) => value.checked_abs().map(Value::Int).ok_or_else(overflow),
+        Value::BigInt(value) if value.is_negative() => Ok(Value::BigInt(-value)),
+        value @ Value::BigInt(_) => Ok(value.clone()),
+        Value::Rational(value) => {
+            let (numerator, denominator) = (i128::from(value.numerator()), i128::from(value.denominator()));
+            Rational::new(numerator.abs(), denominator).map(Value::Rational).ok_or_else(overflow)
+        }
+        Value::Float(value) => Ok(Value::Float(value.abs())),
+        value => Err(mismatch("math.abs", "a number", value)),
+    }
+}
+
+// Ints stay as they are, and anything else is rounded to an int
+fn round(
+    function: &'static str,
+    value: &Value,
+    float: fn(f64) -> f64,
+    rational: fn(&Rational) -> i64,
+) -> Result<Value, RuntimeError> {
+    match value {
+        value @ (Value::Int(_) | Value::BigInt(_)) => Ok(value.clone()),
+        Value::Rational(value) => Ok(Value::Int(rational(value))),
+        Value::Float(value) => {
+            let value = float(*value);
+            let limit = 2_f64.powi(63);
+            if (-limit..limit).contains(&value) {
+                Ok(Value::Int(value as i64))
+            } else {
+                Err(overflow())
+            }
+        }
+        value => Err(mismatch(function, "a number", value)),
+    }
+}
+
+fn floor(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    round("math.floor", &arguments[0], f64::floor, |value| value.numerator().div_euclid(value.denominator()))
+}
+
+// The denominator is always positive, so rounding the negation down rounds
+// up
+fn ceil(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    round("math.ceil", &arguments[0], f64::ceil, |value| -(-value.numerator()).div_euclid(value.denominator()))
+}
+
+// Never negative, and zero only when both of them are
+fn gcd(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    let [Value::Int(a), Value::Int(b)] = arguments else {
+        let argument = arguments.iter().find(|argument| !matches!(argument, Value::Int(_))).unwrap_or(&Value::Nil);
+        return Err(mismatch("math.gcd", "an int", argument));
+    };
+    let (mut a, mut b) = (a.unsigned_abs(), b.unsigned_abs());
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    i64::try_from(a).map(Value::Int).map_err(|_| overflow())
+}
+
+// The first argument if `operator` holds between it and the second, and the
+// second otherwise, so the first of two equal ones is given back. They're
+// compared exactly as the operator compares them, so an int can be given with
+// a float, and a type error names the operator.
+fn extreme(arguments: &[Value], operator: BinaryOperator) -> Result<Value, RuntimeError> {
+    let span = Span::default();
+    match apply(&operator, arguments[0].clone(), arguments[1].clone(), OverflowMode::default(), span, span, span)? {
+        Value::Bool(true) => Ok(arguments[0].clone()),
+        _ => Ok(arguments[1].clone()),
+    }
+}
+
+fn max(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    extreme(arguments, BinaryOperator::GreaterEqual)
+}
+
+fn min(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    extreme(arguments, BinaryOperator::LessEqual)
+}
+
+// Always a float, unlike `**`
+fn pow(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::Float(to_float("math.pow", &arguments[0])?.powf(to_float("math.pow", &arguments[1])?)))
+}
+
+fn sqrt(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::Float(to_float("math.sqrt", &arguments[0])?.sqrt()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::evaluate::{evaluate_source, execute};
+    use crate::grammar::lexer::get_tokens;
+    use crate::grammar::parser::get_program;
+
+    #[test]
+    fn test_functions() -> Result<(), String> {
+        assert_eq!(evaluate_source("math.abs(-3)").unwrap(), Value::Int(3));
+        assert_eq!(evaluate_source("math.abs(-1 / 2)").unwrap().to_string(), "1/2");
+        assert_eq!(evaluate_source("math.abs(-2.5)").unwrap(), Value::Float(2.5));
+        assert_eq!(evaluate_source("math.min(3, 2)").unwrap(), Value::Int(2));
+        assert_eq!(evaluate_source("math.max(1 / 3, 1 / 2)").unwrap().to_string(), "1/2");
+        assert_eq!(evaluate_source("math.max(\"a\", \"b\")").unwrap(), Value::Str("b".to_string()));
+        // Numbers are promoted the same as for `<`, but come back as they were given
+        assert_eq!(evaluate_source("[math.min(1, 2.5), math.max(1, 2.5), math.min(1 / 2, 0.25)]").unwrap().to_string(), "[1, 2.5, 0.25]");
+        assert_eq!(evaluate_source("[math.min(1, 1.0), math.max(1.0, 1)]").unwrap().to_string(), "[1, 1.0]");
+        assert_eq!(evaluate_source("math.sqrt(16)").unwrap(), Value::Float(4.0));
+        assert_eq!(evaluate_source("math.pow(2, 10)").unwrap(), Value::Float(1024.0));
+        assert_eq!(evaluate_source("math.pow(4, 0.5)").unwrap(), Value::Float(2.0));
+        assert_eq!(evaluate_source("[math.floor(2.7), math.ceil(2.2), math.floor(-2.5), math.ceil(-2.5)]").unwrap().to_string(), "[2, 3, -3, -2]");
+        assert_eq!(evaluate_source("[math.floor(-7 / 2), math.ceil(-7 / 2), math.floor(7 / 2), math.ceil(7)]").unwrap().to_string(), "[-4, -3, 3, 7]");
+        assert_eq!(evaluate_source("[math.gcd(12, 18), math.gcd(-4, 6), math.gcd(0, 0)]").unwrap().to_string(), "[6, 2, 0]");
+        assert_eq!(evaluate_source("math.PI").unwrap(), Value::Float(consts::PI));
+        assert_eq!(evaluate_source("math.E").unwrap(), Value::Float(consts::E));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_errors() -> Result<(), String> {
+        let input = "1 + math.abs(-9223372036854775807 - 1)";
+        match evaluate_source(input) {
+            Err(RuntimeError::Overflow { span }) => assert_eq!(&input[span.start..span.end], "math.abs(-9223372036854775807 - 1)"),
+            result => panic!("Expected an overflow, got {:?}", result),
+        }
+        assert!(matches!(evaluate_source("math.floor(10.0 ** 300)"), Err(RuntimeError::Overflow { .. })));
+        assert_eq!(evaluate_source("math.min(1, 2.0)").unwrap(), Value::Int(1));
+        let input = "math.sqrt(\"4\")";
+        match evaluate_source(input) {
+            Err(error @ RuntimeError::ArgumentType { .. }) => {
+                assert_eq!(error.message(), "Can't pass str to 'math.sqrt', which takes a number");
+                assert_eq!((error.span().start, error.span().end), (0, input.len()));
+            }
+            result => panic!("Expected an argument of the wrong type, got {:?}", result),
+        }
+        let message = |input: &str| evaluate_source(input).unwrap_err().message();
+        assert_eq!(message("math.gcd(4, 2.0)"), "Can't pass float to 'math.gcd', which takes an int");
+        assert_eq!(message("math.floor(nil)"), "Can't pass nil to 'math.floor', which takes a number");
+        assert_eq!(message("math.pow(2, 'a')"), "Can't pass char to 'math.pow', which takes a number");
+        assert_eq!(message("math.max(1, \"a\")"), "Can't apply '>=' to int and str");
+        assert!(matches!(evaluate_source("math.max(1)"), Err(RuntimeError::ArityMismatch { .. })));
+        assert!(matches!(evaluate_source("math.tau"), Err(RuntimeError::UnknownField { .. })));
+
+        // It's a global like any other, so it can be shadowed
+        let program = get_program(get_tokens("let math = 1\nmath").unwrap()).unwrap();
+        assert_eq!(execute(&program).unwrap(), Some(Value::Int(1)));
+
+        Ok(())
+    }
+}
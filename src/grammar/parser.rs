@@ -1,103 +1,4355 @@
-use crate::grammar::lexer::TokenType;
-
-#[derive(Debug)]
-pub enum TermOperator {
-    Plus,
-    Minus
-}
-
-#[derive(Debug)]
-pub struct Binary {
-    pub left: Box<Expression>,
-    pub operator: TermOperator,
-    pub right: Box<Expression>,
-}
-
-#[derive(Debug)]
-pub struct Integer {
-    pub value: i32
-}
-
-#[derive(Debug)]
-pub enum Expression {
-    Binary(Binary),
-    Integer(Integer),
-}
-
-struct Parser <'a> {
-    tokens: &'a Vec<TokenType>,
-    current: usize,
-}
-
-impl <'a> Parser <'a> {
-    pub fn parse(input: &Vec<TokenType>) -> Expression {
-        let mut parser = Parser::new(input);
-        parser.term()
-    }
-
-    fn new(input: &Vec<TokenType>) -> Parser {
-        Parser {
-            tokens: input,
-            current: 0,
-        }
-    }
-
-    fn term(&mut self) -> Expression {
-        let mut number = self.number();
-
-        while self.match_term_operand() {
-            let operator = match self.previous() {
-                Some(TokenType::Plus(_)) => TermOperator::Plus,
-                Some(TokenType::Minus(_)) => TermOperator::Minus,
-                _ => panic!("Shouldnt have happened")
-            };
-            let right = self.number();
-            number = Expression::Binary(Binary {
-                left: Box::new(number),
-                operator,
-                right: Box::new(right)
-            });
-        }
-
-        number
-    }
-
-    fn match_term_operand(&mut self) -> bool {
-        matches!(self.advance(), Some(TokenType::Plus(_)) | Some(TokenType::Minus(_)))
-    }
-
-    fn number(&mut self) -> Expression {
-        match self.advance() {
-            Some(TokenType::Number(number)) => Expression::Integer(Integer {
-                value: number.literal
-            }),
-            _ => panic!("Shouldnt have happened")
-        }
-    }
-
-    fn advance(&mut self) -> Option<&TokenType> {
-        if self.is_at_end() { return None; }
-
-        let result = &self.tokens[self.current];
-        self.current += 1;
-        Some(result)
-    }
-
-    fn peek(&self) -> Option<&TokenType> {
-        if self.is_at_end() { return None; }
-        Some(&self.tokens[self.current])
-    }
-
-    fn previous(&self) -> Option<&TokenType> {
-        if self.current == 0 || self.is_at_end() { return None; }
-        Some(&self.tokens[self.current - 1])
-    }
-
-    fn is_at_end(&self) -> bool {
-        self.current >= self.tokens.len()
-    }
-}
-
-pub fn get_ast(tokens: &Vec<TokenType>) -> Expression {
-    Parser::new(tokens).term()
-}
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::mem;
+use std::path::Path;
+use crate::grammar::lexer::{get_tokens, Lexeme, Span, TokenType};
+
+#[derive(Debug)]
+pub struct UnexpectedToken {
+    pub expected: &'static str,
+    pub lexeme: Lexeme,
+    pub line: u32,
+    pub character: u32,
+}
+
+#[derive(Debug)]
+pub struct UnexpectedEof {
+    pub expected: &'static str,
+    pub line: u32,
+    pub character: u32,
+}
+
+#[derive(Debug)]
+pub struct TrailingToken {
+    pub lexeme: Lexeme,
+    pub line: u32,
+    pub character: u32,
+}
+
+#[derive(Debug)]
+pub struct TooDeeplyNested {
+    pub max_depth: usize,
+    pub line: u32,
+    pub character: u32,
+}
+
+// The left of an `=` that isn't something that can be assigned to
+#[derive(Debug)]
+pub struct InvalidAssignmentTarget {
+    pub span: Span,
+}
+
+// A `break` or `continue` that isn't inside the body of a loop
+#[derive(Debug)]
+pub struct OutsideLoop {
+    pub keyword: &'static str,
+    pub span: Span,
+}
+
+// A `return` that isn't inside the body of a function
+#[derive(Debug)]
+pub struct OutsideFunction {
+    pub span: Span,
+}
+
+// A literal or variant in the pattern of a `let`, which some values of the
+// right shape wouldn't match
+#[derive(Debug)]
+pub struct RefutablePattern {
+    pub span: Span,
+}
+
+// A name that one pattern binds more than once
+#[derive(Debug)]
+pub struct DuplicateBinding {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    UnexpectedToken(UnexpectedToken),
+    UnexpectedEof(UnexpectedEof),
+    TrailingToken(TrailingToken),
+    TooDeeplyNested(TooDeeplyNested),
+    InvalidAssignmentTarget(InvalidAssignmentTarget),
+    OutsideLoop(OutsideLoop),
+    OutsideFunction(OutsideFunction),
+    RefutablePattern(RefutablePattern),
+    DuplicateBinding(DuplicateBinding),
+    // Nothing but whitespace and comments
+    EmptyInput,
+}
+
+impl Display for UnexpectedToken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Expected {} but found '{}' at line {}, character {}",
+            self.expected, self.lexeme, self.line, self.character
+        )
+    }
+}
+
+impl Error for UnexpectedToken {}
+
+impl Display for UnexpectedEof {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Expected {} but reached the end of input at line {}, character {}",
+            self.expected, self.line, self.character
+        )
+    }
+}
+
+impl Error for UnexpectedEof {}
+
+impl Display for TrailingToken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Unexpected token '{}' after expression at line {}, character {}",
+            self.lexeme, self.line, self.character
+        )
+    }
+}
+
+impl Error for TrailingToken {}
+
+impl Display for TooDeeplyNested {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Expression is nested more than {} levels deep at line {}, character {}",
+            self.max_depth, self.line, self.character
+        )
+    }
+}
+
+impl Error for TooDeeplyNested {}
+
+impl Display for InvalidAssignmentTarget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid assignment target at line {}, character {}", self.span.line, self.span.character)
+    }
+}
+
+impl Error for InvalidAssignmentTarget {}
+
+impl Display for OutsideLoop {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "'{}' outside of a loop at line {}, character {}", self.keyword, self.span.line, self.span.character)
+    }
+}
+
+impl Error for OutsideLoop {}
+
+impl Display for OutsideFunction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "'return' outside of a function at line {}, character {}", self.span.line, self.span.character)
+    }
+}
+
+impl Error for OutsideFunction {}
+
+impl Display for RefutablePattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Pattern in 'let' can fail to match at line {}, character {}", self.span.line, self.span.character)
+    }
+}
+
+impl Error for RefutablePattern {}
+
+impl Display for DuplicateBinding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "'{}' is bound more than once in the same pattern at line {}, character {}",
+            self.name, self.span.line, self.span.character
+        )
+    }
+}
+
+impl Error for DuplicateBinding {}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::UnexpectedToken(error) => Display::fmt(error, f),
+            ParseError::UnexpectedEof(error) => Display::fmt(error, f),
+            ParseError::TrailingToken(error) => Display::fmt(error, f),
+            ParseError::TooDeeplyNested(error) => Display::fmt(error, f),
+            ParseError::InvalidAssignmentTarget(error) => Display::fmt(error, f),
+            ParseError::OutsideLoop(error) => Display::fmt(error, f),
+            ParseError::OutsideFunction(error) => Display::fmt(error, f),
+            ParseError::RefutablePattern(error) => Display::fmt(error, f),
+            ParseError::DuplicateBinding(error) => Display::fmt(error, f),
+            ParseError::EmptyInput => f.write_str("Expected an expression but the input is empty"),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BinaryOperator {
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    StarStar,
+    Ampersand,
+    Pipe,
+    Caret,
+    EqualEqual,
+    BangEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    In,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UnaryOperator {
+    Minus,
+    Tilde,
+}
+
+impl Display for BinaryOperator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            BinaryOperator::Plus => "+",
+            BinaryOperator::Minus => "-",
+            BinaryOperator::Star => "*",
+            BinaryOperator::Slash => "/",
+            BinaryOperator::Percent => "%",
+            BinaryOperator::StarStar => "**",
+            BinaryOperator::Ampersand => "&",
+            BinaryOperator::Pipe => "|",
+            BinaryOperator::Caret => "^",
+            BinaryOperator::EqualEqual => "==",
+            BinaryOperator::BangEqual => "!=",
+            BinaryOperator::Less => "<",
+            BinaryOperator::LessEqual => "<=",
+            BinaryOperator::Greater => ">",
+            BinaryOperator::GreaterEqual => ">=",
+            BinaryOperator::In => "in",
+        })
+    }
+}
+
+impl Display for UnaryOperator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            UnaryOperator::Minus => "-",
+            UnaryOperator::Tilde => "~",
+        })
+    }
+}
+
+// Identifies a node within the tree it was parsed into, so that later passes
+// can keep information about nodes in side tables keyed by id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NodeId(pub u32);
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Binary {
+    pub left: Box<Expression>,
+    pub operator: BinaryOperator,
+    // Just the operator, which errors about the operands' types point at
+    pub operator_span: Span,
+    pub right: Box<Expression>,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Unary {
+    pub operator: UnaryOperator,
+    pub right: Box<Expression>,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Integer {
+    pub value: i64,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Float {
+    pub value: f64,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Char {
+    pub value: char,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Str {
+    pub value: String,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Bool {
+    pub value: bool,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Nil {
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// The parts of an interpolated string in order, with the text between the
+// embedded expressions stored as `Str` expressions
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Interpolation {
+    pub parts: Vec<Expression>,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// A read of the variable called `name`
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Variable {
+    pub name: String,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// `name = value`, which evaluates to the value assigned
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Assign {
+    pub name: String,
+    pub value: Box<Expression>,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// `target[index] = value`, where the target is a variable or another index
+// into one. Compound operators such as `+=` are kept as the operator rather
+// than spelled out, so that the index is only evaluated once.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IndexAssign {
+    pub target: Box<Expression>,
+    pub index: Box<Expression>,
+    pub operator: Option<BinaryOperator>,
+    pub value: Box<Expression>,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// `{ statement; statement; value }`, which runs the statements in order and
+// then evaluates to the trailing expression, if there is one
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Block {
+    pub statements: Vec<Statement>,
+    pub value: Option<Box<Expression>>,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// `if condition { ... } else { ... }`. The then branch is always a block and
+// the else branch is either a block or another `if`. Without an else branch
+// a false condition evaluates to nil.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct If {
+    pub condition: Box<Expression>,
+    pub then_branch: Box<Expression>,
+    pub else_branch: Option<Box<Expression>>,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// `start..end`, counting up from `start` and stopping before `end`, or
+// `start..=end`, which includes `end` as well
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Range {
+    pub start: Box<Expression>,
+    pub end: Box<Expression>,
+    pub inclusive: bool,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// `fn(parameter, parameter) { ... }`, a function without a name that can be
+// used as a value
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Lambda {
+    pub parameters: Vec<String>,
+    pub body: Box<Expression>,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// `callee(argument, argument)`
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Call {
+    pub callee: Box<Expression>,
+    pub arguments: Vec<Expression>,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// `condition ? then_branch : else_branch`, which only evaluates the branch
+// that is picked
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Conditional {
+    pub condition: Box<Expression>,
+    pub then_branch: Box<Expression>,
+    pub else_branch: Box<Expression>,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// `value ?? fallback`, which only evaluates the fallback when the value is
+// nil
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Coalesce {
+    pub value: Box<Expression>,
+    pub fallback: Box<Expression>,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// `match scrutinee { pattern => body, pattern if guard => body }`, which
+// evaluates the body of the first arm whose pattern matches and whose guard,
+// if it has one, is true
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Match {
+    pub scrutinee: Box<Expression>,
+    pub arms: Vec<MatchArm>,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// `try { body } catch (name) { handler }`, which evaluates the handler with
+// the error's message as `name` if the body stops with an error
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Try {
+    pub body: Box<Expression>,
+    pub name: String,
+    pub handler: Box<Expression>,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<Expression>,
+    pub body: Expression,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Pattern {
+    // `_`, which matches anything
+    Wildcard(Span),
+    // A name, which matches anything and binds it for the guard and body
+    Binding(String, Span),
+    // An integer, float, char, string, bool or nil literal, which matches
+    // values equal to it. A `-` in front of a number is part of the literal.
+    Literal(Expression),
+    Variant(VariantPattern),
+    Tuple(TuplePattern),
+    Array(ArrayPattern),
+}
+
+impl Pattern {
+    pub fn span(&self) -> Span {
+        match self {
+            Pattern::Wildcard(span) | Pattern::Binding(_, span) => *span,
+            Pattern::Literal(literal) => literal.span(),
+            Pattern::Variant(variant) => variant.span,
+            Pattern::Tuple(tuple) => tuple.span,
+            Pattern::Array(array) => array.span,
+        }
+    }
+}
+
+// `(pattern, pattern)`, which matches a tuple of the same length when each
+// of its elements matches the pattern in the same position. Like a tuple
+// literal, one element needs a trailing comma.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TuplePattern {
+    pub elements: Vec<Pattern>,
+    pub span: Span,
+}
+
+// `[pattern, pattern]`, which matches an array of the same length, or
+// `[pattern, rest...]`, which matches an array with at least as many elements
+// as there are patterns and makes the rest into a new array. The rest is
+// always a binding or a wildcard.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ArrayPattern {
+    pub elements: Vec<Pattern>,
+    pub rest: Option<Box<Pattern>>,
+    pub span: Span,
+}
+
+// `Enum.Variant(pattern, pattern)`, which matches that variant when each of
+// its values matches the pattern in the same position. A variant without
+// fields is written without the parentheses.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VariantPattern {
+    pub enum_name: String,
+    pub variant: String,
+    pub fields: Vec<Pattern>,
+    pub span: Span,
+}
+
+// `(element, element)`, or `(element,)` with one element and `()` with none
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Tuple {
+    pub elements: Vec<Expression>,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// `tuple.position`, with the position written as a number
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TupleIndex {
+    pub tuple: Box<Expression>,
+    pub position: usize,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// `Name { field: value, field: value }`, which makes an instance of the
+// struct called `Name`
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StructLiteral {
+    pub name: String,
+    pub fields: Vec<(String, Expression)>,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// `target.field`
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Field {
+    pub target: Box<Expression>,
+    pub field: String,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// `{ key: value, key: value }`, or `{:}` when there are no entries
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Map {
+    pub entries: Vec<(Expression, Expression)>,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// `target[index]`
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Index {
+    pub target: Box<Expression>,
+    pub index: Box<Expression>,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// `[element, element]`
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Array {
+    pub elements: Vec<Expression>,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// `...array`, which puts the elements of the array in its place. It can only
+// be an element of an array literal or an argument of a call.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Spread {
+    pub value: Box<Expression>,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Expression {
+    Binary(Binary),
+    Unary(Unary),
+    Integer(Integer),
+    Float(Float),
+    Char(Char),
+    Str(Str),
+    Bool(Bool),
+    Nil(Nil),
+    Interpolation(Interpolation),
+    Variable(Variable),
+    Assign(Assign),
+    Block(Block),
+    If(If),
+    Conditional(Conditional),
+    Coalesce(Coalesce),
+    Match(Match),
+    Try(Try),
+    Range(Range),
+    Call(Call),
+    Lambda(Lambda),
+    Array(Array),
+    Map(Map),
+    Tuple(Tuple),
+    TupleIndex(TupleIndex),
+    Index(Index),
+    IndexAssign(IndexAssign),
+    StructLiteral(StructLiteral),
+    Field(Field),
+    Spread(Spread),
+}
+
+impl Expression {
+    pub fn span(&self) -> Span {
+        match self {
+            Expression::Binary(binary) => binary.span,
+            Expression::Unary(unary) => unary.span,
+            Expression::Integer(integer) => integer.span,
+            Expression::Float(float) => float.span,
+            Expression::Char(char) => char.span,
+            Expression::Str(str) => str.span,
+            Expression::Bool(bool) => bool.span,
+            Expression::Nil(nil) => nil.span,
+            Expression::Interpolation(interpolation) => interpolation.span,
+            Expression::Variable(variable) => variable.span,
+            Expression::Assign(assign) => assign.span,
+            Expression::Block(block) => block.span,
+            Expression::If(r#if) => r#if.span,
+            Expression::Conditional(conditional) => conditional.span,
+            Expression::Coalesce(coalesce) => coalesce.span,
+            Expression::Match(r#match) => r#match.span,
+            Expression::Try(r#try) => r#try.span,
+            Expression::Range(range) => range.span,
+            Expression::Call(call) => call.span,
+            Expression::Lambda(lambda) => lambda.span,
+            Expression::Array(array) => array.span,
+            Expression::Map(map) => map.span,
+            Expression::Tuple(tuple) => tuple.span,
+            Expression::TupleIndex(index) => index.span,
+            Expression::Index(index) => index.span,
+            Expression::IndexAssign(assign) => assign.span,
+            Expression::StructLiteral(literal) => literal.span,
+            Expression::Field(field) => field.span,
+            Expression::Spread(spread) => spread.span,
+        }
+    }
+
+    pub fn id(&self) -> NodeId {
+        match self {
+            Expression::Binary(binary) => binary.id,
+            Expression::Unary(unary) => unary.id,
+            Expression::Integer(integer) => integer.id,
+            Expression::Float(float) => float.id,
+            Expression::Char(char) => char.id,
+            Expression::Str(str) => str.id,
+            Expression::Bool(bool) => bool.id,
+            Expression::Nil(nil) => nil.id,
+            Expression::Interpolation(interpolation) => interpolation.id,
+            Expression::Variable(variable) => variable.id,
+            Expression::Assign(assign) => assign.id,
+            Expression::Block(block) => block.id,
+            Expression::If(r#if) => r#if.id,
+            Expression::Conditional(conditional) => conditional.id,
+            Expression::Coalesce(coalesce) => coalesce.id,
+            Expression::Match(r#match) => r#match.id,
+            Expression::Try(r#try) => r#try.id,
+            Expression::Range(range) => range.id,
+            Expression::Call(call) => call.id,
+            Expression::Lambda(lambda) => lambda.id,
+            Expression::Array(array) => array.id,
+            Expression::Map(map) => map.id,
+            Expression::Tuple(tuple) => tuple.id,
+            Expression::TupleIndex(index) => index.id,
+            Expression::Index(index) => index.id,
+            Expression::IndexAssign(assign) => assign.id,
+            Expression::StructLiteral(literal) => literal.id,
+            Expression::Field(field) => field.id,
+            Expression::Spread(spread) => spread.id,
+        }
+    }
+
+    // Moves the expression out and leaves nil in its place, which is how
+    // passes take the operands out of a Binary since it can't be destructured
+    pub fn take(&mut self) -> Expression {
+        let nil = Expression::Nil(Nil { span: self.span(), id: self.id() });
+        mem::replace(self, nil)
+    }
+}
+
+// Chains of operators that group to the left can be as long as the source,
+// so copying and dropping them walk the chain in a loop instead of recursing
+// once per operator
+impl Clone for Binary {
+    fn clone(&self) -> Self {
+        let mut chain = vec![self];
+        while let Expression::Binary(left) = &*chain[chain.len() - 1].left {
+            chain.push(left);
+        }
+        let mut left = (*chain[chain.len() - 1].left).clone();
+        for binary in chain[1..].iter().rev() {
+            left = Expression::Binary(binary.with_left(left));
+        }
+        self.with_left(left)
+    }
+}
+
+impl Drop for Binary {
+    fn drop(&mut self) {
+        // Takes a nested operator out of its box, so that each operator is
+        // dropped without any operators inside it
+        fn detach(operand: &mut Expression, detached: &mut Vec<Binary>) {
+            if matches!(operand, Expression::Binary(_)) {
+                if let Expression::Binary(binary) = operand.take() {
+                    detached.push(binary);
+                }
+            }
+        }
+
+        let mut detached = Vec::new();
+        detach(&mut self.left, &mut detached);
+        detach(&mut self.right, &mut detached);
+        while let Some(mut binary) = detached.pop() {
+            detach(&mut binary.left, &mut detached);
+            detach(&mut binary.right, &mut detached);
+        }
+    }
+}
+
+impl Binary {
+    fn with_left(&self, left: Expression) -> Binary {
+        Binary {
+            left: Box::new(left),
+            operator: self.operator.clone(),
+            operator_span: self.operator_span,
+            right: self.right.clone(),
+            span: self.span,
+            id: self.id,
+        }
+    }
+}
+
+// An expression evaluated for its value or its effects, ended by a `;` or
+// the end of the line
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExpressionStatement {
+    pub expression: Expression,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// `let name = initializer`
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Let {
+    pub name: String,
+    pub initializer: Expression,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// `let (a, b) = initializer` or `let [first, rest...] = initializer`, which
+// binds every name in the pattern
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Destructure {
+    pub pattern: Pattern,
+    pub initializer: Expression,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// `while condition { ... }`, where the body is always a block
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct While {
+    pub condition: Expression,
+    pub body: Expression,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// `for variable in iterable { ... }`, where the body is always a block. The
+// variable only exists inside the loop.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct For {
+    pub variable: String,
+    pub iterable: Expression,
+    pub body: Expression,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Break {
+    pub span: Span,
+    pub id: NodeId,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Continue {
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// `return value`, or just `return` to give back nil
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Return {
+    pub value: Option<Expression>,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// `fn name(parameter, parameter) { ... }`, where the body is always a block
+// and its value is what a call gives back. It can also be written with types,
+// as `fn name<T>(parameter: T, parameter: Int) -> T { ... }`, which give one
+// annotation for each parameter, `None` where there's no type.
+//
+// The `doc` of this and the other declarations is the text of the `///`
+// comments right before it, one line per comment.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Function {
+    pub name: String,
+    pub doc: Option<String>,
+    pub type_parameters: Vec<String>,
+    pub parameters: Vec<String>,
+    pub annotations: Vec<Option<TypeAnnotation>>,
+    pub return_type: Option<TypeAnnotation>,
+    pub body: Expression,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// A type such as `Int`, `T` or `Map<Str, Array<T>>`. Nothing checks these yet
+// beyond their syntax, and the evaluator ignores them.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TypeAnnotation {
+    pub name: String,
+    pub arguments: Vec<TypeAnnotation>,
+    pub span: Span,
+}
+
+// `struct Name { field, field }`, which declares `Name` as a struct with at
+// least one field
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Struct {
+    pub name: String,
+    pub doc: Option<String>,
+    pub fields: Vec<String>,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// `enum Name { Variant, Variant(field, field) }`, which declares `Name` as an
+// enum with at least one variant
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Enum {
+    pub name: String,
+    pub doc: Option<String>,
+    pub variants: Vec<Variant>,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// `impl Name { fn method(self, other) { ... } }`, which gives the struct or
+// enum called `Name` methods. The first parameter of each method is the value
+// it's called on. A method named after an operator, like `__add__` for `+`,
+// is called in place of the operator when the value is on its left.
+//
+// `impl Trait for Name { ... }` does the same, but has to give `Name` exactly
+// the methods the trait asks for.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Impl {
+    pub name: String,
+    pub r#trait: Option<String>,
+    pub methods: Vec<Function>,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// `import "path/to/name.rat"`, or `import name` for `name.rat`, which runs the
+// file as a module of its own and binds what it defines to `name`. The path is
+// relative to the directory of the file doing the importing.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Import {
+    pub path: String,
+    pub name: String,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// `macro name(parameter, parameter) { ... }`. A call to `name` after it is
+// replaced by the body, with the arguments in place of the parameters, before
+// the program is run.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Macro {
+    pub name: String,
+    pub parameters: Vec<String>,
+    pub body: Expression,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// `trait Name { fn method(self, other) fn other(self) }`, which declares the
+// methods a type has to have to implement the trait
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Trait {
+    pub name: String,
+    pub doc: Option<String>,
+    pub methods: Vec<Signature>,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+// Everything of a function but its body, like the methods a trait asks for
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Signature {
+    pub name: String,
+    pub doc: Option<String>,
+    pub type_parameters: Vec<String>,
+    pub parameters: Vec<String>,
+    pub annotations: Vec<Option<TypeAnnotation>>,
+    pub return_type: Option<TypeAnnotation>,
+}
+
+// One of the variants of an enum. The field names are only there to say
+// what the values are, since they are given and matched by position.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Variant {
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Statement {
+    Expression(ExpressionStatement),
+    Let(Let),
+    Destructure(Destructure),
+    While(While),
+    For(For),
+    Break(Break),
+    Continue(Continue),
+    Function(Function),
+    Return(Return),
+    Struct(Struct),
+    Enum(Enum),
+    Impl(Impl),
+    Trait(Trait),
+    Import(Import),
+    Macro(Macro),
+}
+
+impl Statement {
+    pub fn span(&self) -> Span {
+        match self {
+            Statement::Expression(expression) => expression.span,
+            Statement::Let(declaration) => declaration.span,
+            Statement::Destructure(declaration) => declaration.span,
+            Statement::While(r#while) => r#while.span,
+            Statement::For(r#for) => r#for.span,
+            Statement::Break(r#break) => r#break.span,
+            Statement::Continue(r#continue) => r#continue.span,
+            Statement::Function(function) => function.span,
+            Statement::Return(r#return) => r#return.span,
+            Statement::Struct(r#struct) => r#struct.span,
+            Statement::Enum(r#enum) => r#enum.span,
+            Statement::Impl(r#impl) => r#impl.span,
+            Statement::Trait(r#trait) => r#trait.span,
+            Statement::Import(import) => import.span,
+            Statement::Macro(r#macro) => r#macro.span,
+        }
+    }
+
+    pub fn id(&self) -> NodeId {
+        match self {
+            Statement::Expression(expression) => expression.id,
+            Statement::Let(declaration) => declaration.id,
+            Statement::Destructure(declaration) => declaration.id,
+            Statement::While(r#while) => r#while.id,
+            Statement::For(r#for) => r#for.id,
+            Statement::Break(r#break) => r#break.id,
+            Statement::Continue(r#continue) => r#continue.id,
+            Statement::Function(function) => function.id,
+            Statement::Return(r#return) => r#return.id,
+            Statement::Struct(r#struct) => r#struct.id,
+            Statement::Enum(r#enum) => r#enum.id,
+            Statement::Impl(r#impl) => r#impl.id,
+            Statement::Trait(r#trait) => r#trait.id,
+            Statement::Import(import) => import.id,
+            Statement::Macro(r#macro) => r#macro.id,
+        }
+    }
+}
+
+// The root of a whole script
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Program {
+    pub statements: Vec<Statement>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ParserConfig {
+    // How deeply expressions can nest before the parser gives up instead of
+    // overflowing the stack. Brackets, blocks, unary operators and operators
+    // that group to the right each count, and so do calls and pipelines,
+    // which nest the tree through the node before them. Operators that group
+    // to the left are parsed in a loop and don't count, however long the
+    // chain, since the passes over the tree handle their chains without
+    // recursing. The default fits in a main thread's stack even in debug
+    // builds.
+    pub max_depth: usize,
+}
+
+impl Default for ParserConfig {
+    fn default() -> Self {
+        ParserConfig {
+            max_depth: 128,
+        }
+    }
+}
+
+// Pulls tokens from any iterator as it goes rather than needing them all up
+// front, so the lexer can feed it directly
+struct Parser<I: Iterator<Item = TokenType>> {
+    tokens: I,
+    // Tokens that have been pulled from `tokens` but not consumed yet
+    lookahead: VecDeque<TokenType>,
+    // Where the last token pulled from `tokens` was, for reporting the end of
+    // the input when it runs out without an EOF token
+    last_position: Option<(u32, u32)>,
+    next_id: u32,
+    config: ParserConfig,
+    depth: usize,
+    // How many brackets the parser is inside of. Line breaks only end
+    // statements outside of them.
+    brackets: usize,
+    // The line the last consumed token finished on
+    previous_line: u32,
+    // Where the last consumed token is, which for an operator is what a
+    // binary expression keeps as the operator's span
+    previous_span: Span,
+    // How many loop bodies the parser is inside of
+    loops: usize,
+    // How many function bodies the parser is inside of
+    functions: usize,
+    // The doc comments pulled from `tokens`, by the line and character of the
+    // token they come right before, and the lines of those still waiting for
+    // a token
+    docs: HashMap<(u32, u32), String>,
+    doc_lines: Vec<String>,
+}
+
+impl<I: Iterator<Item = TokenType>> Parser<I> {
+    pub fn parse(input: impl IntoIterator<IntoIter = I>, config: ParserConfig) -> Result<Expression, ParseError> {
+        let mut parser = Parser::new(input, config);
+        parser.complete_expression()
+    }
+
+    // Panic mode recovery: after an error, skip ahead to the next point the
+    // parser can pick back up from and keep going, so that every syntax error
+    // gets reported instead of just the first
+    pub fn parse_resilient(
+        input: impl IntoIterator<IntoIter = I>,
+        config: ParserConfig
+    ) -> Result<Expression, Vec<ParseError>> {
+        let mut parser = Parser::new(input, config);
+        let mut errors = Vec::new();
+
+        loop {
+            match parser.complete_expression() {
+                Ok(expression) if errors.is_empty() => return Ok(expression),
+                Ok(_) => break,
+                Err(error) => {
+                    errors.push(error);
+                    if !parser.synchronize() { break; }
+                }
+            }
+        }
+
+        Err(errors)
+    }
+
+    fn new(input: impl IntoIterator<IntoIter = I>, config: ParserConfig) -> Parser<I> {
+        Parser {
+            tokens: input.into_iter(),
+            lookahead: VecDeque::new(),
+            last_position: None,
+            next_id: 0,
+            config,
+            depth: 0,
+            brackets: 0,
+            previous_line: 1,
+            previous_span: Span::default(),
+            loops: 0,
+            functions: 0,
+            docs: HashMap::new(),
+            doc_lines: Vec::new(),
+        }
+    }
+
+    pub fn parse_program(input: impl IntoIterator<IntoIter = I>, config: ParserConfig) -> Result<Program, ParseError> {
+        let mut parser = Parser::new(input, config);
+        let mut statements = Vec::new();
+
+        while parser.skip_semicolons() {
+            statements.push(parser.top_level_statement()?);
+        }
+
+        Ok(Program { statements })
+    }
+
+    // Recovers from errors one statement at a time, picking back up at the
+    // start of the next statement
+    pub fn parse_program_resilient(
+        input: impl IntoIterator<IntoIter = I>,
+        config: ParserConfig
+    ) -> Result<Program, Vec<ParseError>> {
+        let mut parser = Parser::new(input, config);
+        let mut statements = Vec::new();
+        let mut errors = Vec::new();
+
+        while parser.skip_semicolons() {
+            match parser.top_level_statement() {
+                Ok(statement) => statements.push(statement),
+                Err(error) => {
+                    errors.push(error);
+                    parser.synchronize_statement();
+                }
+            }
+        }
+
+        if errors.is_empty() { Ok(Program { statements }) } else { Err(errors) }
+    }
+
+    // Skips over empty statements, returning whether there is a statement left
+    // to parse
+    fn skip_semicolons(&mut self) -> bool {
+        while matches!(self.peek(), Some(TokenType::Semicolon(_))) {
+            self.advance();
+        }
+        !matches!(self.peek(), None | Some(TokenType::EOF(_)))
+    }
+
+    fn top_level_statement(&mut self) -> Result<Statement, ParseError> {
+        // Errors return without unwinding these, so start again from the top
+        // for each statement
+        self.depth = 0;
+        self.brackets = 0;
+        self.loops = 0;
+        self.functions = 0;
+        self.statement()
+    }
+
+    fn statement(&mut self) -> Result<Statement, ParseError> {
+        if !self.at_statement() {
+            let expression = self.expression()?;
+            return self.expression_statement(expression);
+        }
+
+        match self.peek() {
+            Some(TokenType::Let(_)) => self.let_statement(),
+            Some(TokenType::While(_)) => self.while_statement(),
+            Some(TokenType::For(_)) => self.for_statement(),
+            Some(TokenType::Break(_)) | Some(TokenType::Continue(_)) => self.loop_control(),
+            Some(TokenType::Return(_)) => self.return_statement(),
+            Some(TokenType::Struct(_)) => self.struct_declaration(),
+            Some(TokenType::Enum(_)) => self.enum_declaration(),
+            Some(TokenType::Impl(_)) => self.impl_declaration(),
+            Some(TokenType::Trait(_)) => self.trait_declaration(),
+            Some(TokenType::Import(_)) => self.import(),
+            Some(TokenType::Macro(_)) => self.macro_declaration(),
+            _ => self.function(),
+        }
+    }
+
+    // Whether the next token begins a statement other than an expression
+    // statement. `fn` only declares a function when a name follows it,
+    // otherwise it starts a lambda.
+    fn at_statement(&mut self) -> bool {
+        self.fill(2);
+        match self.lookahead.front() {
+            Some(TokenType::Fn(_)) => matches!(self.lookahead.get(1), Some(TokenType::Identifier(_))),
+            Some(token) => matches!(
+                token,
+                TokenType::Let(_)
+                    | TokenType::While(_)
+                    | TokenType::For(_)
+                    | TokenType::Break(_)
+                    | TokenType::Continue(_)
+                    | TokenType::Return(_)
+                    | TokenType::Struct(_)
+                    | TokenType::Enum(_)
+                    | TokenType::Impl(_)
+                    | TokenType::Trait(_)
+                    | TokenType::Import(_)
+                    | TokenType::Macro(_)
+            ),
+            None => false,
+        }
+    }
+
+    fn expression_statement(&mut self, expression: Expression) -> Result<Statement, ParseError> {
+        self.end_statement()?;
+
+        Ok(Statement::Expression(ExpressionStatement {
+            span: expression.span(),
+            id: self.next_id(),
+            expression,
+        }))
+    }
+
+    fn let_statement(&mut self) -> Result<Statement, ParseError> {
+        let start = self.advance().unwrap().span();
+        if matches!(self.peek(), Some(TokenType::LeftParen(_)) | Some(TokenType::LeftBracket(_))) {
+            return self.destructure(start);
+        }
+        let name = self.consume(|token| matches!(token, TokenType::Identifier(_)), "a variable name after 'let'")?;
+        self.consume(|token| matches!(token, TokenType::Equal(_)), "'=' after the variable name")?;
+        if !self.peek().is_some_and(starts_expression) {
+            return Err(self.error("an initializer after '='"));
+        }
+
+        let initializer = self.expression()?;
+        self.end_statement()?;
+
+        Ok(Statement::Let(Let {
+            name: name.lexeme().to_string(),
+            span: start.to(initializer.span()),
+            id: self.next_id(),
+            initializer,
+        }))
+    }
+
+    // Only the shape of the value can stop the pattern of a `let` from
+    // matching, so it can't hold literals or variants
+    fn destructure(&mut self, start: Span) -> Result<Statement, ParseError> {
+        let pattern = self.pattern()?;
+        check_irrefutable(&pattern)?;
+        check_bindings(&pattern, &mut HashSet::new())?;
+        self.consume(|token| matches!(token, TokenType::Equal(_)), "'=' after the pattern")?;
+        if !self.peek().is_some_and(starts_expression) {
+            return Err(self.error("an initializer after '='"));
+        }
+
+        let initializer = self.expression()?;
+        self.end_statement()?;
+
+        Ok(Statement::Destructure(Destructure {
+            span: start.to(initializer.span()),
+            id: self.next_id(),
+            pattern,
+            initializer,
+        }))
+    }
+
+    fn while_statement(&mut self) -> Result<Statement, ParseError> {
+        let start = self.advance().unwrap().span();
+        let condition = self.expression()?;
+        let open = self.consume(|token| matches!(token, TokenType::LeftBrace(_)), "'{' after while condition")?;
+        let body = self.loop_body(open.span())?;
+        self.end_statement()?;
+
+        Ok(Statement::While(While {
+            span: start.to(body.span()),
+            id: self.next_id(),
+            condition,
+            body,
+        }))
+    }
+
+    fn for_statement(&mut self) -> Result<Statement, ParseError> {
+        let start = self.advance().unwrap().span();
+        let variable = self.consume(|token| matches!(token, TokenType::Identifier(_)), "a loop variable after 'for'")?;
+        self.consume(|token| matches!(token, TokenType::In(_)), "'in' after the loop variable")?;
+        let iterable = self.expression()?;
+        let open = self.consume(|token| matches!(token, TokenType::LeftBrace(_)), "'{' after the iterable")?;
+        let body = self.loop_body(open.span())?;
+        self.end_statement()?;
+
+        Ok(Statement::For(For {
+            variable: variable.lexeme().to_string(),
+            span: start.to(body.span()),
+            id: self.next_id(),
+            iterable,
+            body,
+        }))
+    }
+
+    fn loop_body(&mut self, start: Span) -> Result<Expression, ParseError> {
+        let depth = self.depth;
+        self.nest()?;
+        self.loops += 1;
+        let body = self.block(start)?;
+        self.loops -= 1;
+        self.depth = depth;
+        Ok(body)
+    }
+
+    // The keyword is left unconsumed when it is rejected, so that recovery
+    // skips past it rather than past whatever comes after
+    fn loop_control(&mut self) -> Result<Statement, ParseError> {
+        let next = self.peek().unwrap();
+        let (keyword, span) = match next {
+            TokenType::Break(_) => ("break", next.span()),
+            _ => ("continue", next.span()),
+        };
+        if self.loops == 0 {
+            return Err(ParseError::OutsideLoop(OutsideLoop { keyword, span }));
+        }
+        self.advance();
+        self.end_statement()?;
+
+        let id = self.next_id();
+        Ok(match keyword {
+            "break" => Statement::Break(Break { span, id }),
+            _ => Statement::Continue(Continue { span, id }),
+        })
+    }
+
+    fn function(&mut self) -> Result<Statement, ParseError> {
+        let doc = self.doc();
+        let start = self.advance().unwrap().span();
+        let name = self.advance().unwrap();
+        let signature = self.rest_of_signature(name.lexeme().to_string(), doc, "'(' after the function name", false)?;
+        let body = self.function_body()?;
+        self.end_statement()?;
+        Ok(Statement::Function(self.function_with(signature, start, body)))
+    }
+
+    fn function_with(&mut self, signature: Signature, start: Span, body: Expression) -> Function {
+        Function {
+            name: signature.name,
+            doc: signature.doc,
+            type_parameters: signature.type_parameters,
+            parameters: signature.parameters,
+            annotations: signature.annotations,
+            return_type: signature.return_type,
+            span: start.to(body.span()),
+            id: self.next_id(),
+            body,
+        }
+    }
+
+    // The type parameters, parameters and return type that follow the name of
+    // a function or method. A method has to have a parameter for the value
+    // it's called on.
+    fn rest_of_signature(
+        &mut self,
+        name: String,
+        doc: Option<String>,
+        expected: &'static str,
+        method: bool,
+    ) -> Result<Signature, ParseError> {
+        let type_parameters = match self.peek() {
+            Some(TokenType::Less(_)) => {
+                self.advance();
+                if matches!(self.peek(), Some(TokenType::Greater(_))) {
+                    return Err(self.error("a type parameter name"));
+                }
+                self.brackets += 1;
+                let names = self.names(|token| matches!(token, TokenType::Greater(_)), "a type parameter name")?;
+                self.consume(|token| matches!(token, TokenType::Greater(_)), "'>' after type parameters")?;
+                self.brackets -= 1;
+                names
+            }
+            _ => Vec::new(),
+        };
+        self.consume(|token| matches!(token, TokenType::LeftParen(_)), expected)?;
+        if method && matches!(self.peek(), Some(TokenType::RightParen(_))) {
+            return Err(self.error("a parameter for the receiver"));
+        }
+
+        self.brackets += 1;
+        let (mut parameters, mut annotations) = (Vec::new(), Vec::new());
+        while !matches!(self.peek(), Some(TokenType::RightParen(_))) {
+            let name = self.consume(|token| matches!(token, TokenType::Identifier(_)), "a parameter name")?;
+            parameters.push(name.lexeme().to_string());
+            annotations.push(match self.peek() {
+                Some(TokenType::Colon(_)) => {
+                    self.advance();
+                    Some(self.type_annotation()?)
+                }
+                _ => None,
+            });
+            if !matches!(self.peek(), Some(TokenType::Comma(_))) {
+                break;
+            }
+            self.advance();
+            if matches!(self.peek(), Some(TokenType::RightParen(_))) {
+                return Err(self.error("a parameter name"));
+            }
+        }
+        self.consume(|token| matches!(token, TokenType::RightParen(_)), "')' after parameters")?;
+        self.brackets -= 1;
+
+        let return_type = match self.peek() {
+            Some(TokenType::Arrow(_)) => {
+                self.advance();
+                Some(self.type_annotation()?)
+            }
+            _ => None,
+        };
+        Ok(Signature { name, doc, type_parameters, parameters, annotations, return_type })
+    }
+
+    // A type name, with its type arguments between `<` and `>` if it has any
+    fn type_annotation(&mut self) -> Result<TypeAnnotation, ParseError> {
+        let name = self.consume(|token| matches!(token, TokenType::Identifier(_)), "a type")?;
+        let mut end = name.span();
+        let mut arguments = Vec::new();
+        if matches!(self.peek(), Some(TokenType::Less(_))) {
+            self.advance();
+            self.brackets += 1;
+            let depth = self.depth;
+            self.nest()?;
+            loop {
+                arguments.push(self.type_annotation()?);
+                if !matches!(self.peek(), Some(TokenType::Comma(_))) {
+                    break;
+                }
+                self.advance();
+            }
+            self.depth = depth;
+            end = self.consume(|token| matches!(token, TokenType::Greater(_)), "'>' after type arguments")?.span();
+            self.brackets -= 1;
+        }
+        Ok(TypeAnnotation { name: name.lexeme().to_string(), arguments, span: name.span().to(end) })
+    }
+
+    // Parameter names up to and including the closing `)`
+    fn parameters(&mut self) -> Result<Vec<String>, ParseError> {
+        self.brackets += 1;
+        let parameters = self.names(|token| matches!(token, TokenType::RightParen(_)), "a parameter name")?;
+        self.consume(|token| matches!(token, TokenType::RightParen(_)), "')' after parameters")?;
+        self.brackets -= 1;
+        Ok(parameters)
+    }
+
+    // Comma separated names up to, but not including, the token that closes
+    // the list
+    fn names(&mut self, closes: fn(&TokenType) -> bool, expected: &'static str) -> Result<Vec<String>, ParseError> {
+        let mut names = Vec::new();
+        if self.peek().is_some_and(closes) {
+            return Ok(names);
+        }
+
+        loop {
+            let name = self.consume(|token| matches!(token, TokenType::Identifier(_)), expected)?;
+            names.push(name.lexeme().to_string());
+            if !matches!(self.peek(), Some(TokenType::Comma(_))) {
+                return Ok(names);
+            }
+            self.advance();
+        }
+    }
+
+    // A struct without fields couldn't be told apart from a name followed by
+    // a block when constructing it, so there has to be at least one
+    fn enum_declaration(&mut self) -> Result<Statement, ParseError> {
+        let doc = self.doc();
+        let start = self.advance().unwrap().span();
+        let name = self.consume(|token| matches!(token, TokenType::Identifier(_)), "an enum name")?;
+        self.consume(|token| matches!(token, TokenType::LeftBrace(_)), "'{' after the enum name")?;
+
+        self.brackets += 1;
+        let mut variants = Vec::new();
+        loop {
+            variants.push(self.variant()?);
+            if !matches!(self.peek(), Some(TokenType::Comma(_))) {
+                break;
+            }
+            self.advance();
+        }
+        let end = self.consume(|token| matches!(token, TokenType::RightBrace(_)), "'}' after variants")?;
+        self.brackets -= 1;
+        self.end_statement()?;
+
+        Ok(Statement::Enum(Enum {
+            name: name.lexeme().to_string(),
+            doc,
+            variants,
+            span: start.to(end.span()),
+            id: self.next_id(),
+        }))
+    }
+
+    // The methods follow one another with nothing in between
+    fn impl_declaration(&mut self) -> Result<Statement, ParseError> {
+        let start = self.advance().unwrap().span();
+        let mut name = self.consume(|token| matches!(token, TokenType::Identifier(_)), "a type name after 'impl'")?;
+        let mut r#trait = None;
+        if matches!(self.peek(), Some(TokenType::For(_))) {
+            self.advance();
+            r#trait = Some(name.lexeme().to_string());
+            name = self.consume(|token| matches!(token, TokenType::Identifier(_)), "a type name after 'for'")?;
+        }
+        self.consume(|token| matches!(token, TokenType::LeftBrace(_)), "'{' after the type name")?;
+
+        let mut methods = Vec::new();
+        while matches!(self.peek(), Some(TokenType::Fn(_))) {
+            methods.push(self.method()?);
+        }
+        let end = self.consume(|token| matches!(token, TokenType::RightBrace(_)), "'}' after methods")?;
+        self.end_statement()?;
+
+        Ok(Statement::Impl(Impl {
+            name: name.lexeme().to_string(),
+            r#trait,
+            methods,
+            span: start.to(end.span()),
+            id: self.next_id(),
+        }))
+    }
+
+    fn method(&mut self) -> Result<Function, ParseError> {
+        let (start, signature) = self.signature()?;
+        let body = self.function_body()?;
+        Ok(self.function_with(signature, start, body))
+    }
+
+    // Everything of a method up to its body, along with where it starts
+    fn signature(&mut self) -> Result<(Span, Signature), ParseError> {
+        let doc = self.doc();
+        let start = self.advance().unwrap().span();
+        let name = self.consume(|token| matches!(token, TokenType::Identifier(_)), "a method name")?;
+        let signature = self.rest_of_signature(name.lexeme().to_string(), doc, "'(' after the method name", true)?;
+        Ok((start, signature))
+    }
+
+    // Like the methods of an `impl`, the signatures follow one another with
+    // nothing in between
+    fn trait_declaration(&mut self) -> Result<Statement, ParseError> {
+        let doc = self.doc();
+        let start = self.advance().unwrap().span();
+        let name = self.consume(|token| matches!(token, TokenType::Identifier(_)), "a trait name")?;
+        self.consume(|token| matches!(token, TokenType::LeftBrace(_)), "'{' after the trait name")?;
+
+        let mut methods = Vec::new();
+        while matches!(self.peek(), Some(TokenType::Fn(_))) {
+            methods.push(self.signature()?.1);
+        }
+        let end = self.consume(|token| matches!(token, TokenType::RightBrace(_)), "'}' after method signatures")?;
+        self.end_statement()?;
+
+        Ok(Statement::Trait(Trait {
+            name: name.lexeme().to_string(),
+            doc,
+            methods,
+            span: start.to(end.span()),
+            id: self.next_id(),
+        }))
+    }
+
+    // A variant either has no parentheses or at least one field inside them
+    fn variant(&mut self) -> Result<Variant, ParseError> {
+        let name = self.consume(|token| matches!(token, TokenType::Identifier(_)), "a variant name")?;
+        let mut fields = Vec::new();
+        if matches!(self.peek(), Some(TokenType::LeftParen(_))) {
+            self.advance();
+            if matches!(self.peek(), Some(TokenType::RightParen(_))) {
+                return Err(self.error("a field name"));
+            }
+            fields = self.names(|token| matches!(token, TokenType::RightParen(_)), "a field name")?;
+            self.consume(|token| matches!(token, TokenType::RightParen(_)), "')' after variant fields")?;
+        }
+        Ok(Variant { name: name.lexeme().to_string(), fields })
+    }
+
+    // The name a path is bound to is that of its file without the extension,
+    // so it has to be one a variable could have
+    fn import(&mut self) -> Result<Statement, ParseError> {
+        let start = self.advance().unwrap().span();
+        let (path, name) = match self.peek() {
+            Some(TokenType::Identifier(name)) => (format!("{}.rat", name.lexeme), name.lexeme.to_string()),
+            Some(TokenType::Str(path)) => {
+                let name = Path::new(&path.literal).file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+                let valid = matches!(
+                    get_tokens(name).as_deref(),
+                    Ok([TokenType::Identifier(identifier), TokenType::EOF(_)]) if identifier.lexeme.to_string() == name
+                );
+                if !valid {
+                    return Err(self.error("a path to a file named like a variable"));
+                }
+                (path.literal.clone(), name.to_string())
+            }
+            _ => return Err(self.error("a module name or path after 'import'")),
+        };
+        let end = self.advance().unwrap().span();
+        self.end_statement()?;
+
+        Ok(Statement::Import(Import { path, name, span: start.to(end), id: self.next_id() }))
+    }
+
+    // The body is checked where the macro is declared rather than where it's
+    // used, so a `return` in it needs the declaration to be in a function
+    fn macro_declaration(&mut self) -> Result<Statement, ParseError> {
+        let start = self.advance().unwrap().span();
+        let name = self.consume(|token| matches!(token, TokenType::Identifier(_)), "a macro name")?;
+        self.consume(|token| matches!(token, TokenType::LeftParen(_)), "'(' after the macro name")?;
+        let parameters = self.parameters()?;
+        let open = self.consume(|token| matches!(token, TokenType::LeftBrace(_)), "'{' before the macro body")?;
+        let depth = self.depth;
+        self.nest()?;
+        let body = self.block(open.span())?;
+        self.depth = depth;
+        self.end_statement()?;
+
+        Ok(Statement::Macro(Macro {
+            name: name.lexeme().to_string(),
+            span: start.to(body.span()),
+            id: self.next_id(),
+            parameters,
+            body,
+        }))
+    }
+
+    fn struct_declaration(&mut self) -> Result<Statement, ParseError> {
+        let doc = self.doc();
+        let start = self.advance().unwrap().span();
+        let name = self.consume(|token| matches!(token, TokenType::Identifier(_)), "a struct name")?;
+        self.consume(|token| matches!(token, TokenType::LeftBrace(_)), "'{' after the struct name")?;
+        if matches!(self.peek(), Some(TokenType::RightBrace(_))) {
+            return Err(self.error("a field name"));
+        }
+
+        self.brackets += 1;
+        let fields = self.names(|token| matches!(token, TokenType::RightBrace(_)), "a field name")?;
+        let end = self.consume(|token| matches!(token, TokenType::RightBrace(_)), "'}' after fields")?;
+        self.brackets -= 1;
+        self.end_statement()?;
+
+        Ok(Statement::Struct(Struct {
+            name: name.lexeme().to_string(),
+            doc,
+            fields,
+            span: start.to(end.span()),
+            id: self.next_id(),
+        }))
+    }
+
+    // A loop around a function doesn't make `break` valid inside its body
+    fn function_body(&mut self) -> Result<Expression, ParseError> {
+        let open = self.consume(|token| matches!(token, TokenType::LeftBrace(_)), "'{' before the function body")?;
+        let (depth, loops) = (self.depth, self.loops);
+        self.nest()?;
+        self.loops = 0;
+        self.functions += 1;
+        let body = self.block(open.span())?;
+        self.functions -= 1;
+        self.loops = loops;
+        self.depth = depth;
+        Ok(body)
+    }
+
+    // The value is optional, so a `return` followed by the end of the
+    // statement gives back nil
+    fn return_statement(&mut self) -> Result<Statement, ParseError> {
+        let start = self.peek().unwrap().span();
+        if self.functions == 0 {
+            return Err(ParseError::OutsideFunction(OutsideFunction { span: start }));
+        }
+        self.advance();
+
+        let previous_line = self.previous_line;
+        let value = match self.peek() {
+            Some(token) if token.line() == previous_line && starts_expression(token) => Some(self.expression()?),
+            _ => None,
+        };
+        self.end_statement()?;
+
+        Ok(Statement::Return(Return {
+            span: value.as_ref().map_or(start, |value| start.to(value.span())),
+            id: self.next_id(),
+            value,
+        }))
+    }
+
+    // Statements end at a `;`, a line break, the `}` of the block they are in
+    // or the end of the input
+    fn end_statement(&mut self) -> Result<(), ParseError> {
+        let previous_line = self.previous_line;
+        match self.peek() {
+            Some(TokenType::Semicolon(_)) => {
+                self.advance();
+                Ok(())
+            }
+            None | Some(TokenType::EOF(_)) | Some(TokenType::RightBrace(_)) => Ok(()),
+            Some(token) if token.line() > previous_line => Ok(()),
+            Some(_) => Err(self.error("';' or a line break after statement")),
+        }
+    }
+
+    // The top level production, which has to use up everything up to EOF
+    fn complete_expression(&mut self) -> Result<Expression, ParseError> {
+        // Errors return without unwinding the depth, so start again from the
+        // top each time. A lone expression can span lines freely, as if it
+        // was in brackets.
+        self.depth = 0;
+        self.brackets = 1;
+        if matches!(self.peek(), None | Some(TokenType::EOF(_))) {
+            return Err(ParseError::EmptyInput);
+        }
+
+        let expression = self.expression()?;
+
+        match self.peek() {
+            None | Some(TokenType::EOF(_)) => Ok(expression),
+            Some(token) => Err(ParseError::TrailingToken(TrailingToken {
+                lexeme: token.lexeme().clone(),
+                line: token.line(),
+                character: token.character(),
+            })),
+        }
+    }
+
+    fn expression(&mut self) -> Result<Expression, ParseError> {
+        let depth = self.depth;
+        self.nest()?;
+        let expression = self.assignment()?;
+        self.depth = depth;
+        Ok(expression)
+    }
+
+    // Assignment is right associative and the loosest binding of everything.
+    // The target is parsed as an ordinary expression and then checked, since
+    // there's no telling it apart from one until the `=` turns up. Compound
+    // assignments like `x += 1` are turned into `x = x + 1` here.
+    fn assignment(&mut self) -> Result<Expression, ParseError> {
+        let target = self.conditional()?;
+
+        let operator = match self.peek_operator() {
+            Some(TokenType::Equal(_)) => None,
+            Some(TokenType::PlusEqual(_)) => Some(BinaryOperator::Plus),
+            Some(TokenType::MinusEqual(_)) => Some(BinaryOperator::Minus),
+            Some(TokenType::StarEqual(_)) => Some(BinaryOperator::Star),
+            Some(TokenType::SlashEqual(_)) => Some(BinaryOperator::Slash),
+            _ => return Ok(target),
+        };
+        self.advance();
+        let operator_span = self.previous_span;
+
+        let variable = match target {
+            Expression::Variable(variable) => variable,
+            Expression::Index(index) if is_place(&index.target) => {
+                let value = self.expression()?;
+                return Ok(Expression::IndexAssign(IndexAssign {
+                    span: index.span.to(value.span()),
+                    id: self.next_id(),
+                    target: index.target,
+                    index: index.index,
+                    operator,
+                    value: Box::new(value),
+                }));
+            }
+            target => return Err(ParseError::InvalidAssignmentTarget(InvalidAssignmentTarget { span: target.span() })),
+        };
+
+        let mut value = self.expression()?;
+        if let Some(operator) = operator {
+            let current = Expression::Variable(Variable {
+                name: variable.name.clone(),
+                span: variable.span,
+                id: self.next_id(),
+            });
+            value = self.binary(current, operator, operator_span, value);
+        }
+
+        Ok(Expression::Assign(Assign {
+            name: variable.name,
+            span: variable.span.to(value.span()),
+            id: self.next_id(),
+            value: Box::new(value),
+        }))
+    }
+
+    // Groups to the right, so `a ? b : c ? d : e` picks between `b` and the
+    // rest. The `?` and `:` enclose the middle branch the way brackets would,
+    // so it can be any expression and have line breaks in it.
+    fn conditional(&mut self) -> Result<Expression, ParseError> {
+        let depth = self.depth;
+        let condition = self.pipeline()?;
+        if !matches!(self.peek_operator(), Some(TokenType::Question(_))) {
+            return Ok(condition);
+        }
+        self.advance();
+        self.nest()?;
+
+        self.brackets += 1;
+        let then_branch = self.expression()?;
+        self.brackets -= 1;
+        self.consume(|token| matches!(token, TokenType::Colon(_)), "':' after the first branch of '?'")?;
+        let else_branch = self.conditional()?;
+        self.depth = depth;
+
+        Ok(Expression::Conditional(Conditional {
+            span: condition.span().to(else_branch.span()),
+            id: self.next_id(),
+            condition: Box::new(condition),
+            then_branch: Box::new(then_branch),
+            else_branch: Box::new(else_branch),
+        }))
+    }
+
+    // `value |> function` is only another way to write `function(value)`, so
+    // it becomes a call here. It groups to the left, so `x |> f |> g` is
+    // `g(f(x))`, and binds looser than everything but a conditional or an
+    // assignment, so `a + b |> f` passes `a + b` along.
+    fn pipeline(&mut self) -> Result<Expression, ParseError> {
+        let depth = self.depth;
+        let mut value = self.coalesce()?;
+
+        while matches!(self.peek_operator(), Some(TokenType::PipeGreater(_))) {
+            self.advance();
+            self.nest()?;
+            let function = self.coalesce()?;
+            value = Expression::Call(Call {
+                span: value.span().to(function.span()),
+                id: self.next_id(),
+                callee: Box::new(function),
+                arguments: vec![value],
+            });
+        }
+        self.depth = depth;
+
+        Ok(value)
+    }
+
+    // Groups to the right, so `a ?? b ?? c` gives the first of them that isn't
+    // nil. Anything but a pipeline, a conditional or an assignment can be on
+    // either side without parentheses, so `a ?? b == c` falls back to a
+    // comparison.
+    fn coalesce(&mut self) -> Result<Expression, ParseError> {
+        let depth = self.depth;
+        let value = self.equality()?;
+        if !matches!(self.peek_operator(), Some(TokenType::QuestionQuestion(_))) {
+            return Ok(value);
+        }
+        self.advance();
+        self.nest()?;
+        let fallback = self.coalesce()?;
+        self.depth = depth;
+
+        Ok(Expression::Coalesce(Coalesce {
+            span: value.span().to(fallback.span()),
+            id: self.next_id(),
+            value: Box::new(value),
+            fallback: Box::new(fallback),
+        }))
+    }
+
+    // Comparisons bind looser than the bitwise operators, so `a & b == c`
+    // compares `a & b` against `c`
+    fn equality(&mut self) -> Result<Expression, ParseError> {
+        let mut comparison = self.comparison()?;
+
+        while let Some(operator) = self.match_equality_operator() {
+            let operator_span = self.previous_span;
+            let right = self.comparison()?;
+            comparison = self.binary(comparison, operator, operator_span, right);
+        }
+
+        Ok(comparison)
+    }
+
+    fn match_equality_operator(&mut self) -> Option<BinaryOperator> {
+        let operator = match self.peek_operator() {
+            Some(TokenType::EqualEqual(_)) => BinaryOperator::EqualEqual,
+            Some(TokenType::BangEqual(_)) => BinaryOperator::BangEqual,
+            _ => return None,
+        };
+        self.advance();
+        Some(operator)
+    }
+
+    fn comparison(&mut self) -> Result<Expression, ParseError> {
+        let mut range = self.range()?;
+
+        while let Some(operator) = self.match_comparison_operator() {
+            let operator_span = self.previous_span;
+            let right = self.range()?;
+            range = self.binary(range, operator, operator_span, right);
+        }
+
+        Ok(range)
+    }
+
+    fn match_comparison_operator(&mut self) -> Option<BinaryOperator> {
+        let operator = match self.peek_operator() {
+            Some(TokenType::Less(_)) => BinaryOperator::Less,
+            Some(TokenType::LessEqual(_)) => BinaryOperator::LessEqual,
+            Some(TokenType::Greater(_)) => BinaryOperator::Greater,
+            Some(TokenType::GreaterEqual(_)) => BinaryOperator::GreaterEqual,
+            Some(TokenType::In(_)) => BinaryOperator::In,
+            _ => return None,
+        };
+        self.advance();
+        Some(operator)
+    }
+
+    // Ranges sit between comparison and the bitwise operators, so `0..n + 1`
+    // ends at `n + 1` and `i in 0..n` checks `i` against the whole range. A
+    // range can't be a bound of another one without parentheses.
+    fn range(&mut self) -> Result<Expression, ParseError> {
+        let start = self.bit_or()?;
+        let inclusive = match self.peek_operator() {
+            Some(TokenType::DotDot(_)) => false,
+            Some(TokenType::DotDotEqual(_)) => true,
+            _ => return Ok(start),
+        };
+        self.advance();
+        let end = self.bit_or()?;
+
+        Ok(Expression::Range(Range {
+            span: start.span().to(end.span()),
+            id: self.next_id(),
+            start: Box::new(start),
+            end: Box::new(end),
+            inclusive,
+        }))
+    }
+
+    // Bitwise operators sit between comparison and the additive level, with
+    // `&` binding tighter than `^`, which binds tighter than `|`
+    fn bit_or(&mut self) -> Result<Expression, ParseError> {
+        let mut bit_xor = self.bit_xor()?;
+
+        while matches!(self.peek_operator(), Some(TokenType::Pipe(_))) {
+            self.advance();
+            let operator_span = self.previous_span;
+            let right = self.bit_xor()?;
+            bit_xor = self.binary(bit_xor, BinaryOperator::Pipe, operator_span, right);
+        }
+
+        Ok(bit_xor)
+    }
+
+    fn bit_xor(&mut self) -> Result<Expression, ParseError> {
+        let mut bit_and = self.bit_and()?;
+
+        while matches!(self.peek_operator(), Some(TokenType::Caret(_))) {
+            self.advance();
+            let operator_span = self.previous_span;
+            let right = self.bit_and()?;
+            bit_and = self.binary(bit_and, BinaryOperator::Caret, operator_span, right);
+        }
+
+        Ok(bit_and)
+    }
+
+    fn bit_and(&mut self) -> Result<Expression, ParseError> {
+        let mut term = self.term()?;
+
+        while matches!(self.peek_operator(), Some(TokenType::Ampersand(_))) {
+            self.advance();
+            let operator_span = self.previous_span;
+            let right = self.term()?;
+            term = self.binary(term, BinaryOperator::Ampersand, operator_span, right);
+        }
+
+        Ok(term)
+    }
+
+    fn term(&mut self) -> Result<Expression, ParseError> {
+        let mut factor = self.factor()?;
+
+        while let Some(operator) = self.match_term_operator() {
+            let operator_span = self.previous_span;
+            let right = self.factor()?;
+            factor = self.binary(factor, operator, operator_span, right);
+        }
+
+        Ok(factor)
+    }
+
+    fn match_term_operator(&mut self) -> Option<BinaryOperator> {
+        let operator = match self.peek_operator() {
+            Some(TokenType::Plus(_)) => BinaryOperator::Plus,
+            Some(TokenType::Minus(_)) => BinaryOperator::Minus,
+            _ => return None,
+        };
+        self.advance();
+        Some(operator)
+    }
+
+    fn factor(&mut self) -> Result<Expression, ParseError> {
+        let mut unary = self.unary()?;
+
+        while let Some(operator) = self.match_factor_operator() {
+            let operator_span = self.previous_span;
+            let right = self.unary()?;
+            unary = self.binary(unary, operator, operator_span, right);
+        }
+
+        Ok(unary)
+    }
+
+    fn match_factor_operator(&mut self) -> Option<BinaryOperator> {
+        let operator = match self.peek_operator() {
+            Some(TokenType::Star(_)) => BinaryOperator::Star,
+            Some(TokenType::Slash(_)) => BinaryOperator::Slash,
+            Some(TokenType::Percent(_)) => BinaryOperator::Percent,
+            _ => return None,
+        };
+        self.advance();
+        Some(operator)
+    }
+
+    fn unary(&mut self) -> Result<Expression, ParseError> {
+        let (operator, span) = match self.peek() {
+            Some(token @ TokenType::Minus(_)) => (UnaryOperator::Minus, token.span()),
+            Some(token @ TokenType::Tilde(_)) => (UnaryOperator::Tilde, token.span()),
+            _ => return self.power(),
+        };
+        self.advance();
+
+        let depth = self.depth;
+        self.nest()?;
+        let right = self.unary()?;
+        self.depth = depth;
+        Ok(Expression::Unary(Unary {
+            span: span.to(right.span()),
+            id: self.next_id(),
+            operator,
+            right: Box::new(right)
+        }))
+    }
+
+    // `**` is right associative and binds tighter than unary minus, so
+    // `-2 ** 2` is `-(2 ** 2)` while `2 ** -1` is still accepted
+    fn power(&mut self) -> Result<Expression, ParseError> {
+        let call = self.call()?;
+
+        if matches!(self.peek_operator(), Some(TokenType::StarStar(_))) {
+            self.advance();
+            let operator_span = self.previous_span;
+            let depth = self.depth;
+            self.nest()?;
+            let right = self.unary()?;
+            self.depth = depth;
+            return Ok(self.binary(call, BinaryOperator::StarStar, operator_span, right));
+        }
+
+        Ok(call)
+    }
+
+    // Calls, indexing and tuple positions bind tighter than any operator, and
+    // chain so that `f(1)[2]` indexes into whatever `f(1)` gives back
+    fn call(&mut self) -> Result<Expression, ParseError> {
+        let depth = self.depth;
+        let mut callee = self.primary()?;
+
+        loop {
+            let finish: fn(&mut Self, Expression) -> Result<Expression, ParseError> = match self.peek_operator() {
+                Some(TokenType::LeftParen(_)) => Self::finish_call,
+                Some(TokenType::LeftBracket(_)) => Self::finish_index,
+                Some(TokenType::Dot(_)) => Self::finish_dot,
+                _ => break,
+            };
+            self.advance();
+            self.nest()?;
+            self.brackets += 1;
+            callee = finish(self, callee)?;
+            self.brackets -= 1;
+        }
+        self.depth = depth;
+
+        Ok(callee)
+    }
+
+    fn finish_call(&mut self, callee: Expression) -> Result<Expression, ParseError> {
+        let arguments = self.list(|token| matches!(token, TokenType::RightParen(_)))?;
+        let end = self.consume(|token| matches!(token, TokenType::RightParen(_)), "')' after arguments")?;
+        Ok(Expression::Call(Call {
+            span: callee.span().to(end.span()),
+            id: self.next_id(),
+            callee: Box::new(callee),
+            arguments,
+        }))
+    }
+
+    // A name after the `.` is a field and a number is a tuple position
+    fn finish_dot(&mut self, target: Expression) -> Result<Expression, ParseError> {
+        let token = self.consume(
+            |token| matches!(token, TokenType::Identifier(_) | TokenType::Number(_)),
+            "a field name or tuple position after '.'"
+        )?;
+        let span = target.span().to(token.span());
+        Ok(match token {
+            TokenType::Number(position) => Expression::TupleIndex(TupleIndex {
+                span,
+                id: self.next_id(),
+                tuple: Box::new(target),
+                position: position.literal as usize,
+            }),
+            token => Expression::Field(Field {
+                span,
+                id: self.next_id(),
+                target: Box::new(target),
+                field: token.lexeme().to_string(),
+            }),
+        })
+    }
+
+    fn finish_index(&mut self, target: Expression) -> Result<Expression, ParseError> {
+        let index = self.expression()?;
+        let end = self.consume(|token| matches!(token, TokenType::RightBracket(_)), "']' after index")?;
+        Ok(Expression::Index(Index {
+            span: target.span().to(end.span()),
+            id: self.next_id(),
+            target: Box::new(target),
+            index: Box::new(index),
+        }))
+    }
+
+    // Comma separated expressions up to, but not including, the token that
+    // closes the list. Any of them can be spread.
+    fn list(&mut self, closes: fn(&TokenType) -> bool) -> Result<Vec<Expression>, ParseError> {
+        let mut expressions = Vec::new();
+        if self.peek().is_some_and(closes) {
+            return Ok(expressions);
+        }
+
+        loop {
+            expressions.push(match self.peek() {
+                Some(TokenType::DotDotDot(_)) => self.spread()?,
+                _ => self.expression()?,
+            });
+            if !matches!(self.peek(), Some(TokenType::Comma(_))) {
+                return Ok(expressions);
+            }
+            self.advance();
+        }
+    }
+
+    fn spread(&mut self) -> Result<Expression, ParseError> {
+        let start = self.advance().unwrap().span();
+        let value = self.expression()?;
+        Ok(Expression::Spread(Spread {
+            span: start.to(value.span()),
+            id: self.next_id(),
+            value: Box::new(value),
+        }))
+    }
+
+    fn primary(&mut self) -> Result<Expression, ParseError> {
+        let Some(token) = self.advance() else {
+            return Err(self.error("an expression"));
+        };
+        let span = token.span();
+
+        let expression = match token {
+            TokenType::Number(number) => Expression::Integer(Integer {
+                value: number.literal,
+                span,
+                id: self.next_id(),
+            }),
+            TokenType::Float(float) => Expression::Float(Float {
+                value: float.literal,
+                span,
+                id: self.next_id(),
+            }),
+            TokenType::Char(char) => Expression::Char(Char {
+                value: char.literal,
+                span,
+                id: self.next_id(),
+            }),
+            TokenType::Str(str) => Expression::Str(Str {
+                value: str.literal,
+                span,
+                id: self.next_id(),
+            }),
+            TokenType::True(_) | TokenType::False(_) => Expression::Bool(Bool {
+                value: matches!(token, TokenType::True(_)),
+                span,
+                id: self.next_id(),
+            }),
+            TokenType::Nil(_) => Expression::Nil(Nil { span, id: self.next_id() }),
+            TokenType::Identifier(identifier) if self.at_struct_literal() => {
+                return self.struct_literal(identifier.lexeme.to_string(), span);
+            }
+            TokenType::Identifier(identifier) => Expression::Variable(Variable {
+                name: identifier.lexeme.to_string(),
+                span,
+                id: self.next_id(),
+            }),
+            TokenType::LeftBrace(_) => return self.block_or_map(span),
+            TokenType::If(_) => return self.if_expression(span),
+            TokenType::Match(_) => return self.match_expression(span),
+            TokenType::Try(_) => return self.try_expression(span),
+            TokenType::Fn(_) => {
+                self.consume(|token| matches!(token, TokenType::LeftParen(_)), "a function name or '(' after 'fn'")?;
+                let parameters = self.parameters()?;
+                let body = self.function_body()?;
+                Expression::Lambda(Lambda {
+                    parameters,
+                    span: span.to(body.span()),
+                    id: self.next_id(),
+                    body: Box::new(body),
+                })
+            }
+            TokenType::InterpolationStart(start) => {
+                self.brackets += 1;
+                let interpolation = self.interpolation(start.literal, span)?;
+                self.brackets -= 1;
+                return Ok(interpolation);
+            }
+            TokenType::LeftBracket(_) => {
+                self.brackets += 1;
+                let elements = self.list(|token| matches!(token, TokenType::RightBracket(_)))?;
+                let end = self.consume(|token| matches!(token, TokenType::RightBracket(_)), "']' after array elements")?;
+                self.brackets -= 1;
+                Expression::Array(Array {
+                    elements,
+                    span: span.to(end.span()),
+                    id: self.next_id(),
+                })
+            }
+            TokenType::LeftParen(_) => {
+                self.brackets += 1;
+                let expression = self.parenthesized(span)?;
+                self.brackets -= 1;
+                expression
+            }
+            token => {
+                self.lookahead.push_front(token);
+                return Err(self.error("an expression"));
+            }
+        };
+
+        Ok(expression)
+    }
+
+    // Brackets around a single expression only group it, while a comma turns
+    // them into a tuple
+    fn parenthesized(&mut self, start: Span) -> Result<Expression, ParseError> {
+        let mut elements = Vec::new();
+        if !matches!(self.peek(), Some(TokenType::RightParen(_))) {
+            let first = self.expression()?;
+            if !matches!(self.peek(), Some(TokenType::Comma(_))) {
+                self.consume(|token| matches!(token, TokenType::RightParen(_)), "')' after expression")?;
+                return Ok(first);
+            }
+            elements.push(first);
+            while matches!(self.peek(), Some(TokenType::Comma(_))) {
+                self.advance();
+                if matches!(self.peek(), Some(TokenType::RightParen(_))) {
+                    break;
+                }
+                elements.push(self.expression()?);
+            }
+        }
+
+        let end = self.consume(|token| matches!(token, TokenType::RightParen(_)), "')' after tuple elements")?;
+        Ok(Expression::Tuple(Tuple {
+            elements,
+            span: start.to(end.span()),
+            id: self.next_id(),
+        }))
+    }
+
+    // Like an `if` condition, the scrutinee ends at the `{`. Inside the braces
+    // arms are separated by commas, and line breaks don't matter.
+    fn match_expression(&mut self, start: Span) -> Result<Expression, ParseError> {
+        let scrutinee = self.expression()?;
+        self.consume(|token| matches!(token, TokenType::LeftBrace(_)), "'{' after match value")?;
+        let brackets = self.brackets;
+        self.brackets = 1;
+
+        let mut arms = Vec::new();
+        while !matches!(self.peek(), None | Some(TokenType::EOF(_)) | Some(TokenType::RightBrace(_))) {
+            self.nest()?;
+            arms.push(self.match_arm()?);
+            self.depth -= 1;
+            if !matches!(self.peek(), Some(TokenType::Comma(_))) {
+                break;
+            }
+            self.advance();
+        }
+
+        let end = self.consume(|token| matches!(token, TokenType::RightBrace(_)), "'}' after match arms")?;
+        self.brackets = brackets;
+        Ok(Expression::Match(Match {
+            scrutinee: Box::new(scrutinee),
+            arms,
+            span: start.to(end.span()),
+            id: self.next_id(),
+        }))
+    }
+
+    fn try_expression(&mut self, start: Span) -> Result<Expression, ParseError> {
+        let open = self.consume(|token| matches!(token, TokenType::LeftBrace(_)), "'{' after 'try'")?;
+        let body = self.block(open.span())?;
+        self.consume(|token| matches!(token, TokenType::Catch(_)), "'catch' after try block")?;
+        self.consume(|token| matches!(token, TokenType::LeftParen(_)), "'(' after 'catch'")?;
+        let name = self.consume(|token| matches!(token, TokenType::Identifier(_)), "an error name after '('")?;
+        self.consume(|token| matches!(token, TokenType::RightParen(_)), "')' after the error name")?;
+        let open = self.consume(|token| matches!(token, TokenType::LeftBrace(_)), "'{' after catch")?;
+        let handler = self.block(open.span())?;
+
+        Ok(Expression::Try(Try {
+            name: name.lexeme().to_string(),
+            span: start.to(handler.span()),
+            id: self.next_id(),
+            body: Box::new(body),
+            handler: Box::new(handler),
+        }))
+    }
+
+    fn match_arm(&mut self) -> Result<MatchArm, ParseError> {
+        let pattern = self.pattern()?;
+        check_bindings(&pattern, &mut HashSet::new())?;
+        let guard = match self.peek() {
+            Some(TokenType::If(_)) => {
+                self.advance();
+                Some(self.expression()?)
+            }
+            _ => None,
+        };
+        self.consume(|token| matches!(token, TokenType::FatArrow(_)), "'=>' after pattern")?;
+        let body = self.expression()?;
+
+        Ok(MatchArm {
+            span: pattern.span().to(body.span()),
+            id: self.next_id(),
+            pattern,
+            guard,
+            body,
+        })
+    }
+
+    fn pattern(&mut self) -> Result<Pattern, ParseError> {
+        match self.peek() {
+            Some(TokenType::Identifier(identifier)) if identifier.lexeme.as_str() == "_" => {
+                Ok(Pattern::Wildcard(self.advance().unwrap().span()))
+            }
+            Some(TokenType::Identifier(_)) => {
+                let token = self.advance().unwrap();
+                if matches!(self.peek(), Some(TokenType::Dot(_))) {
+                    return self.variant_pattern(token);
+                }
+                Ok(Pattern::Binding(token.lexeme().to_string(), token.span()))
+            }
+            Some(TokenType::LeftParen(_)) => self.tuple_pattern(),
+            Some(TokenType::LeftBracket(_)) => self.array_pattern(),
+            Some(
+                TokenType::Number(_)
+                | TokenType::Float(_)
+                | TokenType::Char(_)
+                | TokenType::Str(_)
+                | TokenType::True(_)
+                | TokenType::False(_)
+                | TokenType::Nil(_)
+            ) => Ok(Pattern::Literal(self.primary()?)),
+            Some(TokenType::Minus(_)) => {
+                let start = self.advance().unwrap().span();
+                if !matches!(self.peek(), Some(TokenType::Number(_)) | Some(TokenType::Float(_))) {
+                    return Err(self.error("a number after '-' in a pattern"));
+                }
+                Ok(Pattern::Literal(match self.primary()? {
+                    Expression::Integer(integer) => Expression::Integer(Integer {
+                        value: -integer.value,
+                        span: start.to(integer.span),
+                        id: integer.id,
+                    }),
+                    Expression::Float(float) => Expression::Float(Float {
+                        value: -float.value,
+                        span: start.to(float.span),
+                        id: float.id,
+                    }),
+                    _ => unreachable!("only a number is parsed"),
+                }))
+            }
+            _ => Err(self.error("a pattern")),
+        }
+    }
+
+    fn variant_pattern(&mut self, enum_name: TokenType) -> Result<Pattern, ParseError> {
+        self.advance();
+        let variant = self.consume(|token| matches!(token, TokenType::Identifier(_)), "a variant name after '.'")?;
+        let mut end = variant.span();
+        let mut fields = Vec::new();
+        if matches!(self.peek(), Some(TokenType::LeftParen(_))) {
+            self.advance();
+            self.brackets += 1;
+            loop {
+                fields.push(self.pattern()?);
+                if !matches!(self.peek(), Some(TokenType::Comma(_))) {
+                    break;
+                }
+                self.advance();
+            }
+            end = self.consume(|token| matches!(token, TokenType::RightParen(_)), "')' after variant fields")?.span();
+            self.brackets -= 1;
+        }
+
+        Ok(Pattern::Variant(VariantPattern {
+            enum_name: enum_name.lexeme().to_string(),
+            variant: variant.lexeme().to_string(),
+            fields,
+            span: enum_name.span().to(end),
+        }))
+    }
+
+    // Brackets around a single pattern only group it, the same as they do
+    // around an expression
+    fn tuple_pattern(&mut self) -> Result<Pattern, ParseError> {
+        let start = self.advance().unwrap().span();
+        let depth = self.depth;
+        self.nest()?;
+        self.brackets += 1;
+        let mut elements = Vec::new();
+        if !matches!(self.peek(), Some(TokenType::RightParen(_))) {
+            let first = self.pattern()?;
+            if !matches!(self.peek(), Some(TokenType::Comma(_))) {
+                self.consume(|token| matches!(token, TokenType::RightParen(_)), "')' after pattern")?;
+                self.brackets -= 1;
+                self.depth = depth;
+                return Ok(first);
+            }
+            elements.push(first);
+            while matches!(self.peek(), Some(TokenType::Comma(_))) {
+                self.advance();
+                if matches!(self.peek(), Some(TokenType::RightParen(_))) {
+                    break;
+                }
+                elements.push(self.pattern()?);
+            }
+        }
+
+        let end = self.consume(|token| matches!(token, TokenType::RightParen(_)), "')' after tuple elements")?;
+        self.brackets -= 1;
+        self.depth = depth;
+        Ok(Pattern::Tuple(TuplePattern { elements, span: start.to(end.span()) }))
+    }
+
+    // The rest can only be the last thing in the array
+    fn array_pattern(&mut self) -> Result<Pattern, ParseError> {
+        let start = self.advance().unwrap().span();
+        let depth = self.depth;
+        self.nest()?;
+        self.brackets += 1;
+        let mut elements = Vec::new();
+        let mut rest = None;
+        if !matches!(self.peek(), Some(TokenType::RightBracket(_))) {
+            loop {
+                let element = self.pattern()?;
+                if matches!(element, Pattern::Wildcard(_) | Pattern::Binding(..))
+                    && matches!(self.peek(), Some(TokenType::DotDotDot(_)))
+                {
+                    self.advance();
+                    rest = Some(Box::new(element));
+                    break;
+                }
+                elements.push(element);
+                if !matches!(self.peek(), Some(TokenType::Comma(_))) {
+                    break;
+                }
+                self.advance();
+            }
+        }
+
+        let expected = if rest.is_some() { "']' after the rest of the array" } else { "']' after array elements" };
+        let end = self.consume(|token| matches!(token, TokenType::RightBracket(_)), expected)?;
+        self.brackets -= 1;
+        self.depth = depth;
+        Ok(Pattern::Array(ArrayPattern { elements, rest, span: start.to(end.span()) }))
+    }
+
+    // Statements inside a block are separated the same way as at the top
+    // level, even when the block itself is inside brackets. An expression
+    // right before the closing `}` is the value of the block.
+    fn block(&mut self, start: Span) -> Result<Expression, ParseError> {
+        self.rest_of_block(start, None)
+    }
+
+    // Carries on with a block after its first expression, if that has
+    // already been parsed
+    fn rest_of_block(&mut self, start: Span, mut first: Option<Expression>) -> Result<Expression, ParseError> {
+        let brackets = self.brackets;
+        self.brackets = 0;
+
+        let mut statements = Vec::new();
+        let mut value = None;
+        loop {
+            let expression = match first.take() {
+                Some(expression) => expression,
+                None if self.at_statement() => {
+                    statements.push(self.statement()?);
+                    continue;
+                }
+                None => match self.peek() {
+                    Some(TokenType::Semicolon(_)) => {
+                        self.advance();
+                        continue;
+                    }
+                    None | Some(TokenType::EOF(_)) | Some(TokenType::RightBrace(_)) => break,
+                    Some(_) => self.expression()?,
+                },
+            };
+
+            if matches!(self.peek(), Some(TokenType::RightBrace(_))) {
+                value = Some(Box::new(expression));
+                break;
+            }
+            statements.push(self.expression_statement(expression)?);
+        }
+
+        let end = self.consume(|token| matches!(token, TokenType::RightBrace(_)), "'}' after block")?;
+        self.brackets = brackets;
+
+        Ok(Expression::Block(Block {
+            statements,
+            value,
+            span: start.to(end.span()),
+            id: self.next_id(),
+        }))
+    }
+
+    // A `{` in an expression starts a map rather than a block if there is a
+    // `:` straight after it or after the first expression in it. That first
+    // expression is parsed the way a block would, since until then there is
+    // no telling which it is.
+    fn block_or_map(&mut self, start: Span) -> Result<Expression, ParseError> {
+        if matches!(self.peek(), Some(TokenType::Colon(_))) {
+            self.advance();
+            let end = self.consume(|token| matches!(token, TokenType::RightBrace(_)), "'}' after ':' in an empty map")?;
+            return Ok(Expression::Map(Map {
+                entries: Vec::new(),
+                span: start.to(end.span()),
+                id: self.next_id(),
+            }));
+        }
+        if self.at_statement() || !self.peek().is_some_and(starts_expression) {
+            return self.block(start);
+        }
+
+        let brackets = self.brackets;
+        self.brackets = 0;
+        let first = self.expression()?;
+        let expression = if matches!(self.peek(), Some(TokenType::Colon(_))) {
+            self.brackets = 1;
+            self.map(start, first)
+        } else {
+            self.rest_of_block(start, Some(first))
+        };
+        self.brackets = brackets;
+        expression
+    }
+
+    // The entries of a map after its first key
+    // A name followed by `{`, a name and `:` starts a struct literal. No block
+    // can begin with a name and a `:`, so this doesn't take anything away from
+    // `if` or loop bodies.
+    fn at_struct_literal(&mut self) -> bool {
+        if !matches!(self.peek_operator(), Some(TokenType::LeftBrace(_))) {
+            return false;
+        }
+        self.fill(3);
+        matches!(self.lookahead.get(1), Some(TokenType::Identifier(_)))
+            && matches!(self.lookahead.get(2), Some(TokenType::Colon(_)))
+    }
+
+    fn struct_literal(&mut self, name: String, start: Span) -> Result<Expression, ParseError> {
+        self.advance();
+        self.brackets += 1;
+        let mut fields = Vec::new();
+        loop {
+            let field = self.consume(|token| matches!(token, TokenType::Identifier(_)), "a field name")?;
+            self.consume(|token| matches!(token, TokenType::Colon(_)), "':' after field name")?;
+            fields.push((field.lexeme().to_string(), self.expression()?));
+            if !matches!(self.peek(), Some(TokenType::Comma(_))) {
+                break;
+            }
+            self.advance();
+        }
+        let end = self.consume(|token| matches!(token, TokenType::RightBrace(_)), "'}' after struct fields")?;
+        self.brackets -= 1;
+
+        Ok(Expression::StructLiteral(StructLiteral {
+            name,
+            fields,
+            span: start.to(end.span()),
+            id: self.next_id(),
+        }))
+    }
+
+    fn map(&mut self, start: Span, first: Expression) -> Result<Expression, ParseError> {
+        let mut entries = Vec::new();
+        let mut key = first;
+        loop {
+            self.consume(|token| matches!(token, TokenType::Colon(_)), "':' after map key")?;
+            entries.push((key, self.expression()?));
+            if !matches!(self.peek(), Some(TokenType::Comma(_))) {
+                break;
+            }
+            self.advance();
+            key = self.expression()?;
+        }
+
+        let end = self.consume(|token| matches!(token, TokenType::RightBrace(_)), "'}' after map entries")?;
+        Ok(Expression::Map(Map {
+            entries,
+            span: start.to(end.span()),
+            id: self.next_id(),
+        }))
+    }
+
+    // The condition is an ordinary expression, and a `{` can't continue one,
+    // so the first block after it is the then branch. An `else` may start on
+    // a new line since no statement begins with one.
+    fn if_expression(&mut self, start: Span) -> Result<Expression, ParseError> {
+        let condition = self.expression()?;
+        let open = self.consume(|token| matches!(token, TokenType::LeftBrace(_)), "'{' after if condition")?;
+        let then_branch = self.block(open.span())?;
+
+        let else_branch = match self.peek() {
+            Some(TokenType::Else(_)) => {
+                self.advance();
+                let branch = match self.advance() {
+                    Some(token @ TokenType::LeftBrace(_)) => self.block(token.span())?,
+                    Some(token @ TokenType::If(_)) => {
+                        let depth = self.depth;
+                        self.nest()?;
+                        let branch = self.if_expression(token.span())?;
+                        self.depth = depth;
+                        branch
+                    }
+                    token => {
+                        self.lookahead.extend(token);
+                        return Err(self.error("'{' or 'if' after 'else'"));
+                    }
+                };
+                Some(Box::new(branch))
+            }
+            _ => None,
+        };
+
+        let end = else_branch.as_ref().map_or(then_branch.span(), |branch| branch.span());
+        Ok(Expression::If(If {
+            condition: Box::new(condition),
+            then_branch: Box::new(then_branch),
+            else_branch,
+            span: start.to(end),
+            id: self.next_id(),
+        }))
+    }
+
+    // Text segments become `Str` parts spanning the token they came from,
+    // delimiters included
+    fn interpolation(&mut self, start: String, start_span: Span) -> Result<Expression, ParseError> {
+        let mut parts = Vec::new();
+        let mut text = start;
+        let mut text_span = start_span;
+
+        let end = loop {
+            if !text.is_empty() {
+                parts.push(Expression::Str(Str { value: text, span: text_span, id: self.next_id() }));
+            }
+            parts.push(self.expression()?);
+
+            match self.advance() {
+                Some(TokenType::InterpolationMiddle(middle)) => {
+                    text_span = middle.span();
+                    text = middle.literal;
+                }
+                Some(TokenType::InterpolationEnd(end)) => {
+                    let span = end.span();
+                    if !end.literal.is_empty() {
+                        parts.push(Expression::Str(Str { value: end.literal, span, id: self.next_id() }));
+                    }
+                    break span;
+                }
+                token => {
+                    self.lookahead.extend(token);
+                    return Err(self.error("'}' after interpolated expression"));
+                }
+            }
+        };
+
+        Ok(Expression::Interpolation(Interpolation {
+            parts,
+            span: start_span.to(end),
+            id: self.next_id(),
+        }))
+    }
+
+    // Skips the token an error was reported at, along with anything after it
+    // that can't begin an expression. Returns whether there is anything left
+    // to parse.
+    fn synchronize(&mut self) -> bool {
+        if !matches!(self.peek(), Some(TokenType::EOF(_))) {
+            self.advance();
+        }
+
+        loop {
+            match self.peek() {
+                None | Some(TokenType::EOF(_)) => return false,
+                Some(token) if starts_expression(token) => return true,
+                Some(_) => { self.advance(); }
+            }
+        }
+    }
+
+    fn binary(&mut self, left: Expression, operator: BinaryOperator, operator_span: Span, right: Expression) -> Expression {
+        Expression::Binary(Binary {
+            span: left.span().to(right.span()),
+            id: self.next_id(),
+            left: Box::new(left),
+            operator,
+            operator_span,
+            right: Box::new(right),
+        })
+    }
+
+    // Skips the token an error was reported at and everything after it up to
+    // the end of the statement it was in
+    fn synchronize_statement(&mut self) {
+        if let Some(TokenType::Semicolon(_)) | Some(TokenType::EOF(_)) | None = self.peek() {
+            return;
+        }
+        self.advance();
+
+        loop {
+            let previous_line = self.previous_line;
+            match self.peek() {
+                None | Some(TokenType::EOF(_)) | Some(TokenType::Semicolon(_)) => return,
+                Some(token) if token.line() > previous_line => return,
+                Some(_) => { self.advance(); }
+            }
+        }
+    }
+
+    // The next token if it can continue the current expression. Outside of
+    // brackets a line break ends the statement, so an operator at the start
+    // of a line begins a new statement rather than continuing the last one.
+    fn peek_operator(&mut self) -> Option<&TokenType> {
+        let (previous_line, brackets) = (self.previous_line, self.brackets);
+        match self.peek() {
+            Some(token) if brackets == 0 && token.line() > previous_line => None,
+            token => token,
+        }
+    }
+
+    // Goes one level deeper into the tree, as long as that stays within the
+    // configured limit
+    fn nest(&mut self) -> Result<(), ParseError> {
+        self.depth += 1;
+        if self.depth <= self.config.max_depth {
+            return Ok(());
+        }
+
+        let (line, character) = match self.peek() {
+            Some(token) => (token.line(), token.character()),
+            None => self.last_position.unwrap_or((1, 1)),
+        };
+        Err(ParseError::TooDeeplyNested(TooDeeplyNested { max_depth: self.config.max_depth, line, character }))
+    }
+
+    fn next_id(&mut self) -> NodeId {
+        let id = NodeId(self.next_id);
+        self.next_id += 1;
+        id
+    }
+
+    fn consume(
+        &mut self,
+        matches: fn(&TokenType) -> bool,
+        expected: &'static str
+    ) -> Result<TokenType, ParseError> {
+        match self.peek() {
+            Some(token) if matches(token) => Ok(self.advance().unwrap()),
+            _ => Err(self.error(expected)),
+        }
+    }
+
+    // Builds an error for the token that is about to be consumed
+    fn error(&mut self, expected: &'static str) -> ParseError {
+        match self.peek() {
+            Some(TokenType::EOF(eof)) => ParseError::UnexpectedEof(UnexpectedEof {
+                expected,
+                line: eof.line,
+                character: eof.character,
+            }),
+            Some(token) => ParseError::UnexpectedToken(UnexpectedToken {
+                expected,
+                lexeme: token.lexeme().clone(),
+                line: token.line(),
+                character: token.character(),
+            }),
+            // Token streams from the lexer always end in EOF, but anything
+            // else running out is still the end of the input
+            None => {
+                let (line, character) = self.last_position.unwrap_or((1, 1));
+                ParseError::UnexpectedEof(UnexpectedEof { expected, line, character })
+            }
+        }
+    }
+
+    fn advance(&mut self) -> Option<TokenType> {
+        self.fill(1);
+        let token = self.lookahead.pop_front()?;
+        self.previous_line = token.line() + token.lexeme().as_str().matches('\n').count() as u32;
+        self.previous_span = token.span();
+        Some(token)
+    }
+
+    fn peek(&mut self) -> Option<&TokenType> {
+        self.fill(1);
+        self.lookahead.front()
+    }
+
+    // Buffers up to `count` tokens of lookahead, or as many as are left. Doc
+    // comments are set aside for whichever token comes after them, so they
+    // can go anywhere without getting in the way.
+    fn fill(&mut self, count: usize) {
+        while self.lookahead.len() < count {
+            match self.tokens.next() {
+                Some(TokenType::DocComment(doc)) => self.doc_lines.push(doc.literal),
+                Some(token) => {
+                    self.last_position = Some((token.line(), token.character()));
+                    if !self.doc_lines.is_empty() {
+                        self.docs.insert((token.line(), token.character()), self.doc_lines.join("\n"));
+                        self.doc_lines.clear();
+                    }
+                    self.lookahead.push_back(token);
+                }
+                None => break,
+            }
+        }
+    }
+
+    // The doc comments right before the next token
+    fn doc(&mut self) -> Option<String> {
+        let position = self.peek().map(|token| (token.line(), token.character()))?;
+        self.docs.remove(&position)
+    }
+}
+
+// Whether an expression can have an index assigned to it, which needs a
+// variable to store the changed value back into
+fn is_place(expression: &Expression) -> bool {
+    match expression {
+        Expression::Variable(_) => true,
+        Expression::Index(index) => is_place(&index.target),
+        _ => false,
+    }
+}
+
+fn starts_expression(token: &TokenType) -> bool {
+    matches!(
+        token,
+        TokenType::Number(_)
+            | TokenType::Float(_)
+            | TokenType::Char(_)
+            | TokenType::Str(_)
+            | TokenType::True(_)
+            | TokenType::False(_)
+            | TokenType::Nil(_)
+            | TokenType::Identifier(_)
+            | TokenType::If(_)
+            | TokenType::Match(_)
+            | TokenType::Try(_)
+            | TokenType::Fn(_)
+            | TokenType::InterpolationStart(_)
+            | TokenType::LeftParen(_)
+            | TokenType::LeftBrace(_)
+            | TokenType::LeftBracket(_)
+            | TokenType::Minus(_)
+            | TokenType::Tilde(_)
+    )
+}
+
+fn check_irrefutable(pattern: &Pattern) -> Result<(), ParseError> {
+    match pattern {
+        Pattern::Wildcard(_) | Pattern::Binding(..) => Ok(()),
+        Pattern::Literal(_) | Pattern::Variant(_) => {
+            Err(ParseError::RefutablePattern(RefutablePattern { span: pattern.span() }))
+        }
+        Pattern::Tuple(tuple) => tuple.elements.iter().try_for_each(check_irrefutable),
+        Pattern::Array(array) => array.elements.iter().try_for_each(check_irrefutable),
+    }
+}
+
+// Adds the names a pattern binds to `names`, failing at the first one that's
+// already there
+fn check_bindings<'a>(pattern: &'a Pattern, names: &mut HashSet<&'a str>) -> Result<(), ParseError> {
+    let (elements, rest) = match pattern {
+        Pattern::Wildcard(_) | Pattern::Literal(_) => return Ok(()),
+        Pattern::Binding(name, span) => {
+            if names.insert(name) {
+                return Ok(());
+            }
+            return Err(ParseError::DuplicateBinding(DuplicateBinding { name: name.clone(), span: *span }));
+        }
+        Pattern::Variant(variant) => (&variant.fields, None),
+        Pattern::Tuple(tuple) => (&tuple.elements, None),
+        Pattern::Array(array) => (&array.elements, array.rest.as_deref()),
+    };
+    elements.iter().chain(rest).try_for_each(|element| check_bindings(element, names))
+}
+
+pub fn get_ast(tokens: impl IntoIterator<Item = TokenType>) -> Result<Expression, ParseError> {
+    Parser::parse(tokens, ParserConfig::default())
+}
+
+pub fn get_ast_with_config(
+    tokens: impl IntoIterator<Item = TokenType>,
+    config: ParserConfig
+) -> Result<Expression, ParseError> {
+    Parser::parse(tokens, config)
+}
+
+pub fn get_ast_resilient(tokens: impl IntoIterator<Item = TokenType>) -> Result<Expression, Vec<ParseError>> {
+    Parser::parse_resilient(tokens, ParserConfig::default())
+}
+
+pub fn get_program(tokens: impl IntoIterator<Item = TokenType>) -> Result<Program, ParseError> {
+    Parser::parse_program(tokens, ParserConfig::default())
+}
+
+pub fn get_program_with_config(
+    tokens: impl IntoIterator<Item = TokenType>,
+    config: ParserConfig
+) -> Result<Program, ParseError> {
+    Parser::parse_program(tokens, config)
+}
+
+pub fn get_program_resilient(tokens: impl IntoIterator<Item = TokenType>) -> Result<Program, Vec<ParseError>> {
+    Parser::parse_program_resilient(tokens, ParserConfig::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::lexer::{get_tokens, TokenStream};
+
+    fn parse_source(input: &str) -> Result<Expression, ParseError> {
+        get_ast(get_tokens(input).unwrap())
+    }
+
+    #[test]
+    fn test_parses_expression() -> Result<(), String> {
+        let result = parse_source("1 + 2 * 3").unwrap();
+
+        match &result {
+            Expression::Binary(Binary { left, operator: BinaryOperator::Plus, right, .. }) => {
+                assert!(matches!(**left, Expression::Integer(Integer { value: 1, .. })));
+                assert!(matches!(**right, Expression::Binary(Binary { operator: BinaryOperator::Star, .. })));
+            }
+            _ => panic!("Expression did not parse as an addition")
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_spans() -> Result<(), String> {
+        let input = "1 +\n -(22 * 3)";
+        let result = parse_source(input).unwrap();
+
+        assert_eq!(result.span(), Span { start: 0, end: 13, line: 1, character: 1 });
+        match &result {
+            Expression::Binary(Binary { left, right, .. }) => {
+                assert_eq!(left.span(), Span { start: 0, end: 1, line: 1, character: 1 });
+                assert_eq!(right.span(), Span { start: 5, end: 13, line: 2, character: 2 });
+                match &**right {
+                    Expression::Unary(Unary { right, .. }) => {
+                        assert_eq!(&input[right.span().start..right.span().end], "22 * 3");
+                        assert_eq!((right.span().line, right.span().character), (2, 4));
+                    }
+                    _ => panic!("Expected a negation")
+                }
+            }
+            _ => panic!("Expression did not parse as an addition")
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_interpolation_spans() -> Result<(), String> {
+        let input = "\"a ${1} b\"";
+        match parse_source(input).unwrap() {
+            Expression::Interpolation(Interpolation { parts, span, .. }) => {
+                assert_eq!((span.start, span.end), (0, input.len()));
+                let parts = parts.iter()
+                    .map(|part| &input[part.span().start..part.span().end])
+                    .collect::<Vec<_>>();
+                assert_eq!(parts, vec!["\"a ${", "1", "} b\""]);
+            }
+            _ => panic!("Expected an interpolation")
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_node_ids_are_unique() -> Result<(), String> {
+        fn collect(expression: &Expression, ids: &mut Vec<NodeId>) {
+            ids.push(expression.id());
+            match expression {
+                Expression::Binary(binary) => {
+                    collect(&binary.left, ids);
+                    collect(&binary.right, ids);
+                }
+                Expression::Unary(unary) => collect(&unary.right, ids),
+                Expression::Interpolation(interpolation) => {
+                    interpolation.parts.iter().for_each(|part| collect(part, ids));
+                }
+                _ => {}
+            }
+        }
+
+        let mut ids = Vec::new();
+        collect(&parse_source("1 + -2 * \"a${3 ** 4}b\"").unwrap(), &mut ids);
+        assert_eq!(ids.len(), 11);
+
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 11);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() -> Result<(), String> {
+        let json = serde_json::to_string(&parse_source("-(1 + 2.5)").unwrap()).map_err(|e| e.to_string())?;
+        let result: Expression = serde_json::from_str(&json).map_err(|e| e.to_string())?;
+
+        match result {
+            Expression::Unary(Unary { operator: UnaryOperator::Minus, right, span, .. }) => {
+                assert_eq!((span.start, span.end), (0, 9));
+                assert!(matches!(*right, Expression::Binary(Binary { operator: BinaryOperator::Plus, .. })));
+            }
+            _ => panic!("Expected a negation")
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parses_from_token_stream() -> Result<(), String> {
+        let result = get_ast(TokenStream::new("(1 + 2) * 3").map(Result::unwrap)).unwrap();
+        assert!(matches!(result, Expression::Binary(Binary { operator: BinaryOperator::Star, .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tokens_ending_without_eof() -> Result<(), String> {
+        let tokens = get_tokens("1 +").unwrap()
+            .into_iter()
+            .filter(|token| !matches!(token, TokenType::EOF(_)));
+
+        match get_ast(tokens) {
+            Err(ParseError::UnexpectedEof(error)) => assert_eq!((error.line, error.character), (1, 3)),
+            _ => panic!("Expected the missing operand to be reported at the last token")
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_nesting_limit() -> Result<(), String> {
+        // The default limit is sized for a main thread's stack, which test
+        // threads don't get
+        std::thread::Builder::new()
+            .stack_size(8 * 1024 * 1024)
+            .spawn(|| {
+                let nested = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+                assert!(parse_source(&nested(120)).is_ok());
+                assert!(matches!(parse_source(&nested(200)), Err(ParseError::TooDeeplyNested(_))));
+                assert!(matches!(parse_source(&"-".repeat(200)), Err(ParseError::TooDeeplyNested(_))));
+                assert!(parse_source(&format!("1{}", "+1".repeat(200))).is_ok());
+                assert!(parse_source(&format!("2{}", " * 3 - 1 < 4 | 5".repeat(200))).is_ok());
+                assert!(matches!(parse_source(&format!("2{}", "**2".repeat(200))), Err(ParseError::TooDeeplyNested(_))));
+            })
+            .unwrap()
+            .join()
+            .map_err(|_| "Parsing deeply nested input panicked".to_string())?;
+
+        let config = ParserConfig { max_depth: 5 };
+        assert!(get_ast_with_config(get_tokens("1 + 2 * -(((3)))").unwrap(), config).is_ok());
+        match get_ast_with_config(get_tokens("1 + 2 * -((((3))))").unwrap(), config) {
+            Err(ParseError::TooDeeplyNested(error)) => assert_eq!((error.line, error.character), (1, 14)),
+            _ => panic!("Expected the second bracket to go past the limit")
+        }
+
+        Ok(())
+    }
+
+    fn parse_program_source(input: &str) -> Result<Program, ParseError> {
+        get_program(get_tokens(input).unwrap())
+    }
+
+    fn statement_sources(input: &str) -> Vec<&str> {
+        parse_program_source(input).unwrap().statements.iter()
+            .map(|statement| &input[statement.span().start..statement.span().end])
+            .collect()
+    }
+
+    #[test]
+    fn test_program_statements() -> Result<(), String> {
+        assert_eq!(statement_sources("1 + 2; 3\n4 * 5;"), vec!["1 + 2", "3", "4 * 5"]);
+        assert_eq!(statement_sources(";;1;;\n;2"), vec!["1", "2"]);
+        assert!(parse_program_source("").unwrap().statements.is_empty());
+        assert!(parse_program_source(" // nothing\n;").unwrap().statements.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_statements_across_lines() -> Result<(), String> {
+        // A trailing operator or an open bracket carries on to the next line,
+        // but an operator starting a line begins a new statement
+        assert_eq!(statement_sources("1 +\n2\n(3\n* 4)"), vec!["1 +\n2", "3\n* 4"]);
+        assert_eq!(statement_sources("1\n-2"), vec!["1", "-2"]);
+        assert!(parse_program_source("\"a\nb\" 1").is_err());
+        assert_eq!(statement_sources("\"a\nb\"\n1"), vec!["\"a\nb\"", "1"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_let() -> Result<(), String> {
+        let program = parse_program_source("let x = 2 + 3\nx * 2").unwrap();
+
+        match &program.statements[..] {
+            [Statement::Let(Let { name, initializer, span, .. }), Statement::Expression(statement)] => {
+                assert_eq!(name, "x");
+                assert!(matches!(initializer, Expression::Binary(Binary { operator: BinaryOperator::Plus, .. })));
+                assert_eq!((span.start, span.end), (0, 13));
+                assert!(matches!(
+                    &statement.expression,
+                    Expression::Binary(Binary { left, .. }) if matches!(&**left, Expression::Variable(Variable { name, .. }) if name == "x")
+                ));
+            }
+            _ => panic!("Expected a let followed by an expression")
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_let_errors() -> Result<(), String> {
+        let expected = |input: &str| match parse_program_source(input) {
+            Err(ParseError::UnexpectedToken(error)) => (error.expected, error.character),
+            Err(ParseError::UnexpectedEof(error)) => (error.expected, error.character),
+            _ => panic!("Expected {:?} to be rejected", input)
+        };
+
+        assert_eq!(expected("let = 1"), ("a variable name after 'let'", 5));
+        assert_eq!(expected("let x 1"), ("'=' after the variable name", 7));
+        assert_eq!(expected("let x = ;"), ("an initializer after '='", 9));
+        assert_eq!(expected("let x ="), ("an initializer after '='", 8));
+        assert_eq!(expected("let x = 1 2"), ("';' or a line break after statement", 11));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_assignment() -> Result<(), String> {
+        match parse_program_source("x = y = 1 + 2").unwrap().statements.pop() {
+            Some(Statement::Expression(ExpressionStatement { expression: Expression::Assign(assign), .. })) => {
+                assert_eq!(assign.name, "x");
+                assert_eq!((assign.span.start, assign.span.end), (0, 13));
+                assert!(matches!(*assign.value, Expression::Assign(Assign { ref name, .. }) if name == "y"));
+            }
+            _ => panic!("Expected an assignment")
+        }
+
+        match parse_program_source("1 + (x = 2)").unwrap().statements.pop() {
+            Some(Statement::Expression(ExpressionStatement { expression: Expression::Binary(binary), .. })) => {
+                assert!(matches!(*binary.right, Expression::Assign(_)));
+            }
+            _ => panic!("Expected an addition")
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compound_assignment() -> Result<(), String> {
+        match parse_program_source("x -= 1 + 2").unwrap().statements.pop() {
+            Some(Statement::Expression(ExpressionStatement { expression: Expression::Assign(assign), .. })) => {
+                assert_eq!(assign.name, "x");
+                assert_eq!((assign.span.start, assign.span.end), (0, 10));
+                match &*assign.value {
+                    Expression::Binary(Binary { left, operator: BinaryOperator::Minus, right, .. }) => {
+                        assert!(matches!(**left, Expression::Variable(Variable { ref name, .. }) if name == "x"));
+                        assert!(matches!(**right, Expression::Binary(Binary { operator: BinaryOperator::Plus, .. })));
+                    }
+                    _ => panic!("Expected the assignment to subtract from x")
+                }
+            }
+            _ => panic!("Expected an assignment")
+        }
+        assert!(matches!(parse_program_source("1 *= 2"), Err(ParseError::InvalidAssignmentTarget(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_assignment_target() -> Result<(), String> {
+        let input = "let a = 1\n  2 + 3 = a";
+        match parse_program_source(input) {
+            Err(ParseError::InvalidAssignmentTarget(error)) => {
+                assert_eq!(&input[error.span.start..error.span.end], "2 + 3");
+                assert_eq!(error.to_string(), "Invalid assignment target at line 2, character 3");
+            }
+            _ => panic!("Expected the assignment to be rejected")
+        }
+        assert!(parse_program_source("(a) = 1").is_ok());
+        assert!(matches!(parse_program_source("-a = 1"), Err(ParseError::InvalidAssignmentTarget(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_blocks() -> Result<(), String> {
+        let input = "let x = { let y = 1; y + 1 }\n{\n  x\n  x;\n}\n{}";
+        let statements = parse_program_source(input).unwrap().statements;
+
+        match &statements[..] {
+            [Statement::Let(declaration), Statement::Expression(second), Statement::Expression(third)] => {
+                match &declaration.initializer {
+                    Expression::Block(Block { statements, value: Some(value), span, .. }) => {
+                        assert!(matches!(&statements[..], [Statement::Let(_)]));
+                        assert!(matches!(**value, Expression::Binary(_)));
+                        assert_eq!(&input[span.start..span.end], "{ let y = 1; y + 1 }");
+                    }
+                    _ => panic!("Expected the initializer to be a block with a value")
+                }
+                assert!(matches!(&second.expression, Expression::Block(Block { statements, value: None, .. }) if statements.len() == 2));
+                assert!(matches!(&third.expression, Expression::Block(Block { statements, value: None, .. }) if statements.is_empty()));
+            }
+            _ => panic!("Expected a let followed by two blocks")
+        }
+
+        // Lines end statements inside a block even when it is in brackets
+        assert!(parse_program_source("({ 1\n2 })").is_ok());
+        assert!(matches!(parse_program_source("{ 1 2 }"), Err(ParseError::UnexpectedToken(_))));
+        assert!(matches!(parse_program_source("{ 1"), Err(ParseError::UnexpectedEof(error)) if error.expected == "'}' after block"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_comparisons() -> Result<(), String> {
+        match &parse_source("1 & 2 == 3 < 4 | 5").unwrap() {
+            Expression::Binary(Binary { left, operator: BinaryOperator::EqualEqual, right, .. }) => {
+                assert!(matches!(**left, Expression::Binary(Binary { operator: BinaryOperator::Ampersand, .. })));
+                assert!(matches!(**right, Expression::Binary(Binary { operator: BinaryOperator::Less, .. })));
+            }
+            _ => panic!("Expected an equality at the root")
+        }
+        assert!(matches!(&parse_source("true != false").unwrap(), Expression::Binary(Binary { left, .. }) if matches!(**left, Expression::Bool(Bool { value: true, .. }))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_if() -> Result<(), String> {
+        let input = "let x = if a { 1 } else if b { 2 }\nif c {\n  x\n}\nelse {\n  3\n}";
+        let statements = parse_program_source(input).unwrap().statements;
+
+        match &statements[..] {
+            [Statement::Let(declaration), Statement::Expression(second)] => {
+                match &declaration.initializer {
+                    Expression::If(If { condition, else_branch: Some(else_branch), span, .. }) => {
+                        assert!(matches!(**condition, Expression::Variable(_)));
+                        assert!(matches!(**else_branch, Expression::If(If { else_branch: None, .. })));
+                        assert_eq!(&input[span.start..span.end], "if a { 1 } else if b { 2 }");
+                    }
+                    _ => panic!("Expected the initializer to be an if with an else if")
+                }
+                assert!(matches!(&second.expression, Expression::If(If { else_branch: Some(branch), .. }) if matches!(**branch, Expression::Block(_))));
+            }
+            _ => panic!("Expected a let followed by an if")
+        }
+
+        assert!(matches!(parse_program_source("if a 1"), Err(ParseError::UnexpectedToken(error)) if error.expected == "'{' after if condition"));
+        assert!(matches!(parse_program_source("if a {} else 1"), Err(ParseError::UnexpectedToken(error)) if error.expected == "'{' or 'if' after 'else'"));
+        assert!(matches!(parse_program_source("else {}"), Err(ParseError::UnexpectedToken(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_while() -> Result<(), String> {
+        let input = "while x < 3 {\n  x += 1\n}\n{ while false {} }";
+        let statements = parse_program_source(input).unwrap().statements;
+
+        match &statements[..] {
+            [Statement::While(first), Statement::Expression(second)] => {
+                assert!(matches!(first.condition, Expression::Binary(Binary { operator: BinaryOperator::Less, .. })));
+                assert!(matches!(&first.body, Expression::Block(Block { value: Some(_), .. })));
+                assert_eq!(&input[first.span.start..first.span.end], "while x < 3 {\n  x += 1\n}");
+                assert!(matches!(&second.expression, Expression::Block(Block { statements, value: None, .. }) if matches!(statements[..], [Statement::While(_)])));
+            }
+            _ => panic!("Expected a while followed by a block")
+        }
+
+        assert!(matches!(parse_program_source("while x 1"), Err(ParseError::UnexpectedToken(error)) if error.expected == "'{' after while condition"));
+        assert!(matches!(parse_program_source("while x {} 1"), Err(ParseError::UnexpectedToken(_))));
+        assert!(matches!(parse_program_source("1 + while x {}"), Err(ParseError::UnexpectedToken(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_for() -> Result<(), String> {
+        let input = "for i in 0..n + 1 {\n  total += i\n}";
+        match parse_program_source(input).unwrap().statements.pop() {
+            Some(Statement::For(For { variable, iterable: Expression::Range(range), span, .. })) => {
+                assert_eq!(variable, "i");
+                assert!(matches!(*range.start, Expression::Integer(Integer { value: 0, .. })));
+                assert!(matches!(*range.end, Expression::Binary(Binary { operator: BinaryOperator::Plus, .. })));
+                assert_eq!(&input[range.span.start..range.span.end], "0..n + 1");
+                assert_eq!(&input[span.start..span.end], input);
+            }
+            _ => panic!("Expected a for loop over a range")
+        }
+
+        for (input, expected) in [
+            ("for 1 in 0..1 {}", "a loop variable after 'for'"),
+            ("for i 0..1 {}", "'in' after the loop variable"),
+            ("for i in 0..1 2", "'{' after the iterable"),
+        ] {
+            match parse_program_source(input) {
+                Err(ParseError::UnexpectedToken(error)) => assert_eq!(error.expected, expected),
+                _ => panic!("Expected {} to be rejected", input)
+            }
+        }
+        assert!(matches!(parse_program_source("for x in xs {}").unwrap().statements.pop(), Some(Statement::For(For { iterable: Expression::Variable(_), .. }))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ranges() -> Result<(), String> {
+        let input = "i in 1..=n * 2 == true";
+        match &parse_source(input).unwrap() {
+            Expression::Binary(Binary { left, operator: BinaryOperator::EqualEqual, .. }) => match &**left {
+                Expression::Binary(Binary { left, operator: BinaryOperator::In, right, .. }) => {
+                    assert!(matches!(**left, Expression::Variable(_)));
+                    match &**right {
+                        Expression::Range(Range { start, end, inclusive, span, .. }) => {
+                            assert!(inclusive);
+                            assert!(matches!(**start, Expression::Integer(_)));
+                            assert!(matches!(**end, Expression::Binary(Binary { operator: BinaryOperator::Star, .. })));
+                            assert_eq!(&input[span.start..span.end], "1..=n * 2");
+                        }
+                        _ => panic!("Expected a range on the right of 'in'")
+                    }
+                }
+                _ => panic!("Expected a membership test")
+            }
+            _ => panic!("Expected an equality at the root")
+        }
+
+        assert!(matches!(parse_source("xs[1..len(xs)]").unwrap(), Expression::Index(Index { index, .. }) if matches!(*index, Expression::Range(Range { inclusive: false, .. }))));
+        assert!(matches!(parse_source("0..1 < 2").unwrap(), Expression::Binary(Binary { operator: BinaryOperator::Less, .. })));
+        assert!(matches!(parse_source("(0..1)..2").unwrap(), Expression::Range(_)));
+        assert!(parse_source("0..1..2").is_err());
+        assert!(parse_program_source("0\n..1").is_err());
+        assert!(matches!(parse_source("0.."), Err(ParseError::UnexpectedEof(error)) if error.expected == "an expression"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_destructuring() -> Result<(), String> {
+        let input = "let (a, [b, _, rest...]) = pair";
+        match &parse_program_source(input).unwrap().statements[..] {
+            [Statement::Destructure(Destructure { pattern: Pattern::Tuple(tuple), initializer, span, .. })] => {
+                assert_eq!(&input[span.start..span.end], input);
+                assert_eq!(&input[tuple.span.start..tuple.span.end], "(a, [b, _, rest...])");
+                assert!(matches!(initializer, Expression::Variable(_)));
+                match &tuple.elements[..] {
+                    [Pattern::Binding(a, _), Pattern::Array(ArrayPattern { elements, rest: Some(rest), .. })] => {
+                        assert_eq!(a, "a");
+                        assert!(matches!(&elements[..], [Pattern::Binding(..), Pattern::Wildcard(_)]));
+                        assert!(matches!(&**rest, Pattern::Binding(name, _) if name == "rest"));
+                    }
+                    _ => panic!("Expected a binding and an array pattern")
+                }
+            }
+            _ => panic!("Expected a single destructuring let")
+        }
+
+        assert!(matches!(&parse_program_source("let (a,) = t").unwrap().statements[..], [Statement::Destructure(Destructure { pattern: Pattern::Tuple(tuple), .. })] if tuple.elements.len() == 1));
+        assert!(matches!(&parse_program_source("let (a) = t").unwrap().statements[..], [Statement::Destructure(Destructure { pattern: Pattern::Binding(..), .. })]));
+        assert!(matches!(&parse_program_source("let [] = xs").unwrap().statements[..], [Statement::Destructure(Destructure { pattern: Pattern::Array(array), .. })] if array.elements.is_empty() && array.rest.is_none()));
+        assert!(matches!(&parse_program_source("let [_...] = xs").unwrap().statements[..], [Statement::Destructure(Destructure { pattern: Pattern::Array(array), .. })] if matches!(array.rest.as_deref(), Some(Pattern::Wildcard(_)))));
+        assert!(matches!(parse_source("match t { (0, [x...]) => x }").unwrap(), Expression::Match(Match { arms, .. }) if matches!(arms[0].pattern, Pattern::Tuple(_))));
+
+        let input = "let [x, (1, y)] = xs";
+        match parse_program_source(input) {
+            Err(ParseError::RefutablePattern(error)) => assert_eq!(&input[error.span.start..error.span.end], "1"),
+            result => panic!("Expected a refutable pattern, got {:?}", result)
+        }
+        assert!(matches!(parse_program_source("let (E.A, x) = t"), Err(ParseError::RefutablePattern(_))));
+        let input = "let (x, [y, x...]) = t";
+        match parse_program_source(input) {
+            Err(ParseError::DuplicateBinding(error)) => {
+                assert_eq!(error.name, "x");
+                assert_eq!(error.span.character, 13);
+            }
+            result => panic!("Expected a duplicate binding, got {:?}", result)
+        }
+        assert!(matches!(parse_source("match t { (x, x) => 0 }"), Err(ParseError::DuplicateBinding(_))));
+        assert!(parse_program_source("let (_, _) = t").is_ok());
+
+        assert!(matches!(parse_program_source("let [a..., b] = xs"), Err(ParseError::UnexpectedToken(error)) if error.expected == "']' after the rest of the array"));
+        assert!(matches!(parse_program_source("let [(a, b)...] = xs"), Err(ParseError::UnexpectedToken(error)) if error.expected == "']' after array elements"));
+        assert!(matches!(parse_program_source("let (a b) = t"), Err(ParseError::UnexpectedToken(error)) if error.expected == "')' after pattern"));
+        assert!(matches!(parse_program_source("let (a, b c) = t"), Err(ParseError::UnexpectedToken(error)) if error.expected == "')' after tuple elements"));
+        assert!(matches!(parse_program_source("let (a, b) t"), Err(ParseError::UnexpectedToken(error)) if error.expected == "'=' after the pattern"));
+        assert!(matches!(parse_program_source("let (a, b) ="), Err(ParseError::UnexpectedEof(error)) if error.expected == "an initializer after '='"));
+        assert_eq!(
+            parse_program_source("let (a, a) = t").unwrap_err().to_string(),
+            "'a' is bound more than once in the same pattern at line 1, character 9"
+        );
+        assert_eq!(
+            parse_program_source("let (a, 2) = t").unwrap_err().to_string(),
+            "Pattern in 'let' can fail to match at line 1, character 9"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pipeline() -> Result<(), String> {
+        let input = "xs |> map(double) |> sum";
+        match parse_source(input).unwrap() {
+            Expression::Call(Call { callee, arguments, span, .. }) => {
+                assert_eq!(&input[span.start..span.end], input);
+                assert!(matches!(*callee, Expression::Variable(Variable { ref name, .. }) if name == "sum"));
+                match &arguments[..] {
+                    [Expression::Call(Call { callee, arguments, span, .. })] => {
+                        assert_eq!(&input[span.start..span.end], "xs |> map(double)");
+                        assert!(matches!(**callee, Expression::Call(_)));
+                        assert!(matches!(&arguments[..], [Expression::Variable(_)]));
+                    }
+                    _ => panic!("Expected the first step as the only argument")
+                }
+            }
+            _ => panic!("Expected a call at the root")
+        }
+
+        assert!(matches!(parse_source("a + b |> f").unwrap(), Expression::Call(Call { arguments, .. }) if matches!(arguments[0], Expression::Binary(_))));
+        assert!(matches!(parse_source("a ?? b |> f").unwrap(), Expression::Call(Call { arguments, .. }) if matches!(arguments[0], Expression::Coalesce(_))));
+        assert!(matches!(parse_source("x |> f ? 1 : 2").unwrap(), Expression::Conditional(Conditional { condition, .. }) if matches!(*condition, Expression::Call(_))));
+        assert!(matches!(parse_source("y = x |> f").unwrap(), Expression::Assign(Assign { value, .. }) if matches!(*value, Expression::Call(_))));
+        assert!(matches!(parse_source("x |> fn(a) { a }").unwrap(), Expression::Call(Call { callee, .. }) if matches!(*callee, Expression::Lambda(_))));
+        assert_eq!(parse_program_source("x\n|> f").err().map(|error| error.to_string()), Some("Expected an expression but found '|>' at line 2, character 1".to_string()));
+        assert!(matches!(parse_source("(x\n  |> f)").unwrap(), Expression::Call(_)));
+        assert!(matches!(parse_source("x |>"), Err(ParseError::UnexpectedEof(error)) if error.expected == "an expression"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_spread() -> Result<(), String> {
+        let input = "[1, ...xs + ys, 2]";
+        match parse_source(input).unwrap() {
+            Expression::Array(Array { elements, .. }) => match &elements[..] {
+                [Expression::Integer(_), Expression::Spread(Spread { value, span, .. }), Expression::Integer(_)] => {
+                    assert_eq!(&input[span.start..span.end], "...xs + ys");
+                    assert!(matches!(**value, Expression::Binary(Binary { operator: BinaryOperator::Plus, .. })));
+                }
+                _ => panic!("Expected a spread between two integers")
+            }
+            _ => panic!("Expected an array at the root")
+        }
+        assert!(matches!(parse_source("f(...args, 1)").unwrap(), Expression::Call(Call { arguments, .. }) if matches!(arguments[0], Expression::Spread(_))));
+        assert!(matches!(parse_source("f(\n  ...args\n)").unwrap(), Expression::Call(Call { arguments, .. }) if arguments.len() == 1));
+
+        assert!(matches!(parse_source("...xs"), Err(ParseError::UnexpectedToken(error)) if error.expected == "an expression"));
+        assert!(matches!(parse_source("(1, ...xs)"), Err(ParseError::UnexpectedToken(error)) if error.expected == "an expression"));
+        assert!(matches!(parse_source("{ \"a\": ...xs }"), Err(ParseError::UnexpectedToken(error)) if error.expected == "an expression"));
+        assert!(matches!(parse_source("[...]"), Err(ParseError::UnexpectedToken(error)) if error.expected == "an expression"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_break_and_continue() -> Result<(), String> {
+        let input = "while true {\n  if x { break }\n  for i in 0..1 { continue; }\n}";
+        assert!(parse_program_source(input).is_ok());
+
+        for (input, keyword, character) in [("break", "break", 1), ("{ continue }", "continue", 3), ("while { break } {}", "break", 9)] {
+            match parse_program_source(input) {
+                Err(ParseError::OutsideLoop(error)) => {
+                    assert_eq!(error.keyword, keyword);
+                    assert_eq!((error.span.line, error.span.character), (1, character));
+                }
+                _ => panic!("Expected {} to be rejected", input)
+            }
+        }
+        assert_eq!(parse_program_source("1\ncontinue").unwrap_err().to_string(), "'continue' outside of a loop at line 2, character 1");
+        assert!(matches!(parse_program_source("while true { break 1 }"), Err(ParseError::UnexpectedToken(_))));
+
+        let errors = get_program_resilient(get_tokens("break\n1 +\ncontinue").unwrap()).unwrap_err();
+        assert!(matches!(errors[..], [ParseError::OutsideLoop(_), ParseError::UnexpectedToken(_)]), "{:?}", errors);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_functions() -> Result<(), String> {
+        let input = "fn add(a, b) {\n  a + b\n}\nfn answer(\n) { 42 }";
+        let statements = parse_program_source(input).unwrap().statements;
+
+        match &statements[..] {
+            [Statement::Function(add), Statement::Function(answer)] => {
+                assert_eq!(add.name, "add");
+                assert_eq!(add.parameters, vec!["a", "b"]);
+                assert!(matches!(&add.body, Expression::Block(Block { value: Some(_), .. })));
+                assert_eq!(&input[add.span.start..add.span.end], "fn add(a, b) {\n  a + b\n}");
+                assert!(answer.parameters.is_empty());
+            }
+            _ => panic!("Expected two function declarations")
+        }
+
+        for (input, expected) in [
+            ("fn 1() {}", "a function name or '(' after 'fn'"),
+            ("fn f {}", "'(' after the function name"),
+            ("fn f(a, 1) {}", "a parameter name"),
+            ("fn f(a b) {}", "')' after parameters"),
+            ("fn f(a) a", "'{' before the function body"),
+        ] {
+            match parse_program_source(input) {
+                Err(ParseError::UnexpectedToken(error)) => assert_eq!(error.expected, expected),
+                _ => panic!("Expected {} to be rejected", input)
+            }
+        }
+        assert!(matches!(parse_program_source("while true { fn f() { break } }"), Err(ParseError::OutsideLoop(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_generic_functions() -> Result<(), String> {
+        let input = "fn first<T, U>(pair: Tuple<T, U>, fallback) -> T {\n  pair.0\n}";
+        match &parse_program_source(input).unwrap().statements[..] {
+            [Statement::Function(first)] => {
+                assert_eq!(first.type_parameters, vec!["T", "U"]);
+                assert_eq!(first.parameters, vec!["pair", "fallback"]);
+                match &first.annotations[..] {
+                    [Some(TypeAnnotation { name, arguments, span }), None] => {
+                        assert_eq!(name, "Tuple");
+                        let arguments = arguments.iter().map(|argument| argument.name.as_str()).collect::<Vec<_>>();
+                        assert_eq!(arguments, ["T", "U"]);
+                        assert_eq!(&input[span.start..span.end], "Tuple<T, U>");
+                    }
+                    annotations => panic!("Expected one annotation, got {:?}", annotations),
+                }
+                assert!(matches!(&first.return_type, Some(TypeAnnotation { name, arguments, .. }) if name == "T" && arguments.is_empty()));
+                assert_eq!(&input[first.span.start..first.span.end], input);
+            }
+            _ => panic!("Expected a function declaration")
+        }
+
+        let input = "fn id(x) { x }\nfn nested(m: Map<Str, Array<Array<Int>>>) {}\nimpl P { fn get<T>(p, x: T) -> T { x } }";
+        match &parse_program_source(input).unwrap().statements[..] {
+            [Statement::Function(id), Statement::Function(nested), Statement::Impl(r#impl)] => {
+                assert!(id.type_parameters.is_empty() && id.return_type.is_none());
+                assert_eq!(id.annotations, vec![None]);
+                assert!(matches!(&nested.annotations[0], Some(map) if map.arguments[1].arguments[0].arguments[0].name == "Int"));
+                assert_eq!(r#impl.methods[0].type_parameters, vec!["T"]);
+            }
+            _ => panic!("Expected two functions and an impl")
+        }
+        assert!(parse_program_source("trait Container { fn get<T>(c, index: Int) -> T }").is_ok());
+
+        for (input, expected) in [
+            ("fn f<>() {}", "a type parameter name"),
+            ("fn f<T() {}", "'>' after type parameters"),
+            ("fn f<T>(x:) {}", "a type"),
+            ("fn f(x: Array<T) {}", "'>' after type arguments"),
+            ("fn f(x: Array<>) {}", "a type"),
+            ("fn f(x,) {}", "a parameter name"),
+            ("fn f() -> {}", "a type"),
+            ("fn f() -> T x", "'{' before the function body"),
+        ] {
+            match parse_program_source(input) {
+                Err(ParseError::UnexpectedToken(error)) => assert_eq!(error.expected, expected),
+                _ => panic!("Expected {} to be rejected", input)
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_return() -> Result<(), String> {
+        let input = "fn f(x) {\n  while true { return x + 1 }\n  return\n}";
+        match parse_program_source(input).unwrap().statements.pop() {
+            Some(Statement::Function(Function { body: Expression::Block(Block { statements, value, .. }), .. })) => {
+                match &statements[..] {
+                    [
+                        Statement::While(While { body: Expression::Block(Block { statements, .. }), .. }),
+                        Statement::Return(Return { value: None, .. }),
+                    ] => {
+                        match &statements[..] {
+                            [Statement::Return(Return { value: Some(value), span, .. })] => {
+                                assert!(matches!(value, Expression::Binary(_)));
+                                assert_eq!(&input[span.start..span.end], "return x + 1");
+                            }
+                            _ => panic!("Expected the loop to return a value")
+                        }
+                    }
+                    _ => panic!("Expected a while loop and a bare return in the body")
+                }
+                // `return` right before the `}` is a statement, not the value of the block
+                assert!(value.is_none());
+            }
+            _ => panic!("Expected a function declaration")
+        }
+        assert!(parse_program_source("fn f() { return }").is_ok());
+
+        match parse_program_source("fn f() {}\n{ return 1 }") {
+            Err(ParseError::OutsideFunction(error)) => {
+                assert_eq!((error.span.line, error.span.character), (2, 3));
+                assert_eq!(
+                    ParseError::OutsideFunction(error).to_string(),
+                    "'return' outside of a function at line 2, character 3"
+                );
+            }
+            _ => panic!("Expected a return outside of a function to be rejected")
+        }
+        assert!(matches!(parse_program_source("return"), Err(ParseError::OutsideFunction(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lambdas() -> Result<(), String> {
+        let input = "let inc = fn(x) { x + 1 }\nfn(a, b) {\n  return a\n}(1, 2)\n{ fn() {} }";
+        let statements = parse_program_source(input).unwrap().statements;
+
+        match &statements[..] {
+            [Statement::Let(declaration), Statement::Expression(second), Statement::Expression(third)] => {
+                match &declaration.initializer {
+                    Expression::Lambda(Lambda { parameters, span, .. }) => {
+                        assert_eq!(parameters, &vec!["x"]);
+                        assert_eq!(&input[span.start..span.end], "fn(x) { x + 1 }");
+                    }
+                    _ => panic!("Expected the initializer to be a lambda")
+                }
+                assert!(matches!(&second.expression, Expression::Call(Call { callee, .. }) if matches!(**callee, Expression::Lambda(_))));
+                assert!(matches!(&third.expression, Expression::Block(Block { value: Some(value), .. }) if matches!(**value, Expression::Lambda(_))));
+            }
+            _ => panic!("Expected a let and two expression statements")
+        }
+
+        assert!(matches!(parse_source("fn(x) x"), Err(ParseError::UnexpectedToken(error)) if error.expected == "'{' before the function body"));
+        assert!(matches!(parse_program_source("while true { fn() { continue } }"), Err(ParseError::OutsideLoop(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_calls() -> Result<(), String> {
+        let input = "-f(1, g(2)\n, 3)(4) ** 2";
+        match parse_source(input).unwrap() {
+            Expression::Unary(Unary { right, .. }) => match &*right {
+                Expression::Binary(Binary { left, operator: BinaryOperator::StarStar, .. }) => match &**left {
+                    Expression::Call(Call { callee, arguments, span, .. }) => {
+                        assert_eq!(&input[span.start..span.end], "f(1, g(2)\n, 3)(4)");
+                        assert_eq!(arguments.len(), 1);
+                        assert!(matches!(**callee, Expression::Call(Call { ref arguments, .. }) if arguments.len() == 3));
+                    }
+                    _ => panic!("Expected the call to be the base of the power")
+                },
+                _ => panic!("Expected a power under the negation")
+            },
+            _ => panic!("Expected a negation at the root")
+        }
+
+        assert!(matches!(parse_source("f()").unwrap(), Expression::Call(Call { arguments, .. }) if arguments.is_empty()));
+        assert_eq!(parse_program_source("f\n(1)").unwrap().statements.len(), 2);
+        assert!(matches!(parse_source("f(1,)"), Err(ParseError::UnexpectedToken(error)) if error.expected == "an expression"));
+        assert!(matches!(parse_source("f(1"), Err(ParseError::UnexpectedEof(error)) if error.expected == "')' after arguments"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_arrays() -> Result<(), String> {
+        let input = "[1, [2,\n3], []]";
+        match parse_source(input).unwrap() {
+            Expression::Array(Array { elements, span, .. }) => {
+                assert_eq!(&input[span.start..span.end], input);
+                assert_eq!(elements.len(), 3);
+                assert!(matches!(&elements[1], Expression::Array(Array { elements, .. }) if elements.len() == 2));
+                assert!(matches!(&elements[2], Expression::Array(Array { elements, .. }) if elements.is_empty()));
+            }
+            _ => panic!("Expected an array at the root")
+        }
+
+        assert_eq!(parse_program_source("let xs = [1]\n[2]").unwrap().statements.len(), 2);
+        assert!(matches!(parse_source("[1,]"), Err(ParseError::UnexpectedToken(error)) if error.expected == "an expression"));
+        assert!(matches!(parse_source("[1 2]"), Err(ParseError::UnexpectedToken(error)) if error.expected == "']' after array elements"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_match() -> Result<(), String> {
+        let input = "match x + 1 {\n  -1 => a,\n  n if n > 2 => { n },\n  _ => \"c\",\n}";
+        match parse_source(input).unwrap() {
+            Expression::Match(Match { scrutinee, arms, span, .. }) => {
+                assert_eq!(&input[span.start..span.end], input);
+                assert!(matches!(*scrutinee, Expression::Binary(_)));
+                assert_eq!(arms.len(), 3);
+                assert!(matches!(&arms[0].pattern, Pattern::Literal(Expression::Integer(Integer { value: -1, span, .. })) if span.character == 3));
+                assert!(matches!(&arms[1].pattern, Pattern::Binding(name, _) if name == "n"));
+                assert!(matches!(&arms[1].guard, Some(Expression::Binary(_))));
+                assert!(matches!(&arms[1].body, Expression::Block(_)));
+                assert!(matches!(&arms[2].pattern, Pattern::Wildcard(_)));
+                assert!(arms[2].guard.is_none());
+            }
+            _ => panic!("Expected a match at the root")
+        }
+        assert!(matches!(parse_source("match x {}").unwrap(), Expression::Match(Match { arms, .. }) if arms.is_empty()));
+        assert!(matches!(parse_source("match x { 1.5 => 1, 'a' => 2, true => 3 }").unwrap(), Expression::Match(Match { arms, .. }) if arms.len() == 3));
+        assert_eq!(parse_program_source("match x { _ => 1 }\nmatch x { _ => 2 }").unwrap().statements.len(), 2);
+
+        assert!(matches!(parse_source("match x { 1 + 1 => 2 }"), Err(ParseError::UnexpectedToken(error)) if error.expected == "'=>' after pattern"));
+        assert!(matches!(parse_source("match x { {} => 2 }"), Err(ParseError::UnexpectedToken(error)) if error.expected == "a pattern"));
+        assert!(matches!(parse_source("match x { -y => 2 }"), Err(ParseError::UnexpectedToken(error)) if error.expected == "a number after '-' in a pattern"));
+        assert!(matches!(parse_source("match x { 1 => 2 3 => 4 }"), Err(ParseError::UnexpectedToken(error)) if error.expected == "'}' after match arms"));
+        assert!(matches!(parse_source("match x 1"), Err(ParseError::UnexpectedToken(error)) if error.expected == "'{' after match value"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_structs() -> Result<(), String> {
+        let input = "struct Point {\n  x,\n  y\n}";
+        match &parse_program_source(input).unwrap().statements[..] {
+            [Statement::Struct(Struct { name, fields, span, .. })] => {
+                assert_eq!(&input[span.start..span.end], input);
+                assert_eq!(name, "Point");
+                assert_eq!(fields, &["x", "y"]);
+            }
+            _ => panic!("Expected a single struct declaration")
+        }
+
+        let input = "Point { x: 1,\n  y: a.b }.x";
+        match parse_source(input).unwrap() {
+            Expression::Field(Field { target, field, span, .. }) => {
+                assert_eq!(&input[span.start..span.end], input);
+                assert_eq!(field, "x");
+                match *target {
+                    Expression::StructLiteral(StructLiteral { name, fields, .. }) => {
+                        assert_eq!(name, "Point");
+                        assert!(matches!(&fields[..], [(x, Expression::Integer(_)), (y, Expression::Field(_))] if x == "x" && y == "y"));
+                    }
+                    _ => panic!("Expected a struct literal before the field")
+                }
+            }
+            _ => panic!("Expected a field at the root")
+        }
+        assert!(matches!(parse_source("p.pair.0").unwrap(), Expression::TupleIndex(TupleIndex { tuple, .. }) if matches!(*tuple, Expression::Field(_))));
+        assert!(matches!(parse_source("if x { y }").unwrap(), Expression::If(_)));
+        assert!(parse_source("while x { y: 1 }").is_err());
+        assert_eq!(parse_program_source("p\n{ x: 1 }").unwrap().statements.len(), 2);
+
+        assert!(matches!(parse_program_source("struct Point {}"), Err(ParseError::UnexpectedToken(error)) if error.expected == "a field name"));
+        assert!(matches!(parse_program_source("struct { x }"), Err(ParseError::UnexpectedToken(error)) if error.expected == "a struct name"));
+        assert!(matches!(parse_program_source("struct P x"), Err(ParseError::UnexpectedToken(error)) if error.expected == "'{' after the struct name"));
+        assert!(matches!(parse_program_source("struct P { x y }"), Err(ParseError::UnexpectedToken(error)) if error.expected == "'}' after fields"));
+        assert!(matches!(parse_source("P { x: 1, y }"), Err(ParseError::UnexpectedToken(error)) if error.expected == "':' after field name"));
+        assert!(matches!(parse_source("P { x: 1 y: 2 }"), Err(ParseError::UnexpectedToken(error)) if error.expected == "'}' after struct fields"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_enums() -> Result<(), String> {
+        let input = "enum Shape {\n  Circle(r),\n  Rect(w, h),\n  Empty\n}";
+        match &parse_program_source(input).unwrap().statements[..] {
+            [Statement::Enum(Enum { name, variants, span, .. })] => {
+                assert_eq!(&input[span.start..span.end], input);
+                assert_eq!(name, "Shape");
+                let variants = variants.iter().map(|variant| (variant.name.as_str(), variant.fields.len())).collect::<Vec<_>>();
+                assert_eq!(variants, [("Circle", 1), ("Rect", 2), ("Empty", 0)]);
+            }
+            _ => panic!("Expected a single enum declaration")
+        }
+
+        let input = "match s { Shape.Rect(w, Option.Some(1)) => w, Shape.Empty => 0 }";
+        match parse_source(input).unwrap() {
+            Expression::Match(Match { arms, .. }) => match &arms[0].pattern {
+                Pattern::Variant(VariantPattern { enum_name, variant, fields, span }) => {
+                    assert_eq!(&input[span.start..span.end], "Shape.Rect(w, Option.Some(1))");
+                    assert_eq!((enum_name.as_str(), variant.as_str()), ("Shape", "Rect"));
+                    assert!(matches!(&fields[..], [Pattern::Binding(..), Pattern::Variant(inner)] if inner.fields.len() == 1));
+                    assert!(matches!(&arms[1].pattern, Pattern::Variant(VariantPattern { fields, .. }) if fields.is_empty()));
+                }
+                _ => panic!("Expected a variant pattern")
+            }
+            _ => panic!("Expected a match at the root")
+        }
+        assert!(matches!(parse_source("Shape.Circle(1)").unwrap(), Expression::Call(Call { callee, .. }) if matches!(*callee, Expression::Field(_))));
+
+        assert!(matches!(parse_program_source("enum E {}"), Err(ParseError::UnexpectedToken(error)) if error.expected == "a variant name"));
+        assert!(matches!(parse_program_source("enum { A }"), Err(ParseError::UnexpectedToken(error)) if error.expected == "an enum name"));
+        assert!(matches!(parse_program_source("enum E A"), Err(ParseError::UnexpectedToken(error)) if error.expected == "'{' after the enum name"));
+        assert!(matches!(parse_program_source("enum E { A() }"), Err(ParseError::UnexpectedToken(error)) if error.expected == "a field name"));
+        assert!(matches!(parse_program_source("enum E { A(x y) }"), Err(ParseError::UnexpectedToken(error)) if error.expected == "')' after variant fields"));
+        assert!(matches!(parse_program_source("enum E { A B }"), Err(ParseError::UnexpectedToken(error)) if error.expected == "'}' after variants"));
+        assert!(matches!(parse_source("match s { E.1 => 0 }"), Err(ParseError::UnexpectedToken(error)) if error.expected == "a variant name after '.'"));
+        assert!(matches!(parse_source("match s { E.A(x => 0 }"), Err(ParseError::UnexpectedToken(error)) if error.expected == "')' after variant fields"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_impl() -> Result<(), String> {
+        let input = "impl Point {\n  fn __add__(a, b) { a }\n  fn norm(p) {\n    return p.x\n  }\n}\nimpl Point {}";
+        match &parse_program_source(input).unwrap().statements[..] {
+            [Statement::Impl(Impl { name, methods, span, .. }), Statement::Impl(empty)] => {
+                assert_eq!(&input[span.start..span.end], &input[..input.find("\nimpl Point {}").unwrap()]);
+                assert_eq!(name, "Point");
+                let methods = methods.iter().map(|method| (method.name.as_str(), method.parameters.len())).collect::<Vec<_>>();
+                assert_eq!(methods, [("__add__", 2), ("norm", 1)]);
+                assert!(empty.methods.is_empty());
+            }
+            _ => panic!("Expected two impls")
+        }
+        assert!(parse_program_source("impl P { fn f(p) { 1 } fn g(p) { 2 } }").is_ok());
+
+        assert!(matches!(parse_program_source("impl { fn f(p) {} }"), Err(ParseError::UnexpectedToken(error)) if error.expected == "a type name after 'impl'"));
+        assert!(matches!(parse_program_source("impl P fn f(p) {}"), Err(ParseError::UnexpectedToken(error)) if error.expected == "'{' after the type name"));
+        assert!(matches!(parse_program_source("impl P { let x = 1 }"), Err(ParseError::UnexpectedToken(error)) if error.expected == "'}' after methods"));
+        assert!(matches!(parse_program_source("impl P { fn (p) {} }"), Err(ParseError::UnexpectedToken(error)) if error.expected == "a method name"));
+        assert!(matches!(parse_program_source("impl P { fn f {} }"), Err(ParseError::UnexpectedToken(error)) if error.expected == "'(' after the method name"));
+        assert!(matches!(parse_program_source("impl P { fn f() {} }"), Err(ParseError::UnexpectedToken(error)) if error.expected == "a parameter for the receiver"));
+        assert!(matches!(parse_program_source("impl P { fn f(p) { break } }"), Err(ParseError::OutsideLoop(_))));
+        assert!(parse_program_source("impl P { fn f(p) { return 1 } }").is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_traits() -> Result<(), String> {
+        let input = "trait Shape {\n  fn area(s)\n  fn scale(s, k)\n}\nimpl Shape for Circle { fn area(c) { c.r } }";
+        match &parse_program_source(input).unwrap().statements[..] {
+            [Statement::Trait(Trait { name, methods, span, .. }), Statement::Impl(r#impl)] => {
+                assert_eq!(&input[span.start..span.end], &input[..input.find("\nimpl").unwrap()]);
+                assert_eq!(name, "Shape");
+                let methods = methods.iter().map(|method| (method.name.as_str(), method.parameters.len())).collect::<Vec<_>>();
+                assert_eq!(methods, [("area", 1), ("scale", 2)]);
+                assert_eq!(r#impl.r#trait.as_deref(), Some("Shape"));
+                assert_eq!(r#impl.name, "Circle");
+                assert_eq!(r#impl.methods.len(), 1);
+            }
+            _ => panic!("Expected a trait and an impl")
+        }
+        assert!(parse_program_source("trait T {}").is_ok());
+        assert!(matches!(parse_program_source("impl P { fn f(p) {} }").unwrap().statements[..], [Statement::Impl(Impl { r#trait: None, .. })]));
+
+        assert!(matches!(parse_program_source("trait { fn f(p) }"), Err(ParseError::UnexpectedToken(error)) if error.expected == "a trait name"));
+        assert!(matches!(parse_program_source("trait T fn f(p)"), Err(ParseError::UnexpectedToken(error)) if error.expected == "'{' after the trait name"));
+        assert!(matches!(parse_program_source("trait T { fn f(p) { 1 } }"), Err(ParseError::UnexpectedToken(error)) if error.expected == "'}' after method signatures"));
+        assert!(matches!(parse_program_source("trait T { fn f() }"), Err(ParseError::UnexpectedToken(error)) if error.expected == "a parameter for the receiver"));
+        assert!(matches!(parse_program_source("impl T for { fn f(p) {} }"), Err(ParseError::UnexpectedToken(error)) if error.expected == "a type name after 'for'"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_doc_comments() -> Result<(), String> {
+        let input = "/// Adds\n/// two numbers\nfn add(a, b) { a + b }\n/// A point\nstruct P { x }\n\
+            impl P {\n  /// Its x\n  fn x(p) { p.x }\n}\ntrait T {\n  /// Needed\n  fn f(t)\n}\n/// Stray\nlet x = 1";
+        match &parse_program_source(input).unwrap().statements[..] {
+            [Statement::Function(add), Statement::Struct(r#struct), Statement::Impl(r#impl), Statement::Trait(r#trait), _] => {
+                assert_eq!(add.doc.as_deref(), Some("Adds\ntwo numbers"));
+                assert_eq!(r#struct.doc.as_deref(), Some("A point"));
+                assert_eq!(r#impl.methods[0].doc.as_deref(), Some("Its x"));
+                assert_eq!(r#trait.doc, None);
+                assert_eq!(r#trait.methods[0].doc.as_deref(), Some("Needed"));
+            }
+            _ => panic!("Expected a function, a struct, an impl, a trait and a let")
+        }
+        // Undocumented declarations have no doc, and a doc comment before anything else is ignored
+        match &parse_program_source("fn f() {}\nlet y = 2 /// trailing\n").unwrap().statements[..] {
+            [Statement::Function(f), _] => assert_eq!(f.doc, None),
+            _ => panic!("Expected a function and a let")
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_macros() -> Result<(), String> {
+        let input = "macro swap(a, b) {\n  let t = a; a = b; b = t\n}";
+        match &parse_program_source(input).unwrap().statements[..] {
+            [Statement::Macro(Macro { name, parameters, body, span, .. })] => {
+                assert_eq!(name, "swap");
+                assert_eq!(parameters, &["a", "b"]);
+                assert!(matches!(body, Expression::Block(Block { statements, .. }) if statements.len() == 2));
+                assert_eq!(&input[span.start..span.end], input);
+            }
+            _ => panic!("Expected a macro")
+        }
+        assert!(parse_program_source("macro nothing() {}").is_ok());
+
+        assert!(matches!(parse_program_source("macro (a) {}"), Err(ParseError::UnexpectedToken(error)) if error.expected == "a macro name"));
+        assert!(matches!(parse_program_source("macro m {}"), Err(ParseError::UnexpectedToken(error)) if error.expected == "'(' after the macro name"));
+        assert!(matches!(parse_program_source("macro m(a) a"), Err(ParseError::UnexpectedToken(error)) if error.expected == "'{' before the macro body"));
+        assert!(matches!(parse_program_source("macro m(a) { return a }"), Err(ParseError::OutsideFunction(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_imports() -> Result<(), String> {
+        let input = "import math\nimport \"lib/strings.rat\"; import \"../_util2\"";
+        match &parse_program_source(input).unwrap().statements[..] {
+            [Statement::Import(math), Statement::Import(strings), Statement::Import(util)] => {
+                assert_eq!((math.path.as_str(), math.name.as_str()), ("math.rat", "math"));
+                assert_eq!(&input[math.span.start..math.span.end], "import math");
+                assert_eq!((strings.path.as_str(), strings.name.as_str()), ("lib/strings.rat", "strings"));
+                assert_eq!(&input[strings.span.start..strings.span.end], "import \"lib/strings.rat\"");
+                assert_eq!(util.name, "_util2");
+            }
+            _ => panic!("Expected three imports")
+        }
+
+        assert!(matches!(parse_program_source("import"), Err(ParseError::UnexpectedEof(error)) if error.expected == "a module name or path after 'import'"));
+        for (input, expected) in [
+            ("import 1", "a module name or path after 'import'"),
+            ("import \"my-lib.rat\"", "a path to a file named like a variable"),
+            ("import \"lib/while.rat\"", "a path to a file named like a variable"),
+            ("import \"\"", "a path to a file named like a variable"),
+            ("import math utils", "';' or a line break after statement"),
+        ] {
+            match parse_program_source(input) {
+                Err(ParseError::UnexpectedToken(error)) => assert_eq!(error.expected, expected),
+                _ => panic!("Expected {} to be rejected", input)
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_conditionals() -> Result<(), String> {
+        let input = "a == 1 ? x = 2 : b ? 3 : 4";
+        match parse_source(input).unwrap() {
+            Expression::Conditional(Conditional { condition, then_branch, else_branch, span, .. }) => {
+                assert_eq!(&input[span.start..span.end], input);
+                assert!(matches!(*condition, Expression::Binary(Binary { operator: BinaryOperator::EqualEqual, .. })));
+                assert!(matches!(*then_branch, Expression::Assign(_)));
+                assert!(matches!(*else_branch, Expression::Conditional(_)));
+            }
+            _ => panic!("Expected a conditional at the root")
+        }
+
+        assert!(matches!(parse_source("x = a ? 1 : 2").unwrap(), Expression::Assign(Assign { value, .. }) if matches!(*value, Expression::Conditional(_))));
+        assert!(matches!(parse_source("{ a ? b : c }").unwrap(), Expression::Block(_)));
+        assert_eq!(parse_program_source("a ? b\n  : c").unwrap().statements.len(), 1);
+        assert_eq!(parse_program_source("a ? b\n+ 1 : c").unwrap().statements.len(), 1);
+        assert!(parse_program_source("a\n? b : c").is_err());
+
+        assert!(matches!(parse_source("a ? b"), Err(ParseError::UnexpectedEof(error)) if error.expected == "':' after the first branch of '?'"));
+        assert!(matches!(parse_source("a ? b : c = 1"), Err(ParseError::InvalidAssignmentTarget(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_coalesce() -> Result<(), String> {
+        let input = "a ?? nil ?? b == 1";
+        match parse_source(input).unwrap() {
+            Expression::Coalesce(Coalesce { value, fallback, span, .. }) => {
+                assert_eq!(&input[span.start..span.end], input);
+                assert!(matches!(*value, Expression::Variable(_)));
+                match *fallback {
+                    Expression::Coalesce(Coalesce { value, fallback, .. }) => {
+                        assert!(matches!(*value, Expression::Nil(_)));
+                        assert!(matches!(*fallback, Expression::Binary(Binary { operator: BinaryOperator::EqualEqual, .. })));
+                    }
+                    _ => panic!("Expected the fallback to be another coalesce")
+                }
+            }
+            _ => panic!("Expected a coalesce at the root")
+        }
+
+        assert!(matches!(parse_source("a ?? b ? c : d").unwrap(), Expression::Conditional(Conditional { condition, .. }) if matches!(*condition, Expression::Coalesce(_))));
+        assert!(matches!(parse_source("x = a ?? 1").unwrap(), Expression::Assign(Assign { value, .. }) if matches!(*value, Expression::Coalesce(_))));
+        assert!(matches!(parse_source("match x { nil => 1 }").unwrap(), Expression::Match(Match { arms, .. }) if matches!(&arms[0].pattern, Pattern::Literal(Expression::Nil(_)))));
+        assert_eq!(parse_program_source("a ??\nb").unwrap().statements.len(), 1);
+        assert!(parse_program_source("a\n?? b").is_err());
+        assert!(matches!(parse_source("a ??"), Err(ParseError::UnexpectedEof(error)) if error.expected == "an expression"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_try() -> Result<(), String> {
+        let input = "try { 1 / x }\ncatch (error) { 0 }";
+        match parse_source(input).unwrap() {
+            Expression::Try(Try { body, name, handler, span, .. }) => {
+                assert_eq!(&input[span.start..span.end], input);
+                assert!(matches!(*body, Expression::Block(Block { value: Some(_), .. })));
+                assert_eq!(name, "error");
+                assert!(matches!(*handler, Expression::Block(_)));
+            }
+            _ => panic!("Expected a try at the root")
+        }
+        assert!(matches!(parse_source("try { 1 }"), Err(ParseError::UnexpectedEof(error)) if error.expected == "'catch' after try block"));
+        assert!(matches!(parse_source("try { 1 } catch e { 2 }"), Err(ParseError::UnexpectedToken(error)) if error.expected == "'(' after 'catch'"));
+        assert!(matches!(parse_source("try 1 catch (e) { 2 }"), Err(ParseError::UnexpectedToken(error)) if error.expected == "'{' after 'try'"));
+        assert_eq!(parse_program_source("let x = try { f() } catch (e) { nil }\nx").unwrap().statements.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tuples() -> Result<(), String> {
+        let input = "(1, \"two\",\n3.0)";
+        match parse_source(input).unwrap() {
+            Expression::Tuple(Tuple { elements, span, .. }) => {
+                assert_eq!(&input[span.start..span.end], input);
+                assert!(matches!(elements.as_slice(), [Expression::Integer(_), Expression::Str(_), Expression::Float(_)]));
+            }
+            _ => panic!("Expected a tuple at the root")
+        }
+        assert!(matches!(parse_source("(1)").unwrap(), Expression::Integer(_)));
+        assert!(matches!(parse_source("(1,)").unwrap(), Expression::Tuple(Tuple { elements, .. }) if elements.len() == 1));
+        assert!(matches!(parse_source("(1, 2,)").unwrap(), Expression::Tuple(Tuple { elements, .. }) if elements.len() == 2));
+        assert!(matches!(parse_source("()").unwrap(), Expression::Tuple(Tuple { elements, .. }) if elements.is_empty()));
+
+        let input = "pair.0.1";
+        match parse_source(input).unwrap() {
+            Expression::TupleIndex(TupleIndex { tuple, position: 1, span, .. }) => {
+                assert_eq!(&input[span.start..span.end], input);
+                assert!(matches!(*tuple, Expression::TupleIndex(TupleIndex { position: 0, .. })));
+            }
+            _ => panic!("Expected a tuple position at the root")
+        }
+        assert!(matches!(parse_source("f().1(2)").unwrap(), Expression::Call(Call { callee, .. }) if matches!(*callee, Expression::TupleIndex(_))));
+
+        assert!(matches!(parse_source("(1, 2 3)"), Err(ParseError::UnexpectedToken(error)) if error.expected == "')' after tuple elements"));
+        assert!(matches!(parse_source("(1 2)"), Err(ParseError::UnexpectedToken(error)) if error.expected == "')' after expression"));
+        assert!(matches!(parse_source("(,)"), Err(ParseError::UnexpectedToken(error)) if error.expected == "an expression"));
+        assert!(matches!(parse_source("t.[0]"), Err(ParseError::UnexpectedToken(error)) if error.expected == "a field name or tuple position after '.'"));
+        assert!(matches!(parse_source("t.0 = 1"), Err(ParseError::InvalidAssignmentTarget(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_maps() -> Result<(), String> {
+        let input = "{ \"name\": \"rat\",\n  1 + 1: {:}, key: [] }";
+        match parse_source(input).unwrap() {
+            Expression::Map(Map { entries, span, .. }) => {
+                assert_eq!(&input[span.start..span.end], input);
+                assert_eq!(entries.len(), 3);
+                assert!(matches!(&entries[0], (Expression::Str(key), Expression::Str(_)) if key.value == "name"));
+                assert!(matches!(&entries[1], (Expression::Binary(_), Expression::Map(Map { entries, .. })) if entries.is_empty()));
+                assert!(matches!(&entries[2], (Expression::Variable(_), Expression::Array(_))));
+            }
+            _ => panic!("Expected a map at the root")
+        }
+
+        assert!(matches!(parse_source("{}").unwrap(), Expression::Block(_)));
+        assert!(matches!(parse_source("{ x }").unwrap(), Expression::Block(Block { value: Some(_), .. })));
+        assert!(matches!(parse_source("{ x\n-1 }").unwrap(), Expression::Block(Block { statements, .. }) if statements.len() == 1));
+        assert!(matches!(parse_source("{ let x = 1; x }").unwrap(), Expression::Block(_)));
+        assert!(matches!(parse_source("{ x: 1 }[\"x\"]").unwrap(), Expression::Index(_)));
+
+        assert!(matches!(parse_source("{ 1: 2, 3 }"), Err(ParseError::UnexpectedToken(error)) if error.expected == "':' after map key"));
+        assert!(matches!(parse_source("{ 1: 2; 3: 4 }"), Err(ParseError::UnexpectedToken(error)) if error.expected == "'}' after map entries"));
+        assert!(matches!(parse_source("{ 1: 2, }"), Err(ParseError::UnexpectedToken(error)) if error.expected == "an expression"));
+        assert!(matches!(parse_source("{:1}"), Err(ParseError::UnexpectedToken(error)) if error.expected == "'}' after ':' in an empty map"));
+        assert!(matches!(parse_source("{ x; 1: 2 }"), Err(ParseError::UnexpectedToken(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_indexing() -> Result<(), String> {
+        let input = "xs[i + 1][0]";
+        match parse_source(input).unwrap() {
+            Expression::Index(Index { target, index, span, .. }) => {
+                assert_eq!(&input[span.start..span.end], input);
+                assert!(matches!(*index, Expression::Integer(Integer { value: 0, .. })));
+                assert!(matches!(*target, Expression::Index(Index { ref index, .. }) if matches!(**index, Expression::Binary(_))));
+            }
+            _ => panic!("Expected an index at the root")
+        }
+        assert!(matches!(parse_source("f(1)[2](3)").unwrap(), Expression::Call(Call { callee, .. }) if matches!(*callee, Expression::Index(_))));
+        assert!(matches!(parse_source("-xs[0]").unwrap(), Expression::Unary(Unary { right, .. }) if matches!(*right, Expression::Index(_))));
+        assert_eq!(parse_program_source("xs\n[0]").unwrap().statements.len(), 2);
+        assert!(matches!(parse_source("xs[]"), Err(ParseError::UnexpectedToken(error)) if error.expected == "an expression"));
+        assert!(matches!(parse_source("xs[0"), Err(ParseError::UnexpectedEof(error)) if error.expected == "']' after index"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_assignment() -> Result<(), String> {
+        let input = "xs[0][i] += 1";
+        match parse_source(input).unwrap() {
+            Expression::IndexAssign(IndexAssign { target, index, operator, value, span, .. }) => {
+                assert_eq!(&input[span.start..span.end], input);
+                assert!(matches!(*target, Expression::Index(_)));
+                assert!(matches!(*index, Expression::Variable(Variable { ref name, .. }) if name == "i"));
+                assert!(matches!(operator, Some(BinaryOperator::Plus)));
+                assert!(matches!(*value, Expression::Integer(Integer { value: 1, .. })));
+            }
+            _ => panic!("Expected an index assignment at the root")
+        }
+        assert!(matches!(parse_source("xs[0] = 1").unwrap(), Expression::IndexAssign(IndexAssign { operator: None, .. })));
+        assert!(matches!(parse_source("f()[0] = 1"), Err(ParseError::InvalidAssignmentTarget(_))));
+        assert!(matches!(parse_source("[1][0] = 1"), Err(ParseError::InvalidAssignmentTarget(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unterminated_statement() -> Result<(), String> {
+        match parse_program_source("1 + 2 3") {
+            Err(ParseError::UnexpectedToken(error)) => {
+                assert_eq!(error.expected, "';' or a line break after statement");
+                assert_eq!((error.line, error.character), (1, 7));
+            }
+            _ => panic!("Expected the second expression to be rejected")
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_program_resilient() -> Result<(), String> {
+        let errors = get_program_resilient(get_tokens("1 +; 2\n* 3 4; 5 )\n6").unwrap()).unwrap_err();
+        let positions = errors.iter()
+            .map(|error| match error {
+                ParseError::UnexpectedToken(error) => (error.line, error.character),
+                _ => panic!("Expected only unexpected tokens")
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(positions, vec![(1, 4), (2, 1), (2, 10)]);
+
+        let program = get_program_resilient(get_tokens("1; 2").unwrap()).unwrap();
+        assert_eq!(program.statements.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unexpected_token() -> Result<(), String> {
+        match parse_source("1 + * 2") {
+            Err(ParseError::UnexpectedToken(error)) => {
+                assert_eq!(error.expected, "an expression");
+                assert_eq!(error.lexeme.as_str(), "*");
+                assert_eq!((error.line, error.character), (1, 5));
+            }
+            _ => panic!("Expected an unexpected token error")
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unexpected_eof() -> Result<(), String> {
+        match parse_source("(1 +\n 2") {
+            Err(ParseError::UnexpectedEof(error)) => {
+                assert_eq!(error.expected, "')' after expression");
+                assert_eq!((error.line, error.character), (2, 3));
+            }
+            _ => panic!("Expected an unexpected end of input error")
+        }
+
+        assert!(matches!(parse_source("2 -"), Err(ParseError::UnexpectedEof(_))));
+        assert!(matches!(parse_source("\"${1 2}\""), Err(ParseError::UnexpectedToken(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_input() -> Result<(), String> {
+        for input in ["", "   ", "\n\t\r\n", "// just a comment\n"] {
+            assert!(matches!(parse_source(input), Err(ParseError::EmptyInput)), "{:?} was not empty", input);
+        }
+        assert!(matches!(get_ast(Vec::new()), Err(ParseError::EmptyInput)));
+        assert!(matches!(
+            get_ast_resilient(get_tokens(" ").unwrap()).unwrap_err()[..],
+            [ParseError::EmptyInput]
+        ));
+        assert_eq!(parse_source("").unwrap_err().to_string(), "Expected an expression but the input is empty");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_trailing_tokens() -> Result<(), String> {
+        for (input, lexeme, character) in [("2+3 4", "4", 5), ("1 2 3", "2", 3), ("(1) )", ")", 5)] {
+            match parse_source(input) {
+                Err(ParseError::TrailingToken(error)) => {
+                    assert_eq!(error.lexeme.as_str(), lexeme);
+                    assert_eq!((error.line, error.character), (1, character));
+                }
+                _ => panic!("Expected a trailing token error for {}", input)
+            }
+        }
+
+        assert_eq!(
+            parse_source("2+3 4").unwrap_err().to_string(),
+            "Unexpected token '4' after expression at line 1, character 5"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resilient_trailing_tokens() -> Result<(), String> {
+        let errors = get_ast_resilient(get_tokens("1 2 3 + * 4").unwrap()).unwrap_err();
+
+        match &errors[..] {
+            [ParseError::TrailingToken(first), ParseError::UnexpectedToken(second)] => {
+                assert_eq!(first.lexeme.as_str(), "2");
+                assert_eq!(second.lexeme.as_str(), "*");
+            }
+            _ => panic!("Expected two parse errors but got {:?}", errors)
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resilient_reports_every_error() -> Result<(), String> {
+        let errors = get_ast_resilient(get_tokens("1 + * 2 - ) 3 * (4 +").unwrap()).unwrap_err();
+
+        match &errors[..] {
+            [
+                ParseError::UnexpectedToken(first),
+                ParseError::UnexpectedToken(second),
+                ParseError::UnexpectedEof(third),
+            ] => {
+                assert_eq!((first.lexeme.as_str(), first.character), ("*", 5));
+                assert_eq!((second.lexeme.as_str(), second.character), (")", 11));
+                assert_eq!(third.character, 21);
+            }
+            _ => panic!("Expected three parse errors but got {:?}", errors)
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resilient_skips_cascading_errors() -> Result<(), String> {
+        let errors = get_ast_resilient(get_tokens("1 + * / ) 2").unwrap()).unwrap_err();
+        assert_eq!(errors.len(), 1);
+
+        let errors = get_ast_resilient(get_tokens("(1 + ) * (2 + )").unwrap()).unwrap_err();
+        assert_eq!(errors.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resilient_without_errors() -> Result<(), String> {
+        let result = get_ast_resilient(get_tokens("1 + 2").unwrap());
+        assert!(matches!(result, Ok(Expression::Binary(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_messages() -> Result<(), String> {
+        assert_eq!(
+            parse_source("(1 2)").unwrap_err().to_string(),
+            "Expected ')' after expression but found '2' at line 1, character 4"
+        );
+        assert_eq!(
+            parse_source("3 *").unwrap_err().to_string(),
+            "Expected an expression but reached the end of input at line 1, character 4"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// A small generator of pseudorandom numbers (splitmix64), which is all
+// `random` and `random_int` need. It isn't fit for anything to do with
+// security. Two made with the same seed give the same numbers.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    // Seeded from the clock, so it's different each time
+    pub fn from_time() -> Rng {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_nanos());
+        Rng::new(nanos as u64 ^ (nanos >> 64) as u64)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // At least 0 and less than 1, from the top 53 bits so that every float
+    // it can give is as likely as any other
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1_u64 << 53) as f64
+    }
+
+    // At least `low` and less than `high`, which has to be bigger. Numbers
+    // from the end that would make some results more likely than others are
+    // thrown away.
+    pub fn next_in(&mut self, low: i64, high: i64) -> i64 {
+        let width = (i128::from(high) - i128::from(low)) as u64;
+        let zone = u64::MAX - u64::MAX.wrapping_sub(width - 1) % width;
+        loop {
+            let value = self.next_u64();
+            if value <= zone {
+                return (i128::from(low) + i128::from(value % width)) as i64;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeded() -> Result<(), String> {
+        let (mut first, mut second, mut other) = (Rng::new(42), Rng::new(42), Rng::new(43));
+        let numbers = (0..10).map(|_| first.next_u64()).collect::<Vec<_>>();
+        assert_eq!(numbers, (0..10).map(|_| second.next_u64()).collect::<Vec<_>>());
+        assert_ne!(numbers, (0..10).map(|_| other.next_u64()).collect::<Vec<_>>());
+
+        let mut rng = Rng::new(7);
+        assert!((0..1000).map(|_| rng.next_f64()).all(|value| (0.0..1.0).contains(&value)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ranges() -> Result<(), String> {
+        let mut rng = Rng::new(1);
+        let values = (0..1000).map(|_| rng.next_in(-2, 3)).collect::<Vec<_>>();
+        assert!(values.iter().all(|value| (-2..3).contains(value)));
+        assert!((-2..3).all(|value| values.contains(&value)));
+        assert_eq!(rng.next_in(5, 6), 5);
+        assert!((i64::MIN..i64::MAX).contains(&rng.next_in(i64::MIN, i64::MAX)));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+
+// A fraction in its lowest terms, with the sign on the numerator. Dividing
+// one int by another that doesn't go into it exactly makes one of these. The
+// arithmetic is done with i128 so that nothing overflows along the way, and
+// only gives nothing when the result itself doesn't fit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rational {
+    numerator: i64,
+    denominator: i64,
+}
+
+impl Rational {
+    // Nothing if the denominator is zero or the fraction doesn't fit in i64s
+    // once it's reduced
+    pub fn new(numerator: i128, denominator: i128) -> Option<Rational> {
+        if denominator == 0 {
+            return None;
+        }
+        let divisor = gcd(numerator.unsigned_abs(), denominator.unsigned_abs()) as i128;
+        let sign = denominator.signum();
+        Some(Rational {
+            numerator: i64::try_from(numerator / divisor * sign).ok()?,
+            denominator: i64::try_from(denominator / divisor * sign).ok()?,
+        })
+    }
+
+    pub fn numerator(&self) -> i64 {
+        self.numerator
+    }
+
+    pub fn denominator(&self) -> i64 {
+        self.denominator
+    }
+
+    // The int it's equal to, if it's a whole number
+    pub fn to_integer(&self) -> Option<i64> {
+        (self.denominator == 1).then_some(self.numerator)
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.numerator as f64 / self.denominator as f64
+    }
+
+    // The whole part, rounding toward zero
+    pub fn truncate(&self) -> i64 {
+        self.numerator / self.denominator
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.numerator == 0
+    }
+
+    fn parts(&self) -> (i128, i128) {
+        (self.numerator.into(), self.denominator.into())
+    }
+
+    pub fn checked_add(&self, other: &Rational) -> Option<Rational> {
+        let ((a, b), (c, d)) = (self.parts(), other.parts());
+        Rational::new(a * d + c * b, b * d)
+    }
+
+    pub fn checked_sub(&self, other: &Rational) -> Option<Rational> {
+        let ((a, b), (c, d)) = (self.parts(), other.parts());
+        Rational::new(a * d - c * b, b * d)
+    }
+
+    pub fn checked_mul(&self, other: &Rational) -> Option<Rational> {
+        let ((a, b), (c, d)) = (self.parts(), other.parts());
+        Rational::new(a * c, b * d)
+    }
+
+    // Nothing when `other` is zero, too
+    pub fn checked_div(&self, other: &Rational) -> Option<Rational> {
+        let ((a, b), (c, d)) = (self.parts(), other.parts());
+        Rational::new(a * d, b * c)
+    }
+
+    // What's left over once `other` has been taken away a whole number of
+    // times, rounding toward zero the same as `%` on ints does
+    pub fn checked_rem(&self, other: &Rational) -> Option<Rational> {
+        let ((a, b), (c, d)) = (self.parts(), other.parts());
+        if c == 0 {
+            return None;
+        }
+        Rational::new((a * d) % (c * b), b * d)
+    }
+
+    // A negative exponent raises the reciprocal instead
+    pub fn checked_pow(&self, exponent: i64) -> Option<Rational> {
+        let base = if exponent < 0 { Rational::from(1).checked_div(self)? } else { *self };
+        let (mut result, mut base, mut exponent) = (Rational::from(1), base, exponent.unsigned_abs());
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = result.checked_mul(&base)?;
+            }
+            exponent >>= 1;
+            if exponent > 0 {
+                base = base.checked_mul(&base)?;
+            }
+        }
+        Some(result)
+    }
+}
+
+impl From<i64> for Rational {
+    fn from(value: i64) -> Rational {
+        Rational { numerator: value, denominator: 1 }
+    }
+}
+
+impl Ord for Rational {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let ((a, b), (c, d)) = (self.parts(), other.parts());
+        (a * d).cmp(&(c * b))
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Display for Rational {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.denominator {
+            1 => write!(f, "{}", self.numerator),
+            denominator => write!(f, "{}/{}", self.numerator, denominator),
+        }
+    }
+}
+
+fn gcd(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a.max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rational(numerator: i128, denominator: i128) -> Rational {
+        Rational::new(numerator, denominator).unwrap()
+    }
+
+    #[test]
+    fn test_normalization() -> Result<(), String> {
+        assert_eq!(rational(2, 4), rational(1, 2));
+        assert_eq!(rational(3, -6).to_string(), "-1/2");
+        assert_eq!(rational(-3, -6).to_string(), "1/2");
+        assert_eq!(rational(0, -5), Rational::from(0));
+        assert_eq!(rational(6, 3).to_integer(), Some(2));
+        assert_eq!(rational(1, 3).to_integer(), None);
+        assert_eq!(rational(i64::MIN.into(), 2).to_string(), "-4611686018427387904");
+        assert_eq!(Rational::new(1, 0), None);
+        assert_eq!(Rational::new(i64::MIN.into(), -1), None);
+        assert_eq!(Rational::new(1, i128::from(i64::MAX) + 1), None);
+        assert_eq!(rational(-7, 2).truncate(), -3);
+        assert_eq!(rational(7, 2).to_f64(), 3.5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_arithmetic() -> Result<(), String> {
+        let (third, sixth) = (rational(1, 3), rational(1, 6));
+        assert_eq!(third.checked_add(&sixth), Some(rational(1, 2)));
+        assert_eq!(third.checked_sub(&sixth), Some(sixth));
+        assert_eq!(sixth.checked_sub(&third), Some(rational(-1, 6)));
+        assert_eq!(third.checked_mul(&rational(3, 1)), Some(Rational::from(1)));
+        assert_eq!(third.checked_div(&sixth), Some(Rational::from(2)));
+        assert_eq!(third.checked_div(&Rational::from(0)), None);
+        assert_eq!(rational(7, 2).checked_rem(&Rational::from(2)), Some(rational(3, 2)));
+        assert_eq!(rational(-7, 2).checked_rem(&Rational::from(2)), Some(rational(-3, 2)));
+        assert_eq!(rational(7, 2).checked_rem(&Rational::from(0)), None);
+        assert_eq!(rational(2, 3).checked_pow(3), Some(rational(8, 27)));
+        assert_eq!(rational(2, 3).checked_pow(-2), Some(rational(9, 4)));
+        assert_eq!(rational(2, 3).checked_pow(0), Some(Rational::from(1)));
+        assert_eq!(Rational::from(0).checked_pow(-1), None);
+        assert_eq!(rational(1, 3).checked_pow(100), None);
+
+        let large = rational(i64::MAX.into(), 2);
+        assert_eq!(large.checked_add(&large), Some(Rational::from(i64::MAX)));
+        assert_eq!(large.checked_mul(&Rational::from(4)), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ordering() -> Result<(), String> {
+        assert!(rational(1, 3) < rational(1, 2));
+        assert!(rational(-1, 2) < rational(-1, 3));
+        assert!(rational(7, 2) > Rational::from(3));
+        assert_eq!(rational(2, 4).cmp(&rational(1, 2)), Ordering::Equal);
+        assert!(rational(i64::MAX.into(), i64::MAX as i128 - 1) > Rational::from(1));
+
+        Ok(())
+    }
+}
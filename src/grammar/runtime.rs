@@ -0,0 +1,496 @@
+use std::cell::{Ref, RefCell, RefMut};
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::rc::Rc;
+use crate::grammar::bigint::BigInt;
+use crate::grammar::evaluate::Callable;
+use crate::grammar::heap::Contents;
+use crate::grammar::rational::Rational;
+
+// What the evaluator works with: every expression evaluates to one of these.
+// Values are compared structurally, except for functions, which are only
+// equal to copies of themselves. Arrays, maps and struct instances are
+// references to storage that every copy of them shares.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    // An int that doesn't fit in an i64, which is only ever made when
+    // `OverflowMode::Promote` is on. Its type is still `Type::Int`.
+    BigInt(BigInt),
+    // What dividing one int by another gives when it doesn't go in exactly.
+    // A whole number is always an int instead.
+    Rational(Rational),
+    Float(f64),
+    Char(char),
+    Str(String),
+    Bool(bool),
+    Function(Callable),
+    Array(Shared<Vec<Value>>),
+    Map(Shared<HashMap<Key, Value>>),
+    Tuple(Vec<Value>),
+    Range(RangeValue),
+    // What the name of a struct evaluates to
+    StructType(StructType),
+    Struct(Shared<Instance>),
+    // What the name of an enum evaluates to
+    EnumType(EnumType),
+    Enum(EnumValue),
+    // What an `import` binds its name to
+    Module(Module),
+    // What `nil` and a block with no trailing expression evaluate to
+    Nil,
+}
+
+// What sort of value something is, which is what its methods are looked up by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Type {
+    Int,
+    Rational,
+    Float,
+    Char,
+    Str,
+    Bool,
+    Function,
+    Array,
+    Map,
+    Tuple,
+    Range,
+    StructType,
+    Struct,
+    EnumType,
+    Enum,
+    Module,
+    Nil,
+}
+
+impl Value {
+    pub fn array(elements: Vec<Value>) -> Value {
+        Value::Array(Shared::new(elements))
+    }
+
+    pub fn map(entries: HashMap<Key, Value>) -> Value {
+        Value::Map(Shared::new(entries))
+    }
+
+    pub fn instance(instance: Instance) -> Value {
+        Value::Struct(Shared::new(instance))
+    }
+
+    pub fn type_of(&self) -> Type {
+        match self {
+            Value::Int(_) | Value::BigInt(_) => Type::Int,
+            Value::Rational(_) => Type::Rational,
+            Value::Float(_) => Type::Float,
+            Value::Char(_) => Type::Char,
+            Value::Str(_) => Type::Str,
+            Value::Bool(_) => Type::Bool,
+            Value::Function(_) => Type::Function,
+            Value::Array(_) => Type::Array,
+            Value::Map(_) => Type::Map,
+            Value::Tuple(_) => Type::Tuple,
+            Value::Range(_) => Type::Range,
+            Value::StructType(_) => Type::StructType,
+            Value::Struct(_) => Type::Struct,
+            Value::EnumType(_) => Type::EnumType,
+            Value::Enum(_) => Type::Enum,
+            Value::Module(_) => Type::Module,
+            Value::Nil => Type::Nil,
+        }
+    }
+}
+
+// How a type is named in error messages
+impl Display for Type {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Type::Int => "int",
+            Type::Rational => "rational",
+            Type::Float => "float",
+            Type::Char => "char",
+            Type::Str => "str",
+            Type::Bool => "bool",
+            Type::Function => "function",
+            Type::Array => "array",
+            Type::Map => "map",
+            Type::Tuple => "tuple",
+            Type::Range => "range",
+            Type::StructType => "struct type",
+            Type::Struct => "struct",
+            Type::EnumType => "enum type",
+            Type::Enum => "enum",
+            Type::Module => "module",
+            Type::Nil => "nil",
+        })
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{}", value),
+            Value::BigInt(value) => write!(f, "{}", value),
+            Value::Rational(value) => write!(f, "{}", value),
+            // Debug formatting keeps the `.0` on whole numbers so floats
+            // don't print like ints
+            Value::Float(value) => write!(f, "{:?}", value),
+            Value::Char(value) => write!(f, "{}", value),
+            Value::Str(value) => f.write_str(value),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Function(function) => Display::fmt(function, f),
+            // Something inside itself is only printed the first time round
+            Value::Array(elements) => {
+                let elements = elements.enter(|elements| elements.iter().map(Value::to_string).collect::<Vec<_>>());
+                match elements {
+                    Some(elements) => write!(f, "[{}]", elements.join(", ")),
+                    None => f.write_str("[...]"),
+                }
+            }
+            Value::Tuple(elements) => {
+                let elements = elements.iter().map(Value::to_string).collect::<Vec<_>>();
+                match elements.as_slice() {
+                    [element] => write!(f, "({},)", element),
+                    _ => write!(f, "({})", elements.join(", ")),
+                }
+            }
+            // Sorted by key, since the order a hash map iterates in would
+            // change from one run to the next
+            Value::Map(entries) => {
+                let entries = entries.enter(|entries| {
+                    let mut entries = entries.iter().collect::<Vec<_>>();
+                    entries.sort_by_key(|(key, _)| *key);
+                    entries.iter().map(|(key, value)| format!("{}: {}", key, value)).collect::<Vec<_>>()
+                });
+                match entries {
+                    Some(entries) => write!(f, "{{{}}}", entries.join(", ")),
+                    None => f.write_str("{...}"),
+                }
+            }
+            Value::Range(range) if range.inclusive => write!(f, "{}..={}", range.start, range.end),
+            Value::Range(range) => write!(f, "{}..{}", range.start, range.end),
+            Value::StructType(r#type) => write!(f, "<struct {}>", r#type.name),
+            Value::Struct(instance) => {
+                let fields = instance.enter(|instance| {
+                    instance.fields.iter().map(|(name, value)| format!("{}: {}", name, value)).collect::<Vec<_>>()
+                });
+                match fields {
+                    Some(fields) => write!(f, "{} {{ {} }}", instance.borrow().name, fields.join(", ")),
+                    None => write!(f, "{} {{ ... }}", instance.borrow().name),
+                }
+            }
+            Value::EnumType(r#type) => write!(f, "<enum {}>", r#type.name),
+            Value::Enum(value) if value.values.is_empty() => write!(f, "{}.{}", value.enum_name, value.variant),
+            Value::Enum(value) => {
+                let values = value.values.iter().map(Value::to_string).collect::<Vec<_>>();
+                write!(f, "{}.{}({})", value.enum_name, value.variant, values.join(", "))
+            }
+            Value::Module(module) => write!(f, "<module {}>", module.name),
+            Value::Nil => f.write_str("nil"),
+        }
+    }
+}
+
+// The storage of an array, map or struct instance. Copying one of those
+// values only copies the reference, so changing it through one copy changes
+// it for all of them.
+//
+// A value can end up inside itself, by being assigned to one of its own
+// elements. Comparing and printing keep track of the storage they are in the
+// middle of, so that they stop when they come back round to it rather than
+// going on forever. Storage in a cycle like that keeps itself alive, and is
+// only freed by the evaluator's `Heap` once nothing else can reach it.
+pub struct Shared<T: Contents>(Rc<RefCell<T>>);
+
+thread_local! {
+    // The storage being printed, and the pairs being compared, by address
+    static ENTERED: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+    static COMPARED: RefCell<Vec<(usize, usize)>> = const { RefCell::new(Vec::new()) };
+}
+
+impl<T: Contents> Shared<T> {
+    pub fn new(value: T) -> Shared<T> {
+        Shared(Rc::new(RefCell::new(value)))
+    }
+
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.0.borrow()
+    }
+
+    pub fn borrow_mut(&self) -> RefMut<'_, T> {
+        self.0.borrow_mut()
+    }
+
+    pub fn ptr_eq(&self, other: &Shared<T>) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    // What tells one storage apart from another, which is the same as the
+    // address `Heap` sees
+    pub fn address(&self) -> usize {
+        Rc::as_ptr(&self.0) as *const () as usize
+    }
+
+    pub fn inner(&self) -> &Rc<RefCell<T>> {
+        &self.0
+    }
+
+    // Runs `run` on what's stored, unless this is already being run on it
+    // further up, in which case nothing is run
+    pub fn enter<R>(&self, run: impl FnOnce(&T) -> R) -> Option<R> {
+        let address = self.address();
+        if ENTERED.with(|entered| entered.borrow().contains(&address)) {
+            return None;
+        }
+        ENTERED.with(|entered| entered.borrow_mut().push(address));
+        let result = run(&self.borrow());
+        ENTERED.with(|entered| entered.borrow_mut().pop());
+        Some(result)
+    }
+
+    // Moves what's stored out into `pending` if this is the last reference
+    // to it, since it's about to be freed
+    fn release(&self, pending: &mut Vec<Value>) {
+        if Rc::strong_count(&self.0) == 1 {
+            if let Ok(mut contents) = self.0.try_borrow_mut() {
+                contents.drain(pending);
+            }
+        }
+    }
+}
+
+// Freeing storage frees what's in it, which for storage nested thousands deep
+// would go as deep on the stack. Whatever is freed along with it is moved out
+// first and freed here one at a time instead, each emptied before it goes.
+impl<T: Contents> Drop for Shared<T> {
+    fn drop(&mut self) {
+        let mut pending = Vec::new();
+        self.release(&mut pending);
+        while let Some(mut value) = pending.pop() {
+            match &mut value {
+                Value::Array(elements) => elements.release(&mut pending),
+                Value::Map(entries) => entries.release(&mut pending),
+                Value::Struct(instance) => instance.release(&mut pending),
+                Value::Tuple(values) => pending.append(values),
+                Value::Enum(value) => pending.append(&mut value.values),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl<T: Contents> Clone for Shared<T> {
+    fn clone(&self) -> Shared<T> {
+        Shared(Rc::clone(&self.0))
+    }
+}
+
+// Storage is equal to itself, and two that come back round to being compared
+// with each other are equal as long as nothing on the way there differed
+impl<T: Contents + PartialEq> PartialEq for Shared<T> {
+    fn eq(&self, other: &Shared<T>) -> bool {
+        let pair = (self.address(), other.address());
+        if self.ptr_eq(other) || COMPARED.with(|compared| compared.borrow().contains(&pair)) {
+            return true;
+        }
+        COMPARED.with(|compared| compared.borrow_mut().push(pair));
+        let equal = *self.borrow() == *other.borrow();
+        COMPARED.with(|compared| compared.borrow_mut().pop());
+        equal
+    }
+}
+
+impl<T: Contents + Debug> Debug for Shared<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.enter(|value| Debug::fmt(value, f)).unwrap_or_else(|| f.write_str("..."))
+    }
+}
+
+// The ints a range counts through. Two ranges are only equal if they are
+// written the same way, so `0..2` isn't equal to `0..=1`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RangeValue {
+    pub start: i64,
+    pub end: i64,
+    pub inclusive: bool,
+}
+
+impl RangeValue {
+    // One past the last value, which for an inclusive range up to the largest
+    // int is more than an int can hold
+    pub fn end_exclusive(&self) -> i128 {
+        i128::from(self.end) + i128::from(self.inclusive)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = i64> {
+        (i128::from(self.start)..self.end_exclusive()).map(|value| value as i64)
+    }
+
+    pub fn contains(&self, value: i64) -> bool {
+        self.start <= value && i128::from(value) < self.end_exclusive()
+    }
+
+    // A range that ends before it starts is empty rather than counting down.
+    // One too long to count in a usize is as long as a usize can count.
+    pub fn len(&self) -> usize {
+        usize::try_from((self.end_exclusive() - i128::from(self.start)).max(0)).unwrap_or(usize::MAX)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// What a `struct` declaration defines, with the fields in the order they
+// were declared in
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructType {
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+// What an `enum` declaration defines. Each variant is what `Enum.Variant`
+// evaluates to, which is the value itself for a variant without fields and a
+// function making one for a variant with them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumType {
+    pub name: String,
+    pub variants: Vec<(String, Value)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumValue {
+    pub enum_name: String,
+    pub variant: String,
+    pub values: Vec<Value>,
+}
+
+// A value of a struct, holding every one of its fields in declaration order.
+// Two instances are equal when their names and fields all are.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instance {
+    pub name: String,
+    pub fields: Vec<(String, Value)>,
+}
+
+// Everything a module defined at its top level, sorted by name. Builtins
+// aren't part of it unless the module defined its own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    pub name: String,
+    pub members: Vec<(String, Value)>,
+}
+
+// The values that can be used to look up an entry of a map
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Key {
+    Int(i64),
+    Str(String),
+}
+
+impl Display for Key {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Key::Int(key) => write!(f, "{}", key),
+            Key::Str(key) => f.write_str(key),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::evaluate::{evaluate_source, execute};
+    use crate::grammar::lexer::get_tokens;
+    use crate::grammar::parser::get_program;
+
+    #[test]
+    fn test_values() -> Result<(), String> {
+        assert_eq!(evaluate_source("2147483647 + 1").unwrap(), Value::Int(2_147_483_648));
+        assert_eq!(evaluate_source("1.5 * 2.0").unwrap(), Value::Float(3.0));
+        assert_eq!(evaluate_source("'a' == 'a'").unwrap(), Value::Bool(true));
+        assert_eq!(evaluate_source("\"ab\"").unwrap(), Value::Str("ab".to_string()));
+        assert_eq!(evaluate_source("nil").unwrap(), Value::Nil);
+
+        let values = [Value::Int(1), Value::Float(2.0), Value::Char('c'), Value::Str("s".to_string()), Value::Bool(false)];
+        let types = values.iter().map(|value| value.type_of().to_string()).collect::<Vec<_>>();
+        assert_eq!(types, ["int", "float", "char", "str", "bool"]);
+        let printed = values.iter().map(Value::to_string).collect::<Vec<_>>();
+        assert_eq!(printed, ["1", "2.0", "c", "s", "false"]);
+
+        let nested = Value::array(vec![Value::Tuple(vec![Value::Int(1)]), Value::Tuple(vec![]), Value::Nil]);
+        assert_eq!(nested.to_string(), "[(1,), (), nil]");
+        let map = Value::map(HashMap::from([(Key::Str("b".to_string()), Value::Int(2)), (Key::Int(1), Value::Int(1))]));
+        assert_eq!(map.to_string(), "{1: 1, b: 2}");
+
+        // Values of different types are never the same, even when they print the same
+        let error = evaluate_source("1 + true").unwrap_err();
+        assert_eq!(error.message(), "Can't apply '+' to int and bool");
+        assert_ne!(Value::Int(1), Value::Float(1.0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_shared_storage() -> Result<(), String> {
+        let array = Value::array(vec![Value::Int(1)]);
+        let copy = array.clone();
+        if let Value::Array(elements) = &copy {
+            elements.borrow_mut().push(Value::Int(2));
+        }
+        assert_eq!(array.to_string(), "[1, 2]");
+        assert_eq!(array, Value::array(vec![Value::Int(1), Value::Int(2)]));
+
+        // Storage inside itself prints and compares without going round forever
+        let other = Value::array(vec![Value::Int(1), Value::Int(2)]);
+        for value in [&array, &other] {
+            if let Value::Array(elements) = value {
+                elements.borrow_mut().push(value.clone());
+            }
+        }
+        assert_eq!(array.to_string(), "[1, 2, [...]]");
+        assert_eq!(array, other);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deep_nesting() -> Result<(), String> {
+        // Freed without going a level deeper on the stack for each level
+        let mut value = Value::Nil;
+        for depth in 0..300_000 {
+            let key = Key::Int(depth);
+            value = match depth % 3 {
+                0 => Value::array(vec![value, Value::Tuple(vec![])]),
+                1 => Value::map(HashMap::from([(key, Value::Tuple(vec![value]))])),
+                _ => Value::instance(Instance { name: "Node".to_string(), fields: vec![("next".to_string(), value)] }),
+            };
+        }
+        drop(value);
+
+        // Storage still used elsewhere is left as it is
+        let inner = Value::array(vec![Value::Int(1)]);
+        drop(Value::array(vec![inner.clone(), inner.clone()]));
+        assert_eq!(inner.to_string(), "[1]");
+
+        let program = "let a = nil\nfor i in 0..100000 { a = [a] }\nlen(a)";
+        assert_eq!(execute(&get_program(get_tokens(program).unwrap()).unwrap()).unwrap(), Some(Value::Int(1)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ranges() -> Result<(), String> {
+        let range = RangeValue { start: 1, end: 4, inclusive: false };
+        assert_eq!(range.values().collect::<Vec<_>>(), [1, 2, 3]);
+        assert!(range.contains(3) && !range.contains(4));
+        assert_eq!(Value::Range(range).to_string(), "1..4");
+
+        let inclusive = RangeValue { start: i64::MAX - 1, end: i64::MAX, inclusive: true };
+        assert_eq!(inclusive.len(), 2);
+        assert!(inclusive.contains(i64::MAX));
+        assert_eq!(Value::Range(inclusive).to_string(), format!("{}..={}", i64::MAX - 1, i64::MAX));
+        assert!(RangeValue { start: 3, end: 1, inclusive: true }.is_empty());
+        assert_ne!(Value::Range(range), Value::Range(RangeValue { end: 3, inclusive: true, ..range }));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,663 @@
+use crate::grammar::parser::{
+    Array, Assign, Binary, Block, BinaryOperator, Bool, Break, Call, Char, Coalesce, Conditional, Continue, Destructure,
+    Enum, Expression, ExpressionStatement, Field, Float, For, Function, If, Impl, Import, Index, IndexAssign, Integer,
+    Interpolation, Lambda, Let, Macro, Map, Match, MatchArm, Nil, Pattern, Program, Range, Return, Spread, Str, Struct,
+    StructLiteral, Trait, Try, Tuple, TupleIndex, TypeAnnotation, Unary, Variable, While,
+};
+use crate::grammar::visit::{StatementVisitor, Visitor};
+
+// How tightly each kind of expression binds, following the order of the
+// parser's productions from `assignment` up to `primary`
+const ASSIGNMENT: u8 = 0;
+const CONDITIONAL: u8 = 1;
+const COALESCE: u8 = 2;
+const EQUALITY: u8 = 3;
+const COMPARISON: u8 = 4;
+const RANGE: u8 = 5;
+const BIT_OR: u8 = 6;
+const BIT_XOR: u8 = 7;
+const BIT_AND: u8 = 8;
+const TERM: u8 = 9;
+const FACTOR: u8 = 10;
+const UNARY: u8 = 11;
+const POWER: u8 = 12;
+const PRIMARY: u8 = 13;
+
+fn precedence(expression: &Expression) -> u8 {
+    match expression {
+        Expression::Binary(binary) => binary_precedence(&binary.operator),
+        Expression::Unary(_) => UNARY,
+        Expression::Assign(_) | Expression::IndexAssign(_) => ASSIGNMENT,
+        Expression::Conditional(_) => CONDITIONAL,
+        Expression::Coalesce(_) => COALESCE,
+        Expression::Range(_) => RANGE,
+        // Only folding can produce these, and they print with a leading `-`
+        Expression::Integer(integer) if integer.value < 0 => UNARY,
+        Expression::Float(float) if float.value.is_sign_negative() => UNARY,
+        _ => PRIMARY,
+    }
+}
+
+// How tightly the operands on each side of the operator have to bind to do
+// without parentheses. `**` groups to the right and takes a unary expression
+// on its right, everything else groups to the left.
+fn operand_precedences(operator: &BinaryOperator) -> (u8, u8) {
+    match operator {
+        BinaryOperator::StarStar => (PRIMARY, UNARY),
+        operator => (binary_precedence(operator), binary_precedence(operator) + 1),
+    }
+}
+
+fn binary_precedence(operator: &BinaryOperator) -> u8 {
+    match operator {
+        BinaryOperator::EqualEqual | BinaryOperator::BangEqual => EQUALITY,
+        BinaryOperator::Less
+        | BinaryOperator::LessEqual
+        | BinaryOperator::Greater
+        | BinaryOperator::GreaterEqual
+        | BinaryOperator::In => COMPARISON,
+        BinaryOperator::Pipe => BIT_OR,
+        BinaryOperator::Caret => BIT_XOR,
+        BinaryOperator::Ampersand => BIT_AND,
+        BinaryOperator::Plus | BinaryOperator::Minus => TERM,
+        BinaryOperator::Star | BinaryOperator::Slash | BinaryOperator::Percent => FACTOR,
+        BinaryOperator::StarStar => POWER,
+    }
+}
+
+struct Unparser;
+
+impl Unparser {
+    // Renders `expression`, wrapped in parentheses if it binds more loosely
+    // than the position it is in allows
+    fn operand(&mut self, expression: &Expression, minimum: u8) -> String {
+        let source = self.visit_expression(expression);
+        if precedence(expression) < minimum {
+            format!("({})", source)
+        } else {
+            source
+        }
+    }
+}
+
+impl Unparser {
+    fn pattern(&mut self, pattern: &Pattern) -> String {
+        match pattern {
+            Pattern::Wildcard(_) => "_".to_string(),
+            Pattern::Binding(name, _) => name.clone(),
+            Pattern::Literal(literal) => self.visit_expression(literal),
+            Pattern::Variant(variant) if variant.fields.is_empty() => format!("{}.{}", variant.enum_name, variant.variant),
+            Pattern::Variant(variant) => {
+                let fields = variant.fields.iter().map(|field| self.pattern(field)).collect::<Vec<_>>();
+                format!("{}.{}({})", variant.enum_name, variant.variant, fields.join(", "))
+            }
+            Pattern::Tuple(tuple) if tuple.elements.len() == 1 => format!("({},)", self.pattern(&tuple.elements[0])),
+            Pattern::Tuple(tuple) => {
+                let elements = tuple.elements.iter().map(|element| self.pattern(element)).collect::<Vec<_>>();
+                format!("({})", elements.join(", "))
+            }
+            Pattern::Array(array) => {
+                let mut elements = array.elements.iter().map(|element| self.pattern(element)).collect::<Vec<_>>();
+                if let Some(rest) = &array.rest {
+                    elements.push(format!("{}...", self.pattern(rest)));
+                }
+                format!("[{}]", elements.join(", "))
+            }
+        }
+    }
+
+    fn match_arm(&mut self, arm: &MatchArm) -> String {
+        let guard = match &arm.guard {
+            Some(guard) => format!(" if {}", self.visit_expression(guard)),
+            None => String::new(),
+        };
+        format!("{}{} => {}", self.pattern(&arm.pattern), guard, self.visit_expression(&arm.body))
+    }
+}
+
+impl Visitor<String> for Unparser {
+    // Goes down the left side of a chain of operators that print without
+    // parentheses in a loop, so that a long chain doesn't recurse once per
+    // operator
+    fn visit_binary(&mut self, binary: &Binary) -> String {
+        let mut chain = vec![binary];
+        loop {
+            let last = chain[chain.len() - 1];
+            match &*last.left {
+                Expression::Binary(left) if binary_precedence(&left.operator) >= operand_precedences(&last.operator).0 => {
+                    chain.push(left)
+                }
+                _ => break,
+            }
+        }
+
+        let innermost = chain[chain.len() - 1];
+        let mut source = self.operand(&innermost.left, operand_precedences(&innermost.operator).0);
+        for binary in chain.into_iter().rev() {
+            let right = self.operand(&binary.right, operand_precedences(&binary.operator).1);
+            source.push_str(&format!(" {} {}", binary.operator, right));
+        }
+        source
+    }
+
+    fn visit_unary(&mut self, unary: &Unary) -> String {
+        format!("{}{}", unary.operator, self.operand(&unary.right, UNARY))
+    }
+
+    fn visit_integer(&mut self, integer: &Integer) -> String {
+        integer.value.to_string()
+    }
+
+    fn visit_float(&mut self, float: &Float) -> String {
+        format!("{:?}", float.value)
+    }
+
+    fn visit_char(&mut self, char: &Char) -> String {
+        let mut source = String::from("'");
+        escape_into(&mut source, char.value, '\'', None);
+        source.push('\'');
+        source
+    }
+
+    fn visit_str(&mut self, str: &Str) -> String {
+        let mut source = String::from("\"");
+        escape_text_into(&mut source, &str.value);
+        source.push('"');
+        source
+    }
+
+    fn visit_bool(&mut self, bool: &Bool) -> String {
+        bool.value.to_string()
+    }
+
+    fn visit_nil(&mut self, _: &Nil) -> String {
+        String::from("nil")
+    }
+
+    fn visit_interpolation(&mut self, interpolation: &Interpolation) -> String {
+        let mut source = String::from("\"");
+        for part in &interpolation.parts {
+            match part {
+                Expression::Str(str) => escape_text_into(&mut source, &str.value),
+                _ => {
+                    source.push_str("${");
+                    source.push_str(&self.visit_expression(part));
+                    source.push('}');
+                }
+            }
+        }
+        source.push('"');
+        source
+    }
+
+    fn visit_variable(&mut self, variable: &Variable) -> String {
+        variable.name.clone()
+    }
+
+    fn visit_assign(&mut self, assign: &Assign) -> String {
+        format!("{} = {}", assign.name, self.operand(&assign.value, ASSIGNMENT))
+    }
+
+    // Blocks go on one line, with a `;` after every statement so that only
+    // the value is left without one
+    fn visit_block(&mut self, block: &Block) -> String {
+        let mut parts = block.statements.iter()
+            .map(|statement| self.visit_statement(statement) + ";")
+            .collect::<Vec<_>>();
+        if let Some(value) = &block.value {
+            parts.push(self.visit_expression(value));
+        }
+
+        if parts.is_empty() {
+            String::from("{}")
+        } else {
+            format!("{{ {} }}", parts.join(" "))
+        }
+    }
+
+    fn visit_if(&mut self, r#if: &If) -> String {
+        let mut source = format!("if {} {}", self.visit_expression(&r#if.condition), self.visit_expression(&r#if.then_branch));
+        if let Some(branch) = &r#if.else_branch {
+            source.push_str(" else ");
+            source.push_str(&self.visit_expression(branch));
+        }
+        source
+    }
+
+    fn visit_range(&mut self, range: &Range) -> String {
+        let operator = if range.inclusive { "..=" } else { ".." };
+        format!("{}{}{}", self.operand(&range.start, RANGE + 1), operator, self.operand(&range.end, RANGE + 1))
+    }
+
+    fn visit_call(&mut self, call: &Call) -> String {
+        let arguments = call.arguments.iter()
+            .map(|argument| self.visit_expression(argument))
+            .collect::<Vec<_>>();
+        format!("{}({})", self.operand(&call.callee, PRIMARY), arguments.join(", "))
+    }
+
+    fn visit_lambda(&mut self, lambda: &Lambda) -> String {
+        format!("fn({}) {}", lambda.parameters.join(", "), self.visit_expression(&lambda.body))
+    }
+
+    fn visit_map(&mut self, map: &Map) -> String {
+        if map.entries.is_empty() {
+            return "{:}".to_string();
+        }
+        let entries = map.entries.iter()
+            .map(|(key, value)| format!("{}: {}", self.visit_expression(key), self.visit_expression(value)))
+            .collect::<Vec<_>>();
+        format!("{{ {} }}", entries.join(", "))
+    }
+
+    // The middle branch is enclosed by the `?` and `:`, so it never needs
+    // parentheses
+    fn visit_conditional(&mut self, conditional: &Conditional) -> String {
+        format!(
+            "{} ? {} : {}",
+            self.operand(&conditional.condition, CONDITIONAL + 1),
+            self.visit_expression(&conditional.then_branch),
+            self.operand(&conditional.else_branch, CONDITIONAL)
+        )
+    }
+
+    fn visit_coalesce(&mut self, coalesce: &Coalesce) -> String {
+        format!("{} ?? {}", self.operand(&coalesce.value, COALESCE + 1), self.operand(&coalesce.fallback, COALESCE))
+    }
+
+    fn visit_match(&mut self, r#match: &Match) -> String {
+        let arms = r#match.arms.iter().map(|arm| self.match_arm(arm)).collect::<Vec<_>>();
+        match arms.as_slice() {
+            [] => format!("match {} {{}}", self.visit_expression(&r#match.scrutinee)),
+            _ => format!("match {} {{ {} }}", self.visit_expression(&r#match.scrutinee), arms.join(", ")),
+        }
+    }
+
+    fn visit_try(&mut self, r#try: &Try) -> String {
+        format!(
+            "try {} catch ({}) {}",
+            self.visit_expression(&r#try.body),
+            r#try.name,
+            self.visit_expression(&r#try.handler)
+        )
+    }
+
+    fn visit_tuple(&mut self, tuple: &Tuple) -> String {
+        let elements = tuple.elements.iter()
+            .map(|element| self.visit_expression(element))
+            .collect::<Vec<_>>();
+        match elements.as_slice() {
+            [element] => format!("({},)", element),
+            _ => format!("({})", elements.join(", ")),
+        }
+    }
+
+    // An integer right before the `.` would lex as a float along with the
+    // position
+    fn visit_tuple_index(&mut self, index: &TupleIndex) -> String {
+        match &*index.tuple {
+            Expression::Integer(integer) => format!("({}).{}", integer.value, index.position),
+            tuple => format!("{}.{}", self.operand(tuple, PRIMARY), index.position),
+        }
+    }
+
+    fn visit_index(&mut self, index: &Index) -> String {
+        format!("{}[{}]", self.operand(&index.target, PRIMARY), self.visit_expression(&index.index))
+    }
+
+    fn visit_index_assign(&mut self, assign: &IndexAssign) -> String {
+        let operator = assign.operator.as_ref().map(BinaryOperator::to_string).unwrap_or_default();
+        format!(
+            "{}[{}] {}= {}",
+            self.visit_expression(&assign.target),
+            self.visit_expression(&assign.index),
+            operator,
+            self.operand(&assign.value, ASSIGNMENT)
+        )
+    }
+
+    fn visit_array(&mut self, array: &Array) -> String {
+        let elements = array.elements.iter()
+            .map(|element| self.visit_expression(element))
+            .collect::<Vec<_>>();
+        format!("[{}]", elements.join(", "))
+    }
+
+    fn visit_struct_literal(&mut self, literal: &StructLiteral) -> String {
+        let fields = literal.fields.iter()
+            .map(|(name, value)| format!("{}: {}", name, self.visit_expression(value)))
+            .collect::<Vec<_>>();
+        format!("{} {{ {} }}", literal.name, fields.join(", "))
+    }
+
+    fn visit_field(&mut self, field: &Field) -> String {
+        format!("{}.{}", self.operand(&field.target, PRIMARY), field.field)
+    }
+
+    fn visit_spread(&mut self, spread: &Spread) -> String {
+        format!("...{}", self.visit_expression(&spread.value))
+    }
+}
+
+impl StatementVisitor<String> for Unparser {
+    fn visit_expression_statement(&mut self, statement: &ExpressionStatement) -> String {
+        self.visit_expression(&statement.expression)
+    }
+
+    fn visit_let(&mut self, declaration: &Let) -> String {
+        format!("let {} = {}", declaration.name, self.visit_expression(&declaration.initializer))
+    }
+
+    fn visit_destructure(&mut self, declaration: &Destructure) -> String {
+        format!("let {} = {}", self.pattern(&declaration.pattern), self.visit_expression(&declaration.initializer))
+    }
+
+    fn visit_while(&mut self, r#while: &While) -> String {
+        format!("while {} {}", self.visit_expression(&r#while.condition), self.visit_expression(&r#while.body))
+    }
+
+    fn visit_for(&mut self, r#for: &For) -> String {
+        format!("for {} in {} {}", r#for.variable, self.visit_expression(&r#for.iterable), self.visit_expression(&r#for.body))
+    }
+
+    fn visit_break(&mut self, _: &Break) -> String {
+        String::from("break")
+    }
+
+    fn visit_continue(&mut self, _: &Continue) -> String {
+        String::from("continue")
+    }
+
+    fn visit_function(&mut self, function: &Function) -> String {
+        let signature = unparse_signature(
+            &function.name,
+            &function.type_parameters,
+            &function.parameters,
+            &function.annotations,
+            &function.return_type,
+        );
+        format!("fn {} {}", signature, self.visit_expression(&function.body))
+    }
+
+    fn visit_return(&mut self, r#return: &Return) -> String {
+        match &r#return.value {
+            Some(value) => format!("return {}", self.visit_expression(value)),
+            None => String::from("return"),
+        }
+    }
+
+    fn visit_struct(&mut self, r#struct: &Struct) -> String {
+        format!("struct {} {{ {} }}", r#struct.name, r#struct.fields.join(", "))
+    }
+
+    fn visit_enum(&mut self, r#enum: &Enum) -> String {
+        let variants = r#enum.variants.iter()
+            .map(|variant| match variant.fields.as_slice() {
+                [] => variant.name.clone(),
+                fields => format!("{}({})", variant.name, fields.join(", ")),
+            })
+            .collect::<Vec<_>>();
+        format!("enum {} {{ {} }}", r#enum.name, variants.join(", "))
+    }
+
+    fn visit_impl(&mut self, r#impl: &Impl) -> String {
+        let methods = r#impl.methods.iter().map(|method| self.visit_function(method)).collect::<Vec<_>>();
+        let header = match &r#impl.r#trait {
+            Some(r#trait) => format!("impl {} for {}", r#trait, r#impl.name),
+            None => format!("impl {}", r#impl.name),
+        };
+        match methods.as_slice() {
+            [] => format!("{} {{}}", header),
+            _ => format!("{} {{ {} }}", header, methods.join(" ")),
+        }
+    }
+
+    // A module given by name came from `import name`, so it's written back
+    // that way
+    fn visit_import(&mut self, import: &Import) -> String {
+        if import.path == format!("{}.rat", import.name) {
+            return format!("import {}", import.name);
+        }
+        let mut source = String::from("import \"");
+        escape_text_into(&mut source, &import.path);
+        source.push('"');
+        source
+    }
+
+    fn visit_macro(&mut self, r#macro: &Macro) -> String {
+        format!("macro {}({}) {}", r#macro.name, r#macro.parameters.join(", "), self.visit_expression(&r#macro.body))
+    }
+
+    fn visit_trait(&mut self, r#trait: &Trait) -> String {
+        let methods = r#trait.methods.iter()
+            .map(|method| {
+                let signature = unparse_signature(
+                    &method.name, &method.type_parameters, &method.parameters, &method.annotations, &method.return_type,
+                );
+                format!("fn {}", signature)
+            })
+            .collect::<Vec<_>>();
+        match methods.as_slice() {
+            [] => format!("trait {} {{}}", r#trait.name),
+            _ => format!("trait {} {{ {} }}", r#trait.name, methods.join(" ")),
+        }
+    }
+}
+
+// A function's name through to its return type, as in `id<T>(x: T) -> T`
+pub fn unparse_signature(
+    name: &str,
+    type_parameters: &[String],
+    parameters: &[String],
+    annotations: &[Option<TypeAnnotation>],
+    return_type: &Option<TypeAnnotation>,
+) -> String {
+    let mut source = name.to_string();
+    if !type_parameters.is_empty() {
+        source.push_str(&format!("<{}>", type_parameters.join(", ")));
+    }
+    let parameters = parameters.iter().zip(annotations)
+        .map(|(parameter, annotation)| match annotation {
+            Some(annotation) => format!("{}: {}", parameter, type_annotation(annotation)),
+            None => parameter.clone(),
+        })
+        .collect::<Vec<_>>();
+    source.push_str(&format!("({})", parameters.join(", ")));
+    if let Some(return_type) = return_type {
+        source.push_str(&format!(" -> {}", type_annotation(return_type)));
+    }
+    source
+}
+
+fn type_annotation(annotation: &TypeAnnotation) -> String {
+    match annotation.arguments.as_slice() {
+        [] => annotation.name.clone(),
+        arguments => {
+            let arguments = arguments.iter().map(type_annotation).collect::<Vec<_>>();
+            format!("{}<{}>", annotation.name, arguments.join(", "))
+        }
+    }
+}
+
+fn escape_text_into(source: &mut String, text: &str) {
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        escape_into(source, c, '"', chars.peek().copied());
+    }
+}
+
+// `quote` is the delimiter that needs escaping, and `next` is used to only
+// escape a `$` when it would otherwise start an interpolation
+fn escape_into(source: &mut String, c: char, quote: char, next: Option<char>) {
+    match c {
+        '\n' => source.push_str("\\n"),
+        '\t' => source.push_str("\\t"),
+        '\r' => source.push_str("\\r"),
+        '\0' => source.push_str("\\0"),
+        '\\' => source.push_str("\\\\"),
+        '$' if next == Some('{') => source.push_str("\\$"),
+        c if c == quote => {
+            source.push('\\');
+            source.push(c);
+        }
+        c => source.push(c),
+    }
+}
+
+pub fn unparse(expression: &Expression) -> String {
+    Unparser.visit_expression(expression)
+}
+
+pub fn unparse_pattern(pattern: &Pattern) -> String {
+    Unparser.pattern(pattern)
+}
+
+// Puts each statement on its own line
+pub fn unparse_program(program: &Program) -> String {
+    program.statements.iter()
+        .map(|statement| Unparser.visit_statement(statement) + "\n")
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::lexer::get_tokens;
+    use crate::grammar::parser::{get_ast, get_program};
+
+    fn unparse_source(input: &str) -> String {
+        let tokens = get_tokens(input).unwrap();
+        unparse(&get_ast(tokens).unwrap())
+    }
+
+    #[test]
+    fn test_minimal_parentheses() -> Result<(), String> {
+        assert_eq!(unparse_source("2+3-1+456"), "2 + 3 - 1 + 456");
+        assert_eq!(unparse_source("((2*3))+4"), "2 * 3 + 4");
+        assert_eq!(unparse_source("(2+3)*4"), "(2 + 3) * 4");
+        assert_eq!(unparse_source("1-(2-3)"), "1 - (2 - 3)");
+        assert_eq!(unparse_source("(1-2)-3"), "1 - 2 - 3");
+        assert_eq!(unparse_source("1|(2^(3&4))"), "1 | 2 ^ 3 & 4");
+        assert_eq!(unparse_source("(1|2)&3"), "(1 | 2) & 3");
+        assert_eq!(unparse_source("(1^2)&3"), "(1 ^ 2) & 3");
+        assert_eq!(unparse_source("(1==2)==(3<4)"), "1 == 2 == 3 < 4");
+        let chain = format!("x{}", " - 1 * 2".repeat(50_000));
+        assert_eq!(unparse_source(&chain), chain);
+        assert_eq!(unparse_source("1<(2<3)"), "1 < (2 < 3)");
+        assert_eq!(unparse_source("(1|2)>=3"), "1 | 2 >= 3");
+        assert_eq!(unparse_source("(1!=2)&true"), "(1 != 2) & true");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unary_and_power() -> Result<(), String> {
+        assert_eq!(unparse_source("2**3**2"), "2 ** 3 ** 2");
+        assert_eq!(unparse_source("(2**3)**2"), "(2 ** 3) ** 2");
+        assert_eq!(unparse_source("-2**2"), "-2 ** 2");
+        assert_eq!(unparse_source("(-2)**2"), "(-2) ** 2");
+        assert_eq!(unparse_source("2**-1"), "2 ** -1");
+        assert_eq!(unparse_source("-(1+2)"), "-(1 + 2)");
+        assert_eq!(unparse_source("~-(3)"), "~-3");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_literals() -> Result<(), String> {
+        assert_eq!(unparse_source("1.0 + 2.5e3"), "1.0 + 2500.0");
+        assert_eq!(unparse_source("'\\''"), "'\\''");
+        assert_eq!(unparse_source("'\"'"), "'\"'");
+        assert_eq!(unparse_source("\"a\\tb\\\"$5\\${\""), "\"a\\tb\\\"$5\\${\"");
+        assert_eq!(unparse_source("\"sum ${1 + (2)} \\n\""), "\"sum ${1 + 2} \\n\"");
+        assert_eq!(unparse_source("[(1 + 2),[],\n[3]]"), "[1 + 2, [], [3]]");
+        assert_eq!(unparse_source("(-xs)[(i)][0]"), "(-xs)[i][0]");
+        assert_eq!(unparse_source("((1), (2,), (3, 4,), ())"), "(1, (2,), (3, 4), ())");
+        assert_eq!(unparse_source("(-t).0.1 + (1).0"), "(-t).0.1 + (1).0");
+        assert_eq!(unparse_source("{\"a\":{:},\n(b): [1]}"), "{ \"a\": {:}, b: [1] }");
+        assert_eq!(unparse_source("xs[i][j]=(y = 2)"), "xs[i][j] = y = 2");
+        assert_eq!(unparse_source("xs[0] *= 1 + 2"), "xs[0] *= 1 + 2");
+        assert_eq!(unparse_source("(xs[0] = 1) + 2"), "(xs[0] = 1) + 2");
+        assert_eq!(unparse_source("a ? (x = 1) : (b ? c : (d ? e : f))"), "a ? x = 1 : b ? c : d ? e : f");
+        assert_eq!(unparse_source("((a ? b : c) ? d : e) + (f ? g : h)"), "((a ? b : c) ? d : e) + (f ? g : h)");
+        assert_eq!(unparse_source("a ? b : (c = d)"), "a ? b : (c = d)");
+        assert_eq!(unparse_source("match x {\n  -1 => 'a',\n  n if n > (1) => { n },\n  _ => \"c\",\n}"),
+            "match x { -1 => 'a', n if n > 1 => { n }, _ => \"c\" }");
+        assert_eq!(unparse_source("match (x) {}"), "match x {}");
+        assert_eq!(unparse_source("(a ?? (b ?? c)) + ((a ?? b) ?? (c == nil))"), "(a ?? b ?? c) + ((a ?? b) ?? c == nil)");
+        assert_eq!(unparse_source("(a ?? b) ? (c ?? d) : (e ? f : g) ?? h"), "a ?? b ? c ?? d : (e ? f : g) ?? h");
+        assert_eq!(unparse_source("(i in (0..=(n + 1))) == (xs[(1)..(2 | 3)] in ((0..1)..2))"), "i in 0..=n + 1 == xs[1..2 | 3] in (0..1)..2");
+        assert_eq!(unparse_source("(0..1 == r)..(a < b)"), "(0..1 == r)..(a < b)");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_program() -> Result<(), String> {
+        let program = get_program(get_tokens("1+2;(3)\n;\"a\"").unwrap()).unwrap();
+        assert_eq!(unparse_program(&program), "1 + 2\n3\n\"a\"\n");
+
+        let program = get_program(get_tokens("let x=(1) ;-x").unwrap()).unwrap();
+        assert_eq!(unparse_program(&program), "let x = 1\n-x\n");
+
+        let program = get_program(get_tokens("x = (y = 2); (x = 1) * 2").unwrap()).unwrap();
+        assert_eq!(unparse_program(&program), "x = y = 2\n(x = 1) * 2\n");
+
+        let program = get_program(get_tokens("{ let a = 1\n a }\n{ 1\n}\n{2;}\n{}").unwrap()).unwrap();
+        assert_eq!(unparse_program(&program), "{ let a = 1; a }\n{ 1 }\n{ 2; }\n{}\n");
+
+        let program = get_program(get_tokens("if a {\n b\n} else if (c) {}\nelse { d; }\nif a { 1 } + 1").unwrap()).unwrap();
+        assert_eq!(unparse_program(&program), "if a { b } else if c {} else { d; }\nif a { 1 } + 1\n");
+
+        let program = get_program(get_tokens("while (a) {\n  while b { c }\n}").unwrap()).unwrap();
+        assert_eq!(unparse_program(&program), "while a { while b { c }; }\n");
+
+        let program = get_program(get_tokens("for i in (0)..(n+1) {}").unwrap()).unwrap();
+        assert_eq!(unparse_program(&program), "for i in 0..n + 1 {}\n");
+
+        let program = get_program(get_tokens("while true {\n  continue\n  break\n}").unwrap()).unwrap();
+        assert_eq!(unparse_program(&program), "while true { continue; break; }\n");
+
+        let program = get_program(get_tokens("fn f(a,b) {\n  a\n}\n(-f)((1), 2 + 3)(g())").unwrap()).unwrap();
+        assert_eq!(unparse_program(&program), "fn f(a, b) { a }\n(-f)(1, 2 + 3)(g())\n");
+
+        let program = get_program(get_tokens("fn f() {\n  return\n  return (1)\n}").unwrap()).unwrap();
+        assert_eq!(unparse_program(&program), "fn f() { return; return 1; }\n");
+
+        let program = get_program(get_tokens("fn(a,b) {\n  a\n}(1, 2)\nlet f = fn() {}").unwrap()).unwrap();
+        assert_eq!(unparse_program(&program), "fn(a, b) { a }(1, 2)\nlet f = fn() {}\n");
+
+        let program = get_program(get_tokens("struct P {\n  x,\n  y\n}\nP {x: (1), y: 2}.x + (-p).y").unwrap()).unwrap();
+        assert_eq!(unparse_program(&program), "struct P { x, y }\nP { x: 1, y: 2 }.x + (-p).y\n");
+
+        let program = get_program(get_tokens("enum E {\n  A(x,y),\n  B\n}\nmatch e { E.A(_, E.B) => 1, E.B => 2 }").unwrap()).unwrap();
+        assert_eq!(unparse_program(&program), "enum E { A(x, y), B }\nmatch e { E.A(_, E.B) => 1, E.B => 2 }\n");
+
+        let program = get_program(get_tokens("let ((a),[b,c...]) = t\nmatch t { (x,) => x, [_...] => 0 }").unwrap()).unwrap();
+        assert_eq!(unparse_program(&program), "let (a, [b, c...]) = t\nmatch t { (x,) => x, [_...] => 0 }\n");
+
+        assert_eq!(unparse_source("f(...(xs), ...[1,2]+ys)"), "f(...xs, ...[1, 2] + ys)");
+        assert_eq!(unparse_source("x + 1 |> f(2) |> g"), "g(f(2)(x + 1))");
+
+        let program = get_program(get_tokens("impl P {\n  fn __add__(a,b) { a }\n  fn neg(p) { -p }\n}\nimpl P {}").unwrap()).unwrap();
+        assert_eq!(unparse_program(&program), "impl P { fn __add__(a, b) { a } fn neg(p) { -p } }\nimpl P {}\n");
+        let program = get_program(get_tokens("trait Shape {\n  fn area(s)\n  fn scale(s,k)\n}\ntrait Empty {}\nimpl Shape for P {}").unwrap()).unwrap();
+        assert_eq!(unparse_program(&program), "trait Shape { fn area(s) fn scale(s, k) }\ntrait Empty {}\nimpl Shape for P {}\n");
+        let program = get_program(get_tokens("fn id<T,U>(x:T,y:Map<T,Array<U>>)->T { x }\ntrait C { fn get<T>(c,i:Int)->T }").unwrap()).unwrap();
+        assert_eq!(unparse_program(&program), "fn id<T, U>(x: T, y: Map<T, Array<U>>) -> T { x }\ntrait C { fn get<T>(c, i: Int) -> T }\n");
+        let program = get_program(get_tokens("import math\nimport \"math.rat\"\nimport \"a\\\"b/c.rat\"").unwrap()).unwrap();
+        assert_eq!(unparse_program(&program), "import math\nimport math\nimport \"a\\\"b/c.rat\"\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_trip() -> Result<(), String> {
+        for input in ["1 - (2 - 3) * -4 ** 2", "\"${'a'}${\"<${1 | 2}>\"}\"", "~(1 + 2) % 3 ^ 4", "try { 1 / x } catch (e) { e }"] {
+            let once = unparse_source(input);
+            assert_eq!(unparse_source(&once), once);
+        }
+
+        Ok(())
+    }
+}
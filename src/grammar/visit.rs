@@ -0,0 +1,301 @@
+use crate::grammar::parser::{
+    Array, Assign, Binary, Block, Bool, Break, Call, Char, Coalesce, Conditional, Continue, Destructure, Enum, Expression,
+    ExpressionStatement, Field, Float, For, Function, If, Impl, Import, Index, IndexAssign, Integer, Interpolation, Lambda,
+    Let, Macro, Map, Match, Nil, Range, Return, Spread, Statement, Str, Struct, StructLiteral, Trait, Try, Tuple,
+    TupleIndex, Unary, Variable, While,
+};
+
+// A pass over the tree that produces a `T` for every node. Each node kind
+// gets its own method and `walk_expression` picks the right one, so a pass
+// only has to recurse into children where it actually cares about them
+pub trait Visitor<T> {
+    fn visit_binary(&mut self, binary: &Binary) -> T;
+    fn visit_unary(&mut self, unary: &Unary) -> T;
+    fn visit_integer(&mut self, integer: &Integer) -> T;
+    fn visit_float(&mut self, float: &Float) -> T;
+    fn visit_char(&mut self, char: &Char) -> T;
+    fn visit_str(&mut self, str: &Str) -> T;
+    fn visit_bool(&mut self, bool: &Bool) -> T;
+    fn visit_nil(&mut self, nil: &Nil) -> T;
+    fn visit_interpolation(&mut self, interpolation: &Interpolation) -> T;
+    fn visit_variable(&mut self, variable: &Variable) -> T;
+    fn visit_assign(&mut self, assign: &Assign) -> T;
+    fn visit_block(&mut self, block: &Block) -> T;
+    fn visit_if(&mut self, r#if: &If) -> T;
+    fn visit_range(&mut self, range: &Range) -> T;
+    fn visit_call(&mut self, call: &Call) -> T;
+    fn visit_lambda(&mut self, lambda: &Lambda) -> T;
+    fn visit_array(&mut self, array: &Array) -> T;
+    fn visit_map(&mut self, map: &Map) -> T;
+    fn visit_conditional(&mut self, conditional: &Conditional) -> T;
+    fn visit_coalesce(&mut self, coalesce: &Coalesce) -> T;
+    fn visit_match(&mut self, r#match: &Match) -> T;
+    fn visit_try(&mut self, r#try: &Try) -> T;
+    fn visit_tuple(&mut self, tuple: &Tuple) -> T;
+    fn visit_tuple_index(&mut self, index: &TupleIndex) -> T;
+    fn visit_index(&mut self, index: &Index) -> T;
+    fn visit_index_assign(&mut self, assign: &IndexAssign) -> T;
+    fn visit_struct_literal(&mut self, literal: &StructLiteral) -> T;
+    fn visit_field(&mut self, field: &Field) -> T;
+    fn visit_spread(&mut self, spread: &Spread) -> T;
+
+    fn visit_expression(&mut self, expression: &Expression) -> T {
+        walk_expression(self, expression)
+    }
+}
+
+pub fn walk_expression<T, V: Visitor<T> + ?Sized>(visitor: &mut V, expression: &Expression) -> T {
+    match expression {
+        Expression::Binary(binary) => visitor.visit_binary(binary),
+        Expression::Unary(unary) => visitor.visit_unary(unary),
+        Expression::Integer(integer) => visitor.visit_integer(integer),
+        Expression::Float(float) => visitor.visit_float(float),
+        Expression::Char(char) => visitor.visit_char(char),
+        Expression::Str(str) => visitor.visit_str(str),
+        Expression::Bool(bool) => visitor.visit_bool(bool),
+        Expression::Nil(nil) => visitor.visit_nil(nil),
+        Expression::Interpolation(interpolation) => visitor.visit_interpolation(interpolation),
+        Expression::Variable(variable) => visitor.visit_variable(variable),
+        Expression::Assign(assign) => visitor.visit_assign(assign),
+        Expression::Block(block) => visitor.visit_block(block),
+        Expression::If(r#if) => visitor.visit_if(r#if),
+        Expression::Range(range) => visitor.visit_range(range),
+        Expression::Call(call) => visitor.visit_call(call),
+        Expression::Lambda(lambda) => visitor.visit_lambda(lambda),
+        Expression::Array(array) => visitor.visit_array(array),
+        Expression::Map(map) => visitor.visit_map(map),
+        Expression::Conditional(conditional) => visitor.visit_conditional(conditional),
+        Expression::Coalesce(coalesce) => visitor.visit_coalesce(coalesce),
+        Expression::Match(r#match) => visitor.visit_match(r#match),
+        Expression::Try(r#try) => visitor.visit_try(r#try),
+        Expression::Tuple(tuple) => visitor.visit_tuple(tuple),
+        Expression::TupleIndex(index) => visitor.visit_tuple_index(index),
+        Expression::Index(index) => visitor.visit_index(index),
+        Expression::IndexAssign(assign) => visitor.visit_index_assign(assign),
+        Expression::StructLiteral(literal) => visitor.visit_struct_literal(literal),
+        Expression::Field(field) => visitor.visit_field(field),
+        Expression::Spread(spread) => visitor.visit_spread(spread),
+    }
+}
+
+// Statements get their own visitor, since passes usually want a different
+// result from them than from expressions
+pub trait StatementVisitor<T> {
+    fn visit_expression_statement(&mut self, statement: &ExpressionStatement) -> T;
+    fn visit_let(&mut self, declaration: &Let) -> T;
+    fn visit_destructure(&mut self, declaration: &Destructure) -> T;
+    fn visit_while(&mut self, r#while: &While) -> T;
+    fn visit_for(&mut self, r#for: &For) -> T;
+    fn visit_break(&mut self, r#break: &Break) -> T;
+    fn visit_continue(&mut self, r#continue: &Continue) -> T;
+    fn visit_function(&mut self, function: &Function) -> T;
+    fn visit_return(&mut self, r#return: &Return) -> T;
+    fn visit_struct(&mut self, r#struct: &Struct) -> T;
+    fn visit_enum(&mut self, r#enum: &Enum) -> T;
+    fn visit_impl(&mut self, r#impl: &Impl) -> T;
+    fn visit_trait(&mut self, r#trait: &Trait) -> T;
+    fn visit_import(&mut self, import: &Import) -> T;
+    fn visit_macro(&mut self, r#macro: &Macro) -> T;
+
+    fn visit_statement(&mut self, statement: &Statement) -> T {
+        walk_statement(self, statement)
+    }
+}
+
+pub fn walk_statement<T, V: StatementVisitor<T> + ?Sized>(visitor: &mut V, statement: &Statement) -> T {
+    match statement {
+        Statement::Expression(expression) => visitor.visit_expression_statement(expression),
+        Statement::Let(declaration) => visitor.visit_let(declaration),
+        Statement::Destructure(declaration) => visitor.visit_destructure(declaration),
+        Statement::While(r#while) => visitor.visit_while(r#while),
+        Statement::For(r#for) => visitor.visit_for(r#for),
+        Statement::Break(r#break) => visitor.visit_break(r#break),
+        Statement::Continue(r#continue) => visitor.visit_continue(r#continue),
+        Statement::Function(function) => visitor.visit_function(function),
+        Statement::Return(r#return) => visitor.visit_return(r#return),
+        Statement::Struct(r#struct) => visitor.visit_struct(r#struct),
+        Statement::Enum(r#enum) => visitor.visit_enum(r#enum),
+        Statement::Impl(r#impl) => visitor.visit_impl(r#impl),
+        Statement::Trait(r#trait) => visitor.visit_trait(r#trait),
+        Statement::Import(import) => visitor.visit_import(import),
+        Statement::Macro(r#macro) => visitor.visit_macro(r#macro),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::lexer::get_tokens;
+    use crate::grammar::parser::get_ast;
+
+    // Counts the literals in a tree, to check that every kind of node gets
+    // dispatched to
+    struct LiteralCounter;
+
+    impl Visitor<usize> for LiteralCounter {
+        fn visit_binary(&mut self, binary: &Binary) -> usize {
+            self.visit_expression(&binary.left) + self.visit_expression(&binary.right)
+        }
+
+        fn visit_unary(&mut self, unary: &Unary) -> usize {
+            self.visit_expression(&unary.right)
+        }
+
+        fn visit_integer(&mut self, _: &Integer) -> usize { 1 }
+        fn visit_float(&mut self, _: &Float) -> usize { 1 }
+        fn visit_char(&mut self, _: &Char) -> usize { 1 }
+        fn visit_str(&mut self, _: &Str) -> usize { 1 }
+        fn visit_bool(&mut self, _: &Bool) -> usize { 1 }
+        fn visit_nil(&mut self, _: &Nil) -> usize { 1 }
+
+        fn visit_interpolation(&mut self, interpolation: &Interpolation) -> usize {
+            interpolation.parts.iter().map(|part| self.visit_expression(part)).sum()
+        }
+
+        fn visit_variable(&mut self, _: &Variable) -> usize { 0 }
+
+        fn visit_assign(&mut self, assign: &Assign) -> usize {
+            self.visit_expression(&assign.value)
+        }
+
+        fn visit_block(&mut self, block: &Block) -> usize {
+            let statements = block.statements.iter().map(|statement| self.visit_statement(statement)).sum::<usize>();
+            statements + block.value.as_ref().map_or(0, |value| self.visit_expression(value))
+        }
+
+        fn visit_if(&mut self, r#if: &If) -> usize {
+            let branches = self.visit_expression(&r#if.then_branch)
+                + r#if.else_branch.as_ref().map_or(0, |branch| self.visit_expression(branch));
+            self.visit_expression(&r#if.condition) + branches
+        }
+
+        fn visit_range(&mut self, range: &Range) -> usize {
+            self.visit_expression(&range.start) + self.visit_expression(&range.end)
+        }
+
+        fn visit_call(&mut self, call: &Call) -> usize {
+            let arguments = call.arguments.iter().map(|argument| self.visit_expression(argument)).sum::<usize>();
+            self.visit_expression(&call.callee) + arguments
+        }
+
+        fn visit_lambda(&mut self, lambda: &Lambda) -> usize {
+            self.visit_expression(&lambda.body)
+        }
+
+        fn visit_array(&mut self, array: &Array) -> usize {
+            array.elements.iter().map(|element| self.visit_expression(element)).sum()
+        }
+
+        fn visit_map(&mut self, map: &Map) -> usize {
+            map.entries.iter().map(|(key, value)| self.visit_expression(key) + self.visit_expression(value)).sum()
+        }
+
+        fn visit_conditional(&mut self, conditional: &Conditional) -> usize {
+            self.visit_expression(&conditional.condition)
+                + self.visit_expression(&conditional.then_branch)
+                + self.visit_expression(&conditional.else_branch)
+        }
+
+        fn visit_coalesce(&mut self, coalesce: &Coalesce) -> usize {
+            self.visit_expression(&coalesce.value) + self.visit_expression(&coalesce.fallback)
+        }
+
+        fn visit_match(&mut self, r#match: &Match) -> usize {
+            let arms = r#match.arms.iter()
+                .map(|arm| arm.guard.iter().chain([&arm.body]).map(|expression| self.visit_expression(expression)).sum::<usize>())
+                .sum::<usize>();
+            self.visit_expression(&r#match.scrutinee) + arms
+        }
+
+        fn visit_try(&mut self, r#try: &Try) -> usize {
+            self.visit_expression(&r#try.body) + self.visit_expression(&r#try.handler)
+        }
+
+        fn visit_tuple(&mut self, tuple: &Tuple) -> usize {
+            tuple.elements.iter().map(|element| self.visit_expression(element)).sum()
+        }
+
+        fn visit_tuple_index(&mut self, index: &TupleIndex) -> usize {
+            self.visit_expression(&index.tuple)
+        }
+
+        fn visit_index(&mut self, index: &Index) -> usize {
+            self.visit_expression(&index.target) + self.visit_expression(&index.index)
+        }
+
+        fn visit_index_assign(&mut self, assign: &IndexAssign) -> usize {
+            self.visit_expression(&assign.target) + self.visit_expression(&assign.index) + self.visit_expression(&assign.value)
+        }
+
+        fn visit_struct_literal(&mut self, literal: &StructLiteral) -> usize {
+            literal.fields.iter().map(|(_, value)| self.visit_expression(value)).sum()
+        }
+
+        fn visit_field(&mut self, field: &Field) -> usize {
+            self.visit_expression(&field.target)
+        }
+
+        fn visit_spread(&mut self, spread: &Spread) -> usize {
+            self.visit_expression(&spread.value)
+        }
+    }
+
+    impl StatementVisitor<usize> for LiteralCounter {
+        fn visit_expression_statement(&mut self, statement: &ExpressionStatement) -> usize {
+            self.visit_expression(&statement.expression)
+        }
+
+        fn visit_let(&mut self, declaration: &Let) -> usize {
+            self.visit_expression(&declaration.initializer)
+        }
+
+        fn visit_destructure(&mut self, declaration: &Destructure) -> usize {
+            self.visit_expression(&declaration.initializer)
+        }
+
+        fn visit_while(&mut self, r#while: &While) -> usize {
+            self.visit_expression(&r#while.condition) + self.visit_expression(&r#while.body)
+        }
+
+        fn visit_for(&mut self, r#for: &For) -> usize {
+            self.visit_expression(&r#for.iterable) + self.visit_expression(&r#for.body)
+        }
+
+        fn visit_break(&mut self, _: &Break) -> usize { 0 }
+        fn visit_continue(&mut self, _: &Continue) -> usize { 0 }
+
+        fn visit_function(&mut self, function: &Function) -> usize {
+            self.visit_expression(&function.body)
+        }
+
+        fn visit_return(&mut self, r#return: &Return) -> usize {
+            r#return.value.as_ref().map_or(0, |value| self.visit_expression(value))
+        }
+
+        fn visit_struct(&mut self, _: &Struct) -> usize { 0 }
+        fn visit_enum(&mut self, _: &Enum) -> usize { 0 }
+
+        fn visit_impl(&mut self, r#impl: &Impl) -> usize {
+            r#impl.methods.iter().map(|method| self.visit_function(method)).sum()
+        }
+
+        fn visit_trait(&mut self, _: &Trait) -> usize { 0 }
+        fn visit_import(&mut self, _: &Import) -> usize { 0 }
+
+        fn visit_macro(&mut self, r#macro: &Macro) -> usize {
+            self.visit_expression(&r#macro.body)
+        }
+    }
+
+    #[test]
+    fn test_walk_expression() -> Result<(), String> {
+        let tokens = get_tokens("1 + -2.0 * \"a ${'b'} c\" - (x = 2) * { let y = 3; 4 } + if true { 5 } else { 6 }").unwrap();
+        let ast = get_ast(tokens).unwrap();
+        assert_eq!(LiteralCounter.visit_expression(&ast), 11);
+
+        let ast = get_ast(get_tokens("{ while true { 1 }; for i in 0..10 { 2 }; fn f() { return 3 }; f(4, fn() { 5 }, [6, [7]]); xs[8] = xs[9]; { 10: 11 }; (12, 13).0; x ? 14 : 15; match 16 { 0 if 17 => 18, _ => 19 }; struct P { x }; P { x: 20 }.x; enum E { A(x) }; E.A(21); nil ?? 23; let (a, [b]) = (24, [25]); f(...[26]); impl P { fn m(p) { 27 } fn n(p) { 28 } }; trait T { fn m(p) }; impl T for P { fn m(p) { 29 } }; import m; macro m(a) { 30 } }").unwrap()).unwrap();
+        assert_eq!(LiteralCounter.visit_expression(&ast), 33);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,684 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Instant;
+use crate::grammar::compile::{compile, Chunk, Function, Opcode, NO_OPERATION};
+use crate::grammar::evaluate::{apply, insert, negate, subscript, Callable, EvalConfig, EvalError, Interpreter, RuntimeError};
+use crate::grammar::lexer::get_tokens;
+use crate::grammar::parser::{get_program, Statement, UnaryOperator};
+use crate::grammar::runtime::{RangeValue, Value};
+
+// A call of a compiled function that's running. Its arguments and locals are
+// on the stack from `base` up, with the function just below them.
+struct Frame {
+    function: Callable,
+    ip: usize,
+    base: usize,
+}
+
+// Runs what `compile` makes. Builtins are those of an interpreter, which is
+// what they're called through, so they write to the same output and fail the
+// same way. Errors are the evaluator's too, with the same spans.
+//
+// Every instruction is a step, so the fuel in the config runs out and
+// cancelling or a deadline stops it the same as they stop the evaluator,
+// though after a different number of steps. A loop over anything but a
+// range, an array, a tuple or a string is the one thing that compiles but
+// can't be run, since the VM doesn't call iterators.
+pub struct Vm {
+    interpreter: Interpreter,
+    config: EvalConfig,
+    // The globals the program declared, which shadow the builtins
+    globals: HashMap<String, Value>,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+}
+
+impl Vm {
+    pub fn new(config: EvalConfig) -> Vm {
+        Vm {
+            interpreter: Interpreter::new(config),
+            config,
+            globals: HashMap::new(),
+            stack: Vec::new(),
+            frames: Vec::new(),
+        }
+    }
+
+    // Where `print` and `println` write to, which is stdout to begin with
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.interpreter.set_output(output);
+    }
+
+    // Whenever `cancel` is set, whatever is running stops with
+    // `RuntimeError::Cancelled`
+    pub fn set_cancel(&mut self, cancel: Arc<AtomicBool>) {
+        self.interpreter.set_cancel(cancel);
+    }
+
+    // Once `deadline` has passed, whatever is running stops with
+    // `RuntimeError::TimedOut`
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.interpreter.set_deadline(deadline);
+    }
+
+    // Gives back what the function does. Everything run by the same VM
+    // shares its globals, and each run gets the whole budget of steps.
+    pub fn run(&mut self, function: Function) -> Result<Value, RuntimeError> {
+        self.interpreter.start();
+        let function = Callable::compiled(function);
+        self.stack.push(Value::Function(function.clone()));
+        self.frames.push(Frame { function, ip: 0, base: self.stack.len() });
+        let result = self.execute();
+        self.stack.clear();
+        self.frames.clear();
+        result
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("the compiler never pops more than it pushed")
+    }
+
+    fn top(&self) -> &Value {
+        self.stack.last().expect("the compiler never reads more than it pushed")
+    }
+
+    fn global(&self, name: &str) -> Option<Value> {
+        self.globals.get(name).cloned().or_else(|| self.interpreter.global(name))
+    }
+
+    // Each time round the outer loop starts running the frame on top where it
+    // left off, which the inner one keeps at until a call or a return changes
+    // which frame that is
+    fn execute(&mut self) -> Result<Value, RuntimeError> {
+        loop {
+            let frame = self.frames.last().expect("there's a frame until the last one returns");
+            let (function, mut ip, base) = (frame.function.clone(), frame.ip, frame.base);
+            let chunk = &function.as_compiled().expect("only compiled functions get frames").chunk;
+            loop {
+                let offset = ip;
+                let opcode = Opcode::decode(chunk.code[ip]).expect("the compiler only emits opcodes");
+                let span = chunk.span(offset);
+                self.interpreter.step(span)?;
+                ip += 1;
+                match opcode {
+                    Opcode::Constant => {
+                        self.stack.push(chunk.constants[chunk.read(ip)].clone());
+                        ip += 2;
+                    }
+                    Opcode::Nil => self.stack.push(Value::Nil),
+                    Opcode::True => self.stack.push(Value::Bool(true)),
+                    Opcode::False => self.stack.push(Value::Bool(false)),
+                    Opcode::Pop => {
+                        self.pop();
+                    }
+                    Opcode::PopBelow => {
+                        let value = self.pop();
+                        let length = self.stack.len() - chunk.read(ip);
+                        self.stack.truncate(length);
+                        self.stack.push(value);
+                        ip += 2;
+                    }
+                    Opcode::GetLocal => {
+                        self.stack.push(self.stack[base + chunk.read(ip)].clone());
+                        ip += 2;
+                    }
+                    Opcode::SetLocal => {
+                        self.stack[base + chunk.read(ip)] = self.top().clone();
+                        ip += 2;
+                    }
+                    Opcode::GetGlobal | Opcode::GetFunction => {
+                        let name = name(chunk, ip);
+                        let value = self.global(name).ok_or_else(|| match opcode {
+                            Opcode::GetFunction => RuntimeError::UndefinedFunction { name: name.to_string(), span },
+                            _ => RuntimeError::UndefinedVariable { name: name.to_string(), span },
+                        })?;
+                        self.stack.push(value);
+                        ip += 2;
+                    }
+                    // A builtin that hasn't been assigned to yet is shadowed
+                    // from then on, which is the same as changing it
+                    Opcode::SetGlobal => {
+                        let name = name(chunk, ip);
+                        if self.global(name).is_none() {
+                            return Err(RuntimeError::UndefinedVariable { name: name.to_string(), span });
+                        }
+                        self.globals.insert(name.to_string(), self.top().clone());
+                        ip += 2;
+                    }
+                    Opcode::DefineGlobal => {
+                        let value = self.pop();
+                        self.globals.insert(name(chunk, ip).to_string(), value);
+                        ip += 2;
+                    }
+                    Opcode::Binary => {
+                        let operation = &chunk.operations[chunk.read(ip)];
+                        let right = self.pop();
+                        let left = self.pop();
+                        let (operator_span, divisor) = (operation.operator_span, operation.right);
+                        let (overflow, operator) = (self.config.overflow, &operation.operator);
+                        self.stack.push(apply(operator, left, right, overflow, span, operator_span, divisor)?);
+                        ip += 2;
+                    }
+                    Opcode::Negate | Opcode::Invert => {
+                        let operator = if opcode == Opcode::Negate { UnaryOperator::Minus } else { UnaryOperator::Tilde };
+                        let right = self.pop();
+                        self.stack.push(negate(&operator, right, self.config.overflow, span)?);
+                    }
+                    Opcode::Jump => ip += 2 + chunk.read(ip),
+                    Opcode::JumpIfFalse => match self.pop() {
+                        Value::Bool(true) => ip += 2,
+                        Value::Bool(false) => ip += 2 + chunk.read(ip),
+                        _ => return Err(RuntimeError::NonBooleanCondition { span }),
+                    },
+                    Opcode::JumpIfNotNil => {
+                        if let Value::Nil = self.top() {
+                            self.pop();
+                            ip += 2;
+                        } else {
+                            ip += 2 + chunk.read(ip);
+                        }
+                    }
+                    Opcode::Loop => ip = ip + 2 - chunk.read(ip),
+                    Opcode::Call | Opcode::TailCall => {
+                        let count = usize::from(chunk.code[ip]);
+                        ip += 1;
+                        self.frames.last_mut().expect("the caller's frame is still there").ip = ip;
+                        if self.call(chunk, offset, count, opcode == Opcode::TailCall)? {
+                            break;
+                        }
+                        if opcode == Opcode::TailCall {
+                            match self.finish() {
+                                Some(value) => return Ok(value),
+                                None => break,
+                            }
+                        }
+                    }
+                    Opcode::Return => match self.finish() {
+                        Some(value) => return Ok(value),
+                        None => break,
+                    },
+                    Opcode::Array | Opcode::Tuple => {
+                        let start = self.stack.len() - chunk.read(ip);
+                        let elements = self.stack.split_off(start);
+                        self.stack.push(if opcode == Opcode::Array { Value::array(elements) } else { Value::Tuple(elements) });
+                        ip += 2;
+                    }
+                    Opcode::Range => {
+                        let end = self.pop();
+                        let start = self.pop();
+                        let (Value::Int(start), Value::Int(end)) = (start, end) else {
+                            return Err(RuntimeError::TypeMismatch { span });
+                        };
+                        self.stack.push(Value::Range(RangeValue { start, end, inclusive: chunk.code[ip] != 0 }));
+                        ip += 1;
+                    }
+                    Opcode::Interpolate => {
+                        let start = self.stack.len() - chunk.read(ip);
+                        let text = self.stack.drain(start..).map(|part| part.to_string()).collect::<String>();
+                        self.stack.push(Value::Str(text));
+                        ip += 2;
+                    }
+                    Opcode::TupleIndex => {
+                        let position = chunk.read(ip);
+                        let Value::Tuple(mut elements) = self.pop() else {
+                            return Err(RuntimeError::TypeMismatch { span: chunk.span(offset + 1) });
+                        };
+                        if position >= elements.len() {
+                            let length = elements.len();
+                            return Err(RuntimeError::IndexOutOfBounds { index: position as i64, length, span });
+                        }
+                        self.stack.push(elements.swap_remove(position));
+                        ip += 2;
+                    }
+                    Opcode::Index => {
+                        let position = self.pop();
+                        let target = self.pop();
+                        self.stack.push(subscript(&target, position, span)?);
+                    }
+                    Opcode::SetIndex => {
+                        let mut value = self.pop();
+                        let position = self.pop();
+                        let target = self.pop();
+                        if chunk.read(ip) != NO_OPERATION {
+                            let operation = &chunk.operations[chunk.read(ip)];
+                            let current = subscript(&target, position.clone(), span)?;
+                            let (operator_span, divisor) = (operation.operator_span, operation.right);
+                            let (overflow, operator) = (self.config.overflow, &operation.operator);
+                            value = apply(operator, current, value, overflow, operation.span, operator_span, divisor)?;
+                        }
+                        insert(&target, position, span, value.clone())?;
+                        self.stack.push(value);
+                        ip += 2;
+                    }
+                    // What an array has in it when the loop starts is what
+                    // it goes over, as it is for the evaluator. The VM can't
+                    // call an iterator for each value, so a function is
+                    // unsupported rather than a mismatch.
+                    Opcode::Count => {
+                        let (iterable, first) = match self.pop() {
+                            Value::Range(range) => {
+                                let first = if range.contains(range.start) { Value::Int(range.start) } else { Value::Nil };
+                                (Value::Range(range), first)
+                            }
+                            Value::Array(elements) => elements_of(elements.borrow().clone()),
+                            Value::Tuple(elements) => elements_of(elements),
+                            Value::Str(string) => elements_of(string.chars().map(Value::Char).collect()),
+                            Value::Function(_) => {
+                                return Err(RuntimeError::Unsupported { construct: "loops over iterators", span });
+                            }
+                            _ => return Err(RuntimeError::TypeMismatch { span }),
+                        };
+                        self.stack.push(iterable);
+                        self.stack.push(first);
+                    }
+                    Opcode::Iterate => {
+                        let slot = base + chunk.read(ip);
+                        let (value, after) = match (&self.stack[slot], &self.stack[slot + 1]) {
+                            (Value::Range(range), Value::Int(next)) => {
+                                (Value::Int(*next), next.checked_add(1).filter(|after| range.contains(*after)))
+                            }
+                            (Value::Tuple(elements), Value::Int(next)) => {
+                                let position = *next as usize;
+                                (elements[position].clone(), Some(*next + 1).filter(|_| position + 1 < elements.len()))
+                            }
+                            _ => {
+                                ip += 4 + chunk.read(ip + 2);
+                                continue;
+                            }
+                        };
+                        self.stack[slot + 1] = after.map_or(Value::Nil, Value::Int);
+                        self.stack.push(value);
+                        ip += 4;
+                    }
+                }
+            }
+        }
+    }
+
+    // Calls the function under the `count` arguments on top of the stack,
+    // giving back whether that made a frame for it to run in. A call in tail
+    // position reuses the frame of the one it's in. A function that isn't
+    // compiled is called through the interpreter there and then, leaving its
+    // value on the stack.
+    fn call(&mut self, chunk: &Chunk, offset: usize, count: usize, tail: bool) -> Result<bool, RuntimeError> {
+        let position = self.stack.len() - count - 1;
+        let span = chunk.span(offset);
+        let Value::Function(function) = &self.stack[position] else {
+            return Err(RuntimeError::NotCallable { span: chunk.span(offset + 1) });
+        };
+        let function = function.clone();
+        let Some(compiled) = function.as_compiled() else {
+            let arguments = self.stack.split_off(position + 1);
+            self.stack.pop();
+            let value = self.interpreter.call(&function, arguments, span)?;
+            self.stack.push(value);
+            return Ok(false);
+        };
+        if compiled.arity != count {
+            return Err(RuntimeError::ArityMismatch { expected: compiled.arity, found: count, span });
+        }
+        if tail {
+            let frame = self.frames.last_mut().expect("the caller's frame is still there");
+            self.stack.drain(frame.base - 1..position);
+            (frame.function, frame.ip) = (function, 0);
+        } else {
+            if self.frames.len() - 1 == self.config.max_call_depth {
+                return Err(RuntimeError::RecursionLimitExceeded { limit: self.config.max_call_depth, span });
+            }
+            self.frames.push(Frame { function, ip: 0, base: position + 1 });
+        }
+        Ok(true)
+    }
+
+    // Leaves the frame on top with the value on top of the stack, which is
+    // given back once the last frame is gone
+    fn finish(&mut self) -> Option<Value> {
+        let value = self.pop();
+        let frame = self.frames.pop().expect("only a running frame can return");
+        self.stack.truncate(frame.base - 1);
+        if self.frames.is_empty() {
+            return Some(value);
+        }
+        self.stack.push(value);
+        None
+    }
+}
+
+impl Default for Vm {
+    fn default() -> Vm {
+        Vm::new(EvalConfig::default())
+    }
+}
+
+// An array, tuple or string a loop goes over, as the tuple of what's in it
+// and the position of the first, or nil if there's nothing
+fn elements_of(elements: Vec<Value>) -> (Value, Value) {
+    let first = if elements.is_empty() { Value::Nil } else { Value::Int(0) };
+    (Value::Tuple(elements), first)
+}
+
+fn name(chunk: &Chunk, ip: usize) -> &str {
+    match &chunk.constants[chunk.read(ip)] {
+        Value::Str(name) => name,
+        _ => unreachable!("the compiler only gives names as strings"),
+    }
+}
+
+// Runs `source` the way `execute` would, but compiled to bytecode and run by
+// the VM
+pub fn run_vm(source: &str) -> Result<Option<Value>, EvalError> {
+    let program = get_program(get_tokens(source)?)?;
+    let value = Vm::default().run(compile(&program)?)?;
+    Ok(matches!(program.statements.last(), Some(Statement::Expression(_))).then_some(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::io;
+    use std::rc::Rc;
+    use std::sync::atomic::Ordering;
+    use std::thread;
+    use std::time::Duration;
+    use crate::grammar::compile::CompileError;
+    use crate::grammar::evaluate::execute;
+    use crate::grammar::runtime::Type;
+
+    fn run_both(input: &str) -> (Result<Option<Value>, RuntimeError>, Result<Option<Value>, EvalError>) {
+        let expected = execute(&get_program(get_tokens(input).unwrap()).unwrap());
+        (expected, run_vm(input))
+    }
+
+    // The VM has to give back what the evaluator does
+    fn same(input: &str) -> String {
+        let (expected, actual) = run_both(input);
+        let (expected, actual) = (expected.unwrap(), actual.unwrap());
+        assert_eq!(format!("{:?}", actual), format!("{:?}", expected), "{}", input);
+        actual.map_or("none".to_string(), |value| value.to_string())
+    }
+
+    // And fail the same way, with the same span
+    fn same_error(input: &str) -> RuntimeError {
+        let (expected, actual) = run_both(input);
+        let Err(EvalError::Runtime(actual)) = actual else {
+            panic!("Expected {} to fail, got {:?}", input, actual);
+        };
+        let expected = expected.unwrap_err();
+        assert_eq!((actual.message(), actual.span()), (expected.message(), expected.span()), "{}", input);
+        actual
+    }
+
+    fn compile_error(input: &str) -> CompileError {
+        match run_vm(input) {
+            Err(EvalError::Compile(error)) => error,
+            result => panic!("Expected {} not to compile, got {:?}", input, result),
+        }
+    }
+
+    #[test]
+    fn test_values() -> Result<(), String> {
+        assert_eq!(same("1 + 2 * 3 - 4 / 2"), "5");
+        assert_eq!(same(&vec!["1"; 20_000].join(" + ")), "20000");
+        assert_eq!(same("[7 % 3 ** 2, -(3) + ~5, 2.5 * 2, 1 / 2 + 1 / 3]"), "[7, -9, 5.0, 5/6]");
+        assert_eq!(same("[\"a\" == \"a\", 3 in 1..5, 'c', nil, true, false]"), "[true, true, c, nil, true, false]");
+        assert_eq!(same("\"sum is ${2 + 3}, ${(1, 'x')}\""), "sum is 5, (1, x)");
+        assert_eq!(same("let t = (1, \"two\", 3.0)\nt.1"), "two");
+        assert_eq!(same("let x = 1\nlet y = { let z = x + 1; z * 2 }\ny + x"), "5");
+        assert_eq!(same("let x = 1\nx = x + 2\nx += 1\nx"), "4");
+        assert_eq!(same("1 + { let a = 2; let b = 3; a * b } + { 4 }"), "11");
+        assert_eq!(same("[if 1 < 2 { 10 } else { 20 }, if false { 1 }, true ? 1 : 2, nil ?? 5, 4 ?? 5]"), "[10, nil, 1, 5, 4]");
+        assert_eq!(same("let xs = [1, 2, 3]\nxs[1] = 20\nxs[2] += 5\n[xs, xs[0..2], len(xs), parse_int(\"42\")]"), "[[1, 20, 8], [1, 20], 3, 42]");
+        assert_eq!(same("let r = 1..=3\nr"), "1..=3");
+        assert_eq!(same("let x = 1"), "none");
+        assert_eq!(same(""), "none");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_loops() -> Result<(), String> {
+        assert_eq!(same("let total = 0\nfor i in 0..10 { total += i }\ntotal"), "45");
+        assert_eq!(same("let total = 0\nfor i in 3..=1 { total += i }\nfor i in 1..=1 { total += i }\ntotal"), "1");
+        let input = "let i = 0
+            let odd = 0
+            while true {
+                i += 1
+                if i > 10 { break }
+                if i % 2 == 0 { continue }
+                odd += i
+            }
+            odd";
+        assert_eq!(same(input), "25");
+        let input = "let found = nil
+            for i in 1..=100 {
+                let square = i * i
+                if square > 50 { found = [i, square]; break }
+            }
+            found";
+        assert_eq!(same(input), "[8, 64]");
+        let input = "let pairs = []
+            for i in 0..3 {
+                for j in 0..3 {
+                    if j == i { continue }
+                    if j > i { break }
+                    pairs = [pairs, (i, j)]
+                }
+            }
+            pairs";
+        assert_eq!(same(input), "[[[[], (1, 0)], (2, 0)], (2, 1)]");
+        assert_eq!(same("let last = 0\nfor i in 9223372036854775806..=9223372036854775807 { last = i }\nlast"), "9223372036854775807");
+
+        // Arrays, tuples and strings give what's in them, as they were when
+        // the loop started
+        let input = "let seen = []
+            for x in [1, \"a\", [2]] { seen = [seen, x] }
+            for c in \"añ\" { seen = [seen, c] }
+            for x in (true, nil) { seen = [seen, x] }
+            seen";
+        assert_eq!(same(input), "[[[[[[[[], 1], a], [2]], a], ñ], true], nil]");
+        let input = "let xs = [1, 2, 3, 4]
+            let total = 0
+            for x in xs {
+                xs[3] = 100
+                if x == 2 { continue }
+                if x > 50 { break }
+                total += x
+            }
+            (total, xs)";
+        assert_eq!(same(input), "(8, [1, 2, 3, 100])");
+        assert_eq!(same("let n = 0\nfor x in [] { n += 1 }\nfor c in \"\" { n += 1 }\nfor x in () { n += 1 }\nn"), "0");
+        assert_eq!(same("fn sum(xs) { let total = 0\nfor x in xs { total += x }\ntotal }\n[sum([1, 2, 3]), sum((4, 5))]"), "[6, 9]");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_functions() -> Result<(), String> {
+        assert_eq!(same("fn fib(n) { if n < 2 { n } else { fib(n - 1) + fib(n - 2) } }\nfib(15)"), "610");
+        assert_eq!(same("let add = fn(a, b) { a + b }\nadd(2, 3)"), "5");
+        assert_eq!(same("fn twice(f, x) { f(f(x)) }\ntwice(fn(x) { x * 3 }, 2)"), "18");
+        assert_eq!(same("fn outer(n) { fn double(x) { x * 2 }\ndouble(n) + 1 }\nouter(4)"), "9");
+        let input = "fn first(xs) {
+                for i in 0..len(xs) {
+                    if xs[i] > 2 { return xs[i] }
+                }
+                nil
+            }
+            [first([1, 5, 3]), first([])]";
+        assert_eq!(same(input), "[5, nil]");
+        assert_eq!(same("fn nothing() { return }\nnothing()"), "nil");
+        assert_eq!(same("let scale = 10\nfn scaled(x) { x * scale }\nscale = 3\nscaled(2)"), "6");
+        assert_eq!(same("fn f() { 1 }\nf"), "<fn f>");
+
+        // Calls in tail position don't count towards the limit on call depth
+        let input = "fn count(n, total) { if n == 0 { total } else { count(n - 1, total + n) } }\ncount(10000, 0)";
+        assert_eq!(same(input), "50005000");
+        let input = "fn even(n) { if n == 0 { return true }\nodd(n - 1) }\nfn odd(n) { n == 0 ? false : even(n - 1) }\neven(5001)";
+        assert_eq!(same(input), "false");
+        assert_eq!(same("fn size(xs) { len(xs) }\nsize([1, 2])"), "2");
+
+        // Functions that don't use the variables around them can be made
+        // anywhere and passed around
+        let input = "fn compose(f, g) { fn(x) { x } }
+            fn apply_all(fs, x) { for f in fs { x = f(x) }\nx }
+            apply_all([fn(x) { x + 1 }, fn(x) { x * 10 }, compose(1, 2)], 4)";
+        assert_eq!(same(input), "50");
+        assert_eq!(same("let make = fn() { fn(a, b) { a ?? b } }\n[make()(nil, 2), make()(1, 2)]"), "[2, 1]");
+        assert_eq!(same("fn describe(n) { \"${n} is ${n % 2 == 0 ? \"even\" : \"odd\"}\" }\n[describe(3), describe(4)]"), "[3 is odd, 4 is even]");
+        assert_eq!(same("[int(2.7), float(1 / 4), format(\"{:>4}|\", 7), len(\"añ\"), parse_int(\"x\")]"), "[2, 0.25,    7|, 2, nil]");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_errors() -> Result<(), String> {
+        assert!(matches!(same_error("1 + nil"), RuntimeError::TypeError { right: Type::Nil, .. }));
+        assert!(matches!(same_error("1 / 0"), RuntimeError::DivisionByZero { .. }));
+        assert!(matches!(same_error("9223372036854775807 + 1"), RuntimeError::Overflow { .. }));
+        assert!(matches!(same_error("-\"a\""), RuntimeError::TypeMismatch { .. }));
+        assert!(matches!(same_error("undefined_thing"), RuntimeError::UndefinedVariable { .. }));
+        assert!(matches!(same_error("undefined_thing = 1"), RuntimeError::UndefinedVariable { .. }));
+        assert!(matches!(same_error("nope(1)"), RuntimeError::UndefinedFunction { name, .. } if name == "nope"));
+        assert!(matches!(same_error("let x = 1\nx(2)"), RuntimeError::NotCallable { .. }));
+        assert!(matches!(same_error("fn f(a) { a }\nf(1, 2)"), RuntimeError::ArityMismatch { expected: 1, found: 2, .. }));
+        assert!(matches!(same_error("len(1)"), RuntimeError::TypeMismatch { .. }));
+        assert!(matches!(same_error("if 1 { 2 }"), RuntimeError::NonBooleanCondition { .. }));
+        assert!(matches!(same_error("while nil { 2 }"), RuntimeError::NonBooleanCondition { .. }));
+        assert!(matches!(same_error("[1][5]"), RuntimeError::IndexOutOfBounds { .. }));
+        assert!(matches!(same_error("let xs = [1]\nxs[3] += 1"), RuntimeError::IndexOutOfBounds { .. }));
+        assert!(matches!(same_error("let xs = [\"a\"]\nxs[0] += 1"), RuntimeError::TypeError { .. }));
+        assert!(matches!(same_error("(1, 2).5"), RuntimeError::IndexOutOfBounds { .. }));
+        assert!(matches!(same_error("[1].0"), RuntimeError::TypeMismatch { .. }));
+        assert!(matches!(same_error("1..\"a\""), RuntimeError::TypeMismatch { .. }));
+        let error = same_error("fn down(n) { 1 + down(n) }\ndown(1)");
+        assert!(matches!(error, RuntimeError::RecursionLimitExceeded { limit: 200, .. }));
+
+        assert!(matches!(same_error("for i in 5 { i }"), RuntimeError::TypeMismatch { .. }));
+        assert!(matches!(same_error("let total = 0\nfor i in [1, nil] { total += i }"), RuntimeError::TypeError { .. }));
+
+        // The VM can't run iterators, and the evaluator can't run what the VM
+        // compiled
+        let input = "fn naturals() { fn() { 1 } }\nfor i in naturals() { break }";
+        let Err(EvalError::Runtime(error @ RuntimeError::Unsupported { .. })) = run_vm(input) else {
+            panic!("Expected {} to be unsupported", input);
+        };
+        assert_eq!(error.message(), "The VM can't run loops over iterators");
+        assert_eq!(&input[error.span().start..error.span().end], "naturals()");
+        let mut interpreter = Interpreter::default();
+        interpreter.define("f", run_vm("fn f() { 1 }\nf").unwrap().unwrap());
+        for input in ["f()", "[1].map(f)", "for i in f { i }"] {
+            let result = interpreter.execute(&get_program(get_tokens(input).unwrap()).unwrap());
+            assert!(matches!(result, Err(RuntimeError::NotCallable { .. } | RuntimeError::TypeMismatch { .. })), "{}", input);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compile_errors() -> Result<(), String> {
+        assert!(matches!(compile_error("match 1 { _ => 2 }"), CompileError::Unsupported { construct: "match expressions", .. }));
+        assert!(matches!(compile_error("struct Point { x }"), CompileError::Unsupported { construct: "structs", .. }));
+        assert!(matches!(compile_error("[1].len()"), CompileError::Unsupported { .. }));
+        assert!(matches!(compile_error("len([...[1]])"), CompileError::Unsupported { construct: "spreads", .. }));
+        let unsupported = [
+            ("math.sqrt(2.0)", "fields and methods"),
+            ("let t = (1, 2)\nt.len", "fields and methods"),
+            ("{\"a\": 1}", "maps"),
+            ("enum Color { Red }", "enums"),
+            ("try { 1 } catch (e) { 2 }", "try expressions"),
+            ("let (a, b) = (1, 2)", "destructuring"),
+            ("import other", "imports"),
+            ("trait Shape { fn area(s) }", "traits"),
+        ];
+        for (input, expected) in unsupported {
+            let error = compile_error(input);
+            assert!(matches!(error, CompileError::Unsupported { construct, .. } if construct == expected), "{}", input);
+        }
+        let error = compile_error("1 + {\"a\": 1}");
+        assert_eq!(error.to_string(), "The VM can't run maps at line 1, character 5");
+
+        let input = "fn outer() {\n  let x = 1\n  fn() { x }\n}";
+        let error = compile_error(input);
+        assert!(matches!(&error, CompileError::Captured { name, .. } if name == "x"));
+        assert_eq!(
+            error.to_string(),
+            "The VM can't run a function using 'x', which is a variable of the function around it at line 3, character 10"
+        );
+        assert!(matches!(compile_error("fn outer() { fn inner(n) { inner(n) }\n1 }"), CompileError::Captured { .. }));
+        assert!(matches!(compile_error("{ let y = 2; fn() { y } }"), CompileError::Captured { .. }));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_limits() -> Result<(), String> {
+        let run = |vm: &mut Vm, input: &str| vm.run(compile(&get_program(get_tokens(input).unwrap()).unwrap()).unwrap());
+
+        // Loops and calls, in tail position or not, run out of fuel
+        let mut vm = Vm::new(EvalConfig { fuel: Some(1000), max_call_depth: 100_000, ..EvalConfig::default() });
+        for input in ["while true {}", "for i in 0..1000000 {}", "fn f() { f() }\nf()", "fn f(n) { n + f(n) }\nf(1)"] {
+            match run(&mut vm, input) {
+                Err(error @ RuntimeError::BudgetExhausted { limit: 1000, .. }) => {
+                    assert_eq!(error.message(), "Ran out of steps after 1000")
+                }
+                result => panic!("Expected {} to run out of fuel, got {:?}", input, result),
+            }
+        }
+        // Each run gets all of it
+        assert_eq!(run(&mut vm, "let total = 0\nfor i in 0..50 { total += i }\ntotal").unwrap(), Value::Int(1225));
+        assert_eq!(run(&mut vm, "let total = 0\nfor i in 0..50 { total += i }\ntotal").unwrap(), Value::Int(1225));
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        let mut vm = Vm::default();
+        vm.set_cancel(Arc::clone(&cancel));
+        let setter = Arc::clone(&cancel);
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            setter.store(true, Ordering::Relaxed);
+        });
+        assert!(matches!(run(&mut vm, "while true {}"), Err(RuntimeError::Cancelled { .. })));
+        handle.join().unwrap();
+        cancel.store(false, Ordering::Relaxed);
+        assert_eq!(run(&mut vm, "1 + 1").unwrap(), Value::Int(2));
+
+        vm.set_deadline(Some(Instant::now() + Duration::from_millis(20)));
+        assert!(matches!(run(&mut vm, "fn spin(n) { spin(n + 1) }\nspin(0)"), Err(RuntimeError::TimedOut { .. })));
+        vm.set_deadline(None);
+        assert_eq!(run(&mut vm, "1 + 1").unwrap(), Value::Int(2));
+
+        Ok(())
+    }
+
+    #[derive(Clone, Default)]
+    struct Captured(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buffer)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_output() -> Result<(), String> {
+        let captured = Captured::default();
+        let mut vm = Vm::default();
+        vm.set_output(Box::new(captured.clone()));
+        let program = get_program(get_tokens("fn greet(name) { println(\"hi ${name}\") }\ngreet(1)\ngreet(2)").unwrap()).unwrap();
+        assert_eq!(vm.run(compile(&program).unwrap()).unwrap(), Value::Nil);
+        assert_eq!(String::from_utf8(captured.0.borrow().clone()).unwrap(), "hi 1\nhi 2\n");
+
+        // The globals are still there for the next run
+        let program = get_program(get_tokens("greet(3)").unwrap()).unwrap();
+        assert!(vm.run(compile(&program).unwrap()).is_ok());
+        assert!(String::from_utf8(captured.0.borrow().clone()).unwrap().ends_with("hi 3\n"));
+
+        Ok(())
+    }
+}
@@ -1,17 +1,120 @@
-extern crate core;
+use std::{env, fs, process};
+use std::path::Path;
+use rat_lang::expand::expand_macros;
+use rat_lang::grammar::compile::{compile, disassemble};
+use rat_lang::grammar::doc::{to_html, to_markdown};
+use rat_lang::grammar::evaluate::{evaluate, execute_file};
+use rat_lang::grammar::lexer::get_tokens;
+use rat_lang::grammar::parser::{get_ast, get_program_resilient, Program};
+use rat_lang::grammar::runtime::Value;
+use rat_lang::grammar::unparse::unparse_program;
+use rat_lang::optimize::optimize;
 
-use crate::grammar::evaluate::evaluate;
-use crate::grammar::lexer::get_tokens;
-use crate::grammar::parser::get_ast;
+fn main() {
+    let args = env::args().collect::<Vec<_>>();
+    match args.get(1).map(String::as_str) {
+        // `rat doc script.rat [--html]` prints the documentation of the script
+        Some("doc") => match args.get(2) {
+            Some(path) => run_doc(path, args.get(3).map(String::as_str) == Some("--html")),
+            None => {
+                eprintln!("Usage: rat doc <file> [--html]");
+                process::exit(1);
+            }
+        },
+        // `rat --disasm script.rat` prints the bytecode the script compiles to
+        Some("--disasm") => match args.get(2) {
+            Some(path) => run_disasm(path),
+            None => {
+                eprintln!("Usage: rat --disasm <file>");
+                process::exit(1);
+            }
+        },
+        // `rat --optimize script.rat` prints the script after optimizing it,
+        // and what was taken out of it to stderr
+        Some("--optimize") => match args.get(2) {
+            Some(path) => run_optimize(path),
+            None => {
+                eprintln!("Usage: rat --optimize <file>");
+                process::exit(1);
+            }
+        },
+        Some(path) => run_file(path),
+        None => run_demo(),
+    }
+}
 
-mod grammar;
+fn run_doc(path: &str, html: bool) {
+    let program = parse_file(path);
+    if html {
+        print!("{}", to_html(&program));
+    } else {
+        print!("{}", to_markdown(&program));
+    }
+}
 
-fn main() {
+fn parse_file(path: &str) -> Program {
+    let input = fs::read_to_string(path).unwrap_or_else(|error| {
+        eprintln!("Could not read {}: {}", path, error);
+        process::exit(1);
+    });
+    let tokens = get_tokens(&input).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        process::exit(1);
+    });
+    let program = get_program_resilient(tokens).unwrap_or_else(|errors| {
+        for error in errors {
+            eprintln!("{}", error);
+        }
+        process::exit(1);
+    });
+    program
+}
+
+fn expand_file(path: &str) -> Program {
+    expand_macros(parse_file(path)).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        process::exit(1);
+    })
+}
+
+fn run_disasm(path: &str) {
+    let function = compile(&expand_file(path)).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        process::exit(1);
+    });
+    print!("{}", disassemble(&function.chunk));
+}
+
+fn run_optimize(path: &str) {
+    let (program, removed) = optimize(expand_file(path));
+    for removal in removed {
+        eprintln!("{}", removal);
+    }
+    print!("{}", unparse_program(&program));
+}
+
+fn run_file(path: &str) {
+    let program = expand_file(path);
+    // Imports are relative to the file, wherever it's run from
+    match execute_file(&program, Path::new(path)) {
+        // A script ending in something run for its effect, like a call to
+        // `println`, would otherwise print nil after what it printed
+        Ok(None | Some(Value::Nil)) => {}
+        Ok(Some(value)) => println!("{}", value),
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    }
+}
+
+fn run_demo() {
     let input = "2+3-1+456-1-3-2";
     let tokens = get_tokens(input).unwrap();
-    let ast = get_ast(&tokens);
+    let ast = get_ast(tokens).unwrap();
     println!("{:#?}", ast);
-    let value = evaluate(&ast);
-    println!("{}", value);
+    match evaluate(&ast) {
+        Ok(value) => println!("{}", value),
+        Err(error) => println!("{}", error),
+    }
 }
-
@@ -0,0 +1,666 @@
+use std::fmt::{Display, Formatter};
+use crate::grammar::evaluate::evaluate;
+use crate::grammar::lexer::Span;
+use crate::grammar::parser::{
+    Array, Assign, Binary, Block, Bool, Call, Char, Coalesce, Conditional, Destructure, Expression, ExpressionStatement,
+    Field, Float, For, Function, If, Impl, Index, IndexAssign, Integer, Interpolation, Lambda, Let, Map, Match, MatchArm,
+    Nil, Program, Range, Return, Spread, Statement, Str, StructLiteral, Try, Tuple, TupleIndex, Unary, While,
+};
+use crate::grammar::runtime::Value;
+
+// Something that eliminating dead code took out, with where it was
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Removal {
+    // The statements after a `return`, `break` or `continue` in a block,
+    // which nothing can reach
+    Unreachable(Span),
+    // The branch of an `if` or a conditional whose condition is always false
+    DeadBranch(Span),
+    // An expression statement with no effects, whose value nothing uses
+    UnusedValue(Span),
+}
+
+impl Removal {
+    pub fn span(&self) -> Span {
+        match self {
+            Removal::Unreachable(span) | Removal::DeadBranch(span) | Removal::UnusedValue(span) => *span,
+        }
+    }
+}
+
+impl Display for Removal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            Removal::Unreachable(_) => "unreachable code",
+            Removal::DeadBranch(_) => "a branch that never runs",
+            Removal::UnusedValue(_) => "an unused value",
+        };
+        write!(f, "Removed {} at line {}, character {}", description, self.span().line, self.span().character)
+    }
+}
+
+// Folds constants and then eliminates the dead code that's left, which the
+// folding can make more of, such as a condition that folds to `false`
+pub fn optimize(program: Program) -> (Program, Vec<Removal>) {
+    eliminate_dead_code(fold_program(program))
+}
+
+// Collapses every subtree made up only of literals into the literal it
+// evaluates to, keeping the span and id of the node it replaces. Anything
+// that would fail at runtime is left alone so that it still fails there.
+pub fn fold_constants(expression: Expression) -> Expression {
+    match expression {
+        Expression::Binary(binary) => fold_binary(binary),
+        Expression::Unary(unary) => fold_unary(unary),
+        Expression::Interpolation(interpolation) => fold_interpolation(interpolation),
+        Expression::Assign(assign) => Expression::Assign(Assign {
+            value: Box::new(fold_constants(*assign.value)),
+            ..assign
+        }),
+        Expression::Block(block) => Expression::Block(Block {
+            statements: block.statements.into_iter().map(fold_statement).collect(),
+            value: block.value.map(|value| Box::new(fold_constants(*value))),
+            ..block
+        }),
+        Expression::If(r#if) => Expression::If(If {
+            condition: Box::new(fold_constants(*r#if.condition)),
+            then_branch: Box::new(fold_constants(*r#if.then_branch)),
+            else_branch: r#if.else_branch.map(|branch| Box::new(fold_constants(*branch))),
+            ..r#if
+        }),
+        Expression::Conditional(conditional) => Expression::Conditional(Conditional {
+            condition: Box::new(fold_constants(*conditional.condition)),
+            then_branch: Box::new(fold_constants(*conditional.then_branch)),
+            else_branch: Box::new(fold_constants(*conditional.else_branch)),
+            ..conditional
+        }),
+        Expression::Coalesce(coalesce) => Expression::Coalesce(Coalesce {
+            value: Box::new(fold_constants(*coalesce.value)),
+            fallback: Box::new(fold_constants(*coalesce.fallback)),
+            ..coalesce
+        }),
+        Expression::Match(r#match) => Expression::Match(Match {
+            scrutinee: Box::new(fold_constants(*r#match.scrutinee)),
+            arms: r#match.arms.into_iter()
+                .map(|arm| MatchArm {
+                    guard: arm.guard.map(fold_constants),
+                    body: fold_constants(arm.body),
+                    ..arm
+                })
+                .collect(),
+            ..r#match
+        }),
+        Expression::Try(r#try) => Expression::Try(Try {
+            body: Box::new(fold_constants(*r#try.body)),
+            handler: Box::new(fold_constants(*r#try.handler)),
+            ..r#try
+        }),
+        Expression::Range(range) => Expression::Range(Range {
+            start: Box::new(fold_constants(*range.start)),
+            end: Box::new(fold_constants(*range.end)),
+            ..range
+        }),
+        Expression::Call(call) => Expression::Call(Call {
+            callee: Box::new(fold_constants(*call.callee)),
+            arguments: call.arguments.into_iter().map(fold_constants).collect(),
+            ..call
+        }),
+        Expression::Lambda(lambda) => Expression::Lambda(Lambda {
+            body: Box::new(fold_constants(*lambda.body)),
+            ..lambda
+        }),
+        Expression::Map(map) => Expression::Map(Map {
+            entries: map.entries.into_iter().map(|(key, value)| (fold_constants(key), fold_constants(value))).collect(),
+            ..map
+        }),
+        Expression::Tuple(tuple) => Expression::Tuple(Tuple {
+            elements: tuple.elements.into_iter().map(fold_constants).collect(),
+            ..tuple
+        }),
+        Expression::TupleIndex(index) => Expression::TupleIndex(TupleIndex {
+            tuple: Box::new(fold_constants(*index.tuple)),
+            ..index
+        }),
+        Expression::Index(index) => Expression::Index(Index {
+            target: Box::new(fold_constants(*index.target)),
+            index: Box::new(fold_constants(*index.index)),
+            ..index
+        }),
+        Expression::IndexAssign(assign) => Expression::IndexAssign(IndexAssign {
+            target: Box::new(fold_constants(*assign.target)),
+            index: Box::new(fold_constants(*assign.index)),
+            value: Box::new(fold_constants(*assign.value)),
+            ..assign
+        }),
+        Expression::Array(array) => Expression::Array(Array {
+            elements: array.elements.into_iter().map(fold_constants).collect(),
+            ..array
+        }),
+        Expression::StructLiteral(literal) => Expression::StructLiteral(StructLiteral {
+            fields: literal.fields.into_iter().map(|(name, value)| (name, fold_constants(value))).collect(),
+            ..literal
+        }),
+        Expression::Field(field) => Expression::Field(Field {
+            target: Box::new(fold_constants(*field.target)),
+            ..field
+        }),
+        Expression::Spread(spread) => Expression::Spread(Spread {
+            value: Box::new(fold_constants(*spread.value)),
+            ..spread
+        }),
+        literal => literal,
+    }
+}
+
+// Takes out what can never run or has no effect, giving back what it took
+// out in the order it was in. Like folding, it leaves anything that could
+// fail at runtime alone, so a variable on its own is kept since it might not
+// be defined. The last statement of the program is kept too, since its value
+// is the program's.
+pub fn eliminate_dead_code(mut program: Program) -> (Program, Vec<Removal>) {
+    let mut removed = Vec::new();
+    for statement in &mut program.statements {
+        eliminate_statement(statement, &mut removed);
+    }
+    let last = program.statements.pop();
+    remove_unused(&mut program.statements, &mut removed);
+    program.statements.extend(last);
+    removed.sort_by_key(|removal| removal.span().start);
+    (program, removed)
+}
+
+fn eliminate_statement(statement: &mut Statement, removed: &mut Vec<Removal>) {
+    match statement {
+        Statement::Expression(statement) => eliminate(&mut statement.expression, removed),
+        Statement::Let(declaration) => eliminate(&mut declaration.initializer, removed),
+        Statement::Destructure(declaration) => eliminate(&mut declaration.initializer, removed),
+        Statement::While(r#while) => {
+            eliminate(&mut r#while.condition, removed);
+            eliminate(&mut r#while.body, removed);
+        }
+        Statement::For(r#for) => {
+            eliminate(&mut r#for.iterable, removed);
+            eliminate(&mut r#for.body, removed);
+        }
+        Statement::Function(function) => eliminate(&mut function.body, removed),
+        Statement::Impl(r#impl) => {
+            for method in &mut r#impl.methods {
+                eliminate(&mut method.body, removed);
+            }
+        }
+        Statement::Return(r#return) => {
+            if let Some(value) = &mut r#return.value {
+                eliminate(value, removed);
+            }
+        }
+        Statement::Break(_)
+        | Statement::Continue(_)
+        | Statement::Struct(_)
+        | Statement::Enum(_)
+        | Statement::Trait(_)
+        | Statement::Import(_)
+        | Statement::Macro(_) => {}
+    }
+}
+
+fn eliminate(expression: &mut Expression, removed: &mut Vec<Removal>) {
+    match expression {
+        // Down the left side of a chain of operators in a loop, so that a
+        // long chain doesn't recurse once per operator
+        Expression::Binary(binary) => {
+            let mut rights = vec![&mut binary.right];
+            let mut left = &mut binary.left;
+            loop {
+                match &mut **left {
+                    Expression::Binary(binary) => {
+                        rights.push(&mut binary.right);
+                        left = &mut binary.left;
+                    }
+                    operand => {
+                        eliminate(operand, removed);
+                        break;
+                    }
+                }
+            }
+            for right in rights.into_iter().rev() {
+                eliminate(right, removed);
+            }
+        }
+        Expression::Unary(unary) => eliminate(&mut unary.right, removed),
+        Expression::Interpolation(interpolation) => eliminate_all(&mut interpolation.parts, removed),
+        Expression::Assign(assign) => eliminate(&mut assign.value, removed),
+        Expression::Block(block) => eliminate_block(block, removed),
+        Expression::If(r#if) => {
+            eliminate(&mut r#if.condition, removed);
+            eliminate(&mut r#if.then_branch, removed);
+            if let Some(branch) = &mut r#if.else_branch {
+                eliminate(branch, removed);
+            }
+            if never(&r#if.condition) {
+                removed.push(Removal::DeadBranch(r#if.then_branch.span()));
+                // Without an else branch, the `if` evaluates to nil
+                let otherwise = match r#if.else_branch.take() {
+                    Some(branch) => *branch,
+                    None => Expression::Nil(Nil { span: r#if.span, id: r#if.id }),
+                };
+                *expression = otherwise;
+            }
+        }
+        Expression::Conditional(conditional) => {
+            eliminate(&mut conditional.condition, removed);
+            eliminate(&mut conditional.then_branch, removed);
+            eliminate(&mut conditional.else_branch, removed);
+            if never(&conditional.condition) {
+                removed.push(Removal::DeadBranch(conditional.then_branch.span()));
+                let otherwise = conditional.else_branch.take();
+                *expression = otherwise;
+            }
+        }
+        Expression::Coalesce(coalesce) => {
+            eliminate(&mut coalesce.value, removed);
+            eliminate(&mut coalesce.fallback, removed);
+        }
+        Expression::Match(r#match) => {
+            eliminate(&mut r#match.scrutinee, removed);
+            for arm in &mut r#match.arms {
+                if let Some(guard) = &mut arm.guard {
+                    eliminate(guard, removed);
+                }
+                eliminate(&mut arm.body, removed);
+            }
+        }
+        Expression::Try(r#try) => {
+            eliminate(&mut r#try.body, removed);
+            eliminate(&mut r#try.handler, removed);
+        }
+        Expression::Range(range) => {
+            eliminate(&mut range.start, removed);
+            eliminate(&mut range.end, removed);
+        }
+        Expression::Call(call) => {
+            eliminate(&mut call.callee, removed);
+            eliminate_all(&mut call.arguments, removed);
+        }
+        Expression::Lambda(lambda) => eliminate(&mut lambda.body, removed),
+        Expression::Map(map) => {
+            for (key, value) in &mut map.entries {
+                eliminate(key, removed);
+                eliminate(value, removed);
+            }
+        }
+        Expression::Tuple(tuple) => eliminate_all(&mut tuple.elements, removed),
+        Expression::TupleIndex(index) => eliminate(&mut index.tuple, removed),
+        Expression::Index(index) => {
+            eliminate(&mut index.target, removed);
+            eliminate(&mut index.index, removed);
+        }
+        Expression::IndexAssign(assign) => {
+            eliminate(&mut assign.target, removed);
+            eliminate(&mut assign.index, removed);
+            eliminate(&mut assign.value, removed);
+        }
+        Expression::Array(array) => eliminate_all(&mut array.elements, removed),
+        Expression::StructLiteral(literal) => {
+            for (_, value) in &mut literal.fields {
+                eliminate(value, removed);
+            }
+        }
+        Expression::Field(field) => eliminate(&mut field.target, removed),
+        Expression::Spread(spread) => eliminate(&mut spread.value, removed),
+        Expression::Integer(_)
+        | Expression::Float(_)
+        | Expression::Char(_)
+        | Expression::Str(_)
+        | Expression::Bool(_)
+        | Expression::Nil(_)
+        | Expression::Variable(_) => {}
+    }
+}
+
+fn eliminate_all(expressions: &mut [Expression], removed: &mut Vec<Removal>) {
+    for expression in expressions {
+        eliminate(expression, removed);
+    }
+}
+
+fn eliminate_block(block: &mut Block, removed: &mut Vec<Removal>) {
+    for statement in &mut block.statements {
+        eliminate_statement(statement, removed);
+    }
+    if let Some(value) = &mut block.value {
+        eliminate(value, removed);
+    }
+
+    let jump = block.statements.iter()
+        .position(|statement| matches!(statement, Statement::Return(_) | Statement::Break(_) | Statement::Continue(_)));
+    if let Some(jump) = jump {
+        let mut unreachable = block.statements.split_off(jump + 1).iter().map(Statement::span).collect::<Vec<_>>();
+        unreachable.extend(block.value.take().map(|value| value.span()));
+        if let (Some(first), Some(last)) = (unreachable.first(), unreachable.last()) {
+            removed.push(Removal::Unreachable(first.to(*last)));
+        }
+    }
+    remove_unused(&mut block.statements, removed);
+}
+
+// Takes out the expression statements that have no effect, none of which
+// give the value of what they're in
+fn remove_unused(statements: &mut Vec<Statement>, removed: &mut Vec<Removal>) {
+    statements.retain(|statement| match statement {
+        Statement::Expression(statement) if pure(&statement.expression) => {
+            removed.push(Removal::UnusedValue(statement.span));
+            false
+        }
+        _ => true,
+    });
+}
+
+// Whether evaluating the expression can't do anything or fail, so that
+// leaving it out makes no difference
+fn pure(expression: &Expression) -> bool {
+    match expression {
+        Expression::Array(array) => array.elements.iter().all(pure),
+        Expression::Tuple(tuple) => tuple.elements.iter().all(pure),
+        Expression::Lambda(_) => true,
+        expression => is_literal(expression),
+    }
+}
+
+fn never(condition: &Expression) -> bool {
+    matches!(condition, Expression::Bool(Bool { value: false, .. }))
+}
+
+pub fn fold_program(program: Program) -> Program {
+    Program {
+        statements: program.statements.into_iter().map(fold_statement).collect(),
+    }
+}
+
+fn fold_statement(statement: Statement) -> Statement {
+    match statement {
+        Statement::Expression(statement) => Statement::Expression(ExpressionStatement {
+            expression: fold_constants(statement.expression),
+            ..statement
+        }),
+        Statement::Let(declaration) => Statement::Let(Let {
+            initializer: fold_constants(declaration.initializer),
+            ..declaration
+        }),
+        Statement::Destructure(declaration) => Statement::Destructure(Destructure {
+            initializer: fold_constants(declaration.initializer),
+            ..declaration
+        }),
+        Statement::While(r#while) => Statement::While(While {
+            condition: fold_constants(r#while.condition),
+            body: fold_constants(r#while.body),
+            ..r#while
+        }),
+        Statement::For(r#for) => Statement::For(For {
+            iterable: fold_constants(r#for.iterable),
+            body: fold_constants(r#for.body),
+            ..r#for
+        }),
+        Statement::Function(function) => Statement::Function(fold_function(function)),
+        Statement::Impl(r#impl) => Statement::Impl(Impl {
+            methods: r#impl.methods.into_iter().map(fold_function).collect(),
+            ..r#impl
+        }),
+        Statement::Return(r#return) => Statement::Return(Return {
+            value: r#return.value.map(fold_constants),
+            ..r#return
+        }),
+        statement @ (
+            Statement::Break(_)
+            | Statement::Continue(_)
+            | Statement::Struct(_)
+            | Statement::Enum(_)
+            | Statement::Trait(_)
+            | Statement::Import(_)
+            | Statement::Macro(_)
+        ) => statement,
+    }
+}
+
+fn fold_function(function: Function) -> Function {
+    Function {
+        body: fold_constants(function.body),
+        ..function
+    }
+}
+
+// Goes down the left side of a chain of operators in a loop and folds it on
+// the way back up, so that long chains don't recurse once per operator
+fn fold_binary(binary: Binary) -> Expression {
+    let mut chain = Vec::new();
+    let mut left = Expression::Binary(binary);
+    while let Expression::Binary(mut binary) = left {
+        left = binary.left.take();
+        chain.push(binary);
+    }
+
+    let mut folded = fold_constants(left);
+    while let Some(mut binary) = chain.pop() {
+        *binary.left = folded;
+        *binary.right = fold_constants(binary.right.take());
+        folded = if is_literal(&binary.left) && is_literal(&binary.right) {
+            fold(Expression::Binary(binary))
+        } else {
+            Expression::Binary(binary)
+        };
+    }
+    folded
+}
+
+fn fold_unary(unary: Unary) -> Expression {
+    let unary = Unary {
+        right: Box::new(fold_constants(*unary.right)),
+        ..unary
+    };
+
+    if is_literal(&unary.right) {
+        fold(Expression::Unary(unary))
+    } else {
+        Expression::Unary(unary)
+    }
+}
+
+fn fold_interpolation(interpolation: Interpolation) -> Expression {
+    let interpolation = Interpolation {
+        parts: interpolation.parts.into_iter().map(fold_constants).collect(),
+        ..interpolation
+    };
+
+    if interpolation.parts.iter().all(is_literal) {
+        fold(Expression::Interpolation(interpolation))
+    } else {
+        Expression::Interpolation(interpolation)
+    }
+}
+
+// Replaces an expression whose operands are all literals with its value
+fn fold(expression: Expression) -> Expression {
+    let (span, id) = (expression.span(), expression.id());
+    match evaluate(&expression) {
+        Ok(Value::Int(value)) => Expression::Integer(Integer { value, span, id }),
+        Ok(Value::Float(value)) => Expression::Float(Float { value, span, id }),
+        Ok(Value::Char(value)) => Expression::Char(Char { value, span, id }),
+        Ok(Value::Str(value)) => Expression::Str(Str { value, span, id }),
+        Ok(Value::Bool(value)) => Expression::Bool(Bool { value, span, id }),
+        Ok(
+            Value::Nil
+            | Value::BigInt(_)
+            | Value::Rational(_)
+            | Value::Function(_)
+            | Value::Array(_)
+            | Value::Map(_)
+            | Value::Tuple(_)
+            | Value::Range(_)
+            | Value::StructType(_)
+            | Value::Struct(_)
+            | Value::EnumType(_)
+            | Value::Enum(_)
+            | Value::Module(_)
+        ) | Err(_) => expression,
+    }
+}
+
+fn is_literal(expression: &Expression) -> bool {
+    matches!(
+        expression,
+        Expression::Integer(_)
+            | Expression::Float(_)
+            | Expression::Char(_)
+            | Expression::Str(_)
+            | Expression::Bool(_)
+            | Expression::Nil(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::lexer::get_tokens;
+    use crate::grammar::parser::{get_ast, get_program};
+    use crate::grammar::unparse::{unparse, unparse_program};
+
+    fn fold_source(input: &str) -> Expression {
+        fold_constants(get_ast(get_tokens(input).unwrap()).unwrap())
+    }
+
+    #[test]
+    fn test_folds_constants() -> Result<(), String> {
+        assert!(matches!(fold_source("2+3-1"), Expression::Integer(Integer { value: 4, .. })));
+        assert!(matches!(fold_source("-(2**3)*1.5"), Expression::Float(Float { value: -12.0, .. })));
+        assert!(matches!(fold_source("-(2.0**3.0)*1.5"), Expression::Float(Float { value: -12.0, .. })));
+        assert_eq!(unparse(&fold_source("\"a${1+1}b${'c'}\"")), "\"a2bc\"");
+        assert!(matches!(fold_source("1 + 1 == 2"), Expression::Bool(Bool { value: true, .. })));
+        assert_eq!(unparse(&fold_source("if 1 < 2 { 3 * 4 } else { x }")), "if true { 12 } else { x }");
+        assert_eq!(unparse(&fold_source("[1 + 1, [2 * 3]]")), "[2, [6]]");
+        assert_eq!(unparse(&fold_source("xs[1 + 1][0] += 2 * 3")), "xs[2][0] += 6");
+        assert_eq!(unparse(&fold_source("{ 1 + 1: 2 * 3 }")), "{ 2: 6 }");
+        assert_eq!(unparse(&fold_source("(1 + 1, (2 * 3,)).1")), "(2, (6,)).1");
+        assert_eq!(unparse(&fold_source("1 > 2 ? x : 2 * 3")), "false ? x : 6");
+        assert_eq!(unparse(&fold_source("match 1 + 1 { 2 if 1 < 2 => 3 * 3, _ => x }")), "match 2 { 2 if true => 9, _ => x }");
+        assert_eq!(unparse(&fold_source("(x ?? 1 + 1) + (nil == nil)")), "(x ?? 2) + true");
+        assert_eq!(unparse(&fold_source("nil + 1")), "nil + 1");
+        assert!(matches!(fold_source(&vec!["2"; 1000].join(" * 1 + ")), Expression::Integer(Integer { value: 2000, .. })));
+        let chain = format!("x{}", " + y".repeat(100_000));
+        assert_eq!(unparse(&fold_source(&format!("{} + 1 * 2", chain))), format!("{} + 2", chain));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_folds_program() -> Result<(), String> {
+        let program = fold_program(get_program(get_tokens("1 + 1; 2 * 3 / 0").unwrap()).unwrap());
+        assert_eq!(unparse_program(&program), "2\n6 / 0\n");
+
+        let program = fold_program(get_program(get_tokens("let x = 2 * 3; x + (1 + 1)").unwrap()).unwrap());
+        assert_eq!(unparse_program(&program), "let x = 6\nx + 2\n");
+
+        let program = fold_program(get_program(get_tokens("while x < 2 + 3 { x += 2 * 2 }").unwrap()).unwrap());
+        assert_eq!(unparse_program(&program), "while x < 5 { x = x + 4 }\n");
+
+        let program = fold_program(get_program(get_tokens("for i in 1 + 1..2 * 5 { i * (2 + 2) }").unwrap()).unwrap());
+        assert_eq!(unparse_program(&program), "for i in 2..10 { i * 4 }\n");
+
+        let program = fold_program(get_program(get_tokens("fn f(a) { return a * (1 + 1) }; f(3 - 1)").unwrap()).unwrap());
+        assert_eq!(unparse_program(&program), "fn f(a) { return a * 2; }\nf(2)\n");
+
+        let program = fold_program(get_program(get_tokens("let f = fn() { 2 ** 3 }").unwrap()).unwrap());
+        assert_eq!(unparse_program(&program), "let f = fn() { 8 }\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_keeps_span_and_id() -> Result<(), String> {
+        let ast = get_ast(get_tokens("1 + (2 * 3)").unwrap()).unwrap();
+        let (span, id) = (ast.span(), ast.id());
+
+        let folded = fold_constants(ast);
+        assert!(matches!(folded, Expression::Integer(Integer { value: 7, .. })));
+        assert_eq!((folded.span(), folded.id()), (span, id));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_leaves_runtime_errors() -> Result<(), String> {
+        assert_eq!(unparse(&fold_source("1 + 2 / (3 - 3)")), "1 + 2 / 0");
+        assert_eq!(unparse(&fold_source("9223372036854775807 + (1 + 1)")), "9223372036854775807 + 2");
+        assert_eq!(unparse(&fold_source("(1 + 1) + 'a'")), "2 + 'a'");
+        assert_eq!(unparse(&fold_source("2 ** -(1)")), "2 ** -1");
+
+        Ok(())
+    }
+
+    fn optimize_source(input: &str) -> (String, Vec<Removal>) {
+        let (program, removed) = optimize(get_program(get_tokens(input).unwrap()).unwrap());
+        (unparse_program(&program), removed)
+    }
+
+    fn positions(removed: &[Removal]) -> Vec<(u32, u32)> {
+        removed.iter().map(|removal| (removal.span().line, removal.span().character)).collect()
+    }
+
+    #[test]
+    fn test_removes_unreachable() -> Result<(), String> {
+        let (source, removed) = optimize_source("fn f(x) {\n    return x\n    print(x); x + 1\n}\nf(1)");
+        assert_eq!(source, "fn f(x) { return x; }\nf(1)\n");
+        match removed[..] {
+            [Removal::Unreachable(span)] => {
+                assert_eq!((span.line, span.character), (3, 5));
+                assert_eq!(span.end - span.start, "print(x); x + 1".len());
+            }
+            _ => panic!("Expected the statements after the return to be reported, got {:?}", removed),
+        }
+
+        let (source, removed) = optimize_source("while true { break; x = 1 }\nfor i in 0..n { continue }");
+        assert_eq!(source, "while true { break; }\nfor i in 0..n { continue; }\n");
+        assert_eq!(removed.len(), 1);
+        // Nothing comes after the jump, and a jump inside an `if` doesn't end the block
+        let (_, removed) = optimize_source("fn f(x) { if x { return 1 }\nx }");
+        assert!(removed.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_removes_dead_branches() -> Result<(), String> {
+        let (source, removed) = optimize_source("if 1 > 2 { f() } else { g() }");
+        assert_eq!(source, "{ g() }\n");
+        assert!(matches!(removed[..], [Removal::DeadBranch(span)] if span.character == 10));
+
+        let (source, removed) = optimize_source("let x = if false { 1 } else if true { 2 } else { 3 }\nx");
+        assert_eq!(source, "let x = if true { 2 } else { 3 }\nx\n");
+        assert_eq!(removed.len(), 1);
+        assert_eq!(optimize_source("let x = 2 < 1 ? f() : g()\nx").0, "let x = g()\nx\n");
+
+        // An `if` without an else branch becomes nil, which is then unused
+        let (source, removed) = optimize_source("{ if false { f() }\n1 }");
+        assert_eq!(source, "{ 1 }\n");
+        assert_eq!(positions(&removed), [(1, 3), (1, 12)]);
+        assert_eq!(optimize_source("if true { 1 }\nif x { 2 }").0, "if true { 1 }\nif x { 2 }\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_removes_unused_values() -> Result<(), String> {
+        let (source, removed) = optimize_source("let x = 1\n2; 'c'; [3, (\"a\", nil)]\nx; fn(y) { y }; x + 1");
+        assert_eq!(source, "let x = 1\nx\nx + 1\n");
+        assert_eq!(positions(&removed), [(2, 1), (2, 4), (2, 9), (3, 4)]);
+        assert_eq!(optimize_source("fn f() { 1 + 1; 2 }\nf()").0, "fn f() { 2 }\nf()\n");
+
+        // What could fail or has an effect stays, and so does the program's value
+        let (source, removed) = optimize_source("1 / 0; [f()]; -'a'; 2");
+        assert_eq!(source, "1 / 0\n[f()]\n-'a'\n2\n");
+        assert!(removed.is_empty());
+
+        let report = removed.iter().chain(&optimize_source("1; 2").1).map(Removal::to_string).collect::<Vec<_>>();
+        assert_eq!(report, ["Removed an unused value at line 1, character 1"]);
+
+        Ok(())
+    }
+}